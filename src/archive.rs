@@ -0,0 +1,246 @@
+use {
+    crate::{backend::Backend, format::CodeStr, report::DeletedImage},
+    byte_unit::Byte,
+    flate2::{read::GzDecoder, write::GzEncoder, Compression},
+    std::{
+        fs::{create_dir_all, read_dir, remove_file, File},
+        io::{self, BufReader, BufWriter},
+        path::{Path, PathBuf},
+        time::Duration,
+    },
+    tempfile::NamedTempFile,
+};
+
+// The extension of the archives, which are gzipped tarballs like the ones `docker image save`
+// produces
+const EXTENSION: &str = ".tar.gz";
+
+// How many hexadecimal digits of the image ID appear in the name of its archive, as in the output
+// of `docker image ls`
+const SHORT_ID_LENGTH: usize = 12;
+
+// The name of the archive of an image which was deleted at the given time, e.g.,
+// `1700000000-0123456789ab.tar.gz`
+fn archive_name(image_id: &str, since_epoch: Duration) -> String {
+    let hex = image_id.split_once(':').map_or(image_id, |(_, hex)| hex);
+    format!(
+        "{}-{}{EXTENSION}",
+        since_epoch.as_secs(),
+        &hex[..hex.len().min(SHORT_ID_LENGTH)],
+    )
+}
+
+// Determine whether an archive name belongs to an image with the given ID.
+fn archive_of(file_name: &str, image_id: &str) -> bool {
+    let hex = image_id.split_once(':').map_or(image_id, |(_, hex)| hex);
+    let short_id = &hex[..hex.len().min(SHORT_ID_LENGTH)];
+    file_name
+        .strip_suffix(EXTENSION)
+        .and_then(|stem| stem.split_once('-'))
+        .is_some_and(|(_, archived_id)| !short_id.is_empty() && archived_id == short_id)
+}
+
+// Archive an image in a directory before it's deleted. The archive is saved by tag, so
+// `docker image load` restores the tags along with the image. Untagged images are saved by ID.
+// Returns the path of the archive.
+pub fn save(
+    backend: &Backend,
+    dir: &Path,
+    image: &DeletedImage,
+    since_epoch: Duration,
+) -> io::Result<PathBuf> {
+    let path = dir.join(archive_name(&image.id, since_epoch));
+    info!(
+        "Archiving image {} to {}\u{2026}",
+        image.id.code_str(),
+        path.to_string_lossy().code_str(),
+    );
+
+    let mut images = image
+        .repository_tags
+        .iter()
+        .filter(|repository_tag| !repository_tag.contains("<none>"))
+        .cloned()
+        .collect::<Vec<_>>();
+    if images.is_empty() {
+        images.push(image.id.clone());
+    }
+
+    // Write to a temporary file first, so an interrupted save doesn't leave a truncated archive
+    // behind.
+    create_dir_all(dir)?;
+    let file = NamedTempFile::new_in(dir)?;
+    let mut encoder = GzEncoder::new(BufWriter::new(file), Compression::default());
+    backend.save_images(&images, &mut encoder)?;
+    encoder
+        .finish()?
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?
+        .persist(&path)
+        .map_err(|error| error.error)?;
+
+    Ok(path)
+}
+
+// Find the newest archive of an image in a directory, if there is one.
+pub fn find(dir: &Path, image_id: &str) -> io::Result<Option<PathBuf>> {
+    // If nothing was ever archived, the directory may not exist.
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    let mut newest = None;
+    for entry in entries {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_file() && archive_of(&file_name, image_id) {
+            let since_epoch = file_name
+                .split_once('-')
+                .and_then(|(since_epoch, _)| since_epoch.parse::<u64>().ok())
+                .unwrap_or(0);
+            if newest
+                .as_ref()
+                .is_none_or(|(newest_since_epoch, _)| since_epoch >= *newest_since_epoch)
+            {
+                newest = Some((since_epoch, entry.path()));
+            }
+        }
+    }
+
+    Ok(newest.map(|(_, path)| path))
+}
+
+// Load an archive which `save` wrote, restoring the image along with the tags it was saved by.
+pub fn load(backend: &Backend, path: &Path) -> io::Result<()> {
+    info!(
+        "Loading archive {}\u{2026}",
+        path.to_string_lossy().code_str(),
+    );
+
+    backend.load_images(&mut GzDecoder::new(BufReader::new(File::open(path)?)))
+}
+
+// Delete the oldest archives in a directory until they take up no more than `max_size` in total.
+// The newest archive is always kept, even if it's larger than that. Returns the paths of the
+// deleted archives.
+pub fn prune(dir: &Path, max_size: Byte) -> io::Result<Vec<PathBuf>> {
+    let mut archives = vec![];
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_file() && file_name.ends_with(EXTENSION) {
+            // The names start with the time of the deletion. Other archives are deleted first.
+            let since_epoch = file_name
+                .split_once('-')
+                .and_then(|(since_epoch, _)| since_epoch.parse::<u64>().ok())
+                .unwrap_or(0);
+            archives.push((since_epoch, entry.path(), entry.metadata()?.len()));
+        }
+    }
+
+    // Sort the archives from oldest to newest.
+    archives.sort();
+
+    let mut total_size = archives
+        .iter()
+        .map(|(_, _, size)| u128::from(*size))
+        .sum::<u128>();
+    let mut deleted = vec![];
+    for (_, path, size) in archives.iter().take(archives.len().saturating_sub(1)) {
+        if total_size <= max_size.get_bytes() {
+            break;
+        }
+
+        remove_file(path)?;
+        total_size -= u128::from(*size);
+        deleted.push(path.clone());
+    }
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{archive_name, archive_of, find, prune},
+        byte_unit::Byte,
+        std::{fs, time::Duration},
+        tempfile::tempdir,
+    };
+
+    #[test]
+    fn archive_name_full_id() {
+        assert_eq!(
+            archive_name(
+                "sha256:0123456789abcdef0123456789abcdef",
+                Duration::from_secs(1_700_000_000),
+            ),
+            "1700000000-0123456789ab.tar.gz",
+        );
+    }
+
+    #[test]
+    fn archive_name_short_id() {
+        assert_eq!(
+            archive_name("0123", Duration::from_secs(42)),
+            "42-0123.tar.gz",
+        );
+    }
+
+    #[test]
+    fn archive_of_image() {
+        assert!(archive_of(
+            "1700000000-0123456789ab.tar.gz",
+            "sha256:0123456789abcdef0123456789abcdef",
+        ));
+        assert!(archive_of("1700000000-0123456789ab.tar.gz", "0123456789ab"));
+        assert!(!archive_of("1700000000-0123456789ab.tar.gz", "0123"));
+        assert!(!archive_of("1700000000-0123456789ab.tar", "0123456789ab"));
+        assert!(!archive_of("1700000000-0123456789ab.tar.gz", ""));
+    }
+
+    #[test]
+    fn find_newest() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("900-0123456789ab.tar.gz"), []).unwrap();
+        fs::write(dir.path().join("1000-0123456789ab.tar.gz"), []).unwrap();
+        fs::write(dir.path().join("1100-ba9876543210.tar.gz"), []).unwrap();
+
+        assert_eq!(
+            find(dir.path(), "sha256:0123456789abcdef").unwrap(),
+            Some(dir.path().join("1000-0123456789ab.tar.gz")),
+        );
+        assert_eq!(find(dir.path(), "sha256:fedcba987654").unwrap(), None);
+    }
+
+    #[test]
+    fn prune_oldest_first() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("900-0.tar.gz"), [0_u8; 100]).unwrap();
+        fs::write(dir.path().join("1000-1.tar.gz"), [0_u8; 100]).unwrap();
+        fs::write(dir.path().join("1100-2.tar.gz"), [0_u8; 100]).unwrap();
+        fs::write(dir.path().join("notes.txt"), [0_u8; 100]).unwrap();
+
+        assert_eq!(
+            prune(dir.path(), Byte::from_bytes(250)).unwrap(),
+            vec![dir.path().join("900-0.tar.gz")],
+        );
+        assert!(dir.path().join("1000-1.tar.gz").exists());
+        assert!(dir.path().join("notes.txt").exists());
+    }
+
+    #[test]
+    fn prune_keeps_newest() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("1000-0.tar.gz"), [0_u8; 100]).unwrap();
+        fs::write(dir.path().join("1100-1.tar.gz"), [0_u8; 100]).unwrap();
+
+        assert_eq!(
+            prune(dir.path(), Byte::from_bytes(50)).unwrap(),
+            vec![dir.path().join("1000-0.tar.gz")],
+        );
+        assert!(dir.path().join("1100-1.tar.gz").exists());
+    }
+}
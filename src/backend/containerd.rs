@@ -0,0 +1,293 @@
+use {
+    crate::{backend::Backend, format::CodeStr},
+    byte_unit::Byte,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{HashMap, HashSet},
+        io,
+        path::PathBuf,
+        process::Stdio,
+    },
+};
+
+// The default root directory of containerd
+const ROOT_DIR: &str = "/var/lib/containerd";
+
+// A containerd event (a line of output from `nerdctl events --format '{{json .}}'`)
+#[derive(Deserialize, Serialize, Debug)]
+struct Event {
+    #[serde(rename = "Topic")]
+    topic: String,
+
+    // The payload of the event, which is itself encoded as JSON
+    #[serde(rename = "Event")]
+    event: String,
+}
+
+// The payload of a `/containers/create` event
+#[derive(Deserialize, Serialize, Debug)]
+struct ContainerCreateEvent {
+    image: String,
+}
+
+// The payload of an `/images/create` or `/images/update` event
+#[derive(Deserialize, Serialize, Debug)]
+struct ImageEvent {
+    name: String,
+}
+
+// A line of output from `nerdctl image ls --format '{{json .}}'`
+#[derive(Deserialize, Serialize, Debug)]
+struct ImageSizeRecord {
+    #[serde(rename = "ID")]
+    id: String,
+
+    #[serde(rename = "Size")]
+    size: String,
+
+    // Each platform of a multi-platform image is listed separately under the same ID.
+    #[serde(rename = "Platform", default)]
+    platform: String,
+}
+
+// Determine which image, if any, was used according to a line of output from `nerdctl events`.
+pub fn used_image(line: &str) -> Option<String> {
+    // Parse the line as an event.
+    let event = match serde_json::from_str::<Event>(line) {
+        Ok(event) => {
+            trace!("Parsed as: {}", format!("{event:?}").code_str());
+            event
+        }
+        Err(error) => {
+            trace!("Skipping due to: {error}");
+            return None;
+        }
+    };
+
+    // Extract the image from the payload, if the event is relevant.
+    let image = match event.topic.as_str() {
+        "/containers/create" => {
+            serde_json::from_str::<ContainerCreateEvent>(&event.event).map(|payload| payload.image)
+        }
+        "/images/create" | "/images/update" => {
+            serde_json::from_str::<ImageEvent>(&event.event).map(|payload| payload.name)
+        }
+        _ => {
+            trace!("Skipping due to irrelevance.");
+            return None;
+        }
+    };
+
+    match image {
+        Ok(image) => Some(image),
+        Err(error) => {
+            trace!("Invalid containerd event: {error}");
+            None
+        }
+    }
+}
+
+// Ask containerd for the IDs of the images currently in use by containers. Unlike Docker, `nerdctl`
+// reports the image reference rather than the ID for each container, so we have to resolve them.
+pub fn image_ids_in_use(backend: &Backend) -> io::Result<HashSet<String>> {
+    // Query containerd for the images of all the containers.
+    let output = backend
+        .command()
+        .args([
+            "container",
+            "ls",
+            "--all",
+            "--no-trunc",
+            "--format",
+            "{{.Image}}",
+        ])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        return Err(io::Error::other(
+            "Unable to determine IDs of images currently in use by containers.",
+        ));
+    }
+
+    // Interpret the output bytes as UTF-8 and resolve each distinct image to its ID.
+    let images = String::from_utf8(output.stdout)
+        .map_err(io::Error::other)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(ToOwned::to_owned)
+        .collect::<HashSet<_>>();
+
+    let mut image_ids = HashSet::new();
+    for image in images {
+        match backend.image_id(&image) {
+            Ok(image_id) => {
+                image_ids.insert(image_id);
+            }
+            Err(error) => {
+                // The image may have been deleted out from under the container.
+                debug!("{error}");
+            }
+        }
+    }
+
+    Ok(image_ids)
+}
+
+// Determine containerd's root directory. `nerdctl` doesn't report it, so we assume the default.
+pub fn root_dir() -> PathBuf {
+    PathBuf::from(ROOT_DIR)
+}
+
+// Add up the sizes of the distinct images in a list of image size records. An image is listed once
+// per tag and platform, and its platforms are counted separately.
+#[allow(clippy::map_err_ignore)]
+fn total_size(records: &[ImageSizeRecord]) -> io::Result<Byte> {
+    let mut sizes = HashMap::new();
+    for record in records {
+        let size = Byte::from_str(&record.size).map_err(|_| {
+            io::Error::other(format!(
+                "Unable to parse {} from {}.",
+                record.size.code_str(),
+                "nerdctl image ls".code_str(),
+            ))
+        })?;
+        sizes.insert((&record.id, &record.platform), size.get_bytes());
+    }
+
+    Ok(Byte::from_bytes(sizes.values().sum()))
+}
+
+// Get the total space used by containerd images. There is no equivalent of `docker system df`, so
+// we add up the sizes of the individual images.
+pub fn space_usage(backend: &Backend) -> io::Result<Byte> {
+    // Query containerd for the images and their sizes.
+    let output = backend
+        .command()
+        .args([
+            "image",
+            "ls",
+            "--all",
+            "--no-trunc",
+            "--format",
+            "{{json .}}",
+        ])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        return Err(io::Error::other(
+            "Unable to determine the disk space used by containerd images.",
+        ));
+    }
+
+    // Parse the lines.
+    let records = String::from_utf8(output.stdout)
+        .map_err(io::Error::other)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str::<ImageSizeRecord>(line).map_err(io::Error::other))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    total_size(&records)
+}
+
+// Delete a containerd image. Unlike `docker image rm`, `nerdctl image rm` has no `--no-prune` flag.
+pub fn delete_image(backend: &Backend, image: &str) -> io::Result<()> {
+    info!("Deleting image {}\u{2026}", image.code_str());
+
+    // Tell containerd to delete the image.
+    let mut child = backend
+        .command()
+        .args(["image", "rm", "--force", image])
+        .spawn()?;
+
+    // Ensure the command succeeded.
+    if !child.wait()?.success() {
+        return Err(io::Error::other(format!(
+            "Unable to delete image {}.",
+            image.code_str(),
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{total_size, used_image, ImageSizeRecord};
+
+    #[test]
+    fn used_image_container_create() {
+        assert_eq!(
+            used_image(concat!(
+                r#"{"Topic":"/containers/create","Event":"{\"id\":\"abc\","#,
+                r#"\"image\":\"docker.io/library/alpine:latest\"}"}"#,
+            )),
+            Some("docker.io/library/alpine:latest".to_owned()),
+        );
+    }
+
+    #[test]
+    fn used_image_image_create() {
+        assert_eq!(
+            used_image(concat!(
+                r#"{"Topic":"/images/create","#,
+                r#""Event":"{\"name\":\"docker.io/library/debian:latest\"}"}"#,
+            )),
+            Some("docker.io/library/debian:latest".to_owned()),
+        );
+    }
+
+    #[test]
+    fn used_image_irrelevant() {
+        assert_eq!(
+            used_image(r#"{"Topic":"/tasks/exit","Event":"{\"container_id\":\"abc\"}"}"#),
+            None,
+        );
+    }
+
+    #[test]
+    fn total_size_deduplicates() {
+        let records = vec![
+            ImageSizeRecord {
+                id: "sha256:0".to_owned(),
+                size: "1 MB".to_owned(),
+                platform: "linux/amd64".to_owned(),
+            },
+            ImageSizeRecord {
+                id: "sha256:0".to_owned(),
+                size: "1 MB".to_owned(),
+                platform: "linux/amd64".to_owned(),
+            },
+            ImageSizeRecord {
+                id: "sha256:1".to_owned(),
+                size: "2 MB".to_owned(),
+                platform: "linux/amd64".to_owned(),
+            },
+        ];
+
+        assert_eq!(total_size(&records).unwrap().get_bytes(), 3_000_000);
+    }
+
+    #[test]
+    fn total_size_platforms() {
+        let records = vec![
+            ImageSizeRecord {
+                id: "sha256:0".to_owned(),
+                size: "1 MB".to_owned(),
+                platform: "linux/amd64".to_owned(),
+            },
+            ImageSizeRecord {
+                id: "sha256:0".to_owned(),
+                size: "2 MB".to_owned(),
+                platform: "linux/arm64".to_owned(),
+            },
+        ];
+
+        assert_eq!(total_size(&records).unwrap().get_bytes(), 3_000_000);
+    }
+}
@@ -0,0 +1,203 @@
+use {
+    crate::{backend::Endpoint, format::CodeStr},
+    serde::Deserialize,
+    std::{
+        collections::HashMap,
+        env,
+        fs::{read_dir, read_to_string},
+        io,
+        path::{Path, PathBuf},
+    },
+};
+
+// The name of the context which refers to the default endpoint
+pub const DEFAULT_CONTEXT: &str = "default";
+
+// The endpoint of the default context
+#[cfg(unix)]
+const DEFAULT_HOST: &str = "unix:///var/run/docker.sock";
+#[cfg(windows)]
+const DEFAULT_HOST: &str = "npipe:////./pipe/docker_engine";
+
+// The contents of a `meta.json` file in the context store
+#[derive(Deserialize, Debug)]
+struct Metadata {
+    #[serde(rename = "Name")]
+    name: String,
+
+    #[serde(rename = "Endpoints")]
+    endpoints: HashMap<String, EndpointMetadata>,
+}
+
+// The metadata for an endpoint of a context
+#[derive(Deserialize, Debug)]
+struct EndpointMetadata {
+    #[serde(rename = "Host")]
+    host: String,
+
+    #[serde(rename = "SkipTLSVerify", default)]
+    skip_tls_verify: bool,
+}
+
+// Where the Docker CLI keeps its configuration, including the context store
+pub fn config_dir() -> Option<PathBuf> {
+    env::var_os("DOCKER_CONFIG")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".docker")))
+}
+
+// Look up the Docker endpoint of a context in the context store of a Docker configuration
+// directory. The metadata for each context is stored in a directory named after the SHA-256 digest
+// of the context name, so we search through all of them rather than computing the digest.
+fn endpoint_in(config_dir: &Path, context: &str) -> io::Result<Endpoint> {
+    let meta_dir = config_dir.join("contexts").join("meta");
+    let entries = match read_dir(&meta_dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Docker context {} not found.", context.code_str()),
+            ));
+        }
+        Err(error) => return Err(error),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let metadata = match read_to_string(entry.path().join("meta.json")) {
+            Ok(json) => serde_json::from_str::<Metadata>(&json).map_err(|error| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Unable to parse the metadata in {}. Details: {}",
+                        entry.path().to_string_lossy().code_str(),
+                        error,
+                    ),
+                )
+            })?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+            Err(error) => return Err(error),
+        };
+
+        if metadata.name != context {
+            continue;
+        }
+
+        let endpoint = metadata.endpoints.get("docker").ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Docker context {} has no Docker endpoint.",
+                    context.code_str(),
+                ),
+            )
+        })?;
+
+        // The TLS material, if any, lives in a parallel directory.
+        let cert_path = config_dir
+            .join("contexts")
+            .join("tls")
+            .join(entry.file_name())
+            .join("docker");
+
+        return Ok(Endpoint {
+            host: endpoint.host.clone(),
+            cert_path: if cert_path.is_dir() {
+                Some(cert_path)
+            } else {
+                None
+            },
+            tls_verify: !endpoint.skip_tls_verify,
+        });
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("Docker context {} not found.", context.code_str()),
+    ))
+}
+
+// Look up the Docker endpoint of a context.
+pub fn endpoint(context: &str) -> io::Result<Endpoint> {
+    // The default context isn't in the context store.
+    if context == DEFAULT_CONTEXT {
+        return Ok(Endpoint {
+            host: env::var("DOCKER_HOST").unwrap_or_else(|_| DEFAULT_HOST.to_owned()),
+            cert_path: env::var_os("DOCKER_CERT_PATH").map(PathBuf::from),
+            tls_verify: env::var_os("DOCKER_TLS_VERIFY").is_some(),
+        });
+    }
+
+    let config_dir = config_dir()
+        .ok_or_else(|| io::Error::other("Unable to locate the Docker configuration directory."))?;
+
+    endpoint_in(&config_dir, context)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::endpoint_in,
+        crate::backend::Endpoint,
+        std::fs::{create_dir_all, write},
+        tempfile::tempdir,
+    };
+
+    #[test]
+    fn endpoint_in_missing_store() {
+        let config_dir = tempdir().unwrap();
+
+        assert!(endpoint_in(config_dir.path(), "remote").is_err());
+    }
+
+    #[test]
+    fn endpoint_in_plain() {
+        let config_dir = tempdir().unwrap();
+        let meta_dir = config_dir.path().join("contexts/meta/0123");
+        create_dir_all(&meta_dir).unwrap();
+        write(
+            meta_dir.join("meta.json"),
+            concat!(
+                r#"{"Name":"remote","Metadata":{},"Endpoints":{"docker":{"Host":"ssh://builder","#,
+                r#""SkipTLSVerify":false}}}"#,
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(
+            endpoint_in(config_dir.path(), "remote").unwrap(),
+            Endpoint {
+                host: "ssh://builder".to_owned(),
+                cert_path: None,
+                tls_verify: true,
+            },
+        );
+        assert!(endpoint_in(config_dir.path(), "other").is_err());
+    }
+
+    #[test]
+    fn endpoint_in_tls() {
+        let config_dir = tempdir().unwrap();
+        let meta_dir = config_dir.path().join("contexts/meta/4567");
+        let tls_dir = config_dir.path().join("contexts/tls/4567/docker");
+        create_dir_all(&meta_dir).unwrap();
+        create_dir_all(&tls_dir).unwrap();
+        write(
+            meta_dir.join("meta.json"),
+            concat!(
+                r#"{"Name":"secure","Metadata":{},"#,
+                r#""Endpoints":{"docker":{"Host":"tcp://10.0.0.2:2376","SkipTLSVerify":true}}}"#,
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(
+            endpoint_in(config_dir.path(), "secure").unwrap(),
+            Endpoint {
+                host: "tcp://10.0.0.2:2376".to_owned(),
+                cert_path: Some(tls_dir),
+                tls_verify: false,
+            },
+        );
+    }
+}
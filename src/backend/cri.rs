@@ -0,0 +1,367 @@
+use {
+    crate::{
+        backend::{Backend, ImageRecord, RepositoryTag},
+        format::CodeStr,
+    },
+    byte_unit::Byte,
+    serde::Deserialize,
+    std::{
+        collections::{HashMap, HashSet},
+        io,
+        path::PathBuf,
+        process::Stdio,
+        time::Duration,
+    },
+};
+
+// The output of `crictl images --output json`
+#[derive(Deserialize, Debug)]
+struct ImageList {
+    images: Vec<Image>,
+}
+
+// An image in the output of `crictl images --output json`
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Image {
+    id: String,
+
+    #[serde(default)]
+    repo_tags: Vec<String>,
+
+    #[serde(default)]
+    repo_digests: Vec<String>,
+
+    // The CRI encodes 64-bit integers as strings in JSON.
+    size: String,
+
+    // The runtime recommends keeping the image, e.g., because it's the sandbox image.
+    #[serde(default)]
+    pinned: bool,
+}
+
+// The output of `crictl inspecti --output json`
+#[derive(Deserialize, Debug)]
+struct ImageStatus {
+    status: ImageStatusImage,
+}
+
+// The `status` field in the output of `crictl inspecti --output json`
+#[derive(Deserialize, Debug)]
+struct ImageStatusImage {
+    id: String,
+}
+
+// The output of `crictl ps --output json`
+#[derive(Deserialize, Debug)]
+struct ContainerList {
+    containers: Vec<Container>,
+}
+
+// A container in the output of `crictl ps --output json`
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Container {
+    image_ref: String,
+}
+
+// The output of `crictl imagefsinfo --output json`. Older versions of `crictl` report a single
+// filesystem, whereas newer ones report a list of them.
+#[derive(Deserialize, Debug)]
+struct ImageFsInfo {
+    status: ImageFsInfoStatus,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ImageFsInfoStatus {
+    #[serde(rename_all = "camelCase")]
+    List {
+        image_filesystems: Vec<FilesystemUsage>,
+    },
+    Single(FilesystemUsage),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FilesystemUsage {
+    fs_id: FilesystemIdentifier,
+}
+
+#[derive(Deserialize, Debug)]
+struct FilesystemIdentifier {
+    mountpoint: String,
+}
+
+// Run a `crictl` command which prints JSON and parse its output.
+fn query<T: for<'de> Deserialize<'de>>(backend: &Backend, args: &[&str]) -> io::Result<T> {
+    let output = backend
+        .command()
+        .args(args)
+        .args(["--output", "json"])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Unable to run {}.",
+            format!("crictl {}", args.join(" ")).code_str(),
+        )));
+    }
+
+    serde_json::from_slice(&output.stdout).map_err(io::Error::other)
+}
+
+// Split an image reference like `docker.io/library/alpine:3.20` into a repository and a tag.
+fn parse_repository_tag(repo_tag: &str) -> RepositoryTag {
+    // The tag is separated by the last colon, as long as that colon isn't part of the registry host
+    // (e.g., `localhost:5000/alpine`).
+    match repo_tag.rsplit_once(':') {
+        Some((repository, tag)) if !tag.contains('/') => RepositoryTag {
+            repository: repository.to_owned(),
+            tag: tag.to_owned(),
+        },
+        _ => RepositoryTag {
+            repository: repo_tag.to_owned(),
+            tag: "<none>".to_owned(),
+        },
+    }
+}
+
+// Convert the images reported by the CRI into image records.
+fn image_records(images: Vec<Image>) -> HashMap<String, ImageRecord> {
+    images
+        .into_iter()
+        .map(|image| {
+            let mut repository_tags = image
+                .repo_tags
+                .iter()
+                .map(|repo_tag| parse_repository_tag(repo_tag))
+                .collect::<Vec<_>>();
+
+            // Uphold [ref:at_least_one_repository_tag].
+            if repository_tags.is_empty() {
+                repository_tags.push(RepositoryTag {
+                    repository: "<none>".to_owned(),
+                    tag: "<none>".to_owned(),
+                });
+            }
+
+            (
+                image.id,
+                ImageRecord {
+                    // The CRI has no notion of parent images.
+                    parent_id: None,
+
+                    // The CRI doesn't report when images were created, so on the first run, all
+                    // images are considered equally old.
+                    created_since_epoch: Duration::ZERO,
+
+                    repository_tags,
+                    repository_digests: image.repo_digests,
+
+                    // The CRI doesn't report image labels.
+                    labels: HashMap::new(),
+
+                    // The size is only used for reporting, so it's not worth failing over.
+                    size: image
+                        .size
+                        .parse::<u128>()
+                        .map_or_else(|_| Byte::from_bytes(0), Byte::from_bytes),
+                },
+            )
+        })
+        .collect()
+}
+
+// Ask the CRI for the ID of an image.
+pub fn image_id(backend: &Backend, image: &str) -> io::Result<String> {
+    query::<ImageStatus>(backend, &["inspecti", image])
+        .map(|image_status| image_status.status.id)
+        .map_err(|_| {
+            io::Error::other(format!(
+                "Unable to determine ID of image {}.",
+                image.code_str(),
+            ))
+        })
+}
+
+// Query the CRI for all the images.
+pub fn list_image_records(backend: &Backend) -> io::Result<HashMap<String, ImageRecord>> {
+    query::<ImageList>(backend, &["images"]).map(|image_list| image_records(image_list.images))
+}
+
+// Collect the IDs of the images which the runtime pinned.
+fn pinned_images(images: Vec<Image>) -> HashSet<String> {
+    images
+        .into_iter()
+        .filter(|image| image.pinned)
+        .map(|image| image.id)
+        .collect()
+}
+
+// Ask the CRI for the IDs of the images which the runtime pinned. The kubelet never deletes these.
+pub fn pinned_image_ids(backend: &Backend) -> io::Result<HashSet<String>> {
+    query::<ImageList>(backend, &["images"]).map(|image_list| pinned_images(image_list.images))
+}
+
+// Ask the CRI for the IDs of the images currently in use by containers, including the containers
+// of pods which have exited.
+pub fn image_ids_in_use(backend: &Backend) -> io::Result<HashSet<String>> {
+    let container_list = query::<ContainerList>(backend, &["ps", "--all"])?;
+
+    // Depending on the runtime, the image reference is either an image ID or a digest reference.
+    // Resolve the latter to image IDs.
+    let mut image_ids = HashSet::new();
+    for image_ref in container_list
+        .containers
+        .into_iter()
+        .map(|container| container.image_ref)
+        .collect::<HashSet<_>>()
+    {
+        if image_ref.starts_with("sha256:") {
+            image_ids.insert(image_ref);
+        } else {
+            match image_id(backend, &image_ref) {
+                Ok(image_id) => {
+                    image_ids.insert(image_id);
+                }
+                Err(error) => {
+                    // The image may have been deleted out from under the container.
+                    debug!("{error}");
+                }
+            }
+        }
+    }
+
+    Ok(image_ids)
+}
+
+// Determine the directory in which the CRI stores images.
+pub fn root_dir(backend: &Backend) -> io::Result<PathBuf> {
+    match query::<ImageFsInfo>(backend, &["imagefsinfo"])?.status {
+        ImageFsInfoStatus::List { image_filesystems } => image_filesystems
+            .into_iter()
+            .next()
+            .map(|filesystem| PathBuf::from(filesystem.fs_id.mountpoint))
+            .ok_or_else(|| io::Error::other("The CRI didn't report an image filesystem.")),
+        ImageFsInfoStatus::Single(filesystem) => Ok(PathBuf::from(filesystem.fs_id.mountpoint)),
+    }
+}
+
+// Get the total space used by images, according to the CRI.
+pub fn space_usage(backend: &Backend) -> io::Result<Byte> {
+    query::<ImageList>(backend, &["images"])?
+        .images
+        .iter()
+        .map(|image| {
+            image.size.parse::<u128>().map_err(|_| {
+                io::Error::other(format!(
+                    "Unable to parse image size {} from {}.",
+                    image.size.code_str(),
+                    "crictl images".code_str(),
+                ))
+            })
+        })
+        .sum::<io::Result<u128>>()
+        .map(Byte::from_bytes)
+}
+
+// Delete an image via the CRI.
+pub fn delete_image(backend: &Backend, image: &str) -> io::Result<()> {
+    info!("Deleting image {}\u{2026}", image.code_str());
+
+    // Tell the CRI to delete the image.
+    let mut child = backend.command().args(["rmi", image]).spawn()?;
+
+    // Ensure the command succeeded.
+    if !child.wait()?.success() {
+        return Err(io::Error::other(format!(
+            "Unable to delete image {}.",
+            image.code_str(),
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{image_records, parse_repository_tag, pinned_images, ImageList},
+        crate::backend::RepositoryTag,
+        std::collections::HashSet,
+    };
+
+    #[test]
+    fn parse_repository_tag_simple() {
+        assert_eq!(
+            parse_repository_tag("docker.io/library/alpine:3.20"),
+            RepositoryTag {
+                repository: "docker.io/library/alpine".to_owned(),
+                tag: "3.20".to_owned(),
+            },
+        );
+    }
+
+    #[test]
+    fn parse_repository_tag_registry_port() {
+        assert_eq!(
+            parse_repository_tag("localhost:5000/alpine"),
+            RepositoryTag {
+                repository: "localhost:5000/alpine".to_owned(),
+                tag: "<none>".to_owned(),
+            },
+        );
+    }
+
+    #[test]
+    fn image_records_untagged() {
+        let image_list = serde_json::from_str::<ImageList>(
+            r#"{"images":[{"id":"sha256:0","repoTags":[],"repoDigests":[],"size":"42"}]}"#,
+        )
+        .unwrap();
+
+        let records = image_records(image_list.images);
+
+        assert_eq!(
+            records.get("sha256:0").unwrap().repository_tags,
+            vec![RepositoryTag {
+                repository: "<none>".to_owned(),
+                tag: "<none>".to_owned(),
+            }],
+        );
+    }
+
+    #[test]
+    fn image_records_digests() {
+        let image_list = serde_json::from_str::<ImageList>(concat!(
+            r#"{"images":[{"id":"sha256:0","repoTags":["docker.io/library/alpine:3.20"],"#,
+            r#""repoDigests":["docker.io/library/alpine@sha256:1"],"size":"42"}]}"#,
+        ))
+        .unwrap();
+
+        let records = image_records(image_list.images);
+
+        assert_eq!(
+            records.get("sha256:0").unwrap().repository_digests,
+            vec!["docker.io/library/alpine@sha256:1".to_owned()],
+        );
+    }
+
+    #[test]
+    fn pinned_images_sandbox() {
+        let image_list = serde_json::from_str::<ImageList>(concat!(
+            r#"{"images":[{"id":"sha256:0","repoTags":["registry.k8s.io/pause:3.10"],"#,
+            r#""size":"42","pinned":true},{"id":"sha256:1","#,
+            r#""repoTags":["docker.io/library/alpine:3.20"],"size":"42"}]}"#,
+        ))
+        .unwrap();
+
+        assert_eq!(
+            pinned_images(image_list.images),
+            HashSet::from(["sha256:0".to_owned()]),
+        );
+    }
+}
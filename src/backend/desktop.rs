@@ -0,0 +1,112 @@
+use {
+    crate::format::CodeStr,
+    byte_unit::Byte,
+    std::{
+        fs::read_to_string,
+        io,
+        path::{Path, PathBuf},
+    },
+};
+
+// The names of Docker Desktop's settings files, in order of precedence. Newer versions of Docker
+// Desktop use the first one, and older versions use the second.
+const SETTINGS_FILE_NAMES: &[&str] = &["settings-store.json", "settings.json"];
+
+// The keys under which Docker Desktop stores the size of the virtual disk of its VM, in mebibytes.
+// As above, the first is used by newer versions and the second by older versions.
+const DISK_SIZE_KEYS: &[&str] = &["DiskSizeMiB", "diskSizeMiB"];
+
+// The directory containing Docker Desktop's settings files
+#[cfg(target_os = "macos")]
+fn settings_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| {
+        home.join("Library")
+            .join("Group Containers")
+            .join("group.com.docker")
+    })
+}
+
+#[cfg(windows)]
+fn settings_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|config_dir| config_dir.join("Docker"))
+}
+
+// Extract the size of the virtual disk from the contents of a settings file.
+fn parse_disk_size(path: &Path, settings: &str) -> io::Result<Byte> {
+    let settings = serde_json::from_str::<serde_json::Value>(settings).map_err(|error| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Unable to parse the Docker Desktop settings in {}. Details: {}",
+                path.to_string_lossy().code_str(),
+                error,
+            ),
+        )
+    })?;
+
+    DISK_SIZE_KEYS
+        .iter()
+        .find_map(|key| settings.get(key).and_then(serde_json::Value::as_u64))
+        .map(|mebibytes| Byte::from_bytes(u128::from(mebibytes) << 20))
+        .ok_or_else(|| {
+            io::Error::other(format!(
+                "Unable to find the disk size in {}.",
+                path.to_string_lossy().code_str(),
+            ))
+        })
+}
+
+// Determine the size of the virtual disk of the Docker Desktop VM, which is where the Docker root
+// directory lives on macOS and (when using Linux containers) Windows.
+pub fn disk_size() -> io::Result<Byte> {
+    let settings_dir = settings_dir().ok_or_else(|| {
+        io::Error::other("Unable to locate the Docker Desktop settings directory.")
+    })?;
+
+    for file_name in SETTINGS_FILE_NAMES {
+        let path = settings_dir.join(file_name);
+        match read_to_string(&path) {
+            Ok(settings) => return parse_disk_size(&path, &settings),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "Unable to find the Docker Desktop settings in {}.",
+            settings_dir.to_string_lossy().code_str(),
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::parse_disk_size, std::path::Path};
+
+    #[test]
+    fn parse_disk_size_new() {
+        assert_eq!(
+            parse_disk_size(Path::new("settings-store.json"), r#"{"DiskSizeMiB":65536}"#)
+                .unwrap()
+                .get_bytes(),
+            64_u128 << 30_u32,
+        );
+    }
+
+    #[test]
+    fn parse_disk_size_old() {
+        assert_eq!(
+            parse_disk_size(Path::new("settings.json"), r#"{"diskSizeMiB":1024}"#)
+                .unwrap()
+                .get_bytes(),
+            1_u128 << 30_u32,
+        );
+    }
+
+    #[test]
+    fn parse_disk_size_missing() {
+        assert!(parse_disk_size(Path::new("settings.json"), "{}").is_err());
+    }
+}
@@ -0,0 +1,2582 @@
+use {
+    crate::{
+        backend::{containerd, Backend, ImageRecord, NestedDaemon, RepositoryTag, Runtime},
+        format::CodeStr,
+        state::State,
+        telemetry,
+    },
+    byte_unit::Byte,
+    chrono::DateTime,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{hash_map::Entry, HashMap, HashSet},
+        io::{self, Read, Write},
+        ops::Deref,
+        panic::resume_unwind,
+        path::PathBuf,
+        process::Stdio,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex, PoisonError,
+        },
+        thread,
+        time::{Duration, UNIX_EPOCH},
+    },
+};
+
+// When querying Docker for the image IDs corresponding to a list of container IDs, this is the
+// maximum number of container IDs to query at once.
+const CONTAINER_IDS_CHUNK_SIZE: usize = 100;
+
+// When querying Docker for when a list of containers stopped, this is the maximum number of
+// container IDs to query at once.
+const STOPPED_CONTAINER_IDS_CHUNK_SIZE: usize = 100;
+
+// When querying Docker for information about a list of networks, this is the maximum number of
+// network IDs to query at once.
+const NETWORK_IDS_CHUNK_SIZE: usize = 100;
+
+// When querying Docker for the creation times of a list of volumes, this is the maximum number of
+// volume names to query at once.
+const VOLUME_NAMES_CHUNK_SIZE: usize = 100;
+
+// When querying Docker for the parents and labels of a list of images, this is the maximum number
+// of image IDs to query at once.
+const IMAGE_IDS_CHUNK_SIZE: usize = 100;
+
+// Docker Compose labels each container it creates with the name of its project.
+const COMPOSE_PROJECT_LABEL_KEY: &str = "com.docker.compose.project";
+
+// The Docker executor of GitLab Runner labels each container of a job with the ID of its pipeline.
+const GITLAB_PIPELINE_LABEL_KEY: &str = "com.gitlab.gitlab-runner.pipeline.id";
+
+// The parents and labels of the images listed last time, and which backend they came from. An image
+// ID is a digest of the image's configuration (or, with the containerd image store, of its manifest
+// or manifest list), so what we learned about it never goes stale.
+static INSPECTIONS: Mutex<Option<(Backend, HashMap<String, ImageInspection>)>> = Mutex::new(None);
+
+// A Docker event (a line of output from `docker events --format '{{json .}}'`)
+#[derive(Deserialize, Serialize, Debug)]
+struct Event {
+    #[serde(rename = "Type")]
+    r#type: String,
+
+    #[serde(rename = "Action")]
+    action: String,
+
+    #[serde(rename = "Actor")]
+    actor: EventActor,
+
+    id: String,
+}
+
+// What kind of Docker event happened. Unlike `Event`, this doesn't require an ID, which some events
+// (e.g., `prune`) lack.
+#[derive(Deserialize, Debug)]
+struct EventKind {
+    #[serde(rename = "Type")]
+    r#type: String,
+
+    #[serde(rename = "Action")]
+    action: String,
+}
+
+// When a Docker event happened. Every event has this, regardless of its type.
+#[derive(Deserialize, Debug)]
+struct EventTime {
+    #[serde(rename = "timeNano")]
+    time_nano: u64,
+}
+
+// A Docker event actor
+#[derive(Deserialize, Serialize, Debug)]
+struct EventActor {
+    #[serde(rename = "ID")]
+    id: Option<String>,
+
+    #[serde(rename = "Attributes")]
+    attributes: EventActorAttributes,
+}
+
+// Docker event actor attributes
+#[derive(Deserialize, Serialize, Debug)]
+struct EventActorAttributes {
+    image: Option<String>,
+    name: Option<String>,
+
+    // The image created by `docker commit`, including the commits of the classic builder
+    #[serde(rename = "imageID")]
+    image_id: Option<String>,
+}
+
+// A line of output from `docker system df --format '{{json .}}'`
+#[derive(Deserialize, Serialize, Debug)]
+struct SpaceRecord {
+    #[serde(rename = "Type")]
+    r#type: String,
+
+    #[serde(rename = "Size")]
+    size: String,
+}
+
+// Ask Docker for the ID of an image.
+pub fn image_id(backend: &Backend, image: &str) -> io::Result<String> {
+    // Query Docker for the image ID.
+    let output = backend
+        .command()
+        .args(["image", "inspect", "--format", "{{.ID}}", image])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Unable to determine ID of image {}.",
+            image.code_str(),
+        )));
+    }
+
+    // Interpret the output bytes as UTF-8 and trim any leading/trailing whitespace.
+    String::from_utf8(output.stdout)
+        .map(|output| output.trim().to_owned())
+        .map_err(io::Error::other)
+}
+
+// Ask Docker for the ID of the image of a container. The container may belong to a different Docker
+// daemon, so errors aren't shown to the user.
+pub fn container_image_id(backend: &Backend, container_id: &str) -> io::Result<String> {
+    // Query Docker for the image ID.
+    let output = backend
+        .command()
+        .args([
+            "container",
+            "inspect",
+            "--format",
+            "{{.Image}}",
+            container_id,
+        ])
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Unable to determine the image of container {}: {}",
+            container_id.code_str(),
+            String::from_utf8_lossy(&output.stderr).trim(),
+        )));
+    }
+
+    // Interpret the output bytes as UTF-8 and trim any leading/trailing whitespace.
+    String::from_utf8(output.stdout)
+        .map(|output| output.trim().to_owned())
+        .map_err(io::Error::other)
+}
+
+// Get the ID of the parent of an image (if the parent exists), querying Docker if necessary.
+pub fn parent_id(backend: &Backend, state: &State, image_id: &str) -> io::Result<Option<String>> {
+    // If we already know the parent, just return it.
+    if let Some(image) = state.images.get(image_id) {
+        return Ok(image.parent_id.clone());
+    }
+
+    // Query Docker for the parent image ID.
+    let output = backend
+        .command()
+        .args(["image", "inspect", "--format", "{{.Parent}}", image_id])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Unable to determine ID of the parent of image {}.",
+            image_id.code_str(),
+        )));
+    }
+
+    // Interpret the output bytes as UTF-8 and trim any leading/trailing whitespace.
+    String::from_utf8(output.stdout)
+        .map(|output| {
+            let trimmed_output = output.trim();
+
+            // Does the image even have a parent?
+            if trimmed_output.is_empty() {
+                None
+            } else {
+                Some(trimmed_output.to_owned())
+            }
+        })
+        .map_err(io::Error::other)
+}
+
+// Parse the output of `docker image ls` with the format used by `list_image_records`. `nerdctl`
+// lists each platform of a multi-platform image separately under the same ID, so its output has an
+// extra column for the platform. The platforms are combined into one image whose size is the sum of
+// theirs, and each tag is only recorded once.
+fn parse_image_list(output: &str) -> io::Result<HashMap<String, ImageRecord>> {
+    let mut image_records = HashMap::<_, ImageRecord>::new();
+    let mut platforms = HashMap::<_, HashSet<_>>::new();
+    for line in output.lines() {
+        let trimmed_line = line.trim();
+
+        if trimmed_line.is_empty() {
+            continue;
+        }
+
+        let image_parts = trimmed_line.split('\t').collect::<Vec<_>>();
+        let (id, repository, tag, digest, date_str, size, platform) = match image_parts[..] {
+            [id, repository, tag, digest, date_str, size] => {
+                (id, repository, tag, digest, date_str, size, "")
+            }
+            [id, repository, tag, digest, date_str, size, platform] => {
+                (id, repository, tag, digest, date_str, size, platform)
+            }
+            _ => {
+                return Err(io::Error::other(
+                    "Failed to parse image list output from Docker.",
+                ));
+            }
+        };
+
+        let repository_tag = RepositoryTag {
+            repository: repository.to_owned(),
+            tag: tag.to_owned(),
+        };
+
+        // The size is only used for reporting, so it's not worth failing over.
+        let size = Byte::from_str(size).unwrap_or_else(|_| Byte::from_bytes(0));
+
+        let image_record = match image_records.entry(id.to_owned()) {
+            Entry::Occupied(entry) => {
+                let image_record = entry.into_mut();
+                if !image_record.repository_tags.contains(&repository_tag) {
+                    image_record.repository_tags.push(repository_tag);
+                }
+                image_record
+            }
+            Entry::Vacant(entry) => entry.insert(ImageRecord {
+                parent_id: None,
+                created_since_epoch: parse_docker_date(date_str)?,
+                repository_tags: vec![repository_tag],
+                repository_digests: vec![],
+                labels: HashMap::new(),
+                size: Byte::from_bytes(0),
+            }),
+        };
+
+        // Each tag of an image is listed with the same size, so only the first row of each platform
+        // counts.
+        if platforms
+            .entry(id.to_owned())
+            .or_default()
+            .insert(platform.to_owned())
+        {
+            image_record.size = Byte::from_bytes(image_record.size.get_bytes() + size.get_bytes());
+        }
+
+        // Images which were built locally and never pushed or pulled have no digest.
+        if repository != "<none>" && digest != "<none>" && !digest.is_empty() {
+            let repository_digest = format!("{repository}@{digest}");
+            if !image_record.repository_digests.contains(&repository_digest) {
+                image_record.repository_digests.push(repository_digest);
+            }
+        }
+    }
+
+    Ok(image_records)
+}
+
+// Query Docker for all the images, inspecting up to `concurrency` chunks of them at a time.
+pub fn list_image_records(
+    backend: &Backend,
+    concurrency: usize,
+) -> io::Result<HashMap<String, ImageRecord>> {
+    // Get the IDs and creation timestamps of all the images. We get the repository digests here
+    // rather than by inspecting the images, since pushing an image adds a digest to it. Docker
+    // doesn't report platforms, since it lists a multi-platform image once per tag.
+    let format = if backend.runtime == Runtime::Containerd {
+        "{{.ID}}\\t{{.Repository}}\\t{{.Tag}}\\t{{.Digest}}\\t{{.CreatedAt}}\\t{{.Size}}\\t\
+            {{.Platform}}"
+    } else {
+        "{{.ID}}\\t{{.Repository}}\\t{{.Tag}}\\t{{.Digest}}\\t{{.CreatedAt}}\\t{{.Size}}"
+    };
+    let output = backend
+        .command()
+        .args(["image", "ls", "--all", "--no-trunc", "--format", format])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        return Err(io::Error::other("Unable to list images."));
+    }
+
+    // Interpret the output bytes as UTF-8 and parse the lines.
+    let mut image_records =
+        parse_image_list(&String::from_utf8(output.stdout).map_err(io::Error::other)?)?;
+
+    // Only inspect the images which weren't around last time.
+    let mut cache = INSPECTIONS.lock().unwrap_or_else(PoisonError::into_inner);
+    if cache
+        .as_ref()
+        .is_some_and(|(cached_backend, _)| cached_backend != backend)
+    {
+        *cache = None;
+    }
+    let (_, inspections) = cache.get_or_insert_with(|| (backend.clone(), HashMap::new()));
+    let new_image_ids = forget_missing_images(inspections, &image_records);
+    debug!(
+        "Inspecting {} new image(s) and reusing what was learned about {} other(s)\u{2026}",
+        new_image_ids.len(),
+        inspections.len(),
+    );
+    inspections.extend(inspect_images(backend, &new_image_ids, concurrency)?);
+
+    // Fill in the parents and labels.
+    let inferred_parent_ids = infer_parents(inspections);
+    for (image_id, image_record) in &mut image_records {
+        if let Some(inspection) = inspections.get(image_id) {
+            image_record.parent_id = inspection
+                .parent_id
+                .clone()
+                .or_else(|| inferred_parent_ids.get(image_id).cloned());
+            image_record.labels.clone_from(&inspection.labels);
+        }
+    }
+
+    Ok(image_records)
+}
+
+// Forget about the images which are no longer listed, and return the IDs of the listed images which
+// haven't been inspected yet.
+fn forget_missing_images(
+    inspections: &mut HashMap<String, ImageInspection>,
+    image_records: &HashMap<String, ImageRecord>,
+) -> Vec<String> {
+    inspections.retain(|image_id, _| image_records.contains_key(image_id));
+    image_records
+        .keys()
+        .filter(|image_id| !inspections.contains_key(*image_id))
+        .cloned()
+        .collect()
+}
+
+// What `inspect_images` learns about an image
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct ImageInspection {
+    parent_id: Option<String>,
+    layers: Vec<String>,
+    labels: HashMap<String, String>,
+}
+
+// Parse a line of output from `docker image inspect` with the format used by `inspect_images`.
+fn parse_image_inspection(line: &str) -> io::Result<(String, ImageInspection)> {
+    let [image_id, parent_id, layers, labels] = line.splitn(4, '\t').collect::<Vec<_>>()[..] else {
+        return Err(io::Error::other(
+            "Failed to parse image details from Docker.",
+        ));
+    };
+
+    Ok((
+        image_id.to_owned(),
+        ImageInspection {
+            // Images without parents have an empty parent ID.
+            parent_id: Some(parent_id.trim())
+                .filter(|parent_id| !parent_id.is_empty())
+                .map(ToOwned::to_owned),
+            layers: serde_json::from_str::<Option<Vec<String>>>(layers)
+                .map_err(io::Error::other)?
+                .unwrap_or_default(),
+            labels: serde_json::from_str::<Option<HashMap<String, String>>>(labels)
+                .map_err(io::Error::other)?
+                .unwrap_or_default(),
+        },
+    ))
+}
+
+// Images built with BuildKit, like images pulled from a registry, don't record their parents.
+// For those, we take the image whose layers are the longest proper prefix of the image's layers to
+// be its parent, since the image was evidently built on top of it. Deleting such a parent would
+// free little space while the image still exists, and it's placed in the polyforest accordingly.
+// Returns the inferred parent of each image which needs one.
+fn infer_parents(inspections: &HashMap<String, ImageInspection>) -> HashMap<String, String> {
+    // Index the images by their layers. If several images have the same layers, pick one of them
+    // consistently.
+    let mut images_by_layers = HashMap::<&[String], &String>::new();
+    for (image_id, inspection) in inspections {
+        if !inspection.layers.is_empty() {
+            images_by_layers
+                .entry(&inspection.layers)
+                .and_modify(|existing_image_id| {
+                    *existing_image_id = (*existing_image_id).min(image_id);
+                })
+                .or_insert(image_id);
+        }
+    }
+
+    inspections
+        .iter()
+        .filter(|(_, inspection)| inspection.parent_id.is_none())
+        .filter_map(|(image_id, inspection)| {
+            (1..inspection.layers.len())
+                .rev()
+                .find_map(|length| images_by_layers.get(&inspection.layers[..length]))
+                .map(|parent_id| (image_id.clone(), (*parent_id).clone()))
+        })
+        .collect()
+}
+
+// Ask Docker for the parents and labels of a chunk of images.
+fn inspect_image_chunk(
+    backend: &Backend,
+    image_ids: &[String],
+) -> io::Result<Vec<(String, ImageInspection)>> {
+    // Query Docker for the parents and labels of the images.
+    let output = backend
+        .command()
+        .args(
+            [
+                "image",
+                "inspect",
+                "--format",
+                "{{.ID}}\t{{.Parent}}\t{{if .RootFS}}{{json .RootFS.Layers}}{{else}}null{{end}}\t\
+                    {{if .Config}}{{json .Config.Labels}}{{else}}null{{end}}",
+            ]
+            .iter()
+            .map(Deref::deref)
+            .chain(image_ids.iter().map(AsRef::as_ref)),
+        )
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        return Err(io::Error::other(
+            "Unable to determine the parents and labels of images.",
+        ));
+    }
+
+    // Interpret the output bytes as UTF-8 and parse the lines.
+    String::from_utf8(output.stdout)
+        .map_err(io::Error::other)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(parse_image_inspection)
+        .collect()
+}
+
+// Ask Docker for the parents and labels of some images. On hosts with thousands of images, this is
+// most of the work of listing them, so up to `concurrency` chunks are inspected at a time.
+fn inspect_images(
+    backend: &Backend,
+    image_ids: &[String],
+    concurrency: usize,
+) -> io::Result<HashMap<String, ImageInspection>> {
+    let span = telemetry::Span::start("inspect_images");
+    span.set("images", image_ids.len());
+    let chunks = image_ids.chunks(IMAGE_IDS_CHUNK_SIZE).collect::<Vec<_>>();
+    let next_chunk = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        // Each worker takes the next chunk until there are none left.
+        let workers = (0..concurrency.clamp(1, chunks.len().max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut inspections = Vec::new();
+                    while let Some(chunk) = chunks.get(next_chunk.fetch_add(1, Ordering::SeqCst)) {
+                        inspections.extend(inspect_image_chunk(backend, chunk)?);
+                    }
+                    Ok::<_, io::Error>(inspections)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut inspections = HashMap::new();
+        for worker in workers {
+            inspections.extend(worker.join().unwrap_or_else(|panic| resume_unwind(panic))?);
+        }
+
+        Ok(inspections)
+    })
+}
+
+// Ask Docker for the IDs of the images currently in use by containers.
+pub fn image_ids_in_use(backend: &Backend) -> io::Result<HashSet<String>> {
+    // Query Docker for all the container IDs.
+    let container_ids_output = backend
+        .command()
+        .args([
+            "container",
+            "ls",
+            "--all",
+            "--no-trunc",
+            "--format",
+            "{{.ID}}",
+        ])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !container_ids_output.status.success() {
+        return Err(io::Error::other(
+            "Unable to determine IDs of images currently in use by containers.",
+        ));
+    }
+
+    // Interpret the output bytes as UTF-8 and parse the lines.
+    let container_ids = String::from_utf8(container_ids_output.stdout)
+        .map_err(io::Error::other)
+        .map(|output| {
+            output
+                .lines()
+                .filter_map(|line| {
+                    let trimmed_line = line.trim();
+
+                    if trimmed_line.is_empty() {
+                        None
+                    } else {
+                        Some(trimmed_line.to_owned())
+                    }
+                })
+                .collect::<Vec<_>>()
+        })?;
+
+    // Group the container IDs into chunks and query Docker for the image IDs for each chunk.
+    let mut image_ids = HashSet::new();
+    for chunk in container_ids.chunks(CONTAINER_IDS_CHUNK_SIZE) {
+        // Query Docker for the image IDs for this chunk.
+        let image_ids_output = backend
+            .command()
+            .args(
+                ["container", "inspect", "--format", "{{.Image}}"]
+                    .iter()
+                    .map(Deref::deref)
+                    .chain(chunk.iter().map(AsRef::as_ref)),
+            )
+            .stderr(Stdio::inherit())
+            .output()?;
+
+        // Ensure the command succeeded.
+        if !image_ids_output.status.success() {
+            return Err(io::Error::other(
+                "Unable to determine IDs of images currently in use by containers.",
+            ));
+        }
+
+        // Interpret the output bytes as UTF-8 and parse the lines.
+        image_ids.extend(
+            String::from_utf8(image_ids_output.stdout)
+                .map_err(io::Error::other)
+                .map(|output| {
+                    output
+                        .lines()
+                        .filter_map(|line| {
+                            let trimmed_line = line.trim();
+
+                            if trimmed_line.is_empty() {
+                                None
+                            } else {
+                                Some(trimmed_line.to_owned())
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                })?,
+        );
+    }
+
+    Ok(image_ids)
+}
+
+// Parse a line of output from `docker container inspect` with the format used by
+// `labeled_image_ids`. Example input: "my-project\tsha256:0123".
+fn parse_labeled_container(line: &str) -> io::Result<(String, String)> {
+    let (group, image_id) = line.trim().split_once('\t').ok_or_else(|| {
+        io::Error::other(format!(
+            "Unable to parse container {}.",
+            line.trim().code_str(),
+        ))
+    })?;
+
+    Ok((group.to_owned(), image_id.to_owned()))
+}
+
+// Ask Docker for the IDs of the images used by the containers which have a label with the given
+// key, grouped by the value of the label, including containers which have stopped. The groups are
+// described as `groups` in errors.
+fn labeled_image_ids(
+    backend: &Backend,
+    label_key: &str,
+    groups: &str,
+) -> io::Result<HashMap<String, HashSet<String>>> {
+    // Query Docker for the IDs of the containers which have the label.
+    let container_ids_output = backend
+        .command()
+        .args([
+            "container",
+            "ls",
+            "--all",
+            "--no-trunc",
+            "--filter",
+            &format!("label={label_key}"),
+            "--format",
+            "{{.ID}}",
+        ])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !container_ids_output.status.success() {
+        return Err(io::Error::other(format!(
+            "Unable to determine the containers of {groups}.",
+        )));
+    }
+
+    // Interpret the output bytes as UTF-8 and parse the lines.
+    let container_ids = String::from_utf8(container_ids_output.stdout)
+        .map_err(io::Error::other)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+
+    // Group the container IDs into chunks and query Docker for the label values and image IDs for
+    // each chunk.
+    let format = format!("{{{{index .Config.Labels \"{label_key}\"}}}}\t{{{{.Image}}}}");
+    let mut image_ids = HashMap::<_, HashSet<_>>::new();
+    for chunk in container_ids.chunks(CONTAINER_IDS_CHUNK_SIZE) {
+        let output = backend
+            .command()
+            .args(
+                ["container", "inspect", "--format", &format]
+                    .iter()
+                    .map(Deref::deref)
+                    .chain(chunk.iter().map(AsRef::as_ref)),
+            )
+            .stderr(Stdio::inherit())
+            .output()?;
+
+        // Ensure the command succeeded.
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "Unable to determine the images of {groups}.",
+            )));
+        }
+
+        // Interpret the output bytes as UTF-8 and parse the lines.
+        for line in String::from_utf8(output.stdout)
+            .map_err(io::Error::other)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+        {
+            let (group, image_id) = parse_labeled_container(line)?;
+            image_ids.entry(group).or_default().insert(image_id);
+        }
+    }
+
+    Ok(image_ids)
+}
+
+// Ask Docker for the IDs of the images used by the containers of each Docker Compose project,
+// including containers which have stopped.
+pub fn compose_project_image_ids(
+    backend: &Backend,
+) -> io::Result<HashMap<String, HashSet<String>>> {
+    labeled_image_ids(
+        backend,
+        COMPOSE_PROJECT_LABEL_KEY,
+        "Docker Compose projects",
+    )
+}
+
+// Ask Docker for the IDs of the images used by the containers of each GitLab CI pipeline, including
+// the build and service containers of its jobs and containers which have stopped.
+pub fn gitlab_pipeline_image_ids(
+    backend: &Backend,
+) -> io::Result<HashMap<String, HashSet<String>>> {
+    labeled_image_ids(backend, GITLAB_PIPELINE_LABEL_KEY, "GitLab CI pipelines")
+}
+
+// The format for `docker container inspect` used by `nested_daemons`: the container's ID, name,
+// whether it's privileged, the PID of its main process, and the command of that process
+const NESTED_DAEMON_FORMAT: &str =
+    "{{.Id}}\t{{.Name}}\t{{.HostConfig.Privileged}}\t{{.State.Pid}}\t{{.Path}}\t\
+        {{join .Args \" \"}}";
+
+// Parse a line of output from `docker container inspect` with `NESTED_DAEMON_FORMAT`. Example
+// input: "0123\t/ci-job\ttrue\t4242\tdockerd-entrypoint.sh\t". Containers which aren't
+// privileged or don't run the Docker daemon (directly or via the entrypoint of the `docker:dind`
+// image) aren't Docker-in-Docker containers. The nested daemon is reached through the container's
+// root directory in `/proc`, since it only listens on a socket inside the container by default.
+fn parse_nested_daemon(line: &str) -> io::Result<Option<NestedDaemon>> {
+    let fields = line.trim_end_matches('\n').split('\t').collect::<Vec<_>>();
+    let [container_id, container_name, privileged, pid, path, args] = fields[..] else {
+        return Err(io::Error::other(format!(
+            "Unable to parse container {}.",
+            line.trim().code_str(),
+        )));
+    };
+
+    let runs_dockerd = path.split(' ').chain(args.split(' ')).any(|word| {
+        word.rsplit('/')
+            .next()
+            .is_some_and(|program| program.starts_with("dockerd"))
+    });
+    if privileged != "true" || pid == "0" || !runs_dockerd {
+        return Ok(None);
+    }
+
+    Ok(Some(NestedDaemon {
+        container_id: container_id.to_owned(),
+        container_name: container_name.trim_start_matches('/').to_owned(),
+        backend: Backend::from_host(
+            Runtime::Docker,
+            &format!("unix:///proc/{pid}/root/var/run/docker.sock"),
+        )?,
+    }))
+}
+
+// Ask Docker for the Docker daemons running in its privileged Docker-in-Docker containers.
+pub fn nested_daemons(backend: &Backend) -> io::Result<Vec<NestedDaemon>> {
+    // Query Docker for the IDs of the running containers.
+    let container_ids_output = backend
+        .command()
+        .args(["container", "ls", "--no-trunc", "--format", "{{.ID}}"])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !container_ids_output.status.success() {
+        return Err(io::Error::other(
+            "Unable to determine the Docker-in-Docker containers.",
+        ));
+    }
+
+    // Interpret the output bytes as UTF-8 and parse the lines.
+    let container_ids = String::from_utf8(container_ids_output.stdout)
+        .map_err(io::Error::other)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+
+    // Group the container IDs into chunks and query Docker for the details of each chunk.
+    let mut nested_daemons = vec![];
+    for chunk in container_ids.chunks(CONTAINER_IDS_CHUNK_SIZE) {
+        let output = backend
+            .command()
+            .args(
+                ["container", "inspect", "--format", NESTED_DAEMON_FORMAT]
+                    .iter()
+                    .map(Deref::deref)
+                    .chain(chunk.iter().map(AsRef::as_ref)),
+            )
+            .stderr(Stdio::inherit())
+            .output()?;
+
+        // Ensure the command succeeded.
+        if !output.status.success() {
+            return Err(io::Error::other(
+                "Unable to determine the Docker-in-Docker containers.",
+            ));
+        }
+
+        // Interpret the output bytes as UTF-8 and parse the lines.
+        for line in String::from_utf8(output.stdout)
+            .map_err(io::Error::other)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+        {
+            nested_daemons.extend(parse_nested_daemon(line)?);
+        }
+    }
+
+    Ok(nested_daemons)
+}
+
+// The prefix of the names of the containers of Docker Buildx builders with the `docker-container`
+// driver, which are named `buildx_buildkit_<builder><node index>`
+const BUILDX_CONTAINER_PREFIX: &str = "buildx_buildkit_";
+
+// Determine which Buildx builder container, if any, was used according to a line of output from
+// `docker events`. Buildx reaches the BuildKit daemon of a builder by running `buildctl dial-stdio`
+// in its container, so that counts as a use of the builder.
+pub fn used_buildx_builder(line: &str) -> Option<String> {
+    let event = parse_event(line)?;
+
+    if event.r#type == "container"
+        && (event.action == "exec_create" || event.action.starts_with("exec_create: "))
+    {
+        event
+            .actor
+            .attributes
+            .name
+            .filter(|name| name.starts_with(BUILDX_CONTAINER_PREFIX))
+    } else {
+        None
+    }
+}
+
+// Parse a line of output from `docker container inspect` with the format used by `buildx_builders`.
+// Returns `None` for containers which don't belong to Buildx builders.
+fn parse_buildx_builder(line: &str) -> io::Result<Option<(String, Duration)>> {
+    let (name, created_since_epoch) = parse_timestamped_line(line.trim_start_matches('/'))?;
+    Ok(name
+        .starts_with(BUILDX_CONTAINER_PREFIX)
+        .then_some((name, created_since_epoch)))
+}
+
+// Ask Docker for the running containers of Buildx builders, along with when they were created.
+pub fn buildx_builders(backend: &Backend) -> io::Result<HashMap<String, Duration>> {
+    // Query Docker for the IDs of the running containers which look like they belong to builders.
+    let container_ids_output = backend
+        .command()
+        .args([
+            "container",
+            "ls",
+            "--no-trunc",
+            "--filter",
+            &format!("name=^/?{BUILDX_CONTAINER_PREFIX}"),
+            "--format",
+            "{{.ID}}",
+        ])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !container_ids_output.status.success() {
+        return Err(io::Error::other("Unable to determine the Buildx builders."));
+    }
+
+    // Interpret the output bytes as UTF-8 and parse the lines.
+    let container_ids = String::from_utf8(container_ids_output.stdout)
+        .map_err(io::Error::other)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+
+    // Group the container IDs into chunks and query Docker for the details of each chunk.
+    let mut builders = HashMap::new();
+    for chunk in container_ids.chunks(CONTAINER_IDS_CHUNK_SIZE) {
+        let output = backend
+            .command()
+            .args(
+                [
+                    "container",
+                    "inspect",
+                    "--format",
+                    "{{.Name}}\t{{.Created}}",
+                ]
+                .iter()
+                .map(Deref::deref)
+                .chain(chunk.iter().map(AsRef::as_ref)),
+            )
+            .stderr(Stdio::inherit())
+            .output()?;
+
+        // Ensure the command succeeded.
+        if !output.status.success() {
+            return Err(io::Error::other("Unable to determine the Buildx builders."));
+        }
+
+        // Interpret the output bytes as UTF-8 and parse the lines.
+        for line in String::from_utf8(output.stdout)
+            .map_err(io::Error::other)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+        {
+            builders.extend(parse_buildx_builder(line)?);
+        }
+    }
+
+    Ok(builders)
+}
+
+// Read the total space used by the cache of a BuildKit daemon from the output of `buildctl du`.
+// Example input: "ID\tRECLAIMABLE\tSIZE\tLAST ACCESSED\n...\nTotal:\t\t1.5GB\n".
+fn parse_buildctl_du(output: &str) -> io::Result<Byte> {
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix("Total:"))
+        .and_then(|size| Byte::from_str(size.trim()).ok())
+        .ok_or_else(|| {
+            io::Error::other(format!(
+                "Unable to parse output of {}: {}",
+                "buildctl du".code_str(),
+                output.code_str(),
+            ))
+        })
+}
+
+// Ask the BuildKit daemon of a Buildx builder for the space used by its cache. The cache lives in
+// the builder's container, so neither `docker system df` nor `docker builder prune` know about it.
+pub fn buildx_cache_size(backend: &Backend, container: &str) -> io::Result<Byte> {
+    let output = backend
+        .command()
+        .args(["container", "exec", container, "buildctl", "du"])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Unable to determine the space used by the cache of Buildx builder {}.",
+            container.code_str(),
+        )));
+    }
+
+    String::from_utf8(output.stdout)
+        .map_err(io::Error::other)
+        .and_then(|output| parse_buildctl_du(&output))
+}
+
+// Tell the BuildKit daemon of a Buildx builder to prune its cache, least recently used first, until
+// it's using no more than the given amount of space.
+pub fn prune_buildx_cache(backend: &Backend, container: &str, keep: Byte) -> io::Result<()> {
+    info!(
+        "Pruning the cache of Buildx builder {} down to {}\u{2026}",
+        container.code_str(),
+        keep.get_appropriate_unit(false).to_string().code_str(),
+    );
+
+    // `buildctl` expects the amount of space to keep in megabytes.
+    let mut child = backend
+        .command()
+        .args([
+            "container",
+            "exec",
+            container,
+            "buildctl",
+            "prune",
+            "--keep-storage",
+            &(keep.get_bytes() / 1_000_000).to_string(),
+        ])
+        .stdout(Stdio::null())
+        .spawn()?;
+
+    // Ensure the command succeeded.
+    if !child.wait()?.success() {
+        return Err(io::Error::other(format!(
+            "Unable to prune the cache of Buildx builder {}.",
+            container.code_str(),
+        )));
+    }
+
+    Ok(())
+}
+
+// The `driver-type` which Docker reports in the status of its storage driver when it uses the
+// containerd image store
+const CONTAINERD_SNAPSHOTTER_DRIVER_TYPE: &str = "io.containerd.snapshotter.v1";
+
+// Determine the directory in which Docker keeps images. With the containerd image store, the images
+// are kept by containerd in its own root directory rather than Docker's.
+pub fn root_dir(backend: &Backend) -> io::Result<PathBuf> {
+    // Query Docker for its root directory and the status of its storage driver.
+    let output = backend
+        .command()
+        .args([
+            "info",
+            "--format",
+            "{{.DockerRootDir}}\t{{json .DriverStatus}}",
+        ])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        return Err(io::Error::other(
+            "Unable to determine the Docker root directory.",
+        ));
+    }
+
+    let (root_dir, containerd_image_store) = String::from_utf8(output.stdout)
+        .map_err(io::Error::other)
+        .and_then(|output| parse_root_dir(&output))?;
+
+    // The containerd installed on the host keeps its data in its default root directory. If there's
+    // no such directory, Docker must be running its own containerd under its root directory.
+    let containerd_root_dir = containerd::root_dir();
+    if containerd_image_store && containerd_root_dir.exists() {
+        debug!(
+            "Docker uses the containerd image store, so images are kept in {}.",
+            containerd_root_dir.to_string_lossy().code_str(),
+        );
+        return Ok(containerd_root_dir);
+    }
+
+    Ok(root_dir)
+}
+
+// Parse Docker's root directory and the status of its storage driver, as printed by `docker info`,
+// and determine whether Docker uses the containerd image store.
+#[allow(clippy::map_err_ignore)]
+fn parse_root_dir(output: &str) -> io::Result<(PathBuf, bool)> {
+    let invalid = || {
+        io::Error::other(format!(
+            "Unable to parse the Docker root directory from {}: {}",
+            "docker info".code_str(),
+            output.code_str(),
+        ))
+    };
+
+    let (root_dir, status) = output.trim().split_once('\t').ok_or_else(invalid)?;
+    let status =
+        serde_json::from_str::<Option<Vec<(String, String)>>>(status).map_err(|_| invalid())?;
+
+    Ok((
+        PathBuf::from(root_dir.trim()),
+        status.unwrap_or_default().iter().any(|(key, value)| {
+            key == "driver-type" && value == CONTAINERD_SNAPSHOTTER_DRIVER_TYPE
+        }),
+    ))
+}
+
+// Determine the size of the thin pool in which the devicemapper storage driver keeps images, and
+// how much space is available in it. Returns `None` for the other storage drivers, which keep
+// images in the root directory.
+pub fn thin_pool_space(backend: &Backend) -> io::Result<Option<(Byte, Byte)>> {
+    // Query Docker for the storage driver and its status.
+    let output = backend
+        .command()
+        .args(["info", "--format", "{{.Driver}}\t{{json .DriverStatus}}"])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        return Err(io::Error::other(
+            "Unable to determine the Docker storage driver.",
+        ));
+    }
+
+    String::from_utf8(output.stdout)
+        .map_err(io::Error::other)
+        .and_then(|output| parse_thin_pool_space(&output))
+}
+
+// Parse the storage driver and its status, as printed by `docker info`. The status of the
+// devicemapper storage driver is a list of pairs like `["Data Space Total","107.4 GB"]`.
+#[allow(clippy::map_err_ignore)]
+fn parse_thin_pool_space(output: &str) -> io::Result<Option<(Byte, Byte)>> {
+    let invalid = || {
+        io::Error::other(format!(
+            "Unable to parse the devicemapper status from {}: {}",
+            "docker info".code_str(),
+            output.code_str(),
+        ))
+    };
+
+    let (driver, status) = output.trim().split_once('\t').ok_or_else(invalid)?;
+    if driver != "devicemapper" {
+        return Ok(None);
+    }
+
+    let status = serde_json::from_str::<Vec<(String, String)>>(status).map_err(|_| invalid())?;
+    let field = |name: &str| {
+        status
+            .iter()
+            .find(|(key, _)| key == name)
+            .and_then(|(_, value)| Byte::from_str(value).ok())
+            .ok_or_else(invalid)
+    };
+
+    Ok(Some((
+        field("Data Space Total")?,
+        field("Data Space Available")?,
+    )))
+}
+
+// Get the total space used by Docker images.
+pub fn space_usage(backend: &Backend) -> io::Result<Byte> {
+    df_size(backend, &["Images"])
+}
+
+// Get the total space used by Docker images and, optionally, volumes and the writable layers of
+// containers. This asks Docker only once, since computing the space usage can take a while on hosts
+// with many images.
+pub fn space_usage_with(
+    backend: &Backend,
+    volumes: bool,
+    container_layers: bool,
+    build_cache: bool,
+) -> io::Result<Byte> {
+    let mut object_types = vec!["Images"];
+    if volumes {
+        object_types.push("Local Volumes");
+    }
+    if container_layers {
+        object_types.push("Containers");
+    }
+    if build_cache {
+        object_types.push("Build Cache");
+    }
+    df_size(backend, &object_types)
+}
+
+// Get the space used by Docker's build cache.
+pub fn build_cache_size(backend: &Backend) -> io::Result<Byte> {
+    df_size(backend, &["Build Cache"])
+}
+
+// Prune Docker's build cache, least recently used first, until it's using no more than the given
+// amount of space. With `min_age`, only the cache records which haven't been used for that long
+// are pruned.
+pub fn prune_build_cache(
+    backend: &Backend,
+    min_age: Option<Duration>,
+    keep: Byte,
+) -> io::Result<()> {
+    info!(
+        "Pruning the build cache down to {}\u{2026}",
+        keep.get_appropriate_unit(false).to_string().code_str(),
+    );
+
+    let mut command = backend.command();
+    command.args([
+        "builder",
+        "prune",
+        "--force",
+        "--keep-storage",
+        &keep.get_bytes().to_string(),
+    ]);
+    if let Some(min_age) = min_age {
+        command.args(["--filter", &format!("until={}s", min_age.as_secs())]);
+    }
+
+    // Ensure the command succeeded.
+    if !command.stdout(Stdio::null()).spawn()?.wait()?.success() {
+        return Err(io::Error::other("Unable to prune the build cache."));
+    }
+
+    Ok(())
+}
+
+// Get the total space used by some types of objects (e.g., `Images`) according to
+// `docker system df`.
+fn df_size(backend: &Backend, object_types: &[&str]) -> io::Result<Byte> {
+    // Query Docker for the space usage.
+    let output = backend
+        .command()
+        .args(["system", "df", "--format", "{{json .}}"])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        return Err(io::Error::other(
+            "Unable to determine the disk space used by Docker.",
+        ));
+    }
+
+    String::from_utf8(output.stdout)
+        .map_err(io::Error::other)
+        .and_then(|output| parse_df_size(&output, object_types))
+}
+
+// Add up the space used by some types of objects according to the output of `docker system df`.
+#[allow(clippy::map_err_ignore)]
+fn parse_df_size(output: &str, object_types: &[&str]) -> io::Result<Byte> {
+    let mut total = 0;
+
+    for object_type in object_types {
+        // Find the relevant line of output.
+        let Some(space_record) = output
+            .lines()
+            .filter_map(|line| serde_json::from_str::<SpaceRecord>(line).ok())
+            .find(|space_record| space_record.r#type == *object_type)
+        else {
+            return Err(io::Error::other(format!(
+                "Unable to parse output of {}: {}",
+                "docker system df".code_str(),
+                output.code_str(),
+            )));
+        };
+
+        total += Byte::from_str(&space_record.size)
+            .map_err(|_| {
+                io::Error::other(format!(
+                    "Unable to parse {} from {}.",
+                    space_record.size.code_str(),
+                    "docker system df".code_str(),
+                ))
+            })?
+            .get_bytes();
+    }
+
+    Ok(Byte::from_bytes(total))
+}
+
+// Delete a Docker image. Unless `prune_parents` is set, its untagged parents are kept, since
+// Docuum tracks them separately.
+pub fn delete_image(backend: &Backend, image: &str, prune_parents: bool) -> io::Result<()> {
+    info!("Deleting image {}\u{2026}", image.code_str());
+
+    // Tell Docker to delete the image.
+    let mut command = backend.command();
+    command.args(["image", "rm", "--force"]);
+    if !prune_parents {
+        command.arg("--no-prune");
+    }
+    let output = command
+        .arg(image)
+        .stderr(Stdio::piped())
+        .spawn()?
+        .wait_with_output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::new(
+            if is_deletion_conflict(&stderr) {
+                io::ErrorKind::ResourceBusy
+            } else if is_missing_image(&stderr) {
+                io::ErrorKind::NotFound
+            } else {
+                io::ErrorKind::Other
+            },
+            format!(
+                "Unable to delete image {}: {}",
+                image.code_str(),
+                stderr.trim(),
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+// Remove a tag from a Docker image. Without `--force`, only the reference is removed, since the
+// image has other tags. This also works for `nerdctl`.
+pub fn untag_image(backend: &Backend, repository_tag: &str) -> io::Result<()> {
+    info!("Untagging {}\u{2026}", repository_tag.code_str());
+
+    // Tell Docker to remove the tag.
+    let mut child = backend
+        .command()
+        .args(["image", "rm", repository_tag])
+        .spawn()?;
+
+    // Ensure the command succeeded.
+    if !child.wait()?.success() {
+        return Err(io::Error::other(format!(
+            "Unable to untag {}.",
+            repository_tag.code_str(),
+        )));
+    }
+
+    Ok(())
+}
+
+// Write an archive of images, like the ones `docker image save` produces, which `docker image load`
+// can restore along with the given tags.
+pub fn save_images(
+    backend: &Backend,
+    images: &[String],
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    // Ask Docker for the archive.
+    let mut child = backend
+        .command()
+        .args(["image", "save"])
+        .args(images)
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // The `unwrap` is safe because standard output was piped above. If copying the archive fails,
+    // we still wait for Docker so it doesn't become a zombie.
+    let copied = io::copy(&mut child.stdout.take().unwrap(), writer);
+    let status = child.wait()?;
+    copied?;
+
+    // Ensure the command succeeded.
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "Unable to save {}.",
+            images.join(", ").code_str(),
+        )));
+    }
+
+    Ok(())
+}
+
+// Load an archive of images, like the ones `docker image save` produces, along with the tags in it.
+pub fn load_images(backend: &Backend, reader: &mut impl Read) -> io::Result<()> {
+    // Hand the archive to Docker.
+    let mut child = backend
+        .command()
+        .args(["image", "load"])
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    // The `unwrap` is safe because standard input was piped above. The handle is dropped right
+    // away to signal the end of the archive. If copying the archive fails, we still wait for Docker
+    // so it doesn't become a zombie.
+    let copied = io::copy(reader, &mut child.stdin.take().unwrap());
+    let status = child.wait()?;
+    copied?;
+
+    // Ensure the command succeeded.
+    if !status.success() {
+        return Err(io::Error::other("Unable to load the images."));
+    }
+
+    Ok(())
+}
+
+// Add a tag to an image.
+pub fn tag_image(backend: &Backend, image_id: &str, repository_tag: &str) -> io::Result<()> {
+    info!(
+        "Tagging {} as {}\u{2026}",
+        image_id.code_str(),
+        repository_tag.code_str(),
+    );
+
+    // Tell Docker to add the tag.
+    let mut child = backend
+        .command()
+        .args(["image", "tag", image_id, repository_tag])
+        .spawn()?;
+
+    // Ensure the command succeeded.
+    if !child.wait()?.success() {
+        return Err(io::Error::other(format!(
+            "Unable to tag {} as {}.",
+            image_id.code_str(),
+            repository_tag.code_str(),
+        )));
+    }
+
+    Ok(())
+}
+
+// Determine whether Docker refused to delete an image because it's in use by a container or has
+// child images, according to the error output of `docker image rm`.
+fn is_deletion_conflict(stderr: &str) -> bool {
+    stderr.contains("image has dependent child images") || stderr.contains("image is being used by")
+}
+
+// Determine whether Docker couldn't delete an image because it no longer exists, according to the
+// error output of `docker image rm`. This happens when the image was pruned along with one of its
+// children.
+fn is_missing_image(stderr: &str) -> bool {
+    stderr.contains("No such image")
+}
+
+// Parse the non-standard timestamp format Docker uses for `docker image ls`.
+// Example input: "2017-12-20 16:30:49 -0500 EST".
+pub fn parse_docker_date(timestamp: &str) -> io::Result<Duration> {
+    // Chrono can't read the "EST", so remove it before parsing.
+    let timestamp_without_timezone_triad = timestamp
+        .trim()
+        .rsplitn(2, ' ')
+        .last()
+        .ok_or_else(|| io::Error::other("Failed to remove timezone string."))?;
+
+    // Parse the date and convert it into a duration since the UNIX epoch.
+    let duration =
+        match DateTime::parse_from_str(timestamp_without_timezone_triad, "%Y-%m-%d %H:%M:%S %z") {
+            Ok(datetime) => {
+                datetime.signed_duration_since::<chrono::offset::Utc>(DateTime::from(UNIX_EPOCH))
+            }
+            Err(error) => return Err(io::Error::other(error)),
+        };
+
+    // Convert the duration into a `std::time::Duration`. If the duration is negative, it will be
+    // clamped to zero. This can occur when building images with `kaniko --reproducible`, as the
+    // resulting images have `0001-01-01 00:00:00 +0000 UTC` for their creation timestamp.
+    Ok(duration.to_std().unwrap_or(Duration::ZERO))
+}
+
+// Parse a line of output from `docker events`.
+fn parse_event(line: &str) -> Option<Event> {
+    match serde_json::from_str::<Event>(line) {
+        Ok(event) => {
+            trace!("Parsed as: {}", format!("{event:?}").code_str());
+            Some(event)
+        }
+        Err(error) => {
+            trace!("Skipping due to: {error}");
+            None
+        }
+    }
+}
+
+// Determine when an event happened according to a line of output from `docker events`.
+pub fn event_time(line: &str) -> Option<Duration> {
+    serde_json::from_str::<EventTime>(line)
+        .ok()
+        .map(|event_time| Duration::from_nanos(event_time.time_nano))
+}
+
+// Format a point in time as `docker events --since` expects it, i.e., seconds since the UNIX epoch
+// with a fractional part.
+pub fn events_since(since_epoch: Duration) -> String {
+    format!(
+        "{}.{:09}",
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos(),
+    )
+}
+
+// Determine whether a container event counts as a use of the container's image. Besides creating
+// and destroying containers, this includes starting them again and running commands in them, so
+// long-lived containers keep their images fresh. Docker appends the command to the action of exec
+// events, e.g., `exec_create: sh -c date`.
+fn is_container_usage(action: &str) -> bool {
+    matches!(action, "create" | "destroy" | "start" | "restart")
+        || action == "exec_create"
+        || action.starts_with("exec_create: ")
+}
+
+// Determine which image, if any, was used according to a line of output from `docker events`.
+pub fn used_image(line: &str) -> Option<String> {
+    // Parse the line as an event.
+    let event = parse_event(line)?;
+
+    if event.r#type == "container" && is_container_usage(&event.action) {
+        if event.actor.attributes.image.is_none() {
+            trace!("Invalid Docker event.");
+        }
+
+        event.actor.attributes.image
+    } else if event.r#type == "container" && event.action == "commit" {
+        // The image which was just created is the one being used, not the image of the container.
+        if event.actor.attributes.image_id.is_none() {
+            trace!("Invalid Docker event.");
+        }
+
+        event.actor.attributes.image_id
+    } else if event.r#type == "image"
+        // Images built with BuildKit are announced with `create` rather than `tag`, and untagged
+        // ones aren't tagged at all.
+        && (event.action == "create"
+            || event.action == "import"
+            || event.action == "load"
+            || event.action == "pull"
+            || event.action == "push"
+            || event.action == "save"
+            || event.action == "tag")
+    {
+        Some(event.id)
+    } else {
+        trace!("Skipping due to irrelevance.");
+        None
+    }
+}
+
+// Determine which image, if any, was deleted according to a line of output from `docker events`.
+// Removing a tag only deletes the image if it was the last one, in which case Docker follows the
+// `untag` event with a `delete` event, so `untag` events are skipped.
+pub fn deleted_image(line: &str) -> Option<String> {
+    let event = parse_event(line)?;
+
+    if event.r#type == "image" && event.action == "delete" {
+        Some(event.id)
+    } else {
+        None
+    }
+}
+
+// Determine whether images were pruned according to a line of output from `docker events`. Docker
+// emits this event after `docker image prune` and `docker system prune`.
+pub fn pruned_images(line: &str) -> bool {
+    serde_json::from_str::<EventKind>(line)
+        .is_ok_and(|event| event.r#type == "image" && event.action == "prune")
+}
+
+// Determine which container, if any, used an image according to a line of output from
+// `docker events`. This is the name of the container for the events which `used_image` accepts.
+pub fn image_container(line: &str) -> Option<String> {
+    let event = parse_event(line)?;
+
+    if event.r#type == "container" && is_container_usage(&event.action) {
+        event.actor.attributes.name
+    } else {
+        None
+    }
+}
+
+// Determine which volume, if any, was mounted or unmounted according to a line of output from
+// `docker events`.
+pub fn used_volume(line: &str) -> Option<String> {
+    // Parse the line as an event.
+    let event = parse_event(line)?;
+
+    if event.r#type == "volume" && (event.action == "mount" || event.action == "unmount") {
+        if event.actor.id.is_none() {
+            trace!("Invalid Docker event.");
+        }
+
+        event.actor.id
+    } else {
+        None
+    }
+}
+
+// Determine which network, if any, had a container connected to or disconnected from it according
+// to a line of output from `docker events`.
+pub fn used_network(line: &str) -> Option<String> {
+    // Parse the line as an event.
+    let event = parse_event(line)?;
+
+    if event.r#type == "network" && (event.action == "connect" || event.action == "disconnect") {
+        if event.actor.id.is_none() {
+            trace!("Invalid Docker event.");
+        }
+
+        event.actor.id
+    } else {
+        None
+    }
+}
+
+// Determine whether a volume is anonymous. Docker names anonymous volumes with 64 hexadecimal
+// digits.
+fn is_anonymous_volume(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+// Parse a line of the form `<name>\t<RFC 3339 timestamp>`, as printed by `docker volume inspect`
+// and `docker container inspect` with the formats used below.
+fn parse_timestamped_line(line: &str) -> io::Result<(String, Duration)> {
+    let (name, timestamp) = line
+        .split_once('\t')
+        .ok_or_else(|| io::Error::other("Failed to parse inspection output from Docker."))?;
+
+    let since_epoch = DateTime::parse_from_rfc3339(timestamp)
+        .map_err(|error| {
+            io::Error::other(format!(
+                "Unable to parse timestamp {}: {error:?}",
+                timestamp.code_str(),
+            ))
+        })?
+        .signed_duration_since(DateTime::<chrono::Utc>::from(UNIX_EPOCH))
+        .to_std()
+        // Timestamps before the UNIX epoch (e.g., for containers which never ran) are clamped.
+        .unwrap_or(Duration::ZERO);
+
+    Ok((name.to_owned(), since_epoch))
+}
+
+// Ask Docker for the anonymous volumes which aren't used by any containers, along with when they
+// were created.
+pub fn unused_anonymous_volumes(backend: &Backend) -> io::Result<HashMap<String, Duration>> {
+    // Query Docker for the volumes which aren't used by any containers.
+    let output = backend
+        .command()
+        .args(["volume", "ls", "--quiet", "--filter", "dangling=true"])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        return Err(io::Error::other("Unable to list unused volumes."));
+    }
+
+    // Interpret the output bytes as UTF-8 and keep only the anonymous volumes.
+    let names = String::from_utf8(output.stdout)
+        .map_err(io::Error::other)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| is_anonymous_volume(line))
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+
+    // Determine when the volumes were created.
+    let mut volumes = HashMap::new();
+    for chunk in names.chunks(VOLUME_NAMES_CHUNK_SIZE) {
+        let output = backend
+            .command()
+            .args(
+                ["volume", "inspect", "--format", "{{.Name}}\t{{.CreatedAt}}"]
+                    .iter()
+                    .map(Deref::deref)
+                    .chain(chunk.iter().map(AsRef::as_ref)),
+            )
+            .stderr(Stdio::inherit())
+            .output()?;
+
+        // Ensure the command succeeded.
+        if !output.status.success() {
+            return Err(io::Error::other("Unable to inspect unused volumes."));
+        }
+
+        for line in String::from_utf8(output.stdout)
+            .map_err(io::Error::other)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+        {
+            let (name, created_since_epoch) = parse_timestamped_line(line)?;
+            volumes.insert(name, created_since_epoch);
+        }
+    }
+
+    Ok(volumes)
+}
+
+// Ask Docker for the containers which have exited or are dead, along with when they stopped.
+pub fn stopped_containers(backend: &Backend) -> io::Result<HashMap<String, Duration>> {
+    // Query Docker for the stopped containers.
+    let output = backend
+        .command()
+        .args([
+            "container",
+            "ls",
+            "--all",
+            "--quiet",
+            "--no-trunc",
+            "--filter",
+            "status=exited",
+            "--filter",
+            "status=dead",
+        ])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        return Err(io::Error::other("Unable to list stopped containers."));
+    }
+
+    // Interpret the output bytes as UTF-8 and parse the lines.
+    let container_ids = String::from_utf8(output.stdout)
+        .map_err(io::Error::other)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+
+    // Determine when the containers stopped.
+    let mut containers = HashMap::new();
+    for chunk in container_ids.chunks(STOPPED_CONTAINER_IDS_CHUNK_SIZE) {
+        let output = backend
+            .command()
+            .args(
+                [
+                    "container",
+                    "inspect",
+                    "--format",
+                    "{{.ID}}\t{{.State.FinishedAt}}",
+                ]
+                .iter()
+                .map(Deref::deref)
+                .chain(chunk.iter().map(AsRef::as_ref)),
+            )
+            .stderr(Stdio::inherit())
+            .output()?;
+
+        // Ensure the command succeeded.
+        if !output.status.success() {
+            return Err(io::Error::other("Unable to inspect stopped containers."));
+        }
+
+        for line in String::from_utf8(output.stdout)
+            .map_err(io::Error::other)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+        {
+            let (container_id, finished_since_epoch) = parse_timestamped_line(line)?;
+            containers.insert(container_id, finished_since_epoch);
+        }
+    }
+
+    Ok(containers)
+}
+
+// Delete a Docker container.
+pub fn delete_container(backend: &Backend, container: &str) -> io::Result<()> {
+    info!("Deleting container {}\u{2026}", container.code_str());
+
+    // Tell Docker to delete the container.
+    let mut child = backend
+        .command()
+        .args(["container", "rm", container])
+        .spawn()?;
+
+    // Ensure the command succeeded.
+    if !child.wait()?.success() {
+        return Err(io::Error::other(format!(
+            "Unable to delete container {}.",
+            container.code_str(),
+        )));
+    }
+
+    Ok(())
+}
+
+// Parse a line of output from `docker network inspect` with the format used by `unused_networks`.
+// Returns `None` for networks with containers connected to them.
+fn parse_network(line: &str) -> io::Result<Option<(String, Duration)>> {
+    let (id_and_created, containers) = line
+        .rsplit_once('\t')
+        .ok_or_else(|| io::Error::other("Failed to parse network information from Docker."))?;
+
+    if containers == "0" {
+        parse_timestamped_line(id_and_created).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+// Ask Docker for the user-defined networks which have no containers connected to them, along with
+// when they were created.
+pub fn unused_networks(backend: &Backend) -> io::Result<HashMap<String, Duration>> {
+    // Query Docker for the user-defined networks.
+    let output = backend
+        .command()
+        .args([
+            "network",
+            "ls",
+            "--quiet",
+            "--no-trunc",
+            "--filter",
+            "type=custom",
+        ])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        return Err(io::Error::other("Unable to list networks."));
+    }
+
+    // Interpret the output bytes as UTF-8 and parse the lines.
+    let network_ids = String::from_utf8(output.stdout)
+        .map_err(io::Error::other)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+
+    // Determine which networks are unused and when they were created.
+    let mut networks = HashMap::new();
+    for chunk in network_ids.chunks(NETWORK_IDS_CHUNK_SIZE) {
+        let output = backend
+            .command()
+            .args(
+                [
+                    "network",
+                    "inspect",
+                    "--format",
+                    "{{.Id}}\t{{.Created.Format \"2006-01-02T15:04:05.999999999Z07:00\"}}\t\
+                        {{len .Containers}}",
+                ]
+                .iter()
+                .map(Deref::deref)
+                .chain(chunk.iter().map(AsRef::as_ref)),
+            )
+            .stderr(Stdio::inherit())
+            .output()?;
+
+        // Ensure the command succeeded.
+        if !output.status.success() {
+            return Err(io::Error::other("Unable to inspect networks."));
+        }
+
+        for line in String::from_utf8(output.stdout)
+            .map_err(io::Error::other)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+        {
+            if let Some((network_id, created_since_epoch)) = parse_network(line)? {
+                networks.insert(network_id, created_since_epoch);
+            }
+        }
+    }
+
+    Ok(networks)
+}
+
+// Delete a Docker network.
+pub fn delete_network(backend: &Backend, network: &str) -> io::Result<()> {
+    info!("Deleting network {}\u{2026}", network.code_str());
+
+    // Tell Docker to delete the network.
+    let mut child = backend.command().args(["network", "rm", network]).spawn()?;
+
+    // Ensure the command succeeded.
+    if !child.wait()?.success() {
+        return Err(io::Error::other(format!(
+            "Unable to delete network {}.",
+            network.code_str(),
+        )));
+    }
+
+    Ok(())
+}
+
+// Delete a Docker volume.
+pub fn delete_volume(backend: &Backend, volume: &str) -> io::Result<()> {
+    info!("Deleting volume {}\u{2026}", volume.code_str());
+
+    // Tell Docker to delete the volume.
+    let mut child = backend.command().args(["volume", "rm", volume]).spawn()?;
+
+    // Ensure the command succeeded.
+    if !child.wait()?.success() {
+        return Err(io::Error::other(format!(
+            "Unable to delete volume {}.",
+            volume.code_str(),
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            deleted_image, event_time, events_since, forget_missing_images, image_container,
+            infer_parents, is_anonymous_volume, is_deletion_conflict, is_missing_image,
+            parse_buildctl_du, parse_buildx_builder, parse_df_size, parse_docker_date,
+            parse_image_inspection, parse_image_list, parse_labeled_container, parse_nested_daemon,
+            parse_network, parse_root_dir, parse_thin_pool_space, parse_timestamped_line,
+            pruned_images, used_buildx_builder, used_image, used_network, used_volume,
+            ImageInspection,
+        },
+        crate::backend::{Backend, ImageRecord, NestedDaemon, RepositoryTag, Runtime},
+        byte_unit::Byte,
+        std::{collections::HashMap, path::PathBuf, time::Duration},
+    };
+
+    #[test]
+    fn parse_docker_date_valid() {
+        assert_eq!(
+            parse_docker_date("2022-02-25 12:53:30 -0800 PST").unwrap(),
+            Duration::from_secs(1_645_822_410),
+        );
+    }
+
+    #[test]
+    fn parse_docker_date_before_unix_epoch() {
+        assert_eq!(
+            parse_docker_date("0001-01-01 00:00:00 +0000 UTC").unwrap(),
+            Duration::ZERO,
+        );
+    }
+
+    #[test]
+    fn parse_image_list_tags() {
+        let image_records = parse_image_list(
+            "\
+sha256:0\talpine\t3.20\tsha256:a\t2022-02-25 12:53:30 -0800 PST\t7.8MB
+sha256:0\talpine\tlatest\tsha256:a\t2022-02-25 12:53:30 -0800 PST\t7.8MB
+sha256:1\t<none>\t<none>\t<none>\t2022-02-25 12:53:30 -0800 PST\t1MB
+",
+        )
+        .unwrap();
+
+        assert_eq!(image_records.len(), 2);
+        let image_record = &image_records["sha256:0"];
+        assert_eq!(
+            image_record.repository_tags,
+            vec![
+                RepositoryTag {
+                    repository: "alpine".to_owned(),
+                    tag: "3.20".to_owned(),
+                },
+                RepositoryTag {
+                    repository: "alpine".to_owned(),
+                    tag: "latest".to_owned(),
+                },
+            ],
+        );
+        assert_eq!(
+            image_record.repository_digests,
+            vec!["alpine@sha256:a".to_owned()],
+        );
+        assert_eq!(image_record.size.get_bytes(), 7_800_000);
+        assert!(image_records["sha256:1"].repository_digests.is_empty());
+    }
+
+    #[test]
+    fn parse_image_list_platforms() {
+        let image_records = parse_image_list(
+            "\
+sha256:0\talpine\t3.20\tsha256:0\t2022-02-25 12:53:30 -0800 PST\t7MB\tlinux/amd64
+sha256:0\talpine\t3.20\tsha256:0\t2022-02-25 12:53:30 -0800 PST\t9MB\tlinux/arm64
+sha256:0\talpine\tlatest\tsha256:0\t2022-02-25 12:53:30 -0800 PST\t7MB\tlinux/amd64
+sha256:0\talpine\tlatest\tsha256:0\t2022-02-25 12:53:30 -0800 PST\t9MB\tlinux/arm64
+",
+        )
+        .unwrap();
+
+        assert_eq!(image_records.len(), 1);
+        let image_record = &image_records["sha256:0"];
+        assert_eq!(image_record.repository_tags.len(), 2);
+        assert_eq!(image_record.repository_digests.len(), 1);
+        assert_eq!(image_record.size.get_bytes(), 16_000_000);
+    }
+
+    #[test]
+    fn parse_image_list_invalid() {
+        assert!(parse_image_list("sha256:0\talpine\tlatest\n").is_err());
+    }
+
+    #[test]
+    fn parse_docker_date_invalid() {
+        assert!(parse_docker_date("invalid").is_err());
+    }
+
+    #[test]
+    fn parse_image_inspection_some() {
+        let mut labels = HashMap::new();
+        labels.insert("team".to_owned(), "platform".to_owned());
+
+        assert_eq!(
+            parse_image_inspection(
+                "sha256:0\tsha256:1\t[\"sha256:a\",\"sha256:b\"]\t{\"team\":\"platform\"}",
+            )
+            .unwrap(),
+            (
+                "sha256:0".to_owned(),
+                ImageInspection {
+                    parent_id: Some("sha256:1".to_owned()),
+                    layers: vec!["sha256:a".to_owned(), "sha256:b".to_owned()],
+                    labels,
+                },
+            ),
+        );
+    }
+
+    #[test]
+    fn parse_image_inspection_none() {
+        assert_eq!(
+            parse_image_inspection("sha256:0\t\tnull\tnull").unwrap(),
+            (
+                "sha256:0".to_owned(),
+                ImageInspection {
+                    parent_id: None,
+                    layers: vec![],
+                    labels: HashMap::new(),
+                },
+            ),
+        );
+    }
+
+    #[test]
+    fn parse_image_inspection_invalid() {
+        assert!(parse_image_inspection("sha256:0\t\tnull").is_err());
+    }
+
+    fn image_record() -> ImageRecord {
+        ImageRecord {
+            parent_id: None,
+            created_since_epoch: Duration::from_secs(1_645_822_410),
+            repository_tags: vec![RepositoryTag {
+                repository: "alpine".to_owned(),
+                tag: "latest".to_owned(),
+            }],
+            repository_digests: vec![],
+            labels: HashMap::new(),
+            size: Byte::from_bytes(42),
+        }
+    }
+
+    fn image_inspection() -> ImageInspection {
+        ImageInspection {
+            parent_id: None,
+            layers: vec![],
+            labels: HashMap::new(),
+        }
+    }
+
+    fn layered_image_inspection(parent_id: Option<&str>, layers: &[&str]) -> ImageInspection {
+        ImageInspection {
+            parent_id: parent_id.map(ToOwned::to_owned),
+            layers: layers.iter().copied().map(ToOwned::to_owned).collect(),
+            labels: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn infer_parents_longest_prefix() {
+        let mut inspections = HashMap::new();
+        inspections.insert(
+            "sha256:0".to_owned(),
+            layered_image_inspection(None, &["a"]),
+        );
+        inspections.insert(
+            "sha256:1".to_owned(),
+            layered_image_inspection(None, &["a", "b"]),
+        );
+        inspections.insert(
+            "sha256:2".to_owned(),
+            layered_image_inspection(None, &["a", "b", "c"]),
+        );
+        inspections.insert(
+            "sha256:3".to_owned(),
+            layered_image_inspection(None, &["a", "x", "y"]),
+        );
+        inspections.insert(
+            "sha256:4".to_owned(),
+            layered_image_inspection(None, &["z"]),
+        );
+
+        let mut expected = HashMap::new();
+        expected.insert("sha256:1".to_owned(), "sha256:0".to_owned());
+        expected.insert("sha256:2".to_owned(), "sha256:1".to_owned());
+        expected.insert("sha256:3".to_owned(), "sha256:0".to_owned());
+
+        assert_eq!(infer_parents(&inspections), expected);
+    }
+
+    #[test]
+    fn infer_parents_recorded_or_identical() {
+        let mut inspections = HashMap::new();
+        inspections.insert(
+            "sha256:0".to_owned(),
+            layered_image_inspection(None, &["a"]),
+        );
+        inspections.insert(
+            "sha256:1".to_owned(),
+            layered_image_inspection(None, &["a"]),
+        );
+        inspections.insert(
+            "sha256:2".to_owned(),
+            layered_image_inspection(Some("sha256:9"), &["a", "b"]),
+        );
+        inspections.insert(
+            "sha256:3".to_owned(),
+            layered_image_inspection(None, &["a", "c"]),
+        );
+
+        let mut expected = HashMap::new();
+        expected.insert("sha256:3".to_owned(), "sha256:0".to_owned());
+
+        assert_eq!(infer_parents(&inspections), expected);
+    }
+
+    #[test]
+    fn forget_missing_images_empty() {
+        let mut inspections = HashMap::new();
+        let mut image_records = HashMap::new();
+        image_records.insert("sha256:0".to_owned(), image_record());
+
+        assert_eq!(
+            forget_missing_images(&mut inspections, &image_records),
+            vec!["sha256:0".to_owned()],
+        );
+        assert!(inspections.is_empty());
+    }
+
+    #[test]
+    fn forget_missing_images_incremental() {
+        let mut inspections = HashMap::new();
+        inspections.insert("sha256:0".to_owned(), image_inspection());
+        inspections.insert("sha256:1".to_owned(), image_inspection());
+
+        let mut image_records = HashMap::new();
+        image_records.insert("sha256:1".to_owned(), image_record());
+        image_records.insert("sha256:2".to_owned(), image_record());
+
+        assert_eq!(
+            forget_missing_images(&mut inspections, &image_records),
+            vec!["sha256:2".to_owned()],
+        );
+        assert_eq!(
+            inspections.keys().collect::<Vec<_>>(),
+            vec![&"sha256:1".to_owned()],
+        );
+    }
+
+    #[test]
+    fn parse_labeled_container_valid() {
+        assert_eq!(
+            parse_labeled_container("my-project\tsha256:0123\n").unwrap(),
+            ("my-project".to_owned(), "sha256:0123".to_owned()),
+        );
+    }
+
+    #[test]
+    fn parse_labeled_container_invalid() {
+        assert!(parse_labeled_container("sha256:0123").is_err());
+    }
+
+    #[test]
+    fn parse_nested_daemon_dind() {
+        assert_eq!(
+            parse_nested_daemon("0123\t/ci-job\ttrue\t4242\tdockerd-entrypoint.sh\t\n").unwrap(),
+            Some(NestedDaemon {
+                container_id: "0123".to_owned(),
+                container_name: "ci-job".to_owned(),
+                backend: Backend::from_host(
+                    Runtime::Docker,
+                    "unix:///proc/4242/root/var/run/docker.sock",
+                )
+                .unwrap(),
+            }),
+        );
+    }
+
+    #[test]
+    fn parse_nested_daemon_dockerd_argument() {
+        assert!(
+            parse_nested_daemon("0123\t/ci-job\ttrue\t4242\t/sbin/tini\t-- /usr/local/bin/dockerd")
+                .unwrap()
+                .is_some(),
+        );
+    }
+
+    #[test]
+    fn parse_nested_daemon_unprivileged() {
+        assert_eq!(
+            parse_nested_daemon("0123\t/ci-job\tfalse\t4242\tdockerd-entrypoint.sh\t").unwrap(),
+            None,
+        );
+    }
+
+    #[test]
+    fn parse_nested_daemon_other_program() {
+        assert_eq!(
+            parse_nested_daemon("0123\t/web\ttrue\t4242\tnginx\t-g daemon off;").unwrap(),
+            None,
+        );
+    }
+
+    #[test]
+    fn parse_nested_daemon_invalid() {
+        assert!(parse_nested_daemon("0123\t/ci-job").is_err());
+    }
+
+    #[test]
+    fn parse_root_dir_overlay2() {
+        assert_eq!(
+            parse_root_dir(
+                "/var/lib/docker\t[[\"Backing Filesystem\",\"extfs\"],\
+                     [\"Supports d_type\",\"true\"]]\n",
+            )
+            .unwrap(),
+            (PathBuf::from("/var/lib/docker"), false),
+        );
+    }
+
+    #[test]
+    fn parse_root_dir_containerd_image_store() {
+        assert_eq!(
+            parse_root_dir(
+                "/var/lib/docker\t[[\"driver-type\",\"io.containerd.snapshotter.v1\"]]\n",
+            )
+            .unwrap(),
+            (PathBuf::from("/var/lib/docker"), true),
+        );
+    }
+
+    #[test]
+    fn parse_root_dir_no_status() {
+        assert_eq!(
+            parse_root_dir("/var/lib/docker\tnull\n").unwrap(),
+            (PathBuf::from("/var/lib/docker"), false),
+        );
+    }
+
+    #[test]
+    fn parse_root_dir_invalid() {
+        assert!(parse_root_dir("/var/lib/docker\n").is_err());
+    }
+
+    #[test]
+    fn is_deletion_conflict_child_images() {
+        assert!(is_deletion_conflict(
+            "Error response from daemon: conflict: unable to delete 0123456789ab (cannot be \
+                forced) - image has dependent child images\n",
+        ));
+    }
+
+    #[test]
+    fn is_deletion_conflict_running_container() {
+        assert!(is_deletion_conflict(
+            "Error response from daemon: conflict: unable to delete 0123456789ab (cannot be \
+                forced) - image is being used by running container 456789abcdef\n",
+        ));
+    }
+
+    #[test]
+    fn is_deletion_conflict_other() {
+        assert!(!is_deletion_conflict(
+            "Error response from daemon: No such image: 0123456789ab\n",
+        ));
+    }
+
+    #[test]
+    fn is_missing_image_no_such_image() {
+        assert!(is_missing_image(
+            "Error response from daemon: No such image: 0123456789ab\n",
+        ));
+    }
+
+    #[test]
+    fn is_missing_image_other() {
+        assert!(!is_missing_image(
+            "Error response from daemon: conflict: unable to delete 0123456789ab (cannot be \
+                forced) - image has dependent child images\n",
+        ));
+    }
+
+    #[test]
+    fn deleted_image_delete() {
+        assert_eq!(
+            deleted_image(concat!(
+                r#"{"Type":"image","Action":"delete","Actor":{"ID":"sha256:0123","#,
+                r#""Attributes":{"name":"sha256:0123"}},"id":"sha256:0123"}"#,
+            )),
+            Some("sha256:0123".to_owned()),
+        );
+    }
+
+    #[test]
+    fn deleted_image_untag() {
+        assert_eq!(
+            deleted_image(concat!(
+                r#"{"Type":"image","Action":"untag","Actor":{"ID":"sha256:0123","#,
+                r#""Attributes":{"name":"sha256:0123"}},"id":"sha256:0123"}"#,
+            )),
+            None,
+        );
+    }
+
+    #[test]
+    fn pruned_images_prune() {
+        assert!(pruned_images(concat!(
+            r#"{"Type":"image","Action":"prune","Actor":{"ID":"","#,
+            r#""Attributes":{"reclaimed":"1234"}},"scope":"local","time":1714651210,"#,
+            r#""timeNano":1714651210123456789}"#,
+        )));
+    }
+
+    #[test]
+    fn pruned_images_other() {
+        assert!(!pruned_images(concat!(
+            r#"{"Type":"container","Action":"prune","Actor":{"ID":"","#,
+            r#""Attributes":{"reclaimed":"0"}}}"#,
+        )));
+        assert!(!pruned_images("Error response from daemon"));
+    }
+
+    #[test]
+    fn event_time_nanoseconds() {
+        assert_eq!(
+            event_time(concat!(
+                r#"{"Type":"network","Action":"connect","Actor":{"ID":"abc","Attributes":{}},"#,
+                r#""time":1714651210,"timeNano":1714651210123456789}"#,
+            )),
+            Some(Duration::new(1_714_651_210, 123_456_789)),
+        );
+    }
+
+    #[test]
+    fn event_time_missing() {
+        assert_eq!(event_time(r#"{"Type":"network","Action":"connect"}"#), None);
+        assert_eq!(event_time("Error response from daemon"), None);
+    }
+
+    #[test]
+    fn events_since_fraction() {
+        assert_eq!(
+            events_since(Duration::new(1_714_651_210, 1_000)),
+            "1714651210.000001000",
+        );
+    }
+
+    #[test]
+    fn used_image_container_restart() {
+        assert_eq!(
+            used_image(concat!(
+                r#"{"Type":"container","Action":"restart","Actor":{"ID":"abc","#,
+                r#""Attributes":{"image":"alpine","name":"web"}},"id":"abc"}"#,
+            )),
+            Some("alpine".to_owned()),
+        );
+    }
+
+    #[test]
+    fn used_image_container_exec() {
+        assert_eq!(
+            used_image(concat!(
+                r#"{"Type":"container","Action":"exec_create: sh -c date","#,
+                r#""Actor":{"ID":"abc","Attributes":{"image":"alpine","name":"web"}},"#,
+                r#""id":"abc"}"#,
+            )),
+            Some("alpine".to_owned()),
+        );
+    }
+
+    #[test]
+    fn used_image_container_commit() {
+        assert_eq!(
+            used_image(concat!(
+                r#"{"Type":"container","Action":"commit","Actor":{"ID":"abc","#,
+                r#""Attributes":{"comment":"","image":"alpine","imageID":"sha256:0123","#,
+                r#""imageRef":"sha256:0123","name":"builder"}},"id":"abc"}"#,
+            )),
+            Some("sha256:0123".to_owned()),
+        );
+    }
+
+    #[test]
+    fn used_image_image_create() {
+        assert_eq!(
+            used_image(concat!(
+                r#"{"Type":"image","Action":"create","Actor":{"ID":"sha256:0123","#,
+                r#""Attributes":{"name":"sha256:0123"}},"id":"sha256:0123"}"#,
+            )),
+            Some("sha256:0123".to_owned()),
+        );
+    }
+
+    #[test]
+    fn used_image_container_die() {
+        assert_eq!(
+            used_image(concat!(
+                r#"{"Type":"container","Action":"die","Actor":{"ID":"abc","#,
+                r#""Attributes":{"image":"alpine","name":"web"}},"id":"abc"}"#,
+            )),
+            None,
+        );
+    }
+
+    #[test]
+    fn image_container_container_create() {
+        assert_eq!(
+            image_container(concat!(
+                r#"{"Type":"container","Action":"create","Actor":{"ID":"abc","#,
+                r#""Attributes":{"image":"alpine","name":"runner-1"}},"id":"abc"}"#,
+            )),
+            Some("runner-1".to_owned()),
+        );
+    }
+
+    #[test]
+    fn image_container_image_pull() {
+        assert_eq!(
+            image_container(concat!(
+                r#"{"Type":"image","Action":"pull","Actor":{"ID":"alpine:latest","#,
+                r#""Attributes":{"name":"alpine"}},"id":"alpine:latest"}"#,
+            )),
+            None,
+        );
+    }
+
+    #[test]
+    fn used_volume_mount() {
+        assert_eq!(
+            used_volume(concat!(
+                r#"{"Type":"volume","Action":"mount","Actor":{"ID":"data","#,
+                r#""Attributes":{"driver":"local"}},"id":""}"#,
+            )),
+            Some("data".to_owned()),
+        );
+    }
+
+    #[test]
+    fn used_volume_irrelevant() {
+        assert_eq!(
+            used_volume(concat!(
+                r#"{"Type":"volume","Action":"create","Actor":{"ID":"data","#,
+                r#""Attributes":{"driver":"local"}},"id":""}"#,
+            )),
+            None,
+        );
+    }
+
+    #[test]
+    fn is_anonymous_volume_anonymous() {
+        assert!(is_anonymous_volume(
+            "4f3a1c0e8b0d2e6f9a7b5c3d1e0f2a4b6c8d0e2f4a6b8c0d2e4f6a8b0c2d4e6f",
+        ));
+    }
+
+    #[test]
+    fn is_anonymous_volume_named() {
+        assert!(!is_anonymous_volume("postgres-data"));
+    }
+
+    #[test]
+    fn parse_timestamped_line_valid() {
+        assert_eq!(
+            parse_timestamped_line("data\t2022-02-25T20:53:30.123456789Z").unwrap(),
+            ("data".to_owned(), Duration::new(1_645_822_410, 123_456_789)),
+        );
+    }
+
+    #[test]
+    fn parse_timestamped_line_before_unix_epoch() {
+        assert_eq!(
+            parse_timestamped_line("abc\t0001-01-01T00:00:00Z").unwrap(),
+            ("abc".to_owned(), Duration::ZERO),
+        );
+    }
+
+    #[test]
+    fn used_buildx_builder_dial_stdio() {
+        assert_eq!(
+            used_buildx_builder(concat!(
+                r#"{"Type":"container","Action":"exec_create: buildctl dial-stdio","#,
+                r#""Actor":{"ID":"abc","Attributes":{"image":"moby/buildkit:buildx-stable-1","#,
+                r#""name":"buildx_buildkit_ci0"}},"id":"abc"}"#,
+            )),
+            Some("buildx_buildkit_ci0".to_owned()),
+        );
+    }
+
+    #[test]
+    fn used_buildx_builder_other_container() {
+        assert_eq!(
+            used_buildx_builder(concat!(
+                r#"{"Type":"container","Action":"exec_create: sh","Actor":{"ID":"abc","#,
+                r#""Attributes":{"image":"alpine","name":"web"}},"id":"abc"}"#,
+            )),
+            None,
+        );
+    }
+
+    #[test]
+    fn parse_buildx_builder_valid() {
+        assert_eq!(
+            parse_buildx_builder("/buildx_buildkit_ci0\t2022-02-25T20:53:30Z").unwrap(),
+            Some((
+                "buildx_buildkit_ci0".to_owned(),
+                Duration::from_secs(1_645_822_410),
+            )),
+        );
+    }
+
+    #[test]
+    fn parse_buildx_builder_other_container() {
+        assert_eq!(
+            parse_buildx_builder("/my_buildx_buildkit_ci0\t2022-02-25T20:53:30Z").unwrap(),
+            None,
+        );
+    }
+
+    #[test]
+    fn parse_buildctl_du_total() {
+        assert_eq!(
+            parse_buildctl_du(
+                "ID\t\t\t\t\t\tRECLAIMABLE\tSIZE\t\tLAST ACCESSED\n\
+                 pb2ygvhy5nw8c5fvx0ydawhcw\ttrue\t\t1.2GB\t\t2 days ago\n\
+                 qz7vd3w1hqs2n0g5h7t9e3l4k\tfalse\t\t300MB\t\t\n\
+                 Reclaimable:\t1.2GB\n\
+                 Total:\t\t1.5GB\n",
+            )
+            .unwrap(),
+            Byte::from_bytes(1_500_000_000),
+        );
+        assert_eq!(
+            parse_buildctl_du("Reclaimable:\t0B\nTotal:\t\t512kB\n").unwrap(),
+            Byte::from_bytes(512_000),
+        );
+    }
+
+    #[test]
+    fn parse_buildctl_du_invalid() {
+        assert!(parse_buildctl_du("").is_err());
+    }
+
+    #[test]
+    fn used_network_disconnect() {
+        assert_eq!(
+            used_network(concat!(
+                r#"{"Type":"network","Action":"disconnect","Actor":{"ID":"abc","#,
+                r#""Attributes":{"container":"def","name":"ci","type":"bridge"}},"id":""}"#,
+            )),
+            Some("abc".to_owned()),
+        );
+    }
+
+    #[test]
+    fn parse_network_unused() {
+        assert_eq!(
+            parse_network("abc\t2022-02-25T20:53:30Z\t0").unwrap(),
+            Some(("abc".to_owned(), Duration::from_secs(1_645_822_410))),
+        );
+    }
+
+    #[test]
+    fn parse_network_in_use() {
+        assert_eq!(parse_network("abc\t2022-02-25T20:53:30Z\t2").unwrap(), None);
+    }
+
+    // A sample of the output of `docker system df --format '{{json .}}'`
+    const DF_OUTPUT: &str = "\
+        {\"Active\":\"2\",\"Reclaimable\":\"1GB (50%)\",\"Size\":\"2GB\",\"TotalCount\":\"5\",\
+            \"Type\":\"Images\"}\n\
+        {\"Active\":\"1\",\"Reclaimable\":\"0B (0%)\",\"Size\":\"250MB\",\"TotalCount\":\"1\",\
+            \"Type\":\"Containers\"}\n\
+        {\"Active\":\"1\",\"Reclaimable\":\"300MB (60%)\",\"Size\":\"500MB\",\
+            \"TotalCount\":\"3\",\"Type\":\"Local Volumes\"}\n";
+
+    #[test]
+    fn parse_df_size_images() {
+        assert_eq!(
+            parse_df_size(DF_OUTPUT, &["Images"]).unwrap(),
+            Byte::from_bytes(2_000_000_000),
+        );
+    }
+
+    #[test]
+    fn parse_df_size_images_and_volumes() {
+        assert_eq!(
+            parse_df_size(DF_OUTPUT, &["Images", "Local Volumes"]).unwrap(),
+            Byte::from_bytes(2_500_000_000),
+        );
+    }
+
+    #[test]
+    fn parse_df_size_images_and_containers() {
+        assert_eq!(
+            parse_df_size(DF_OUTPUT, &["Images", "Containers"]).unwrap(),
+            Byte::from_bytes(2_250_000_000),
+        );
+    }
+
+    #[test]
+    fn parse_thin_pool_space_devicemapper() {
+        assert_eq!(
+            parse_thin_pool_space(
+                "devicemapper\t[[\"Pool Name\",\"docker-thinpool\"],\
+                    [\"Data Space Used\",\"1.2 GB\"],[\"Data Space Total\",\"102 GB\"],\
+                    [\"Data Space Available\",\"100.8 GB\"]]\n",
+            )
+            .unwrap(),
+            Some((
+                Byte::from_bytes(102_000_000_000),
+                Byte::from_bytes(100_800_000_000),
+            )),
+        );
+    }
+
+    #[test]
+    fn parse_thin_pool_space_overlay2() {
+        assert_eq!(
+            parse_thin_pool_space(
+                "overlay2\t[[\"Backing Filesystem\",\"extfs\"],[\"Supports d_type\",\"true\"]]\n",
+            )
+            .unwrap(),
+            None,
+        );
+    }
+
+    #[test]
+    fn parse_thin_pool_space_invalid() {
+        assert!(
+            parse_thin_pool_space("devicemapper\t[[\"Pool Name\",\"docker-thinpool\"]]\n").is_err(),
+        );
+    }
+
+    #[test]
+    fn parse_df_size_missing() {
+        assert!(parse_df_size(DF_OUTPUT, &["Build Cache"]).is_err());
+    }
+}
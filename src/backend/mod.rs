@@ -0,0 +1,775 @@
+mod containerd;
+mod context;
+mod cri;
+#[cfg(any(target_os = "macos", windows))]
+mod desktop;
+mod docker;
+#[cfg(any(target_os = "macos", windows))]
+mod socket;
+
+use {
+    crate::{format::CodeStr, state::State, telemetry},
+    byte_unit::Byte,
+    std::{
+        collections::{HashMap, HashSet},
+        io::{self, Read, Write},
+        path::PathBuf,
+        process::{Child, Command, Stdio},
+        str::FromStr,
+        time::Duration,
+    },
+};
+
+// Images with this label are never deleted, regardless of the settings.
+pub const PROTECTION_LABEL_KEY: &str = "docuum.keep";
+pub const PROTECTION_LABEL_VALUE: &str = "true";
+
+// Each image may be associated with multiple of these repository-tag pairs. Docker will always
+// report at least one repository-tag pair for each image. For untagged images, `tag` will be
+// `<none>`, and `repository` may also take on that value [tag:at_least_one_repository_tag].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RepositoryTag {
+    pub repository: String,
+    pub tag: String,
+}
+
+// This is the information Docker reports about each image when listing images. Note that the image
+// ID is not included here because this struct will be used as the value type for a `HashMap` for
+// which the key type is the image ID.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ImageRecord {
+    pub parent_id: Option<String>,
+    pub created_since_epoch: Duration,
+    pub repository_tags: Vec<RepositoryTag>, // [ref:at_least_one_repository_tag]
+    pub repository_digests: Vec<String>,     // E.g., `alpine@sha256:0123…`
+    pub labels: HashMap<String, String>,
+    pub size: Byte,
+}
+
+/// A kind of container runtime
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Runtime {
+    /// The Docker daemon, via the `docker` CLI
+    Docker,
+
+    /// containerd, via the `nerdctl` CLI
+    Containerd,
+
+    /// The Container Runtime Interface used by Kubernetes (e.g., CRI-O or containerd's CRI plugin),
+    /// via the `crictl` CLI
+    Cri,
+}
+
+impl Runtime {
+    /// The names accepted by `from_str`
+    pub const NAMES: &'static [&'static str] = &["docker", "containerd", "cri"];
+
+    // The CLI used to communicate with the container runtime
+    fn program(self) -> &'static str {
+        match self {
+            Self::Docker => "docker",
+            Self::Containerd => "nerdctl",
+            Self::Cri => "crictl",
+        }
+    }
+}
+
+impl FromStr for Runtime {
+    type Err = io::Error;
+
+    // Parse a `Runtime`.
+    fn from_str(runtime: &str) -> io::Result<Self> {
+        match runtime {
+            "docker" => Ok(Self::Docker),
+            "containerd" => Ok(Self::Containerd),
+            "cri" => Ok(Self::Cri),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid backend {}.", runtime.code_str()),
+            )),
+        }
+    }
+}
+
+// The address of a container runtime and how to connect to it
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Endpoint {
+    // E.g., `unix:///var/run/docker.sock` or `tcp://192.168.1.2:2376`
+    pub host: String,
+
+    // A directory containing `ca.pem`, `cert.pem`, and `key.pem` for TLS, if TLS is used
+    pub cert_path: Option<PathBuf>,
+
+    // Whether to verify the server's certificate when using TLS
+    pub tls_verify: bool,
+}
+
+// A Docker daemon running in a privileged Docker-in-Docker container
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NestedDaemon {
+    pub container_id: String,
+    pub container_name: String,
+    pub backend: Backend, // Connects through the container's file system
+}
+
+// The container runtime whose images Docuum manages, and where to find it
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Backend {
+    pub runtime: Runtime,
+
+    // If this is `None`, the CLI decides which endpoint to use.
+    pub endpoint: Option<Endpoint>,
+}
+
+impl Backend {
+    // Construct a `Backend` for the runtime's default endpoint.
+    pub fn new(runtime: Runtime) -> Self {
+        Self {
+            runtime,
+            endpoint: None,
+        }
+    }
+
+    // Construct a `Backend` for the endpoint which the CLI would use by default. On macOS and
+    // Windows, the Docker socket often isn't in the default location, so we look for it if the CLI
+    // would fail to connect.
+    pub fn local(runtime: Runtime) -> Self {
+        #[cfg(any(target_os = "macos", windows))]
+        if runtime == Runtime::Docker {
+            if let Some(endpoint) = socket::autodetect() {
+                debug!("Using detected endpoint {}.", endpoint.host.code_str());
+                return Self {
+                    runtime,
+                    endpoint: Some(endpoint),
+                };
+            }
+        }
+
+        Self::new(runtime)
+    }
+
+    // Construct a `Backend` for an explicitly given endpoint, e.g., `unix:///run/docker.sock` or
+    // `npipe:////./pipe/docker_engine`. TLS is configured via the environment as usual.
+    pub fn from_host(runtime: Runtime, host: &str) -> io::Result<Self> {
+        if host.starts_with("npipe://") && !cfg!(windows) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Named pipes like {} are only supported on Windows.",
+                    host.code_str(),
+                ),
+            ));
+        }
+
+        Ok(Self {
+            runtime,
+            endpoint: Some(Endpoint {
+                host: host.to_owned(),
+                cert_path: None,
+                tls_verify: false,
+            }),
+        })
+    }
+
+    // Construct a `Backend` for the endpoint of a Docker context.
+    pub fn from_context(runtime: Runtime, context: &str) -> io::Result<Self> {
+        if runtime != Runtime::Docker {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Contexts are only supported by the Docker backend.",
+            ));
+        }
+
+        let endpoint = context::endpoint(context)?;
+        debug!(
+            "Using endpoint {} from Docker context {}.",
+            endpoint.host.code_str(),
+            context.code_str(),
+        );
+
+        Ok(Self {
+            runtime,
+            endpoint: Some(endpoint),
+        })
+    }
+
+    // A description of the endpoint for logging purposes
+    pub fn endpoint_description(&self) -> String {
+        self.endpoint.as_ref().map_or_else(
+            || {
+                format!(
+                    "the default endpoint of {}",
+                    self.runtime.program().code_str(),
+                )
+            },
+            |endpoint| endpoint.host.code_str().to_string(),
+        )
+    }
+
+    // Determine whether the container runtime is reachable at the endpoint.
+    pub fn reachable(&self) -> bool {
+        self.command()
+            .arg("version")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+
+    // Choose the first of several backends, in order of preference, which is reachable. If none of
+    // them are, we stick with the first one and let the usual error handling take its course. If
+    // there's only one backend, there's nothing to choose, so we don't bother checking it.
+    pub fn first_reachable(candidates: &[Self]) -> Self {
+        if let [backend] = candidates {
+            return backend.clone();
+        }
+
+        if let Some(backend) = candidates.iter().find(|backend| backend.reachable()) {
+            info!("Using {}.", backend.endpoint_description());
+            backend.clone()
+        } else {
+            let backend = candidates[0].clone();
+            warn!(
+                "None of the endpoints are reachable, so {} will be used.",
+                backend.endpoint_description(),
+            );
+            backend
+        }
+    }
+
+    // Construct a command which invokes the CLI for the container runtime and points it to the
+    // endpoint.
+    fn command(&self) -> Command {
+        let mut command = Command::new(self.runtime.program());
+
+        if let Some(endpoint) = &self.endpoint {
+            match self.runtime {
+                Runtime::Docker => {
+                    command.env("DOCKER_HOST", &endpoint.host);
+                    if let Some(cert_path) = &endpoint.cert_path {
+                        command.env("DOCKER_CERT_PATH", cert_path);
+                        if endpoint.tls_verify {
+                            command.env("DOCKER_TLS_VERIFY", "1");
+                        } else {
+                            command.env("DOCKER_TLS", "1");
+                        }
+                    }
+                }
+                Runtime::Containerd => {
+                    command.env("CONTAINERD_ADDRESS", &endpoint.host);
+                }
+                Runtime::Cri => {
+                    command.env("CONTAINER_RUNTIME_ENDPOINT", &endpoint.host);
+                    command.env("IMAGE_SERVICE_ENDPOINT", &endpoint.host);
+                }
+            }
+        }
+
+        command
+    }
+
+    // Whether the container runtime can stream events. If not, we have to poll it instead.
+    pub fn streams_events(&self) -> bool {
+        match self.runtime {
+            Runtime::Docker | Runtime::Containerd => true,
+            Runtime::Cri => false,
+        }
+    }
+
+    // The command which streams events, for logging purposes
+    pub fn events_command(&self) -> String {
+        format!("{} events", self.runtime.program())
+    }
+
+    // Spawn a process which streams events as JSON, one per line of standard output
+    // [tag:events_stdout]. If the container runtime can resume streaming events from a point in
+    // time, the events after `since_epoch` (if given) are replayed first.
+    pub fn spawn_events(&self, since_epoch: Option<Duration>) -> io::Result<Child> {
+        if !self.streams_events() {
+            return Err(io::Error::other(format!(
+                "{} can't stream events.",
+                self.runtime.program().code_str(),
+            )));
+        }
+
+        let mut command = self.command();
+        command.args(["events", "--format", "{{json .}}"]);
+        if let (Runtime::Docker, Some(since_epoch)) = (self.runtime, since_epoch) {
+            // Docker includes events which happened at exactly `--since`, so we skip ahead by the
+            // smallest amount of time it can represent.
+            command.args([
+                "--since",
+                &docker::events_since(since_epoch + Duration::from_nanos(1)),
+            ]);
+        }
+
+        command.stdout(Stdio::piped()).spawn()
+    }
+
+    // Determine when an event happened according to a line of output from the events process, if
+    // the container runtime can resume streaming events from then.
+    pub fn event_time(&self, line: &str) -> Option<Duration> {
+        match self.runtime {
+            Runtime::Docker => docker::event_time(line),
+            Runtime::Containerd | Runtime::Cri => None,
+        }
+    }
+
+    // Determine which image, if any, was used according to a line of output from the events
+    // process.
+    pub fn used_image(&self, line: &str) -> Option<String> {
+        match self.runtime {
+            Runtime::Docker => docker::used_image(line),
+            Runtime::Containerd => containerd::used_image(line),
+            Runtime::Cri => None,
+        }
+    }
+
+    // Determine which container, if any, used an image according to a line of output from the
+    // events process.
+    pub fn image_container(&self, line: &str) -> Option<String> {
+        match self.runtime {
+            Runtime::Docker => docker::image_container(line),
+            Runtime::Containerd | Runtime::Cri => None,
+        }
+    }
+
+    // Determine which volume, if any, was mounted or unmounted according to a line of output from
+    // the events process.
+    pub fn used_volume(&self, line: &str) -> Option<String> {
+        match self.runtime {
+            Runtime::Docker => docker::used_volume(line),
+            Runtime::Containerd | Runtime::Cri => None,
+        }
+    }
+
+    // Determine which image, if any, was deleted according to a line of output from the events
+    // process. This includes the images Docuum deletes itself.
+    pub fn deleted_image(&self, line: &str) -> Option<String> {
+        match self.runtime {
+            Runtime::Docker => docker::deleted_image(line),
+            Runtime::Containerd | Runtime::Cri => None,
+        }
+    }
+
+    // Determine whether images were pruned according to a line of output from the events process,
+    // in which case the state should be reconciled with the images which remain.
+    pub fn pruned_images(&self, line: &str) -> bool {
+        match self.runtime {
+            Runtime::Docker => docker::pruned_images(line),
+            Runtime::Containerd | Runtime::Cri => false,
+        }
+    }
+
+    // Ask the container runtime for the ID of an image. Note that `nerdctl` is compatible with the
+    // `docker` CLI for this and several of the other operations below.
+    pub fn image_id(&self, image: &str) -> io::Result<String> {
+        match self.runtime {
+            Runtime::Docker | Runtime::Containerd => docker::image_id(self, image),
+            Runtime::Cri => cri::image_id(self, image),
+        }
+    }
+
+    // Get the ID of the parent of an image (if the parent exists), querying the container runtime
+    // if necessary.
+    pub fn parent_id(&self, state: &State, image_id: &str) -> io::Result<Option<String>> {
+        match self.runtime {
+            Runtime::Docker | Runtime::Containerd => docker::parent_id(self, state, image_id),
+            Runtime::Cri => Ok(None),
+        }
+    }
+
+    // Query the container runtime for all the images, inspecting up to `concurrency` chunks of them
+    // at a time.
+    pub fn list_image_records(
+        &self,
+        concurrency: usize,
+    ) -> io::Result<HashMap<String, ImageRecord>> {
+        telemetry::traced("list_images", |span| {
+            let image_records = match self.runtime {
+                Runtime::Docker | Runtime::Containerd => {
+                    docker::list_image_records(self, concurrency)
+                }
+                Runtime::Cri => cri::list_image_records(self),
+            }?;
+            span.set("images", image_records.len());
+            Ok(image_records)
+        })
+    }
+
+    // Ask the container runtime for the IDs of the images currently in use by containers.
+    pub fn image_ids_in_use(&self) -> io::Result<HashSet<String>> {
+        telemetry::traced("list_containers", |_| match self.runtime {
+            Runtime::Docker => docker::image_ids_in_use(self),
+            Runtime::Containerd => containerd::image_ids_in_use(self),
+            Runtime::Cri => cri::image_ids_in_use(self),
+        })
+    }
+
+    // Ask the container runtime for the IDs of the images which it pinned, e.g., the sandbox image
+    // of Kubernetes pods. Only the CRI pins images.
+    pub fn pinned_image_ids(&self) -> io::Result<HashSet<String>> {
+        match self.runtime {
+            Runtime::Docker | Runtime::Containerd => Ok(HashSet::new()),
+            Runtime::Cri => cri::pinned_image_ids(self),
+        }
+    }
+
+    // Whether Docuum can find containers by their labels, like the containers of Docker Compose
+    // projects or GitLab CI pipelines. We rely on Docker to tell us about those labels.
+    pub fn supports_container_labels(&self) -> bool {
+        match self.runtime {
+            Runtime::Docker => true,
+            Runtime::Containerd | Runtime::Cri => false,
+        }
+    }
+
+    // Ask the container runtime for the IDs of the images used by the containers of each Docker
+    // Compose project, including containers which have stopped.
+    pub fn compose_project_image_ids(&self) -> io::Result<HashMap<String, HashSet<String>>> {
+        match self.runtime {
+            Runtime::Docker => docker::compose_project_image_ids(self),
+            Runtime::Containerd | Runtime::Cri => Err(io::Error::other(format!(
+                "Docker Compose projects aren't supported for {}.",
+                self.runtime.program().code_str(),
+            ))),
+        }
+    }
+
+    // Ask the container runtime for the IDs of the images used by the containers of each GitLab CI
+    // pipeline, including containers which have stopped.
+    pub fn gitlab_pipeline_image_ids(&self) -> io::Result<HashMap<String, HashSet<String>>> {
+        match self.runtime {
+            Runtime::Docker => docker::gitlab_pipeline_image_ids(self),
+            Runtime::Containerd | Runtime::Cri => Err(io::Error::other(format!(
+                "GitLab CI pipelines aren't supported for {}.",
+                self.runtime.program().code_str(),
+            ))),
+        }
+    }
+
+    // Ask the container runtime for the Docker daemons running in its Docker-in-Docker containers.
+    pub fn nested_daemons(&self) -> io::Result<Vec<NestedDaemon>> {
+        match self.runtime {
+            Runtime::Docker => docker::nested_daemons(self),
+            Runtime::Containerd | Runtime::Cri => Err(io::Error::other(format!(
+                "Docker-in-Docker containers aren't supported for {}.",
+                self.runtime.program().code_str(),
+            ))),
+        }
+    }
+
+    // Whether Docuum can manage the caches of Docker Buildx builders, which run in containers of
+    // the container runtime.
+    pub fn supports_buildx_builders(&self) -> bool {
+        match self.runtime {
+            Runtime::Docker => true,
+            Runtime::Containerd | Runtime::Cri => false,
+        }
+    }
+
+    // Fail unless Docuum can manage the caches of Docker Buildx builders.
+    fn ensure_buildx_builders_supported(&self) -> io::Result<()> {
+        if self.supports_buildx_builders() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!(
+                "Buildx builders aren't supported for {}.",
+                self.runtime.program().code_str(),
+            )))
+        }
+    }
+
+    // Determine which Buildx builder container, if any, was used according to a line of output
+    // from the events process.
+    pub fn used_buildx_builder(&self, line: &str) -> Option<String> {
+        match self.runtime {
+            Runtime::Docker => docker::used_buildx_builder(line),
+            Runtime::Containerd | Runtime::Cri => None,
+        }
+    }
+
+    // Ask the container runtime for the containers of Buildx builders, along with when they were
+    // created.
+    pub fn buildx_builders(&self) -> io::Result<HashMap<String, Duration>> {
+        self.ensure_buildx_builders_supported()?;
+        docker::buildx_builders(self)
+    }
+
+    // Ask a Buildx builder for the space used by its cache.
+    pub fn buildx_cache_size(&self, container: &str) -> io::Result<Byte> {
+        self.ensure_buildx_builders_supported()?;
+        docker::buildx_cache_size(self, container)
+    }
+
+    // Prune the cache of a Buildx builder down to the given size.
+    pub fn prune_buildx_cache(&self, container: &str, keep: Byte) -> io::Result<()> {
+        self.ensure_buildx_builders_supported()?;
+        docker::prune_buildx_cache(self, container, keep)
+    }
+
+    // Determine the root directory of the container runtime.
+    pub fn root_dir(&self) -> io::Result<PathBuf> {
+        match self.runtime {
+            Runtime::Docker => docker::root_dir(self),
+            Runtime::Containerd => Ok(containerd::root_dir()),
+            Runtime::Cri => cri::root_dir(self),
+        }
+    }
+
+    // Determine the size of the thin pool in which the container runtime keeps images, and how much
+    // space is available in it, if images are kept in a thin pool rather than the root directory.
+    pub fn thin_pool_space(&self) -> io::Result<Option<(Byte, Byte)>> {
+        match self.runtime {
+            Runtime::Docker => docker::thin_pool_space(self),
+            Runtime::Containerd | Runtime::Cri => Ok(None),
+        }
+    }
+
+    // Determine the size of the disk of the Docker Desktop VM, for when the root directory of the
+    // container runtime isn't on the host.
+    #[cfg(any(target_os = "macos", windows))]
+    pub fn desktop_disk_size(&self) -> io::Result<Byte> {
+        match self.runtime {
+            Runtime::Docker => desktop::disk_size(),
+            Runtime::Containerd | Runtime::Cri => Err(io::Error::other(format!(
+                "Unable to find the root directory of {} on this host.",
+                self.runtime.program().code_str(),
+            ))),
+        }
+    }
+
+    // Get the total space used by images.
+    pub fn space_usage(&self) -> io::Result<Byte> {
+        telemetry::traced("space_usage", |span| {
+            let space = match self.runtime {
+                Runtime::Docker => docker::space_usage(self),
+                Runtime::Containerd => containerd::space_usage(self),
+                Runtime::Cri => cri::space_usage(self),
+            }?;
+            span.set("bytes", space.get_bytes());
+            Ok(space)
+        })
+    }
+
+    // Delete an image. With `prune_parents`, Docker also deletes the parents which are left without
+    // tags or children. The other runtimes always garbage collect unreferenced content.
+    pub fn delete_image(&self, image: &str, prune_parents: bool) -> io::Result<()> {
+        telemetry::traced("delete_image", |span| {
+            span.set("image_id", image);
+            match self.runtime {
+                Runtime::Docker => docker::delete_image(self, image, prune_parents),
+                Runtime::Containerd => containerd::delete_image(self, image),
+                Runtime::Cri => cri::delete_image(self, image),
+            }
+        })
+    }
+
+    // Whether Docuum can remove a single tag from an image without deleting the image. The CRI only
+    // offers to delete images, along with all their tags.
+    pub fn supports_untagging(&self) -> bool {
+        match self.runtime {
+            Runtime::Docker | Runtime::Containerd => true,
+            Runtime::Cri => false,
+        }
+    }
+
+    // Remove a tag from an image which has other tags, leaving the image itself in place.
+    pub fn untag_image(&self, repository_tag: &str) -> io::Result<()> {
+        match self.runtime {
+            Runtime::Docker | Runtime::Containerd => docker::untag_image(self, repository_tag),
+            Runtime::Cri => Err(io::Error::other(
+                "Removing individual tags isn't supported for the CRI.",
+            )),
+        }
+    }
+
+    // Whether Docuum can archive images before deleting them. The CRI has no equivalent of
+    // `docker image save`.
+    pub fn supports_saving_images(&self) -> bool {
+        match self.runtime {
+            Runtime::Docker | Runtime::Containerd => true,
+            Runtime::Cri => false,
+        }
+    }
+
+    // Write an archive of images, given by tag or ID, which the container runtime can load again.
+    pub fn save_images(&self, images: &[String], writer: &mut impl Write) -> io::Result<()> {
+        telemetry::traced("save_images", |_| match self.runtime {
+            Runtime::Docker | Runtime::Containerd => docker::save_images(self, images, writer),
+            Runtime::Cri => Err(io::Error::other(
+                "Saving images isn't supported for the CRI.",
+            )),
+        })
+    }
+
+    // Load an archive of images which `save_images` wrote, along with the tags in it.
+    pub fn load_images(&self, reader: &mut impl Read) -> io::Result<()> {
+        telemetry::traced("load_images", |_| match self.runtime {
+            Runtime::Docker | Runtime::Containerd => docker::load_images(self, reader),
+            Runtime::Cri => Err(io::Error::other(
+                "Loading images isn't supported for the CRI.",
+            )),
+        })
+    }
+
+    // Ask the container runtime for the ID of the image of a container. The CRI doesn't run
+    // Docuum's own container, so there's no need to ask it.
+    pub fn container_image_id(&self, container_id: &str) -> io::Result<Option<String>> {
+        match self.runtime {
+            Runtime::Docker | Runtime::Containerd => {
+                docker::container_image_id(self, container_id).map(Some)
+            }
+            Runtime::Cri => Ok(None),
+        }
+    }
+
+    // Add a tag to an image.
+    pub fn tag_image(&self, image_id: &str, repository_tag: &str) -> io::Result<()> {
+        match self.runtime {
+            Runtime::Docker | Runtime::Containerd => {
+                docker::tag_image(self, image_id, repository_tag)
+            }
+            Runtime::Cri => Err(io::Error::other(
+                "Tagging images isn't supported for the CRI.",
+            )),
+        }
+    }
+
+    // The command which pulls an image again, for humans to run
+    pub fn pull_command(&self, repository_tag: &str) -> String {
+        format!("{} pull {repository_tag}", self.runtime.program())
+    }
+
+    // Whether Docuum can ask the container runtime to delete the untagged parents of an image along
+    // with it. Only Docker keeps such parents around as separate images.
+    pub fn supports_pruning_parents(&self) -> bool {
+        match self.runtime {
+            Runtime::Docker => true,
+            Runtime::Containerd | Runtime::Cri => false,
+        }
+    }
+
+    // Whether Docuum can delete stopped containers. With the CRI, the kubelet is responsible for
+    // that.
+    pub fn supports_containers(&self) -> bool {
+        match self.runtime {
+            Runtime::Docker | Runtime::Containerd => true,
+            Runtime::Cri => false,
+        }
+    }
+
+    // Ask the container runtime for the containers which have stopped, along with when they
+    // stopped.
+    pub fn stopped_containers(&self) -> io::Result<HashMap<String, Duration>> {
+        match self.runtime {
+            Runtime::Docker | Runtime::Containerd => docker::stopped_containers(self),
+            Runtime::Cri => Err(io::Error::other(
+                "Deleting containers isn't supported for the CRI.",
+            )),
+        }
+    }
+
+    // Delete a container.
+    pub fn delete_container(&self, container: &str) -> io::Result<()> {
+        match self.runtime {
+            Runtime::Docker | Runtime::Containerd => docker::delete_container(self, container),
+            Runtime::Cri => Err(io::Error::other(
+                "Deleting containers isn't supported for the CRI.",
+            )),
+        }
+    }
+
+    // Whether Docuum can manage the volumes and networks of the container runtime. Docuum relies on
+    // Docker events to learn when these are used.
+    pub fn supports_volumes_and_networks(&self) -> bool {
+        match self.runtime {
+            Runtime::Docker => true,
+            Runtime::Containerd | Runtime::Cri => false,
+        }
+    }
+
+    // Fail unless Docuum can manage the volumes and networks of the container runtime.
+    fn ensure_volumes_and_networks_supported(&self) -> io::Result<()> {
+        if self.supports_volumes_and_networks() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!(
+                "Volumes and networks aren't supported for {}.",
+                self.runtime.program().code_str(),
+            )))
+        }
+    }
+
+    // Ask the container runtime for the anonymous volumes which aren't used by any containers,
+    // along with when they were created.
+    pub fn unused_anonymous_volumes(&self) -> io::Result<HashMap<String, Duration>> {
+        self.ensure_volumes_and_networks_supported()?;
+        docker::unused_anonymous_volumes(self)
+    }
+
+    // Get the total space used by images and, optionally, volumes, the writable layers of
+    // containers, and the build cache. Like volumes, the space used by containers and the build
+    // cache can only be measured with Docker.
+    pub fn space_usage_with(
+        &self,
+        volumes: bool,
+        container_layers: bool,
+        build_cache: bool,
+    ) -> io::Result<Byte> {
+        self.ensure_volumes_and_networks_supported()?;
+        docker::space_usage_with(self, volumes, container_layers, build_cache)
+    }
+
+    // Get the space used by the build cache of the container runtime.
+    pub fn build_cache_size(&self) -> io::Result<Byte> {
+        match self.runtime {
+            Runtime::Docker => docker::build_cache_size(self),
+            Runtime::Containerd | Runtime::Cri => Err(io::Error::other(format!(
+                "The build cache isn't supported for {}.",
+                self.runtime.program().code_str(),
+            ))),
+        }
+    }
+
+    // Prune the build cache of the container runtime down to the given size, leaving alone the
+    // records which were used within `min_age`.
+    pub fn prune_build_cache(&self, min_age: Option<Duration>, keep: Byte) -> io::Result<()> {
+        match self.runtime {
+            Runtime::Docker => docker::prune_build_cache(self, min_age, keep),
+            Runtime::Containerd | Runtime::Cri => Err(io::Error::other(format!(
+                "The build cache isn't supported for {}.",
+                self.runtime.program().code_str(),
+            ))),
+        }
+    }
+
+    // Delete a volume.
+    pub fn delete_volume(&self, volume: &str) -> io::Result<()> {
+        self.ensure_volumes_and_networks_supported()?;
+        docker::delete_volume(self, volume)
+    }
+
+    // Determine which network, if any, had a container connected to or disconnected from it
+    // according to a line of output from the events process.
+    pub fn used_network(&self, line: &str) -> Option<String> {
+        match self.runtime {
+            Runtime::Docker => docker::used_network(line),
+            Runtime::Containerd | Runtime::Cri => None,
+        }
+    }
+
+    // Ask the container runtime for the user-defined networks which have no containers connected to
+    // them, along with when they were created.
+    pub fn unused_networks(&self) -> io::Result<HashMap<String, Duration>> {
+        self.ensure_volumes_and_networks_supported()?;
+        docker::unused_networks(self)
+    }
+
+    // Delete a network.
+    pub fn delete_network(&self, network: &str) -> io::Result<()> {
+        self.ensure_volumes_and_networks_supported()?;
+        docker::delete_network(self, network)
+    }
+}
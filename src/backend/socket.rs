@@ -0,0 +1,178 @@
+use {
+    crate::backend::{context, Endpoint},
+    serde::Deserialize,
+    std::{
+        env,
+        fs::read_to_string,
+        path::{Path, PathBuf},
+    },
+};
+
+#[cfg(target_os = "macos")]
+use std::os::unix::net::UnixStream;
+
+#[cfg(windows)]
+use std::fs::OpenOptions;
+
+// The socket which the Docker CLI uses by default
+#[cfg(target_os = "macos")]
+const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+#[cfg(windows)]
+const DEFAULT_SOCKET: &str = r"\\.\pipe\docker_engine";
+
+// Where Docker Desktop, Colima, Lima, and Rancher Desktop put their Docker sockets, relative to the
+// home directory, in order of preference. Docker Desktop only links its socket to the default
+// location if it's allowed to, and the others never do.
+#[cfg(target_os = "macos")]
+const CANDIDATE_SOCKETS: &[&str] = &[
+    ".docker/run/docker.sock",
+    ".colima/default/docker.sock",
+    ".config/colima/default/docker.sock",
+    ".lima/docker/sock/docker.sock",
+    ".rd/docker.sock",
+];
+
+// The named pipes of Docker Desktop's Linux and Windows engines. Docker Desktop normally forwards
+// the default pipe to whichever engine is selected, but a standalone daemon for Windows containers
+// may own the default pipe instead, or it may not exist at all while Docker Desktop is switching.
+#[cfg(windows)]
+const CANDIDATE_SOCKETS: &[&str] = &[
+    r"\\.\pipe\dockerDesktopLinuxEngine",
+    r"\\.\pipe\dockerDesktopWindowsEngine",
+];
+
+// The part of the Docker CLI's `config.json` which selects a context
+#[derive(Deserialize, Debug)]
+struct Config {
+    #[serde(rename = "currentContext")]
+    current_context: Option<String>,
+}
+
+// Determine whether the Docker CLI has been told which endpoint to use, in which case we shouldn't
+// second-guess it.
+fn configured() -> bool {
+    if env::var_os("DOCKER_HOST").is_some() || env::var_os("DOCKER_CONTEXT").is_some() {
+        return true;
+    }
+
+    context::config_dir()
+        .and_then(|config_dir| read_to_string(config_dir.join("config.json")).ok())
+        .and_then(|json| serde_json::from_str::<Config>(&json).ok())
+        .and_then(|config| config.current_context)
+        .is_some_and(|current_context| current_context != context::DEFAULT_CONTEXT)
+}
+
+// The sockets to look for, in order of preference
+#[cfg(target_os = "macos")]
+fn candidate_sockets() -> Vec<PathBuf> {
+    dirs::home_dir().map_or_else(Vec::new, |home| {
+        CANDIDATE_SOCKETS
+            .iter()
+            .map(|socket| home.join(socket))
+            .collect()
+    })
+}
+
+#[cfg(windows)]
+fn candidate_sockets() -> Vec<PathBuf> {
+    CANDIDATE_SOCKETS.iter().map(PathBuf::from).collect()
+}
+
+// Find the first socket which is accepting connections.
+fn find_socket(candidates: Vec<PathBuf>, accepting: impl Fn(&Path) -> bool) -> Option<PathBuf> {
+    candidates.into_iter().find(|socket| accepting(socket))
+}
+
+// Determine whether a socket is accepting connections. A socket file can outlive the VM which was
+// listening on it, so its existence isn't enough.
+#[cfg(target_os = "macos")]
+fn accepting(socket: &Path) -> bool {
+    UnixStream::connect(socket).is_ok()
+}
+
+// Determine whether a named pipe is accepting connections. Opening a named pipe connects to it.
+#[cfg(windows)]
+fn accepting(socket: &Path) -> bool {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(socket)
+        .is_ok()
+}
+
+// The address of a socket in the form the Docker CLI expects
+#[cfg(target_os = "macos")]
+fn host(socket: &Path) -> String {
+    format!("unix://{}", socket.to_string_lossy())
+}
+
+// The address of a named pipe in the form the Docker CLI expects, e.g.,
+// `npipe:////./pipe/docker_engine`
+#[cfg(windows)]
+fn host(socket: &Path) -> String {
+    format!("npipe://{}", socket.to_string_lossy().replace('\\', "/"))
+}
+
+// If the Docker CLI would use the default socket but nothing is listening on it, look for a socket
+// in the other places where Docker is commonly found.
+pub fn autodetect() -> Option<Endpoint> {
+    if configured() || accepting(Path::new(DEFAULT_SOCKET)) {
+        return None;
+    }
+
+    find_socket(candidate_sockets(), accepting).map(|socket| Endpoint {
+        host: host(&socket),
+        cert_path: None,
+        tls_verify: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{find_socket, host},
+        std::path::{Path, PathBuf},
+    };
+
+    fn candidates() -> Vec<PathBuf> {
+        vec![
+            PathBuf::from("/Users/alice/.docker/run/docker.sock"),
+            PathBuf::from("/Users/alice/.colima/default/docker.sock"),
+            PathBuf::from("/Users/alice/.rd/docker.sock"),
+        ]
+    }
+
+    #[test]
+    fn find_socket_none() {
+        assert_eq!(find_socket(candidates(), |_| false), None);
+    }
+
+    #[test]
+    fn find_socket_preference() {
+        assert_eq!(
+            find_socket(candidates(), |socket| {
+                socket.ends_with(".colima/default/docker.sock")
+                    || socket.ends_with(".rd/docker.sock")
+            }),
+            Some(PathBuf::from("/Users/alice/.colima/default/docker.sock")),
+        );
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn host_socket() {
+        assert_eq!(
+            host(Path::new("/Users/alice/.rd/docker.sock")),
+            "unix:///Users/alice/.rd/docker.sock",
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn host_named_pipe() {
+        assert_eq!(
+            host(Path::new(r"\\.\pipe\dockerDesktopLinuxEngine")),
+            "npipe:////./pipe/dockerDesktopLinuxEngine",
+        );
+    }
+}
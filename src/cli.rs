@@ -0,0 +1,2571 @@
+use {
+    crate::{
+        backend::{Backend, NestedDaemon, Runtime},
+        config, control,
+        dind::NestedDaemons,
+        event, eviction,
+        format::{CodeStr, Output},
+        grpc, health, log_file, logging, ls, pause,
+        pipeline::{self, Stage, StageKind},
+        plugin::Plugin,
+        reload, restore, retry,
+        run::{run, run_once, KubeletLimits},
+        score, service, state,
+        state::State,
+        stats, systemd, telemetry, tui, EndpointSettings, Settings, Threshold,
+        DEFAULT_DELETION_CHUNK_SIZE, DEFAULT_HISTORY_MAX_AGE, DEFAULT_INSPECT_CONCURRENCY,
+        DEFAULT_POLICY, DEFAULT_POLL_INTERVAL, DEFAULT_RETRY_INITIAL_DELAY,
+        DEFAULT_RETRY_MAX_DELAY, DEFAULT_RETRY_MULTIPLIER, DEFAULT_TIE_BREAKS,
+        FREE_THRESHOLD_PREFIX,
+    },
+    atty::Stream,
+    byte_unit::Byte,
+    clap::{App, AppSettings, Arg, ArgMatches, SubCommand},
+    croner::Cron,
+    log::Level,
+    parse_duration::parse,
+    regex::RegexSet,
+    std::{
+        env, io,
+        iter::once,
+        path::{Path, PathBuf},
+        process::exit,
+        str::FromStr,
+        sync::{Arc, Mutex, PoisonError, TryLockError},
+        thread::{scope, sleep},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+};
+
+// The program version
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// When terminating, this is how long we wait for the state to be free (e.g., for a vacuum to
+// finish) so we can save it
+const STATE_SAVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Defaults
+const DEFAULT_BACKEND: &str = "docker";
+const DEFAULT_COLOR: &str = "auto";
+const DEFAULT_LOG_FILE_MAX_SIZE: &str = "10 MiB";
+const DEFAULT_LOG_FORMAT: &str = "text";
+const DEFAULT_LOG_TARGET: &str = "stderr";
+const DEFAULT_OUTPUT: &str = "text";
+const DEFAULT_THRESHOLD: &str = "10 GB";
+
+// The destructors of the additional endpoints, which also need to run upon termination
+#[allow(clippy::type_complexity)]
+static ENDPOINT_DESTRUCTORS: Mutex<Vec<Arc<Mutex<Vec<Box<dyn FnOnce() + Send>>>>>> =
+    Mutex::new(Vec::new());
+
+// Command-line argument and option names
+const ARCHIVE_DIR_OPTION: &str = "archive-dir";
+const ARCHIVE_MAX_SIZE_OPTION: &str = "archive-max-size";
+const BACKEND_OPTION: &str = "backend";
+const COLOR_OPTION: &str = "color";
+const CONFIG_OPTION: &str = "config";
+const CONTEXT_OPTION: &str = "context";
+const CONTROL_SOCKET_OPTION: &str = "control-socket";
+const COORDINATE_WITH_KUBELET_OPTION: &str = "coordinate-with-kubelet";
+const COUNT_CONTAINER_LAYERS_OPTION: &str = "count-container-layers";
+const COUNT_VOLUMES_OPTION: &str = "count-volumes";
+const CRITICAL_THRESHOLD_OPTION: &str = "critical-threshold";
+const DELETION_CHUNK_SIZE_OPTION: &str = "deletion-chunk-size";
+const DIND_INTERVAL_OPTION: &str = "dind-interval";
+const DISK_CHECK_INTERVAL_OPTION: &str = "disk-check-interval";
+const ENDPOINT_OPTION: &str = "endpoint";
+const FORGET_MISSING_AFTER_OPTION: &str = "forget-missing-after";
+const GRPC_ADDR_OPTION: &str = "grpc-addr";
+const HEALTH_ADDR_OPTION: &str = "health-addr";
+const HISTORY_MAX_AGE_OPTION: &str = "history-max-age";
+const HOST_OPTION: &str = "host";
+const INSPECT_CONCURRENCY_OPTION: &str = "inspect-concurrency";
+const INSTALL_SERVICE_OPTION: &str = "install-service";
+const KEEP_OPTION: &str = "keep";
+const KEEP_DIGEST_OPTION: &str = "keep-digest";
+const KEEP_DOCKERFILE_BASES_OPTION: &str = "keep-dockerfile-bases";
+const KEEP_ID_OPTION: &str = "keep-id";
+const KEEP_LABEL_OPTION: &str = "keep-label";
+const KUBERNETES_OPTION: &str = "kubernetes";
+const KUBERNETES_NODE_OPTION: &str = "kubernetes-node";
+const LOG_FILE_OPTION: &str = "log-file";
+const LOG_FILE_MAX_AGE_OPTION: &str = "log-file-max-age";
+const LOG_FILE_MAX_SIZE_OPTION: &str = "log-file-max-size";
+const LOG_FORMAT_OPTION: &str = "log-format";
+const LOG_TARGET_OPTION: &str = "log-target";
+const MAX_AGE_OPTION: &str = "max-age";
+const MAX_IMAGES_OPTION: &str = "max-images";
+const MIN_FREE_INODES_OPTION: &str = "min-free-inodes";
+const MIN_AGE_OPTION: &str = "min-age";
+const ONCE_OPTION: &str = "once";
+const ONLY_REGISTRY_OPTION: &str = "only-registry";
+const OTLP_ENDPOINT_OPTION: &str = "otlp-endpoint";
+const OUTPUT_OPTION: &str = "output";
+const PLUGIN_OPTION: &str = "plugin";
+const POLICY_OPTION: &str = "policy";
+const POLL_INTERVAL_OPTION: &str = "poll-interval";
+const POST_VACUUM_HOOK_OPTION: &str = "post-vacuum-hook";
+const PRE_DELETE_HOOK_OPTION: &str = "pre-delete-hook";
+const PROTECT_COMPOSE_PROJECTS_OPTION: &str = "protect-compose-projects";
+const PROTECT_GITLAB_PIPELINES_OPTION: &str = "protect-gitlab-pipelines";
+const PROTECT_REGISTRY_OPTION: &str = "protect-registry";
+const PRUNE_BUILDX_CACHES_OPTION: &str = "prune-buildx-caches";
+const PRUNE_CONTAINERS_OPTION: &str = "prune-containers";
+const PRUNE_NETWORKS_OPTION: &str = "prune-networks";
+const PRUNE_PARENTS_OPTION: &str = "prune-parents";
+const PRUNE_VOLUMES_OPTION: &str = "prune-volumes";
+const PULL_GRACE_OPTION: &str = "pull-grace";
+const REMOVE_ONLY_OPTION: &str = "remove-only";
+const RETRY_INITIAL_DELAY_OPTION: &str = "retry-initial-delay";
+const RETRY_MAX_DELAY_OPTION: &str = "retry-max-delay";
+const RETRY_MULTIPLIER_OPTION: &str = "retry-multiplier";
+const SCHEDULE_OPTION: &str = "schedule";
+const SCORE_OPTION: &str = "score";
+const SERVICE_OPTION: &str = "service";
+const STATE_FILE_OPTION: &str = "state-file";
+const STATSD_ADDR_OPTION: &str = "statsd-addr";
+const TARGET_OPTION: &str = "target";
+const THRESHOLD_OPTION: &str = "threshold";
+const TIE_BREAK_OPTION: &str = "tie-break";
+const UNINSTALL_SERVICE_OPTION: &str = "uninstall-service";
+const UNTAG_OPTION: &str = "untag";
+const WEBHOOK_URL_OPTION: &str = "webhook-url";
+
+// Subcommand names
+const CONTROL_SUBCOMMAND: &str = "control";
+const EXPLAIN_SUBCOMMAND: &str = "explain";
+const EXPORT_SUBCOMMAND: &str = "export";
+const IMPORT_SUBCOMMAND: &str = "import";
+const LS_SUBCOMMAND: &str = "ls";
+const PAUSE_SUBCOMMAND: &str = "pause";
+const RESTORE_SUBCOMMAND: &str = "restore";
+const RESUME_SUBCOMMAND: &str = "resume";
+const STATE_SUBCOMMAND: &str = "state";
+const STATS_SUBCOMMAND: &str = "stats";
+const STATUS_SUBCOMMAND: &str = "status";
+const TUI_SUBCOMMAND: &str = "tui";
+const VACUUM_NOW_SUBCOMMAND: &str = "vacuum-now";
+
+// Positional argument names
+const IMAGE_ARGUMENT: &str = "IMAGE";
+
+// Parse the command-line arguments.
+#[allow(clippy::too_many_lines)]
+fn arguments<'a>() -> ArgMatches<'a> {
+    App::new("Docuum")
+        .version(VERSION)
+        .version_short("v")
+        .author("Stephan Boyer <stephan@stephanboyer.com>")
+        .about("Docuum performs LRU cache eviction for Docker images.")
+        .setting(AppSettings::ColoredHelp)
+        .setting(AppSettings::NextLineHelp)
+        .setting(AppSettings::UnifiedHelpMessage)
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name(BACKEND_OPTION)
+                .value_name("BACKEND")
+                .short("b")
+                .long(BACKEND_OPTION)
+                .possible_values(Runtime::NAMES)
+                .help(&format!(
+                    "Sets the container runtime whose images are managed (default: {})",
+                    DEFAULT_BACKEND.code_str(),
+                )),
+        )
+        .arg(
+            Arg::with_name(CONFIG_OPTION)
+                .value_name("PATH")
+                .short("c")
+                .long(CONFIG_OPTION)
+                .help(
+                    "Loads settings from a YAML or TOML configuration file (command-line options \
+                        take precedence)",
+                ),
+        )
+        .arg(
+            Arg::with_name(CONTEXT_OPTION)
+                .value_name("CONTEXT")
+                .long(CONTEXT_OPTION)
+                .help("Connects to the endpoint of the named Docker context"),
+        )
+        .arg(
+            Arg::with_name(HOST_OPTION)
+                .value_name("HOST")
+                .short("H")
+                .long(HOST_OPTION)
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with(CONTEXT_OPTION)
+                .help(
+                    "Connects to the container runtime at <HOST> (e.g., unix:///run/docker.sock or \
+                        npipe:////./pipe/docker_engine); if given multiple times, connects to the \
+                        first reachable one",
+                ),
+        )
+        .arg(
+            Arg::with_name(ENDPOINT_OPTION)
+                .value_name("NAME=HOST")
+                .long(ENDPOINT_OPTION)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Also manages the container runtime at <HOST>, with its own state named after \
+                        <NAME>",
+                ),
+        )
+        .arg(
+            Arg::with_name(DIND_INTERVAL_OPTION)
+                .value_name("DURATION")
+                .long(DIND_INTERVAL_OPTION)
+                .help(
+                    "Also vacuums the Docker daemons in privileged Docker-in-Docker containers, \
+                        looking for them every <DURATION>",
+                ),
+        )
+        .arg(
+            Arg::with_name(THRESHOLD_OPTION)
+                .value_name("THRESHOLD")
+                .short("t")
+                .long(THRESHOLD_OPTION)
+                .help(&format!(
+                    "Sets the maximum amount of space to be used for Docker images, or the amount \
+                        of space to keep free with a {} prefix (default: {})",
+                    FREE_THRESHOLD_PREFIX.code_str(),
+                    DEFAULT_THRESHOLD.code_str(),
+                )),
+        )
+        .arg(
+            Arg::with_name(TARGET_OPTION)
+                .value_name("TARGET")
+                .long(TARGET_OPTION)
+                .help(
+                    "Once the threshold has been exceeded, deletes images until the space usage is \
+                        down to <TARGET> (in the same format as the threshold) rather than just \
+                        within the threshold",
+                ),
+        )
+        .arg(
+            Arg::with_name(CRITICAL_THRESHOLD_OPTION)
+                .value_name("THRESHOLD")
+                .long(CRITICAL_THRESHOLD_OPTION)
+                .help(
+                    "When the space usage is over <THRESHOLD> even after a vacuum, also deletes \
+                        images which only the keep rules and --min-age protect, except those in \
+                        use by containers",
+                ),
+        )
+        .arg(
+            Arg::with_name(POLICY_OPTION)
+                .value_name("POLICY")
+                .long(POLICY_OPTION)
+                .possible_values(eviction::Policy::NAMES)
+                .help(&format!(
+                    "Sets the order in which images are deleted (default: {})",
+                    DEFAULT_POLICY.code_str(),
+                )),
+        )
+        .arg(
+            Arg::with_name(SCORE_OPTION)
+                .value_name("EXPRESSION")
+                .long(SCORE_OPTION)
+                .conflicts_with(POLICY_OPTION)
+                .help(
+                    "Deletes images with the highest <EXPRESSION> first, e.g., \"age_days * \
+                        size_gb\", rather than using a built-in policy",
+                ),
+        )
+        .arg(
+            Arg::with_name(TIE_BREAK_OPTION)
+                .value_name("TIE BREAK")
+                .long(TIE_BREAK_OPTION)
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(eviction::TieBreak::NAMES)
+                .help(&format!(
+                    "Sets the order in which images are deleted when they were last used at the \
+                        same time, applying each <TIE BREAK> in turn (default: {})",
+                    DEFAULT_TIE_BREAKS.join(" ").code_str(),
+                )),
+        )
+        .arg(
+            Arg::with_name(KEEP_OPTION)
+                .value_name("REGEX")
+                .short("k")
+                .long(KEEP_OPTION)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Prevents deletion of images for which repository:tag matches <REGEX>"),
+        )
+        .arg(
+            Arg::with_name(REMOVE_ONLY_OPTION)
+                .value_name("REGEX")
+                .long(REMOVE_ONLY_OPTION)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Only deletes images for which repository:tag matches <REGEX>, keeping all \
+                        others",
+                ),
+        )
+        .arg(
+            Arg::with_name(UNTAG_OPTION)
+                .long(UNTAG_OPTION)
+                .help(
+                    "For images with tags that --keep or --remove-only protect and tags they \
+                        don't, removes the unprotected tags rather than keeping or deleting the \
+                        whole image",
+                ),
+        )
+        .arg(
+            Arg::with_name(KEEP_LABEL_OPTION)
+                .value_name("KEY=VALUE")
+                .long(KEEP_LABEL_OPTION)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Prevents deletion of images which have the label <KEY> set to <VALUE>"),
+        )
+        .arg(
+            Arg::with_name(KEEP_DIGEST_OPTION)
+                .value_name("DIGEST")
+                .long(KEEP_DIGEST_OPTION)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Prevents deletion of images with the content digest <DIGEST>, given as \
+                        repository@sha256:... or just sha256:...",
+                ),
+        )
+        .arg(
+            Arg::with_name(KEEP_DOCKERFILE_BASES_OPTION)
+                .value_name("DIR")
+                .long(KEEP_DOCKERFILE_BASES_OPTION)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Prevents deletion of the images which the Dockerfiles in <DIR> (or its \
+                        subdirectories) start from",
+                ),
+        )
+        .arg(
+            Arg::with_name(KEEP_ID_OPTION)
+                .value_name("ID")
+                .long(KEEP_ID_OPTION)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Prevents deletion of the image with the given (possibly short) ID"),
+        )
+        .arg(
+            Arg::with_name(PROTECT_REGISTRY_OPTION)
+                .value_name("HOST")
+                .long(PROTECT_REGISTRY_OPTION)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Prevents deletion of images from the registry <HOST>, e.g., \
+                        registry.example.com:5000 (use docker.io for Docker Hub)",
+                ),
+        )
+        .arg(
+            Arg::with_name(ONLY_REGISTRY_OPTION)
+                .value_name("HOST")
+                .long(ONLY_REGISTRY_OPTION)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Only deletes images from the registry <HOST> (use docker.io for Docker Hub), \
+                        keeping all others",
+                ),
+        )
+        .arg(
+            Arg::with_name(KUBERNETES_OPTION)
+                .long(KUBERNETES_OPTION)
+                .help(
+                    "Runs as a Kubernetes DaemonSet: never deletes pinned or pause images, and \
+                        uses the kubelet's image garbage collection thresholds unless \
+                        --threshold is given (requires --kubernetes-node)",
+                ),
+        )
+        .arg(
+            Arg::with_name(KUBERNETES_NODE_OPTION)
+                .value_name("NODE")
+                .long(KUBERNETES_NODE_OPTION)
+                .env("DOCUUM_KUBERNETES_NODE")
+                .help(
+                    "Treats the images of the pods on Kubernetes node <NODE> as in use (requires \
+                        kubectl)",
+                ),
+        )
+        .arg(
+            Arg::with_name(COORDINATE_WITH_KUBELET_OPTION)
+                .long(COORDINATE_WITH_KUBELET_OPTION)
+                .help(
+                    "Keeps the disk usage strictly below the image garbage collection and eviction \
+                        thresholds of the kubelet (requires --kubernetes-node)",
+                ),
+        )
+        .arg(
+            Arg::with_name(DELETION_CHUNK_SIZE_OPTION)
+                .value_name("DELETION CHUNK SIZE")
+                .short("d")
+                .long(DELETION_CHUNK_SIZE_OPTION)
+                .help(&format!(
+                    "Removes specified quantity of images at a time \
+                        (default: {DEFAULT_DELETION_CHUNK_SIZE})",
+                )),
+        )
+        .arg(
+            Arg::with_name(INSPECT_CONCURRENCY_OPTION)
+                .value_name("N")
+                .long(INSPECT_CONCURRENCY_OPTION)
+                .help(&format!(
+                    "Inspects up to <N> batches of images at a time when listing them \
+                        (default: {DEFAULT_INSPECT_CONCURRENCY})",
+                )),
+        )
+        .arg(
+            Arg::with_name(MIN_AGE_OPTION)
+                .value_name("MIN AGE")
+                .short("m")
+                .long(MIN_AGE_OPTION)
+                .help("Sets the minimum age of images to be considered for deletion"),
+        )
+        .arg(
+            Arg::with_name(PULL_GRACE_OPTION)
+                .value_name("DURATION")
+                .long(PULL_GRACE_OPTION)
+                .help(
+                    "Prevents deletion of images which were pulled or built less than <DURATION> \
+                        ago, even if the threshold has been exceeded",
+                ),
+        )
+        .arg(
+            Arg::with_name(LOG_FORMAT_OPTION)
+                .value_name("FORMAT")
+                .long(LOG_FORMAT_OPTION)
+                .possible_values(logging::Format::NAMES)
+                .help(&format!(
+                    "Sets the format of the log (default: {DEFAULT_LOG_FORMAT})",
+                )),
+        )
+        .arg(
+            Arg::with_name(COLOR_OPTION)
+                .value_name("WHEN")
+                .long(COLOR_OPTION)
+                .possible_values(logging::ColorMode::NAMES)
+                .help(&format!(
+                    "Sets whether the log is colored (default: {DEFAULT_COLOR})",
+                )),
+        )
+        .arg(
+            Arg::with_name(LOG_TARGET_OPTION)
+                .value_name("TARGET")
+                .long(LOG_TARGET_OPTION)
+                .possible_values(logging::Target::NAMES)
+                .help(&format!(
+                    "Sets where the log is written (default: {DEFAULT_LOG_TARGET})",
+                )),
+        )
+        .arg(
+            Arg::with_name(LOG_FILE_OPTION)
+                .value_name("PATH")
+                .long(LOG_FILE_OPTION)
+                .conflicts_with(LOG_TARGET_OPTION)
+                .help("Writes the log to a file rather than standard error"),
+        )
+        .arg(
+            Arg::with_name(LOG_FILE_MAX_SIZE_OPTION)
+                .value_name("SIZE")
+                .long(LOG_FILE_MAX_SIZE_OPTION)
+                .requires(LOG_FILE_OPTION)
+                .help(&format!(
+                    "Rotates the log file when it reaches this size \
+                        (default: {DEFAULT_LOG_FILE_MAX_SIZE})",
+                )),
+        )
+        .arg(
+            Arg::with_name(LOG_FILE_MAX_AGE_OPTION)
+                .value_name("DURATION")
+                .long(LOG_FILE_MAX_AGE_OPTION)
+                .requires(LOG_FILE_OPTION)
+                .help("Rotates the log file when it reaches this age"),
+        )
+        .arg(
+            Arg::with_name(MAX_AGE_OPTION)
+                .value_name("MAX AGE")
+                .long(MAX_AGE_OPTION)
+                .help(
+                    "Deletes images which haven't been used for longer than <MAX AGE>, even if \
+                        the threshold hasn't been reached",
+                ),
+        )
+        .arg(
+            Arg::with_name(MAX_IMAGES_OPTION)
+                .value_name("MAX IMAGES")
+                .long(MAX_IMAGES_OPTION)
+                .help(
+                    "Deletes the least recently used images when there are more than \
+                        <MAX IMAGES> images, even if the threshold hasn't been reached",
+                ),
+        )
+        .arg(
+            Arg::with_name(MIN_FREE_INODES_OPTION)
+                .value_name("INODES")
+                .long(MIN_FREE_INODES_OPTION)
+                .help(
+                    "Deletes the least recently used images when fewer than <INODES> inodes are \
+                        free on the filesystem of the container runtime, even if the threshold \
+                        hasn't been reached (Linux only)",
+                ),
+        )
+        .arg(
+            Arg::with_name(ONCE_OPTION)
+                .long(ONCE_OPTION)
+                .help("Performs a single vacuum and exits rather than listening for Docker events"),
+        )
+        .arg(
+            Arg::with_name(POLL_INTERVAL_OPTION)
+                .value_name("DURATION")
+                .long(POLL_INTERVAL_OPTION)
+                .help(&format!(
+                    "Sets how often the container runtime is polled if it can't stream events \
+                        (default: {DEFAULT_POLL_INTERVAL})",
+                )),
+        )
+        .arg(
+            Arg::with_name(DISK_CHECK_INTERVAL_OPTION)
+                .value_name("DURATION")
+                .long(DISK_CHECK_INTERVAL_OPTION)
+                .help(
+                    "Also checks the space usage every <DURATION> and vacuums if it's over the \
+                        threshold, even if no Docker events arrive",
+                ),
+        )
+        .arg(
+            Arg::with_name(SCHEDULE_OPTION)
+                .value_name("CRON EXPRESSION")
+                .long(SCHEDULE_OPTION)
+                .help(
+                    "Also vacuums on a schedule given by a cron expression such as \
+                        \"0 3 * * *\", in local time",
+                ),
+        )
+        .arg(
+            Arg::with_name(PROTECT_COMPOSE_PROJECTS_OPTION)
+                .value_name("IDLE PERIOD")
+                .long(PROTECT_COMPOSE_PROJECTS_OPTION)
+                .help(
+                    "Treats the images of each Docker Compose project as in use until the project \
+                        has had no containers for longer than <IDLE PERIOD>",
+                ),
+        )
+        .arg(
+            Arg::with_name(PROTECT_GITLAB_PIPELINES_OPTION)
+                .value_name("IDLE PERIOD")
+                .long(PROTECT_GITLAB_PIPELINES_OPTION)
+                .help(
+                    "Treats the images of the jobs of each GitLab CI pipeline as in use until the \
+                        pipeline has had no containers for longer than <IDLE PERIOD>",
+                ),
+        )
+        .arg(
+            Arg::with_name(PRUNE_CONTAINERS_OPTION)
+                .value_name("AGE")
+                .long(PRUNE_CONTAINERS_OPTION)
+                .help(
+                    "Deletes containers which stopped more than <AGE> ago before deleting images",
+                ),
+        )
+        .arg(
+            Arg::with_name(PRUNE_NETWORKS_OPTION)
+                .value_name("IDLE PERIOD")
+                .long(PRUNE_NETWORKS_OPTION)
+                .help(
+                    "Deletes user-defined networks which have had no containers connected to them \
+                        for longer than <IDLE PERIOD>",
+                ),
+        )
+        .arg(
+            Arg::with_name(PRUNE_PARENTS_OPTION)
+                .long(PRUNE_PARENTS_OPTION)
+                .help(
+                    "Also lets Docker delete the parents of deleted images which are left without \
+                        tags or children",
+                ),
+        )
+        .arg(
+            Arg::with_name(PRUNE_VOLUMES_OPTION)
+                .long(PRUNE_VOLUMES_OPTION)
+                .help(
+                    "Also deletes unused anonymous volumes, which then count toward the threshold",
+                ),
+        )
+        .arg(
+            Arg::with_name(PRUNE_BUILDX_CACHES_OPTION)
+                .long(PRUNE_BUILDX_CACHES_OPTION)
+                .help(
+                    "Also prunes the caches of Docker Buildx builders, which then count toward the \
+                        threshold",
+                ),
+        )
+        .arg(
+            Arg::with_name(COUNT_CONTAINER_LAYERS_OPTION)
+                .long(COUNT_CONTAINER_LAYERS_OPTION)
+                .help(
+                    "Counts the writable layers of containers toward the threshold, even though \
+                        they aren't deleted",
+                ),
+        )
+        .arg(
+            Arg::with_name(COUNT_VOLUMES_OPTION)
+                .long(COUNT_VOLUMES_OPTION)
+                .help(
+                    "Counts the space used by volumes toward the threshold, even though they \
+                        aren't deleted",
+                ),
+        )
+        .arg(
+            Arg::with_name(FORGET_MISSING_AFTER_OPTION)
+                .value_name("DURATION")
+                .long(FORGET_MISSING_AFTER_OPTION)
+                .help(
+                    "Remembers images which no longer exist until they haven't been used for \
+                        <DURATION>, rather than forgetting them at the next vacuum",
+                ),
+        )
+        .arg(
+            Arg::with_name(RETRY_INITIAL_DELAY_OPTION)
+                .value_name("DURATION")
+                .long(RETRY_INITIAL_DELAY_OPTION)
+                .help(&format!(
+                    "Sets how long to wait before retrying after an error \
+                        (default: {DEFAULT_RETRY_INITIAL_DELAY})",
+                )),
+        )
+        .arg(
+            Arg::with_name(RETRY_MULTIPLIER_OPTION)
+                .value_name("MULTIPLIER")
+                .long(RETRY_MULTIPLIER_OPTION)
+                .help(&format!(
+                    "Multiplies the delay by <MULTIPLIER> after each consecutive error \
+                        (default: {DEFAULT_RETRY_MULTIPLIER})",
+                )),
+        )
+        .arg(
+            Arg::with_name(RETRY_MAX_DELAY_OPTION)
+                .value_name("DURATION")
+                .long(RETRY_MAX_DELAY_OPTION)
+                .help(&format!(
+                    "Sets the maximum delay before retrying after an error \
+                        (default: {DEFAULT_RETRY_MAX_DELAY})",
+                )),
+        )
+        .arg(
+            Arg::with_name(GRPC_ADDR_OPTION)
+                .value_name("HOST:PORT")
+                .long(GRPC_ADDR_OPTION)
+                .help(
+                    "Serves the gRPC API on <HOST:PORT> (requires building with the grpc \
+                        feature)",
+                ),
+        )
+        .arg(
+            Arg::with_name(HEALTH_ADDR_OPTION)
+                .value_name("HOST:PORT")
+                .long(HEALTH_ADDR_OPTION)
+                .help(
+                    "Serves /healthz, /status, and what Docuum saw during the last vacuum over \
+                        HTTP on <HOST:PORT>",
+                ),
+        )
+        .arg(
+            Arg::with_name(HISTORY_MAX_AGE_OPTION)
+                .value_name("DURATION")
+                .long(HISTORY_MAX_AGE_OPTION)
+                .help(&format!(
+                    "Forgets deletions older than <DURATION> in the deletion history \
+                        (default: {DEFAULT_HISTORY_MAX_AGE})",
+                )),
+        )
+        .arg(
+            Arg::with_name(INSTALL_SERVICE_OPTION)
+                .long(INSTALL_SERVICE_OPTION)
+                .conflicts_with_all(&[ONCE_OPTION, SERVICE_OPTION, UNINSTALL_SERVICE_OPTION])
+                .help(
+                    "Installs Docuum as a Windows service which runs with the other given \
+                        arguments, then exits",
+                ),
+        )
+        .arg(
+            Arg::with_name(UNINSTALL_SERVICE_OPTION)
+                .long(UNINSTALL_SERVICE_OPTION)
+                .conflicts_with_all(&[ONCE_OPTION, SERVICE_OPTION])
+                .help("Uninstalls the Windows service, then exits"),
+        )
+        .arg(
+            // This is how the Windows service control manager starts Docuum.
+            Arg::with_name(SERVICE_OPTION)
+                .long(SERVICE_OPTION)
+                .hidden(true)
+                .conflicts_with(ONCE_OPTION),
+        )
+        .arg(
+            Arg::with_name(STATE_FILE_OPTION)
+                .value_name("PATH")
+                .long(STATE_FILE_OPTION)
+                .env("DOCUUM_STATE_FILE")
+                .help("Persists the state in the file at <PATH> rather than the default location"),
+        )
+        .arg(
+            Arg::with_name(CONTROL_SOCKET_OPTION)
+                .value_name("PATH")
+                .long(CONTROL_SOCKET_OPTION)
+                .env("DOCUUM_CONTROL_SOCKET")
+                .help(
+                    "Listens for commands on the Unix domain socket at <PATH> rather than the \
+                        default location",
+                ),
+        )
+        .arg(
+            Arg::with_name(STATSD_ADDR_OPTION)
+                .value_name("HOST:PORT")
+                .long(STATSD_ADDR_OPTION)
+                .help("Sends metrics about each vacuum to a StatsD server over UDP"),
+        )
+        .arg(
+            Arg::with_name(WEBHOOK_URL_OPTION)
+                .value_name("URL")
+                .long(WEBHOOK_URL_OPTION)
+                .help("Posts a JSON report to <URL> after each vacuum"),
+        )
+        .arg(
+            Arg::with_name(OTLP_ENDPOINT_OPTION)
+                .value_name("URL")
+                .long(OTLP_ENDPOINT_OPTION)
+                .help(
+                    "Exports traces of each vacuum to the OpenTelemetry collector at <URL> via \
+                        OTLP/HTTP (requires building with the otel feature)",
+                ),
+        )
+        .arg(
+            Arg::with_name(PRE_DELETE_HOOK_OPTION)
+                .value_name("COMMAND")
+                .long(PRE_DELETE_HOOK_OPTION)
+                .help(
+                    "Runs <COMMAND> before deleting each image, which vetoes the deletion by \
+                        exiting with a non-zero status",
+                ),
+        )
+        .arg(
+            Arg::with_name(ARCHIVE_DIR_OPTION)
+                .value_name("PATH")
+                .long(ARCHIVE_DIR_OPTION)
+                .help(
+                    "Saves each image to a compressed archive in <PATH> before deleting it, like \
+                        docker image save",
+                ),
+        )
+        .arg(
+            Arg::with_name(ARCHIVE_MAX_SIZE_OPTION)
+                .value_name("SIZE")
+                .long(ARCHIVE_MAX_SIZE_OPTION)
+                .help(
+                    "Deletes the oldest archives in the --archive-dir directory when they take up \
+                        more than <SIZE>",
+                ),
+        )
+        .arg(
+            Arg::with_name(POST_VACUUM_HOOK_OPTION)
+                .value_name("COMMAND")
+                .long(POST_VACUUM_HOOK_OPTION)
+                .help("Runs <COMMAND> after each vacuum"),
+        )
+        .arg(
+            Arg::with_name(PLUGIN_OPTION)
+                .value_name("PATH")
+                .long(PLUGIN_OPTION)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Loads a WebAssembly module which can keep images or decide the order in \
+                        which they're deleted (experimental; requires building with the plugins \
+                        feature)",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CONTROL_SUBCOMMAND)
+                .about("Sends a command to the running Docuum, then exits")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name(STATUS_SUBCOMMAND)
+                        .about("Prints the status of the running Docuum as JSON"),
+                )
+                .subcommand(
+                    SubCommand::with_name(VACUUM_NOW_SUBCOMMAND)
+                        .about("Asks the running Docuum to vacuum right away"),
+                )
+                .subcommand(SubCommand::with_name(PAUSE_SUBCOMMAND).about("Pauses deletions"))
+                .subcommand(SubCommand::with_name(RESUME_SUBCOMMAND).about("Resumes deletions"))
+                .subcommand(
+                    SubCommand::with_name(EXPLAIN_SUBCOMMAND)
+                        .about("Explains why an image would or wouldn't be deleted")
+                        .arg(
+                            Arg::with_name(IMAGE_ARGUMENT)
+                                .required(true)
+                                .help("The image to explain, by ID or REPOSITORY:TAG"),
+                        )
+                        .arg(output_argument()),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(LS_SUBCOMMAND)
+                .about("Lists the images in the order in which they would be deleted, then exits")
+                .arg(output_argument()),
+        )
+        .subcommand(
+            SubCommand::with_name(RESTORE_SUBCOMMAND)
+                .about(
+                    "Restores a deleted image from its archive, or prints the commands which pull \
+                        it again, then exits",
+                )
+                .arg(
+                    Arg::with_name(IMAGE_ARGUMENT)
+                        .required(true)
+                        .help("The image to restore, by ID or REPOSITORY:TAG"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(STATE_SUBCOMMAND)
+                .about("Exports or imports the state as JSON, then exits")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name(EXPORT_SUBCOMMAND)
+                        .about("Writes the state to standard output"),
+                )
+                .subcommand(
+                    SubCommand::with_name(IMPORT_SUBCOMMAND)
+                        .about("Replaces the state with the one read from standard input"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(STATS_SUBCOMMAND)
+                .about("Prints a summary of the state and the current space usage, then exits")
+                .arg(output_argument()),
+        )
+        .subcommand(
+            SubCommand::with_name(TUI_SUBCOMMAND).about(
+                "Shows a live dashboard of the images, where they can be pinned or deleted \
+                    (requires building with the tui feature)",
+            ),
+        )
+        .get_matches()
+}
+
+// The argument which sets how a subcommand prints its results
+fn output_argument() -> Arg<'static, 'static> {
+    Arg::with_name(OUTPUT_OPTION)
+        .value_name("FORMAT")
+        .long(OUTPUT_OPTION)
+        .possible_values(Output::NAMES)
+        .help("Sets the format of the output (default: text)")
+}
+
+// Determine how a subcommand prints its results. Clap only allows valid values.
+fn output(matches: &ArgMatches) -> Output {
+    Output::from_str(matches.value_of(OUTPUT_OPTION).unwrap_or(DEFAULT_OUTPUT))
+        .unwrap_or(Output::Text)
+}
+
+// Parse a label of the form `KEY=VALUE`.
+fn parse_label(label: &str) -> io::Result<(String, String)> {
+    label
+        .split_once('=')
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Invalid label {}. Expected {}.",
+                    label.code_str(),
+                    "KEY=VALUE".code_str(),
+                ),
+            )
+        })
+}
+
+// Parse an additional endpoint for the `--endpoint` option. The name is used in the name of the
+// endpoint's state file, so it's restricted to characters which are safe there.
+fn parse_endpoint(runtime: Runtime, endpoint: &str) -> io::Result<(String, Backend)> {
+    match endpoint.split_once('=') {
+        Some((name, host))
+            if !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+        {
+            Ok((name.to_owned(), Backend::from_host(runtime, host)?))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Invalid endpoint {}. Expected {}, where the name consists of letters, digits, \
+                    hyphens, and underscores.",
+                endpoint.code_str(),
+                "NAME=HOST".code_str(),
+            ),
+        )),
+    }
+}
+
+// Check a digest for the `--keep-digest` option, which is either a repository digest like
+// `alpine@sha256:0123…` or a bare digest like `sha256:0123…`.
+fn parse_digest(keep_digest: &str) -> io::Result<String> {
+    let digest = keep_digest
+        .rsplit_once('@')
+        .map_or(keep_digest, |(_, digest)| digest);
+    match digest.split_once(':') {
+        Some((algorithm, hex)) if !algorithm.is_empty() && !hex.is_empty() => {
+            Ok(keep_digest.to_owned())
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Invalid digest {}. Expected {} or {}.",
+                keep_digest.code_str(),
+                "REPOSITORY@ALGORITHM:HEX".code_str(),
+                "ALGORITHM:HEX".code_str(),
+            ),
+        )),
+    }
+}
+
+// Check a registry for the `--protect-registry` and `--only-registry` options, which is a host with
+// an optional port, like `registry.example.com:5000`.
+fn parse_registry(protect_registry: &str) -> io::Result<String> {
+    if protect_registry.is_empty() || protect_registry.contains(['/', '@']) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Invalid registry {}. Expected a host like {}.",
+                protect_registry.code_str(),
+                "registry.example.com:5000".code_str(),
+            ),
+        ));
+    }
+
+    Ok(protect_registry.to_ascii_lowercase())
+}
+
+// Check an image ID for the `--keep-id` option, which may be short (e.g., `0123456789ab`) and may
+// include the algorithm (e.g., `sha256:0123456789ab…`).
+fn parse_image_id(keep_id: &str) -> io::Result<String> {
+    let hex = keep_id.split_once(':').map_or(keep_id, |(_, hex)| hex);
+    if hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid image ID {}.", keep_id.code_str()),
+        ));
+    }
+
+    Ok(keep_id.to_ascii_lowercase())
+}
+
+// Parse an optional duration, such as `4 days` or `1 hour`.
+fn parse_duration(value: Option<&str>) -> io::Result<Option<Duration>> {
+    value
+        .map(|value| parse(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)))
+        .transpose()
+}
+
+// Parse the cleanup pipeline from the configuration file. It takes the place of the flags which add
+// stages to the default pipeline, given as pairs of whether they're set and their names.
+fn pipeline_stages(
+    backend: &Backend,
+    stages: &[config::StageConfig],
+    flags: &[(bool, &str)],
+) -> io::Result<Vec<Stage>> {
+    if let Some((_, option)) = flags.iter().find(|(set, _)| *set) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} can't be combined with a pipeline in the configuration file. Add a stage to \
+                    the pipeline instead.",
+                format!("--{option}").code_str(),
+            ),
+        ));
+    }
+
+    stages
+        .iter()
+        .map(|stage| {
+            let kind = StageKind::from_str(&stage.stage)?;
+            let supported = match kind {
+                StageKind::Containers => backend.supports_containers(),
+                StageKind::BuildCache | StageKind::Volumes => {
+                    backend.supports_volumes_and_networks()
+                }
+                StageKind::BuildxCaches => backend.supports_buildx_builders(),
+                StageKind::DanglingImages | StageKind::Images => true,
+            };
+            if !supported {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "The {} stage of the pipeline isn't supported by this backend.",
+                        stage.stage.code_str(),
+                    ),
+                ));
+            }
+
+            let keep = stage
+                .keep
+                .as_deref()
+                .map(|keep| {
+                    if !kind.is_cache() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!(
+                                "Only the stages which prune caches can keep some of them, not {}.",
+                                stage.stage.code_str(),
+                            ),
+                        ));
+                    }
+
+                    Byte::from_str(keep).map_err(|_| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Invalid amount of space to keep {}.", keep.code_str()),
+                        )
+                    })
+                })
+                .transpose()?;
+
+            Ok(Stage {
+                kind,
+                min_age: parse_duration(stage.min_age.as_deref())?,
+                keep,
+            })
+        })
+        .collect()
+}
+
+// Open the log file with the rotation limits from the command-line arguments and the configuration
+// file.
+fn log_file(
+    matches: &ArgMatches,
+    config: &config::Config,
+    path: &Path,
+) -> io::Result<log_file::RotatingFile> {
+    let max_size_str = matches
+        .value_of(LOG_FILE_MAX_SIZE_OPTION)
+        .or(config.log_file_max_size.as_deref())
+        .unwrap_or(DEFAULT_LOG_FILE_MAX_SIZE);
+    let max_size = Byte::from_str(max_size_str).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid log file size {}.", max_size_str.code_str()),
+        )
+    })?;
+    let max_size = u64::try_from(max_size.get_bytes()).unwrap_or(u64::MAX);
+    let max_age = parse_duration(
+        matches
+            .value_of(LOG_FILE_MAX_AGE_OPTION)
+            .or(config.log_file_max_age.as_deref()),
+    )?;
+
+    log_file::RotatingFile::new(path, max_size, max_age)
+}
+
+// Resolve the path of the state file relative to the working directory, and check that it names a
+// file.
+fn state_file(path: &Path) -> io::Result<PathBuf> {
+    let path = env::current_dir()?.join(path);
+
+    // This ensures the path has a parent [ref:state_path_has_parent].
+    if path.file_name().is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid state file {}.", path.to_string_lossy().code_str()),
+        ));
+    }
+
+    Ok(path)
+}
+
+// Determine where the control socket is, and whether it was given explicitly on the command line or
+// in the configuration file. Like the state file, it's resolved relative to the working directory.
+fn control_socket(matches: &ArgMatches) -> io::Result<(Option<PathBuf>, bool)> {
+    let config = config::load(matches.value_of(CONFIG_OPTION).map(Path::new))?;
+
+    match matches
+        .value_of_os(CONTROL_SOCKET_OPTION)
+        .map(Path::new)
+        .or(config.control_socket.as_deref().map(Path::new))
+    {
+        Some(path) => Ok((Some(env::current_dir()?.join(path)), true)),
+        None => Ok((control::default_path(), false)),
+    }
+}
+
+// Determine the settings from the command-line arguments and the configuration file.
+#[allow(clippy::too_many_lines)]
+fn settings(matches: &ArgMatches) -> io::Result<Settings> {
+    // Load the configuration file, if there is one.
+    let config = config::load(matches.value_of(CONFIG_OPTION).map(Path::new))?;
+
+    // Determine which container runtime to manage and how to connect to it.
+    let runtime = Runtime::from_str(
+        matches
+            .value_of(BACKEND_OPTION)
+            .or(config.backend.as_deref())
+            .unwrap_or(DEFAULT_BACKEND),
+    )?;
+    let hosts = matches.values_of(HOST_OPTION).map_or_else(
+        || config.host.unwrap_or_default(),
+        |values| values.map(ToOwned::to_owned).collect(),
+    );
+    let backend_candidates = match (
+        matches
+            .value_of(CONTEXT_OPTION)
+            .or(config.context.as_deref()),
+        &hosts[..],
+    ) {
+        (Some(_), [_, ..]) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} and {} can't be used together.",
+                    format!("--{CONTEXT_OPTION}").code_str(),
+                    format!("--{HOST_OPTION}").code_str(),
+                ),
+            ));
+        }
+        (Some(context), []) => vec![Backend::from_context(runtime, context)?],
+        (None, []) => vec![Backend::local(runtime)],
+        (None, hosts) => hosts
+            .iter()
+            .map(|host| Backend::from_host(runtime, host))
+            .collect::<io::Result<_>>()?,
+    };
+    let backend = Backend::first_reachable(&backend_candidates);
+
+    // Determine which other endpoints to manage alongside the main one, and which settings are
+    // overridden for them.
+    let mut endpoint_configs = config.endpoint_settings.unwrap_or_default();
+    let mut endpoints = Vec::<EndpointSettings>::new();
+    for endpoint in matches.values_of(ENDPOINT_OPTION).map_or_else(
+        || config.endpoint.unwrap_or_default(),
+        |values| values.map(ToOwned::to_owned).collect(),
+    ) {
+        let (name, backend) = parse_endpoint(runtime, &endpoint)?;
+        if endpoints.iter().any(|other| other.name == name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Endpoint {} is given more than once.", name.code_str()),
+            ));
+        }
+
+        let endpoint_config = endpoint_configs.remove(&name).unwrap_or_default();
+        endpoints.push(EndpointSettings {
+            name,
+            backend,
+            keep: endpoint_config
+                .keep
+                .map(|patterns| {
+                    RegexSet::new(patterns)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+                })
+                .transpose()?,
+            min_age: parse_duration(endpoint_config.min_age.as_deref())?,
+            threshold: endpoint_config
+                .threshold
+                .as_deref()
+                .map(Threshold::from_str)
+                .transpose()?,
+        });
+    }
+    if let Some(name) = endpoint_configs.keys().next() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "There are settings for endpoint {}, but no such endpoint is given.",
+                name.code_str(),
+            ),
+        ));
+    }
+
+    // Determine the order in which images are deleted. A score expression takes precedence over a
+    // built-in policy.
+    let policy = match matches.value_of(SCORE_OPTION).or(config.score.as_deref()) {
+        Some(expression) if !matches.is_present(POLICY_OPTION) => {
+            eviction::Policy::Score(score::Expression::parse(expression)?)
+        }
+        _ => eviction::Policy::from_str(
+            matches
+                .value_of(POLICY_OPTION)
+                .or(config.policy.as_deref())
+                .unwrap_or(DEFAULT_POLICY),
+        )?,
+    };
+
+    // Determine the order in which images are deleted when the policy considers them equal.
+    let tie_breaks = matches
+        .values_of(TIE_BREAK_OPTION)
+        .map_or_else(
+            || {
+                config.tie_break.unwrap_or_else(|| {
+                    DEFAULT_TIE_BREAKS
+                        .iter()
+                        .map(|&tie_break| tie_break.to_owned())
+                        .collect()
+                })
+            },
+            |values| values.map(ToOwned::to_owned).collect(),
+        )
+        .iter()
+        .map(|tie_break| eviction::TieBreak::from_str(tie_break))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // Determine how many images to delete at once.
+    let deletion_chunk_size = match matches.value_of(DELETION_CHUNK_SIZE_OPTION) {
+        Some(v) => match v.parse::<usize>() {
+            Ok(chunk_size) => chunk_size,
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+        },
+        None => config
+            .deletion_chunk_size
+            .unwrap_or(DEFAULT_DELETION_CHUNK_SIZE),
+    };
+
+    // Determine how many batches of images to inspect at once.
+    let inspect_concurrency = match matches.value_of(INSPECT_CONCURRENCY_OPTION) {
+        Some(v) => match v.parse::<usize>() {
+            Ok(concurrency) => concurrency,
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+        },
+        None => config
+            .inspect_concurrency
+            .unwrap_or(DEFAULT_INSPECT_CONCURRENCY),
+    };
+    if inspect_concurrency == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Invalid inspection concurrency {}. It must be at least {}.",
+                "0".code_str(),
+                "1".code_str(),
+            ),
+        ));
+    }
+
+    // Determine what images need to be preserved at all costs.
+    let keep_patterns = matches.values_of(KEEP_OPTION).map_or_else(
+        || config.keep.unwrap_or_default(),
+        |values| values.map(ToOwned::to_owned).collect(),
+    );
+    let keep = if keep_patterns.is_empty() {
+        None
+    } else {
+        match RegexSet::new(keep_patterns) {
+            Ok(set) => Some(set),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+        }
+    };
+
+    // Determine which images Docuum may delete at all, if not all of them.
+    let remove_only_patterns = matches.values_of(REMOVE_ONLY_OPTION).map_or_else(
+        || config.remove_only.unwrap_or_default(),
+        |values| values.map(ToOwned::to_owned).collect(),
+    );
+    let remove_only = if remove_only_patterns.is_empty() {
+        None
+    } else {
+        match RegexSet::new(remove_only_patterns) {
+            Ok(set) => Some(set),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+        }
+    };
+
+    // Determine whether to remove only the unwanted tags of images which also have wanted ones.
+    let untag = matches.is_present(UNTAG_OPTION) || config.untag.unwrap_or(false);
+    if untag && !backend.supports_untagging() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} isn't supported by the CRI backend.",
+                format!("--{UNTAG_OPTION}").code_str(),
+            ),
+        ));
+    }
+
+    // Determine where to archive images before deleting them, and how much space the archives may
+    // take up.
+    let archive_dir = matches
+        .value_of(ARCHIVE_DIR_OPTION)
+        .or(config.archive_dir.as_deref())
+        .map(PathBuf::from);
+    if archive_dir.is_some() && !backend.supports_saving_images() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} isn't supported by the CRI backend.",
+                format!("--{ARCHIVE_DIR_OPTION}").code_str(),
+            ),
+        ));
+    }
+    let archive_max_size = matches
+        .value_of(ARCHIVE_MAX_SIZE_OPTION)
+        .or(config.archive_max_size.as_deref())
+        .map(|max_size| {
+            Byte::from_str(max_size).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid archive size {}.", max_size.code_str()),
+                )
+            })
+        })
+        .transpose()?;
+    if archive_max_size.is_some() && archive_dir.is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} requires {}.",
+                format!("--{ARCHIVE_MAX_SIZE_OPTION}").code_str(),
+                format!("--{ARCHIVE_DIR_OPTION}").code_str(),
+            ),
+        ));
+    }
+
+    // Determine which image labels protect images from deletion.
+    let keep_labels = matches
+        .values_of(KEEP_LABEL_OPTION)
+        .map_or_else(
+            || config.keep_label.unwrap_or_default(),
+            |values| values.map(ToOwned::to_owned).collect(),
+        )
+        .iter()
+        .map(|label| parse_label(label))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // Determine which digests protect images from deletion.
+    let keep_digests = matches
+        .values_of(KEEP_DIGEST_OPTION)
+        .map_or_else(
+            || config.keep_digest.unwrap_or_default(),
+            |values| values.map(ToOwned::to_owned).collect(),
+        )
+        .iter()
+        .map(|digest| parse_digest(digest))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // Determine which directories to search for Dockerfiles whose base images are protected from
+    // deletion.
+    let keep_dockerfile_bases = matches.values_of(KEEP_DOCKERFILE_BASES_OPTION).map_or_else(
+        || {
+            config
+                .keep_dockerfile_bases
+                .unwrap_or_default()
+                .iter()
+                .map(PathBuf::from)
+                .collect()
+        },
+        |values| values.map(PathBuf::from).collect(),
+    );
+
+    // Load the plugins.
+    let plugins = matches
+        .values_of(PLUGIN_OPTION)
+        .map_or_else(
+            || config.plugin.unwrap_or_default(),
+            |values| values.map(ToOwned::to_owned).collect(),
+        )
+        .iter()
+        .map(|path| Plugin::load(path))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // Determine which image IDs are protected from deletion.
+    let keep_ids = matches
+        .values_of(KEEP_ID_OPTION)
+        .map_or_else(
+            || config.keep_id.unwrap_or_default(),
+            |values| values.map(ToOwned::to_owned).collect(),
+        )
+        .iter()
+        .map(|keep_id| parse_image_id(keep_id))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // Determine which registries' images are protected from deletion.
+    let protect_registries = matches
+        .values_of(PROTECT_REGISTRY_OPTION)
+        .map_or_else(
+            || config.protect_registry.unwrap_or_default(),
+            |values| values.map(ToOwned::to_owned).collect(),
+        )
+        .iter()
+        .map(|registry| parse_registry(registry))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // Determine which registries' images Docuum may delete, if not all of them.
+    let only_registries = matches
+        .values_of(ONLY_REGISTRY_OPTION)
+        .map_or_else(
+            || config.only_registry.unwrap_or_default(),
+            |values| values.map(ToOwned::to_owned).collect(),
+        )
+        .iter()
+        .map(|registry| parse_registry(registry))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // Determine the minimum age for images to be considered for deletion.
+    let min_age = parse_duration(
+        matches
+            .value_of(MIN_AGE_OPTION)
+            .or(config.min_age.as_deref()),
+    )?;
+
+    // Determine how long freshly pulled or built images are protected from deletion.
+    let pull_grace = parse_duration(
+        matches
+            .value_of(PULL_GRACE_OPTION)
+            .or(config.pull_grace.as_deref()),
+    )?;
+
+    // Determine the age beyond which images are deleted regardless of the threshold.
+    let max_age = parse_duration(
+        matches
+            .value_of(MAX_AGE_OPTION)
+            .or(config.max_age.as_deref()),
+    )?;
+
+    // Determine how long images which no longer exist are remembered.
+    let forget_missing_after = parse_duration(
+        matches
+            .value_of(FORGET_MISSING_AFTER_OPTION)
+            .or(config.forget_missing_after.as_deref()),
+    )?;
+
+    // Determine how long deletions are kept in the deletion history.
+    let history_max_age = parse(
+        matches
+            .value_of(HISTORY_MAX_AGE_OPTION)
+            .or(config.history_max_age.as_deref())
+            .unwrap_or(DEFAULT_HISTORY_MAX_AGE),
+    )
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    // Determine the maximum number of images to keep around.
+    let max_images = match matches.value_of(MAX_IMAGES_OPTION) {
+        Some(v) => match v.parse::<usize>() {
+            Ok(max_images) => Some(max_images),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+        },
+        None => config.max_images,
+    };
+
+    // Determine how many inodes to keep free, which is only supported on Linux.
+    let min_free_inodes = match matches.value_of(MIN_FREE_INODES_OPTION) {
+        Some(v) => match v.parse::<u64>() {
+            Ok(min_free_inodes) => Some(min_free_inodes),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+        },
+        None => config.min_free_inodes,
+    };
+    if min_free_inodes.is_some() && !cfg!(target_os = "linux") {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "{} is only supported on Linux.",
+                format!("--{MIN_FREE_INODES_OPTION}").code_str(),
+            ),
+        ));
+    }
+
+    // Determine how often to poll the container runtime if it can't stream events.
+    let poll_interval = parse(
+        matches
+            .value_of(POLL_INTERVAL_OPTION)
+            .or(config.poll_interval.as_deref())
+            .unwrap_or(DEFAULT_POLL_INTERVAL),
+    )
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    // Determine how often to check the space usage between events, if ever.
+    let disk_check_interval = parse_duration(
+        matches
+            .value_of(DISK_CHECK_INTERVAL_OPTION)
+            .or(config.disk_check_interval.as_deref()),
+    )?;
+
+    // Determine how often to look for Docker-in-Docker containers, if ever.
+    let dind_interval = parse_duration(
+        matches
+            .value_of(DIND_INTERVAL_OPTION)
+            .or(config.dind_interval.as_deref()),
+    )?;
+    if dind_interval.is_some() && runtime != Runtime::Docker {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is only supported by the Docker backend.",
+                format!("--{DIND_INTERVAL_OPTION}").code_str(),
+            ),
+        ));
+    }
+
+    // Determine when to vacuum regardless of events, if ever.
+    let schedule = matches
+        .value_of(SCHEDULE_OPTION)
+        .or(config.schedule.as_deref())
+        .map(|schedule| {
+            Cron::new(schedule).parse().map_err(|error| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid schedule {}. Details: {}",
+                        schedule.code_str(),
+                        error,
+                    ),
+                )
+            })
+        })
+        .transpose()?;
+
+    // Determine how long to protect the images of Docker Compose projects after their containers
+    // are gone, if at all.
+    let protect_compose_projects = parse_duration(
+        matches
+            .value_of(PROTECT_COMPOSE_PROJECTS_OPTION)
+            .or(config.protect_compose_projects.as_deref()),
+    )?;
+    if protect_compose_projects.is_some() && !backend.supports_container_labels() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is only supported by the Docker backend.",
+                format!("--{PROTECT_COMPOSE_PROJECTS_OPTION}").code_str(),
+            ),
+        ));
+    }
+
+    // Determine how long to protect the images of GitLab CI pipelines after their containers are
+    // gone, if at all.
+    let protect_gitlab_pipelines = parse_duration(
+        matches
+            .value_of(PROTECT_GITLAB_PIPELINES_OPTION)
+            .or(config.protect_gitlab_pipelines.as_deref()),
+    )?;
+    if protect_gitlab_pipelines.is_some() && !backend.supports_container_labels() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is only supported by the Docker backend.",
+                format!("--{PROTECT_GITLAB_PIPELINES_OPTION}").code_str(),
+            ),
+        ));
+    }
+
+    // Determine whether to delete stopped containers too, and how long after they stop.
+    let prune_containers = parse_duration(
+        matches
+            .value_of(PRUNE_CONTAINERS_OPTION)
+            .or(config.prune_containers.as_deref()),
+    )?;
+    if prune_containers.is_some() && !backend.supports_containers() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} isn't supported by the CRI backend.",
+                format!("--{PRUNE_CONTAINERS_OPTION}").code_str(),
+            ),
+        ));
+    }
+
+    // Determine whether Docker should delete the untagged parents of deleted images.
+    let prune_parents =
+        matches.is_present(PRUNE_PARENTS_OPTION) || config.prune_parents.unwrap_or(false);
+    if prune_parents && !backend.supports_pruning_parents() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is only supported by the Docker backend.",
+                format!("--{PRUNE_PARENTS_OPTION}").code_str(),
+            ),
+        ));
+    }
+
+    // Determine whether to manage volumes too.
+    let prune_volumes =
+        matches.is_present(PRUNE_VOLUMES_OPTION) || config.prune_volumes.unwrap_or(false);
+    if prune_volumes && !backend.supports_volumes_and_networks() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is only supported by the Docker backend.",
+                format!("--{PRUNE_VOLUMES_OPTION}").code_str(),
+            ),
+        ));
+    }
+
+    // Determine whether to prune the caches of Buildx builders.
+    let prune_buildx_caches = matches.is_present(PRUNE_BUILDX_CACHES_OPTION)
+        || config.prune_buildx_caches.unwrap_or(false);
+    if prune_buildx_caches && !backend.supports_buildx_builders() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is only supported by the Docker backend.",
+                format!("--{PRUNE_BUILDX_CACHES_OPTION}").code_str(),
+            ),
+        ));
+    }
+
+    // Determine the cleanup pipeline. Its volumes and Buildx cache stages need the same bookkeeping
+    // as the flags which add them to the default pipeline.
+    let pipeline = match &config.pipeline {
+        Some(stages) => pipeline_stages(
+            &backend,
+            stages,
+            &[
+                (prune_volumes, PRUNE_VOLUMES_OPTION),
+                (prune_buildx_caches, PRUNE_BUILDX_CACHES_OPTION),
+            ],
+        )?,
+        None => pipeline::default(prune_volumes, prune_buildx_caches),
+    };
+    let prune_volumes = pipeline
+        .iter()
+        .any(|stage| stage.kind == StageKind::Volumes);
+    let prune_buildx_caches = pipeline
+        .iter()
+        .any(|stage| stage.kind == StageKind::BuildxCaches);
+
+    // Determine whether the space used by containers counts toward the threshold.
+    let count_container_layers = matches.is_present(COUNT_CONTAINER_LAYERS_OPTION)
+        || config.count_container_layers.unwrap_or(false);
+    if count_container_layers && !backend.supports_volumes_and_networks() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is only supported by the Docker backend.",
+                format!("--{COUNT_CONTAINER_LAYERS_OPTION}").code_str(),
+            ),
+        ));
+    }
+
+    // Determine whether the space used by volumes counts toward the threshold. This is implied by
+    // pruning them.
+    let count_volumes =
+        matches.is_present(COUNT_VOLUMES_OPTION) || config.count_volumes.unwrap_or(false);
+    if count_volumes && !backend.supports_volumes_and_networks() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is only supported by the Docker backend.",
+                format!("--{COUNT_VOLUMES_OPTION}").code_str(),
+            ),
+        ));
+    }
+
+    // Determine whether to delete unused networks too, and how long after they become unused.
+    let prune_networks = parse_duration(
+        matches
+            .value_of(PRUNE_NETWORKS_OPTION)
+            .or(config.prune_networks.as_deref()),
+    )?;
+    if prune_networks.is_some() && !backend.supports_volumes_and_networks() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is only supported by the Docker backend.",
+                format!("--{PRUNE_NETWORKS_OPTION}").code_str(),
+            ),
+        ));
+    }
+
+    // Determine how long to wait before retrying after errors.
+    let retry_initial_delay = parse(
+        matches
+            .value_of(RETRY_INITIAL_DELAY_OPTION)
+            .or(config.retry_initial_delay.as_deref())
+            .unwrap_or(DEFAULT_RETRY_INITIAL_DELAY),
+    )
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let retry_max_delay = parse(
+        matches
+            .value_of(RETRY_MAX_DELAY_OPTION)
+            .or(config.retry_max_delay.as_deref())
+            .unwrap_or(DEFAULT_RETRY_MAX_DELAY),
+    )
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let retry_multiplier = match matches.value_of(RETRY_MULTIPLIER_OPTION) {
+        Some(v) => match v.parse::<f64>() {
+            Ok(retry_multiplier) => retry_multiplier,
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
+        },
+        None => config.retry_multiplier.unwrap_or(DEFAULT_RETRY_MULTIPLIER),
+    };
+    if !(retry_multiplier.is_finite() && retry_multiplier >= 1.0_f64) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Invalid retry multiplier {}. It must be at least {}.",
+                retry_multiplier.to_string().code_str(),
+                "1".code_str(),
+            ),
+        ));
+    }
+
+    // Determine whether to run as a Kubernetes DaemonSet and whether to coordinate with the
+    // kubelet, both of which require knowing the node.
+    let kubernetes = matches.is_present(KUBERNETES_OPTION) || config.kubernetes.unwrap_or(false);
+    let coordinate_with_kubelet = matches.is_present(COORDINATE_WITH_KUBELET_OPTION)
+        || config.coordinate_with_kubelet.unwrap_or(false);
+    let kubernetes_node = matches
+        .value_of(KUBERNETES_NODE_OPTION)
+        .map(ToOwned::to_owned)
+        .or(config.kubernetes_node);
+    for (enabled, option) in [
+        (kubernetes, KUBERNETES_OPTION),
+        (coordinate_with_kubelet, COORDINATE_WITH_KUBELET_OPTION),
+    ] {
+        if enabled && kubernetes_node.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} requires {}.",
+                    format!("--{option}").code_str(),
+                    format!("--{KUBERNETES_NODE_OPTION}").code_str(),
+                ),
+            ));
+        }
+    }
+
+    // Read the threshold. As a Kubernetes DaemonSet, the threshold and target default to the
+    // kubelet's image garbage collection thresholds, so Docuum cleans up before the kubelet does.
+    let default_threshold = Threshold::Absolute(
+        Byte::from_str(DEFAULT_THRESHOLD).unwrap(), // Manually verified safe
+    );
+    let threshold = matches
+        .value_of(THRESHOLD_OPTION)
+        .or(config.threshold.as_deref());
+    let kubelet_limits = match kubernetes_node.as_deref() {
+        Some(node) if coordinate_with_kubelet || (kubernetes && threshold.is_none()) => {
+            Some(KubeletLimits::fetch(&backend, node)?)
+        }
+        _ => None,
+    };
+    let kubelet_defaults = kubelet_limits
+        .as_ref()
+        .filter(|_| kubernetes && threshold.is_none())
+        .map(|kubelet_limits| kubelet_limits.defaults(coordinate_with_kubelet));
+    let threshold = threshold.map_or_else(
+        || Ok(kubelet_defaults.map_or(default_threshold, |(threshold, _)| threshold)),
+        Threshold::from_str,
+    )?;
+
+    // When coordinating with the kubelet, the limit on the space usage never allows the disk usage
+    // to reach the kubelet's thresholds, even if the threshold would.
+    let kubelet_threshold = match kubelet_limits.filter(|_| coordinate_with_kubelet) {
+        Some(kubelet_limits) => {
+            if kubelet_limits.conflicts_with(threshold) {
+                warn!(
+                    "The {} conflicts with the thresholds of the kubelet on Kubernetes node {}, \
+                        which would delete images or evict pods before Docuum cleans up. Docuum \
+                        will keep the disk usage below the kubelet's thresholds instead.",
+                    format!("--{THRESHOLD_OPTION}").code_str(),
+                    kubernetes_node.as_deref().unwrap_or_default().code_str(),
+                );
+            }
+            Some(kubelet_limits.coordinated())
+        }
+        None => None,
+    };
+
+    // Read the level to clean down to once the threshold has been exceeded, if any. Percentages
+    // depend on the size of the filesystem, so they can only be compared with the threshold later.
+    let target = matches
+        .value_of(TARGET_OPTION)
+        .or(config.target.as_deref())
+        .map(Threshold::from_str)
+        .transpose()?
+        .or(kubelet_defaults.map(|(_, target)| target));
+    if let Some(target) = target {
+        if match (threshold, target) {
+            (Threshold::Absolute(threshold), Threshold::Absolute(target)) => target > threshold,
+            (Threshold::Free(threshold), Threshold::Free(target)) => target < threshold,
+            _ => false,
+        } {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The {} must not allow more space usage than the {}.",
+                    format!("--{TARGET_OPTION}").code_str(),
+                    format!("--{THRESHOLD_OPTION}").code_str(),
+                ),
+            ));
+        }
+    }
+
+    // Read the level above which protected images may be deleted anyway, if any.
+    let critical_threshold = matches
+        .value_of(CRITICAL_THRESHOLD_OPTION)
+        .or(config.critical_threshold.as_deref())
+        .map(Threshold::from_str)
+        .transpose()?;
+    if let Some(critical_threshold) = critical_threshold {
+        if match (threshold, critical_threshold) {
+            (Threshold::Absolute(threshold), Threshold::Absolute(critical_threshold)) => {
+                critical_threshold <= threshold
+            }
+            (Threshold::Free(threshold), Threshold::Free(critical_threshold)) => {
+                critical_threshold >= threshold
+            }
+            _ => false,
+        } {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The {} must allow more space usage than the {}.",
+                    format!("--{CRITICAL_THRESHOLD_OPTION}").code_str(),
+                    format!("--{THRESHOLD_OPTION}").code_str(),
+                ),
+            ));
+        }
+    }
+
+    Ok(Settings {
+        archive_dir,
+        archive_max_size,
+        backend,
+        backend_candidates,
+        count_container_layers,
+        count_volumes,
+        critical_threshold,
+        deletion_chunk_size,
+        dind_interval,
+        disk_check_interval,
+        endpoint_name: None,
+        endpoints,
+        forget_missing_after,
+        grpc_addr: matches
+            .value_of(GRPC_ADDR_OPTION)
+            .map(ToOwned::to_owned)
+            .or(config.grpc_addr),
+        health_addr: matches
+            .value_of(HEALTH_ADDR_OPTION)
+            .map(ToOwned::to_owned)
+            .or(config.health_addr),
+        history_max_age,
+        inspect_concurrency,
+        keep,
+        keep_digests,
+        keep_dockerfile_bases,
+        keep_ids,
+        keep_labels,
+        kubelet_threshold,
+        kubernetes,
+        kubernetes_node,
+        max_age,
+        max_images,
+        min_free_inodes,
+        min_age,
+        once: matches.is_present(ONCE_OPTION) || config.once.unwrap_or(false),
+        only_registries,
+        otlp_endpoint: matches
+            .value_of(OTLP_ENDPOINT_OPTION)
+            .map(ToOwned::to_owned)
+            .or(config.otlp_endpoint),
+        pipeline,
+        plugins,
+        policy,
+        poll_interval,
+        post_vacuum_hook: matches
+            .value_of(POST_VACUUM_HOOK_OPTION)
+            .map(ToOwned::to_owned)
+            .or(config.post_vacuum_hook),
+        pre_delete_hook: matches
+            .value_of(PRE_DELETE_HOOK_OPTION)
+            .map(ToOwned::to_owned)
+            .or(config.pre_delete_hook),
+        protect_compose_projects,
+        protect_gitlab_pipelines,
+        protect_registries,
+        prune_buildx_caches,
+        prune_containers,
+        prune_networks,
+        prune_parents,
+        prune_volumes,
+        pull_grace,
+        remove_only,
+        retry: retry::Policy {
+            initial_delay: retry_initial_delay,
+            multiplier: retry_multiplier,
+            max_delay: retry_max_delay,
+        },
+        schedule,
+        statsd_addr: matches
+            .value_of(STATSD_ADDR_OPTION)
+            .map(ToOwned::to_owned)
+            .or(config.statsd_addr),
+        target,
+        threshold,
+        tie_breaks,
+        untag,
+        webhook_url: matches
+            .value_of(WEBHOOK_URL_OPTION)
+            .map(ToOwned::to_owned)
+            .or(config.webhook_url),
+    })
+}
+
+// This function consumes and runs all the registered destructors. We use this mechanism instead of
+// RAII for things that need to be cleaned up even when the process is killed due to a signal.
+#[allow(clippy::type_complexity)]
+fn run_destructors(destructors: &Arc<Mutex<Vec<Box<dyn FnOnce() + Send>>>>) {
+    // The lock is released before running the destructors, since they may need to wait for another
+    // thread which might be trying to register a destructor.
+    let destructor_fns = std::mem::take(&mut *destructors.lock().unwrap());
+    for destructor in destructor_fns {
+        destructor();
+    }
+}
+
+// Set up the logger according to the command-line arguments and the configuration file. Invalid
+// settings are added to `errors` to be reported once the logger is set up.
+fn set_up_logging(matches: &ArgMatches, config: &config::Config, errors: &mut Vec<io::Error>) {
+    // Determine the log format and where the log goes. Since the logger can only be set up once,
+    // these can't be changed by reloading the settings.
+    let log_format = logging::Format::from_str(
+        matches
+            .value_of(LOG_FORMAT_OPTION)
+            .or(config.log_format.as_deref())
+            .unwrap_or(DEFAULT_LOG_FORMAT),
+    )
+    .unwrap_or_else(|error| {
+        errors.push(error);
+        logging::Format::Text
+    });
+    let log_target = logging::Target::from_str(
+        matches
+            .value_of(LOG_TARGET_OPTION)
+            .or(config.log_target.as_deref())
+            .unwrap_or(DEFAULT_LOG_TARGET),
+    )
+    .unwrap_or_else(|error| {
+        errors.push(error);
+        logging::Target::Stderr
+    });
+    let color_mode = logging::ColorMode::from_str(
+        matches
+            .value_of(COLOR_OPTION)
+            .or(config.color.as_deref())
+            .unwrap_or(DEFAULT_COLOR),
+    )
+    .unwrap_or_else(|error| {
+        errors.push(error);
+        logging::ColorMode::Auto
+    });
+    let log_file_path = matches
+        .value_of(LOG_FILE_OPTION)
+        .or(config.log_file.as_deref());
+
+    // Clap rejects `--log-file` with `--log-target`, but the configuration file can combine them.
+    if log_file_path.is_some()
+        && (matches.is_present(LOG_TARGET_OPTION) || config.log_target.is_some())
+    {
+        errors.push(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} can't be used with {}.",
+                format!("--{LOG_FILE_OPTION}").code_str(),
+                format!("--{LOG_TARGET_OPTION}").code_str(),
+            ),
+        ));
+    }
+
+    // Determine whether to print colored output. Unless told otherwise, only human-readable text on
+    // a terminal is colored.
+    colored::control::set_override(match color_mode {
+        logging::ColorMode::Auto => {
+            log_format == logging::Format::Text
+                && log_target == logging::Target::Stderr
+                && log_file_path.is_none()
+                && atty::is(Stream::Stderr)
+        }
+        logging::ColorMode::Always => true,
+        logging::ColorMode::Never => false,
+    });
+
+    // Set up the logger, falling back to standard error if necessary.
+    if let Err(error) = match log_file_path {
+        Some(path) => log_file(matches, config, Path::new(path))
+            .and_then(|file| logging::set_up_file(log_format, file)),
+        None => logging::set_up(log_format, log_target, color_mode),
+    } {
+        // Setting up the logger for standard error can't fail.
+        let _ = logging::set_up(log_format, logging::Target::Stderr, color_mode);
+        error!("{error}");
+    }
+}
+
+// Register a destructor which saves the state, so timestamps recorded since the last save aren't
+// lost when we're terminated. If the state is in use, we wait a little while for it.
+#[allow(clippy::type_complexity)]
+fn save_state_on_termination(
+    state: &Arc<Mutex<State>>,
+    namespace: Option<&str>,
+    destructors: &Arc<Mutex<Vec<Box<dyn FnOnce() + Send>>>>,
+) {
+    let state = state.clone();
+    let namespace = namespace.map(ToOwned::to_owned);
+    destructors.lock().unwrap().push(Box::new(move || {
+        let deadline = Instant::now() + STATE_SAVE_TIMEOUT;
+        let result = loop {
+            match state.try_lock() {
+                Ok(state) => break state::save(&state, namespace.as_deref()),
+                Err(TryLockError::Poisoned(poisoned)) => {
+                    break state::save(&poisoned.into_inner(), namespace.as_deref());
+                }
+                Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    sleep(Duration::from_millis(100));
+                }
+                Err(TryLockError::WouldBlock) => {
+                    break Err(io::Error::other(
+                        "Unable to save the state, since it's still in use.",
+                    ));
+                }
+            }
+        };
+
+        if let Err(error) = result {
+            error!("{error}");
+        }
+    }));
+}
+
+// Run the command-line interface.
+pub fn main() {
+    // If Docuum is in the foreground process group for some TTY, the process will receive a SIGINT
+    // when the user types CTRL+C at the terminal. The default behavior is to crash when this signal
+    // is received. However, we would rather clean up resources before terminating, so we trap the
+    // signal here. This code also traps SIGHUP and SIGTERM, since we compile the `ctrlc` crate with
+    // the `termination` feature [ref:ctrlc_term]. However, the daemon repurposes SIGHUP.
+    let destructors = Arc::new(Mutex::new(Vec::<Box<dyn FnOnce() + Send>>::new()));
+    let destructors_clone = destructors.clone();
+    if let Err(error) = ctrlc::set_handler(move || {
+        run_destructors(&destructors_clone);
+        for endpoint_destructors in ENDPOINT_DESTRUCTORS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+        {
+            run_destructors(endpoint_destructors);
+        }
+        exit(1);
+    }) {
+        // Log the error and proceed anyway.
+        error!("{error}");
+    }
+
+    // Pause or resume deletions when SIGUSR2 is received.
+    if let Err(error) = pause::install_handler() {
+        // Log the error and proceed anyway.
+        error!("{error}");
+    }
+
+    // Parse the command-line arguments.
+    let matches = arguments();
+
+    // Load the configuration file for the settings which are needed before the logger is set up.
+    // Any errors are reported once the logger is set up.
+    let mut startup_errors = vec![];
+    let config =
+        config::load(matches.value_of(CONFIG_OPTION).map(Path::new)).unwrap_or_else(|error| {
+            startup_errors.push(error);
+            config::Config::default()
+        });
+
+    // Set up the logger.
+    set_up_logging(&matches, &config, &mut startup_errors);
+
+    // Report the errors which occurred before the logger was set up.
+    if !startup_errors.is_empty() {
+        for error in startup_errors {
+            error!("{error}");
+        }
+        exit(1);
+    }
+
+    // Determine where the state is persisted. Since the state is loaded only once, this can't be
+    // changed by reloading the settings.
+    if let Some(path) = matches
+        .value_of_os(STATE_FILE_OPTION)
+        .map(Path::new)
+        .or(config.state_file.as_deref().map(Path::new))
+    {
+        match state_file(path) {
+            Ok(path) => state::set_path(path),
+            Err(error) => {
+                error!("{error}");
+                exit(1);
+            }
+        }
+    }
+
+    // Run the requested subcommand, if any. Clap only allows the subcommands defined above.
+    if let Some(subcommand) = matches.subcommand_name() {
+        if let Err(error) = match subcommand {
+            CONTROL_SUBCOMMAND => control_subcommand(&matches),
+            LS_SUBCOMMAND => ls(&matches),
+            RESTORE_SUBCOMMAND => restore(&matches),
+            STATE_SUBCOMMAND => state_subcommand(&matches),
+            TUI_SUBCOMMAND => tui(&matches),
+            _ => stats(&matches),
+        } {
+            error!("{error}");
+            exit(1);
+        }
+        return;
+    }
+
+    // Install or uninstall the Windows service, if requested.
+    if matches.is_present(INSTALL_SERVICE_OPTION) || matches.is_present(UNINSTALL_SERVICE_OPTION) {
+        let result = if matches.is_present(INSTALL_SERVICE_OPTION) {
+            service::install(&service_arguments())
+        } else {
+            service::uninstall()
+        };
+        if let Err(error) = result {
+            error!("{error}");
+            exit(1);
+        }
+        return;
+    }
+
+    // When started by the Windows service control manager, the daemon runs under its supervision.
+    if matches.is_present(SERVICE_OPTION) {
+        if let Err(error) = service::run(Box::new(move || daemon(&matches, &destructors))) {
+            error!("{error}");
+            exit(1);
+        }
+        return;
+    }
+
+    daemon(&matches, &destructors);
+}
+
+// Load the state from disk, falling back to the initial state. Also returns a boolean indicating
+// whether we fell back to the initial state. Additional endpoints have states of their own. The
+// first time Docuum starts with a state, the time is recorded in its counters.
+fn load_state(namespace: Option<&str>) -> (State, bool) {
+    let (mut state, first_run) = state::load(namespace).map_or_else(
+        |error| {
+            // We couldn't load any state from disk. Log the error.
+            warn!(
+                "Unable to load state from disk. Proceeding with initial state. Details: {}",
+                error.to_string().code_str(),
+            );
+
+            // Start with the initial state.
+            (state::initial(), true)
+        },
+        |state| (state, false),
+    );
+
+    if state.counters.started_since_epoch.is_none() {
+        state.counters.started_since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).ok();
+    }
+
+    (state, first_run)
+}
+
+// Load the state from disk, failing if there isn't any.
+fn load_existing_state() -> io::Result<State> {
+    state::load(None).map_err(|error| {
+        io::Error::new(
+            error.kind(),
+            format!("Unable to load state from disk. Details: {error}"),
+        )
+    })
+}
+
+// Send a command to the running daemon and print its response. Clap only allows the subcommands
+// defined above.
+fn control_subcommand(matches: &ArgMatches) -> io::Result<()> {
+    let (Some(path), _) = control_socket(matches)? else {
+        return Err(io::Error::other("Unable to locate the control socket."));
+    };
+
+    let matches = matches.subcommand_matches(CONTROL_SUBCOMMAND).unwrap(); // Manually verified safe
+    let command = match matches.subcommand() {
+        (STATUS_SUBCOMMAND, _) => control::Command::Status,
+        (VACUUM_NOW_SUBCOMMAND, _) => control::Command::VacuumNow,
+        (PAUSE_SUBCOMMAND, _) => control::Command::Pause,
+        (RESUME_SUBCOMMAND, _) => control::Command::Resume,
+        (_, matches) => {
+            // The `unwrap`s are safe because the image is a required argument.
+            let matches = matches.unwrap();
+            control::Command::Explain(
+                matches.value_of(IMAGE_ARGUMENT).unwrap().to_owned(),
+                output(matches),
+            )
+        }
+    };
+
+    print!("{}", control::send(&path, &command)?);
+    Ok(())
+}
+
+// List the images in eviction order according to the settings.
+fn ls(matches: &ArgMatches) -> io::Result<()> {
+    let settings = settings(matches)?;
+    let (state, first_run) = load_state(None);
+
+    // The `unwrap` is safe because this is only called for the `ls` subcommand.
+    let output = output(matches.subcommand_matches(LS_SUBCOMMAND).unwrap());
+    ls::print(&settings, &state, first_run, output)
+}
+
+// Restore a deleted image according to the settings.
+fn restore(matches: &ArgMatches) -> io::Result<()> {
+    let settings = settings(matches)?;
+
+    // The `unwrap`s are safe because the image is a required argument.
+    let image = matches
+        .subcommand_matches(RESTORE_SUBCOMMAND)
+        .unwrap()
+        .value_of(IMAGE_ARGUMENT)
+        .unwrap();
+
+    restore::restore(&settings, image)
+}
+
+// Export or import the persisted state. Clap only allows the subcommands defined above.
+fn state_subcommand(matches: &ArgMatches) -> io::Result<()> {
+    let matches = matches.subcommand_matches(STATE_SUBCOMMAND).unwrap(); // Manually verified safe
+    if matches.subcommand_name() == Some(IMPORT_SUBCOMMAND) {
+        state::import()
+    } else {
+        state::export(&load_existing_state()?)
+    }
+}
+
+// Print a summary of the persisted state according to the settings.
+fn stats(matches: &ArgMatches) -> io::Result<()> {
+    let settings = settings(matches)?;
+
+    // The `unwrap` is safe because this is only called for the `stats` subcommand.
+    let output = output(matches.subcommand_matches(STATS_SUBCOMMAND).unwrap());
+    stats::print(&settings, &load_existing_state()?, output)
+}
+
+// Show the dashboard according to the settings.
+fn tui(matches: &ArgMatches) -> io::Result<()> {
+    tui::run(&settings(matches)?)
+}
+
+// Determine the arguments the Windows service should run with, which are the ones we were given
+// minus the request to install the service.
+fn service_arguments() -> Vec<String> {
+    once(format!("--{SERVICE_OPTION}"))
+        .chain(
+            env::args_os()
+                .skip(1)
+                .map(|argument| argument.to_string_lossy().into_owned())
+                .filter(|argument| *argument != format!("--{INSTALL_SERVICE_OPTION}")),
+        )
+        .collect()
+}
+
+// Vacuum according to the settings, either once or until stopped.
+#[allow(clippy::type_complexity)]
+fn daemon(matches: &ArgMatches, destructors: &Arc<Mutex<Vec<Box<dyn FnOnce() + Send>>>>) {
+    // Determine the settings.
+    let reload_settings = || settings(matches);
+    let mut settings = match reload_settings() {
+        Ok(settings) => settings,
+        Err(error) => {
+            error!("{error}");
+            exit(1);
+        }
+    };
+
+    // Export traces, if requested. Like the logger, the exporter can only be set up once, so
+    // reloading the settings doesn't move it. Upon termination, the remaining spans are exported.
+    if let Some(endpoint) = &settings.otlp_endpoint {
+        if let Err(error) = telemetry::init(endpoint) {
+            error!("{error}");
+            exit(1);
+        }
+        destructors
+            .lock()
+            .unwrap()
+            .push(Box::new(telemetry::shutdown));
+    }
+
+    // Try to load the state from disk.
+    let (state, mut first_run) = load_state(None);
+    let state = Arc::new(Mutex::new(state));
+
+    // In one-shot mode, vacuum once and exit rather than streaming Docker events. The additional
+    // endpoints, if any, are vacuumed one after another.
+    if settings.once {
+        save_state_on_termination(&state, None, destructors);
+        let exit_code = vacuum_once(matches, &settings, &state, first_run, destructors)
+            .unwrap_or_else(|error| {
+                event::log(Level::Error, "error", &[], format_args!("{error}"));
+                1_i32
+            });
+        telemetry::shutdown();
+        exit(exit_code);
+    }
+
+    // Reload the settings when SIGHUP is received, rather than terminating. In one-shot mode, there
+    // is nothing to reload, so SIGHUP terminates Docuum as usual.
+    if let Err(error) = reload::install_handler() {
+        // Log the error and proceed anyway.
+        error!("{error}");
+    }
+
+    // Start the health server, if requested. Since it keeps running for the life of the process,
+    // reloading the settings doesn't move it.
+    if let Some(address) = &settings.health_addr {
+        if let Err(error) = health::serve(address) {
+            error!("{error}");
+            exit(1);
+        }
+    }
+
+    // Start the gRPC server, if requested. Like the health server, it keeps running for the life of
+    // the process.
+    if let Some(address) = &settings.grpc_addr {
+        if let Err(error) = grpc::serve(address) {
+            error!("{error}");
+            exit(1);
+        }
+    }
+
+    // Listen for commands on the control socket. Since it keeps running for the life of the
+    // process, reloading the settings doesn't move it. If the socket wasn't requested explicitly,
+    // Docuum can do without it.
+    match control_socket(matches) {
+        Ok((Some(path), explicit)) => {
+            if let Err(error) = control::serve(&path) {
+                if explicit {
+                    error!("{error}");
+                    exit(1);
+                }
+                warn!("{error}");
+            }
+        }
+        Ok((None, _)) => warn!("Unable to locate the control socket, so it won't be available."),
+        Err(error) => {
+            error!("{error}");
+            exit(1);
+        }
+    }
+
+    // Manage each additional endpoint on its own thread. Like the servers above, the set of
+    // endpoints can't be changed by reloading the settings.
+    let endpoint_names = settings
+        .endpoints
+        .iter()
+        .map(|endpoint| endpoint.name.clone())
+        .collect::<Vec<_>>();
+    scope(|scope| {
+        for name in &endpoint_names {
+            scope.spawn(move || supervise_endpoint(matches, name));
+        }
+
+        if let Some(interval) = settings.dind_interval {
+            scope.spawn(move || supervise_nested_daemons(matches, interval));
+        }
+
+        supervise(
+            &mut settings,
+            &reload_settings,
+            &state,
+            &mut first_run,
+            destructors,
+        );
+    });
+}
+
+// Vacuum once, followed by the additional endpoints and the nested Docker daemons, if any. With
+// several container runtimes, the exit code reflects the worst outcome among them.
+#[allow(clippy::type_complexity)]
+fn vacuum_once(
+    matches: &ArgMatches,
+    settings: &Settings,
+    state: &Arc<Mutex<State>>,
+    first_run: bool,
+    destructors: &Arc<Mutex<Vec<Box<dyn FnOnce() + Send>>>>,
+) -> io::Result<i32> {
+    let mut outcome = run_once(settings, state, first_run)?;
+
+    for name in settings.endpoints.iter().map(|endpoint| &endpoint.name) {
+        let endpoint_settings = endpoint_settings(matches, name)?;
+        let (state, first_run) = load_state(Some(name));
+        let state = Arc::new(Mutex::new(state));
+        save_state_on_termination(&state, Some(name), destructors);
+        outcome = outcome.max(run_once(&endpoint_settings, &state, first_run)?);
+    }
+
+    if settings.dind_interval.is_some() {
+        outcome = outcome.max(
+            NestedDaemons::default().vacuum(&settings.backend, &|nested_daemon| {
+                nested_daemon_settings(matches, nested_daemon)
+            })?,
+        );
+    }
+
+    Ok(outcome.exit_code())
+}
+
+// Determine the settings for an additional endpoint, which are the main settings with the endpoint
+// and its overrides swapped in.
+fn endpoint_settings(matches: &ArgMatches, name: &str) -> io::Result<Settings> {
+    let mut settings = settings(matches)?;
+    let index = settings
+        .endpoints
+        .iter()
+        .position(|endpoint| endpoint.name == name)
+        .ok_or_else(|| {
+            io::Error::other(format!(
+                "Endpoint {} is no longer configured.",
+                name.code_str(),
+            ))
+        })?;
+
+    let endpoint = settings.endpoints.swap_remove(index);
+
+    settings.backend = endpoint.backend.clone();
+    settings.backend_candidates = vec![endpoint.backend];
+    settings.endpoint_name = Some(endpoint.name);
+    if let Some(keep) = endpoint.keep {
+        settings.keep = Some(keep).filter(|keep| !keep.is_empty());
+    }
+    if endpoint.min_age.is_some() {
+        settings.min_age = endpoint.min_age;
+    }
+    if let Some(threshold) = endpoint.threshold {
+        settings.threshold = threshold;
+    }
+    Ok(settings)
+}
+
+// Determine the settings for the Docker daemon in a Docker-in-Docker container, which are the main
+// settings with the nested daemon swapped in.
+fn nested_daemon_settings(
+    matches: &ArgMatches,
+    nested_daemon: &NestedDaemon,
+) -> io::Result<Settings> {
+    let mut settings = settings(matches)?;
+    settings.backend = nested_daemon.backend.clone();
+    settings.backend_candidates = vec![nested_daemon.backend.clone()];
+    settings.endpoint_name = Some(nested_daemon.container_name.clone());
+    settings.endpoints = vec![];
+    settings.dind_interval = None;
+    Ok(settings)
+}
+
+// Look for Docker-in-Docker containers and vacuum their Docker daemons periodically. Like the
+// additional endpoints, the interval can't be changed by reloading the settings. This never
+// returns.
+fn supervise_nested_daemons(matches: &ArgMatches, interval: Duration) {
+    let mut nested_daemons = NestedDaemons::default();
+    loop {
+        if let Err(error) = settings(matches).and_then(|settings| {
+            nested_daemons.vacuum(&settings.backend, &|nested_daemon| {
+                nested_daemon_settings(matches, nested_daemon)
+            })
+        }) {
+            event::log(Level::Error, "error", &[], format_args!("{error}"));
+        }
+
+        sleep(interval);
+    }
+}
+
+// Stream events from an additional endpoint and vacuum when necessary, with its own state.
+fn supervise_endpoint(matches: &ArgMatches, name: &str) {
+    let reload_settings = || endpoint_settings(matches, name);
+    let mut settings = match reload_settings() {
+        Ok(settings) => settings,
+        Err(error) => {
+            error!("{error}");
+            return;
+        }
+    };
+    info!(
+        "Managing endpoint {} ({}).",
+        name.code_str(),
+        settings.backend.endpoint_description(),
+    );
+
+    let (state, mut first_run) = load_state(Some(name));
+    let state = Arc::new(Mutex::new(state));
+
+    // The endpoint has destructors of its own, so they aren't run when the main endpoint retries.
+    let destructors = Arc::new(Mutex::new(Vec::<Box<dyn FnOnce() + Send>>::new()));
+    ENDPOINT_DESTRUCTORS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(destructors.clone());
+
+    supervise(
+        &mut settings,
+        &reload_settings,
+        &state,
+        &mut first_run,
+        &destructors,
+    );
+}
+
+// Stream Docker events and vacuum when necessary. Restart if an error occurs, waiting longer after
+// each consecutive error. This only returns if we've been asked to stop.
+#[allow(clippy::type_complexity)]
+fn supervise(
+    settings: &mut Settings,
+    reload_settings: &dyn Fn() -> io::Result<Settings>,
+    state: &Arc<Mutex<State>>,
+    first_run: &mut bool,
+    destructors: &Arc<Mutex<Vec<Box<dyn FnOnce() + Send>>>>,
+) {
+    let mut retries = 0;
+    loop {
+        let start = Instant::now();
+
+        // Since the destructors are consumed after each run, this needs to be registered each time.
+        save_state_on_termination(state, settings.endpoint_name.as_deref(), destructors);
+
+        // This will run until an error occurs or we're asked to stop.
+        if let Err(error) = run(settings, reload_settings, state, first_run, destructors) {
+            event::log(Level::Error, "error", &[], format_args!("{error}"));
+        } else {
+            // We've been asked to stop. The state was saved along the way.
+            run_destructors(destructors);
+            return;
+        }
+        health::record_events_disconnected();
+        systemd::activity("Retrying after an error");
+
+        // Clean up any resources left over from that run.
+        run_destructors(destructors);
+
+        // The error may have been due to losing the connection to the container runtime, in which
+        // case another endpoint may be reachable.
+        settings.backend = Backend::first_reachable(&settings.backend_candidates);
+
+        // If the run lasted longer than the maximum delay, it must have gotten somewhere, so we
+        // consider the error unrelated to any previous ones.
+        if start.elapsed() > settings.retry.max_delay {
+            retries = 0;
+        }
+
+        // Wait a moment and then retry.
+        let delay = settings.retry.delay(retries);
+        retries = retries.saturating_add(1);
+        info!("Retrying in {} seconds\u{2026}", delay.as_secs_f64());
+        sleep(delay);
+    }
+}
@@ -0,0 +1,448 @@
+use {
+    crate::format::CodeStr,
+    serde::Deserialize,
+    std::{
+        collections::HashMap,
+        fs::read_to_string,
+        io,
+        path::{Path, PathBuf},
+    },
+};
+
+// The names of the configuration files Docuum looks for in the default locations
+const FILE_NAMES: &[&str] = &["docuum.yml", "docuum.yaml", "docuum.toml"];
+
+// The contents of a configuration file. Each field corresponds to a command-line option of the
+// same name, and command-line options take precedence over the values specified here.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub archive_dir: Option<String>,
+    pub archive_max_size: Option<String>,
+    pub backend: Option<String>,
+    pub color: Option<String>,
+    pub context: Option<String>,
+    pub coordinate_with_kubelet: Option<bool>,
+    pub count_container_layers: Option<bool>,
+    pub count_volumes: Option<bool>,
+    pub critical_threshold: Option<String>,
+    pub control_socket: Option<String>,
+    pub deletion_chunk_size: Option<usize>,
+    pub dind_interval: Option<String>,
+    pub disk_check_interval: Option<String>,
+    pub endpoint: Option<Vec<String>>,
+    pub endpoint_settings: Option<HashMap<String, EndpointConfig>>,
+    pub forget_missing_after: Option<String>,
+    pub grpc_addr: Option<String>,
+    pub health_addr: Option<String>,
+    pub history_max_age: Option<String>,
+    pub host: Option<Vec<String>>,
+    pub inspect_concurrency: Option<usize>,
+    pub keep: Option<Vec<String>>,
+    pub keep_digest: Option<Vec<String>>,
+    pub keep_dockerfile_bases: Option<Vec<String>>,
+    pub keep_id: Option<Vec<String>>,
+    pub keep_label: Option<Vec<String>>,
+    pub kubernetes: Option<bool>,
+    pub kubernetes_node: Option<String>,
+    pub log_file: Option<String>,
+    pub log_file_max_age: Option<String>,
+    pub log_file_max_size: Option<String>,
+    pub log_format: Option<String>,
+    pub log_target: Option<String>,
+    pub max_age: Option<String>,
+    pub max_images: Option<usize>,
+    pub min_free_inodes: Option<u64>,
+    pub min_age: Option<String>,
+    pub once: Option<bool>,
+    pub only_registry: Option<Vec<String>>,
+    pub otlp_endpoint: Option<String>,
+    pub pipeline: Option<Vec<StageConfig>>,
+    pub plugin: Option<Vec<String>>,
+    pub policy: Option<String>,
+    pub poll_interval: Option<String>,
+    pub post_vacuum_hook: Option<String>,
+    pub pre_delete_hook: Option<String>,
+    pub protect_compose_projects: Option<String>,
+    pub protect_gitlab_pipelines: Option<String>,
+    pub protect_registry: Option<Vec<String>>,
+    pub prune_buildx_caches: Option<bool>,
+    pub prune_containers: Option<String>,
+    pub prune_networks: Option<String>,
+    pub prune_parents: Option<bool>,
+    pub prune_volumes: Option<bool>,
+    pub pull_grace: Option<String>,
+    pub remove_only: Option<Vec<String>>,
+    pub retry_initial_delay: Option<String>,
+    pub retry_max_delay: Option<String>,
+    pub retry_multiplier: Option<f64>,
+    pub schedule: Option<String>,
+    pub score: Option<String>,
+    pub state_file: Option<String>,
+    pub statsd_addr: Option<String>,
+    pub target: Option<String>,
+    pub threshold: Option<String>,
+    pub tie_break: Option<Vec<String>>,
+    pub untag: Option<bool>,
+    pub webhook_url: Option<String>,
+}
+
+// The settings which can be overridden for an additional endpoint (see `--endpoint`), by the name
+// of the endpoint
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct EndpointConfig {
+    pub keep: Option<Vec<String>>,
+    pub min_age: Option<String>,
+    pub threshold: Option<String>,
+}
+
+// A stage of the cleanup pipeline (see `pipeline`), along with its own limits
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct StageConfig {
+    pub stage: String,
+    pub min_age: Option<String>,
+    pub keep: Option<String>,
+}
+
+// The directories in which Docuum looks for a configuration file, in order of precedence
+fn default_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![];
+
+    if let Some(dir) = dirs::config_dir() {
+        dirs.push(dir.join("docuum"));
+    }
+
+    if cfg!(unix) {
+        dirs.push(PathBuf::from("/etc/docuum"));
+    }
+
+    dirs
+}
+
+// Find the configuration file in the default locations, if there is one.
+fn default_path() -> Option<PathBuf> {
+    default_dirs()
+        .into_iter()
+        .flat_map(|dir| FILE_NAMES.iter().map(move |file_name| dir.join(file_name)))
+        .find(|path| path.is_file())
+}
+
+// Parse the contents of a YAML configuration file.
+fn parse(yaml: &str) -> io::Result<Config> {
+    // An empty file is a valid (albeit useless) configuration.
+    if yaml.trim().is_empty() {
+        return Ok(Config::default());
+    }
+
+    serde_yaml::from_str(yaml).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+// Parse the contents of a TOML configuration file.
+fn parse_toml(toml: &str) -> io::Result<Config> {
+    toml::from_str(toml).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+// Load the configuration file. If no path is given, the default locations are searched, and it's
+// not an error if no configuration file is found there. Files with a `.toml` extension are parsed
+// as TOML, and everything else is parsed as YAML.
+pub fn load(path: Option<&Path>) -> io::Result<Config> {
+    let Some(path) = path.map(Path::to_owned).or_else(default_path) else {
+        return Ok(Config::default());
+    };
+
+    // Log what we are trying to do in case an error occurs.
+    debug!(
+        "Loading configuration from {}\u{2026}",
+        path.to_string_lossy().code_str(),
+    );
+
+    // Read and parse the file.
+    let is_toml = path
+        .extension()
+        .is_some_and(|extension| extension == "toml");
+    read_to_string(&path)
+        .and_then(|contents| {
+            if is_toml {
+                parse_toml(&contents)
+            } else {
+                parse(&contents)
+            }
+        })
+        .map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!(
+                    "Unable to load configuration file {}. Details: {}",
+                    path.to_string_lossy().code_str(),
+                    error,
+                ),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, parse_toml, Config, EndpointConfig, StageConfig};
+
+    #[test]
+    fn parse_empty() {
+        assert_eq!(parse("").unwrap(), Config::default());
+    }
+
+    #[allow(clippy::too_many_lines)]
+    #[test]
+    fn parse_all_fields() {
+        assert_eq!(
+            parse(
+                "threshold: 50 GB\n\
+                 archive-dir: /var/lib/docuum/archive\n\
+                 archive-max-size: 20 GB\n\
+                 keep:\n\
+                 - '^alpine:'\n\
+                 - '^debian:'\n\
+                 keep-digest:\n\
+                 - alpine@sha256:0123\n\
+                 keep-dockerfile-bases:\n\
+                 - /home/alice/src\n\
+                 keep-id:\n\
+                 - 0123456789ab\n\
+                 keep-label:\n\
+                 - com.example.keep=true\n\
+                 kubernetes: true\n\
+                 coordinate-with-kubelet: true\n\
+                 kubernetes-node: node-1\n\
+                 count-container-layers: true\n\
+                 count-volumes: true\n\
+                 critical-threshold: 70 GB\n\
+                 color: never\n\
+                 control-socket: /run/docuum.sock\n\
+                 log-format: json\n\
+                 log-target: journald\n\
+                 log-file: /var/log/docuum.log\n\
+                 log-file-max-age: 1 day\n\
+                 log-file-max-size: 1 MiB\n\
+                 deletion-chunk-size: 3\n\
+                 dind-interval: 1 minute\n\
+                 disk-check-interval: 5 minutes\n\
+                 endpoint:\n\
+                 - dind=tcp://127.0.0.1:2375\n\
+                 forget-missing-after: 7 days\n\
+                 grpc-addr: 127.0.0.1:50051\n\
+                 health-addr: 127.0.0.1:8080\n\
+                 history-max-age: 1 year\n\
+                 host:\n\
+                 - unix:///run/user/1000/docker.sock\n\
+                 - npipe:////./pipe/docker_engine_custom\n\
+                 inspect-concurrency: 8\n\
+                 max-age: 30 days\n\
+                 max-images: 1000\n\
+                 min-free-inodes: 100000\n\
+                 min-age: 1 day\n\
+                 once: true\n\
+                 only-registry:\n\
+                 - docker.io\n\
+                 otlp-endpoint: http://localhost:4318\n\
+                 pipeline:\n\
+                 - stage: containers\n\
+                 \x20 min-age: 1 day\n\
+                 - stage: build-cache\n\
+                 \x20 keep: 5 GB\n\
+                 - stage: images\n\
+                 plugin:\n\
+                 - /etc/docuum/retention.wasm\n\
+                 policy: size-weighted\n\
+                 poll-interval: 30 seconds\n\
+                 post-vacuum-hook: /usr/local/bin/after-vacuum\n\
+                 pre-delete-hook: /usr/local/bin/may-delete\n\
+                 protect-compose-projects: 1 day\n\
+                 protect-gitlab-pipelines: 1 hour\n\
+                 protect-registry:\n\
+                 - registry.example.com:5000\n\
+                 prune-containers: 2 days\n\
+                 prune-buildx-caches: true\n\
+                 prune-networks: 1 hour\n\
+                 prune-parents: true\n\
+                 prune-volumes: true\n\
+                 pull-grace: 10 minutes\n\
+                 remove-only:\n\
+                 - '^example.com/my-team/'\n\
+                 retry-initial-delay: 1 second\n\
+                 retry-max-delay: 1 minute\n\
+                 retry-multiplier: 1.5\n\
+                 schedule: 0 3 * * *\n\
+                 score: age_days * size_gb\n\
+                 state-file: /var/lib/docuum/state.yml\n\
+                 statsd-addr: localhost:8125\n\
+                 target: 40 GB\n\
+                 tie-break:\n\
+                 - fewest-ancestors-first\n\
+                 - smallest-first\n\
+                 untag: true\n\
+                 webhook-url: https://example.com/docuum\n",
+            )
+            .unwrap(),
+            Config {
+                archive_dir: Some("/var/lib/docuum/archive".to_owned()),
+                archive_max_size: Some("20 GB".to_owned()),
+                backend: None,
+                color: Some("never".to_owned()),
+                context: None,
+                coordinate_with_kubelet: Some(true),
+                count_container_layers: Some(true),
+                count_volumes: Some(true),
+                critical_threshold: Some("70 GB".to_owned()),
+                control_socket: Some("/run/docuum.sock".to_owned()),
+                deletion_chunk_size: Some(3),
+                dind_interval: Some("1 minute".to_owned()),
+                disk_check_interval: Some("5 minutes".to_owned()),
+                endpoint: Some(vec!["dind=tcp://127.0.0.1:2375".to_owned()]),
+                endpoint_settings: None,
+                forget_missing_after: Some("7 days".to_owned()),
+                grpc_addr: Some("127.0.0.1:50051".to_owned()),
+                health_addr: Some("127.0.0.1:8080".to_owned()),
+                history_max_age: Some("1 year".to_owned()),
+                host: Some(vec![
+                    "unix:///run/user/1000/docker.sock".to_owned(),
+                    "npipe:////./pipe/docker_engine_custom".to_owned(),
+                ]),
+                inspect_concurrency: Some(8),
+                keep: Some(vec!["^alpine:".to_owned(), "^debian:".to_owned()]),
+                keep_digest: Some(vec!["alpine@sha256:0123".to_owned()]),
+                keep_dockerfile_bases: Some(vec!["/home/alice/src".to_owned()]),
+                keep_id: Some(vec!["0123456789ab".to_owned()]),
+                keep_label: Some(vec!["com.example.keep=true".to_owned()]),
+                kubernetes: Some(true),
+                kubernetes_node: Some("node-1".to_owned()),
+                log_file: Some("/var/log/docuum.log".to_owned()),
+                log_file_max_age: Some("1 day".to_owned()),
+                log_file_max_size: Some("1 MiB".to_owned()),
+                log_format: Some("json".to_owned()),
+                log_target: Some("journald".to_owned()),
+                max_age: Some("30 days".to_owned()),
+                max_images: Some(1000),
+                min_free_inodes: Some(100_000),
+                min_age: Some("1 day".to_owned()),
+                once: Some(true),
+                only_registry: Some(vec!["docker.io".to_owned()]),
+                otlp_endpoint: Some("http://localhost:4318".to_owned()),
+                pipeline: Some(vec![
+                    StageConfig {
+                        stage: "containers".to_owned(),
+                        min_age: Some("1 day".to_owned()),
+                        keep: None,
+                    },
+                    StageConfig {
+                        stage: "build-cache".to_owned(),
+                        min_age: None,
+                        keep: Some("5 GB".to_owned()),
+                    },
+                    StageConfig {
+                        stage: "images".to_owned(),
+                        ..StageConfig::default()
+                    },
+                ]),
+                plugin: Some(vec!["/etc/docuum/retention.wasm".to_owned()]),
+                policy: Some("size-weighted".to_owned()),
+                poll_interval: Some("30 seconds".to_owned()),
+                post_vacuum_hook: Some("/usr/local/bin/after-vacuum".to_owned()),
+                pre_delete_hook: Some("/usr/local/bin/may-delete".to_owned()),
+                protect_compose_projects: Some("1 day".to_owned()),
+                protect_gitlab_pipelines: Some("1 hour".to_owned()),
+                protect_registry: Some(vec!["registry.example.com:5000".to_owned()]),
+                prune_buildx_caches: Some(true),
+                prune_containers: Some("2 days".to_owned()),
+                prune_networks: Some("1 hour".to_owned()),
+                prune_parents: Some(true),
+                prune_volumes: Some(true),
+                pull_grace: Some("10 minutes".to_owned()),
+                remove_only: Some(vec!["^example.com/my-team/".to_owned()]),
+                retry_initial_delay: Some("1 second".to_owned()),
+                retry_max_delay: Some("1 minute".to_owned()),
+                retry_multiplier: Some(1.5_f64),
+                schedule: Some("0 3 * * *".to_owned()),
+                score: Some("age_days * size_gb".to_owned()),
+                state_file: Some("/var/lib/docuum/state.yml".to_owned()),
+                statsd_addr: Some("localhost:8125".to_owned()),
+                target: Some("40 GB".to_owned()),
+                threshold: Some("50 GB".to_owned()),
+                tie_break: Some(vec![
+                    "fewest-ancestors-first".to_owned(),
+                    "smallest-first".to_owned(),
+                ]),
+                untag: Some(true),
+                webhook_url: Some("https://example.com/docuum".to_owned()),
+            },
+        );
+    }
+
+    #[test]
+    fn parse_unknown_field() {
+        assert!(parse("thresold: 50 GB\n").is_err());
+    }
+
+    #[test]
+    fn parse_toml_empty() {
+        assert_eq!(parse_toml("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn parse_toml_all_fields() {
+        let config = parse_toml(
+            "threshold = \"50 GB\"\n\
+             keep = [\"^alpine:\", \"^debian:\"]\n\
+             deletion-chunk-size = 3\n\
+             min-age = \"1 day\"\n\
+             once = true\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.deletion_chunk_size, Some(3));
+        assert_eq!(
+            config.keep,
+            Some(vec!["^alpine:".to_owned(), "^debian:".to_owned()]),
+        );
+        assert_eq!(config.min_age.as_deref(), Some("1 day"));
+        assert_eq!(config.once, Some(true));
+        assert_eq!(config.threshold.as_deref(), Some("50 GB"));
+    }
+
+    #[test]
+    fn parse_toml_unknown_field() {
+        assert!(parse_toml("thresold = \"50 GB\"\n").is_err());
+    }
+
+    #[test]
+    fn parse_endpoint_settings() {
+        assert_eq!(
+            parse(
+                "endpoint-settings:\n\
+                 \x20 dind:\n\
+                 \x20   keep:\n\
+                 \x20   - '^docker:'\n\
+                 \x20   min-age: 1 hour\n\
+                 \x20   threshold: 5 GB\n",
+            )
+            .unwrap()
+            .endpoint_settings,
+            Some(
+                [(
+                    "dind".to_owned(),
+                    EndpointConfig {
+                        keep: Some(vec!["^docker:".to_owned()]),
+                        min_age: Some("1 hour".to_owned()),
+                        threshold: Some("5 GB".to_owned()),
+                    },
+                )]
+                .into_iter()
+                .collect(),
+            ),
+        );
+    }
+
+    #[test]
+    fn parse_endpoint_settings_unknown_field() {
+        assert!(parse("endpoint-settings:\n  dind:\n    once: true\n").is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use {
+    crate::{backend::Backend, format::CodeStr},
+    std::{fs::read_to_string, sync::Mutex},
+};
+
+// The image of the container which Docuum is running in, if any, and which backend it came from.
+// Docuum's own container doesn't change while it's running, so this only needs to be determined
+// once for each backend.
+static OWN_IMAGE_ID: Mutex<Option<(Backend, Option<String>)>> = Mutex::new(None);
+
+// Determine whether a string is a full container ID.
+fn is_container_id(id: &str) -> bool {
+    id.len() == 64 && id.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+// Find the ID of the container a process is running in from its mount table. Docker bind-mounts
+// files like `/etc/hostname` from the container's directory, e.g.,
+// `/var/lib/docker/containers/<ID>/hostname`. This works with both versions of cgroups.
+fn container_id_from_mountinfo(mountinfo: &str) -> Option<String> {
+    mountinfo.lines().find_map(|line| {
+        line.split(' ').find_map(|path| {
+            let (_, rest) = path.split_once("/containers/")?;
+            let id = rest.split('/').next()?;
+            is_container_id(id).then(|| id.to_owned())
+        })
+    })
+}
+
+// Find the ID of the container a process is running in from its cgroups, which are named after the
+// container with version 1 of cgroups, e.g., `/docker/<ID>` or `/system.slice/docker-<ID>.scope`.
+fn container_id_from_cgroup(cgroup: &str) -> Option<String> {
+    cgroup.lines().find_map(|line| {
+        let name = line.rsplit('/').next()?;
+        let id = name
+            .strip_prefix("docker-")
+            .and_then(|name| name.strip_suffix(".scope"))
+            .unwrap_or(name);
+        is_container_id(id).then(|| id.to_owned())
+    })
+}
+
+// Determine the ID of the container Docuum is running in, if any.
+fn own_container_id() -> Option<String> {
+    read_to_string("/proc/self/mountinfo")
+        .ok()
+        .and_then(|mountinfo| container_id_from_mountinfo(&mountinfo))
+        .or_else(|| {
+            read_to_string("/proc/self/cgroup")
+                .ok()
+                .and_then(|cgroup| container_id_from_cgroup(&cgroup))
+        })
+}
+
+// Determine the ID of the image of the container Docuum is running in, if any, so it can be
+// protected from deletion. The container may not be managed by the given backend (e.g., if Docuum
+// manages a remote daemon), in which case there's nothing to protect.
+pub fn own_image_id(backend: &Backend) -> Option<String> {
+    let mut own_image_id = OWN_IMAGE_ID.lock().unwrap();
+    if let Some((cached_backend, image_id)) = own_image_id.as_ref() {
+        if cached_backend == backend {
+            return image_id.clone();
+        }
+    }
+
+    let image_id = own_container_id().and_then(|container_id| {
+        match backend.container_image_id(&container_id) {
+            Ok(image_id) => {
+                if let Some(image_id) = &image_id {
+                    debug!(
+                        "Docuum is running in container {}, so its image {} won't be deleted.",
+                        container_id.code_str(),
+                        image_id.code_str(),
+                    );
+                }
+                image_id
+            }
+            Err(error) => {
+                debug!(
+                    "Docuum is running in container {}, but its image couldn't be determined. \
+                        Details: {}",
+                    container_id.code_str(),
+                    error,
+                );
+                None
+            }
+        }
+    });
+
+    *own_image_id = Some((backend.clone(), image_id.clone()));
+    image_id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{container_id_from_cgroup, container_id_from_mountinfo};
+
+    const CONTAINER_ID: &str = "8e5b2c9a1f3d4e6b7a8c9d0e1f2a3b4c5d6e7f8091a2b3c4d5e6f708192a3b4c";
+
+    #[test]
+    fn container_id_from_mountinfo_docker() {
+        let mountinfo = format!(
+            "1254 1253 0:58 / / rw,relatime - overlay overlay rw,lowerdir=/var/lib/docker/\
+                overlay2/l/ABC\n\
+             1270 1254 259:2 /var/lib/docker/containers/{CONTAINER_ID}/hostname /etc/hostname \
+                rw,relatime - ext4 /dev/nvme0n1p2 rw\n",
+        );
+
+        assert_eq!(
+            container_id_from_mountinfo(&mountinfo),
+            Some(CONTAINER_ID.to_owned()),
+        );
+    }
+
+    #[test]
+    fn container_id_from_mountinfo_host() {
+        assert_eq!(
+            container_id_from_mountinfo(
+                "22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw\n",
+            ),
+            None,
+        );
+    }
+
+    #[test]
+    fn container_id_from_cgroup_v1() {
+        assert_eq!(
+            container_id_from_cgroup(&format!(
+                "12:memory:/docker/{CONTAINER_ID}\n11:cpu:/docker/{CONTAINER_ID}\n",
+            )),
+            Some(CONTAINER_ID.to_owned()),
+        );
+        assert_eq!(
+            container_id_from_cgroup(&format!(
+                "1:name=systemd:/system.slice/docker-{CONTAINER_ID}.scope\n",
+            )),
+            Some(CONTAINER_ID.to_owned()),
+        );
+    }
+
+    #[test]
+    fn container_id_from_cgroup_host() {
+        assert_eq!(
+            container_id_from_cgroup("0::/user.slice/user-1000.slice/session-2.scope\n"),
+            None,
+        );
+    }
+}
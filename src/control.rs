@@ -0,0 +1,493 @@
+use {
+    crate::{
+        format::Output,
+        health,
+        ls::JsonImage,
+        pause,
+        run::{list_images, ListedImage},
+        state::{self, State},
+        Settings,
+    },
+    chrono::{DateTime, SecondsFormat, Utc},
+    serde::Serialize,
+    std::{
+        env, io,
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc::{channel, Sender},
+            Mutex, PoisonError,
+        },
+        time::{Duration, UNIX_EPOCH},
+    },
+};
+
+#[cfg(unix)]
+use {
+    crate::format::CodeStr,
+    std::{
+        fs::{remove_file, set_permissions, Permissions},
+        io::{BufRead, BufReader, Read, Write},
+        os::unix::{
+            fs::PermissionsExt,
+            net::{UnixListener, UnixStream},
+        },
+        thread,
+    },
+};
+
+// The name of the control socket in its directory
+const SOCKET_FILE_NAME: &str = "docuum.sock";
+
+// Responses to commands which failed start with this, so the client can tell them apart
+const ERROR_PREFIX: &str = "error: ";
+
+// How long to wait for a client to send its command
+#[cfg(unix)]
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How long to wait for the daemon to get around to explaining an image, which it only does between
+// vacuums
+const EXPLAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+// The number of vacuums which have been requested. Each endpoint keeps track of how many of them it
+// has seen, so they all get to vacuum.
+static VACUUMS_REQUESTED: AtomicUsize = AtomicUsize::new(0);
+
+// An image which a client has asked about, along with how to format the explanation and where to
+// send it
+pub type ExplainRequest = (String, Output, Sender<io::Result<String>>);
+
+// Images which clients have asked about
+static EXPLAIN_REQUESTS: Mutex<Vec<ExplainRequest>> = Mutex::new(Vec::new());
+
+// An explanation as JSON
+#[derive(Serialize)]
+struct JsonExplanation {
+    #[serde(flatten)]
+    image: JsonImage,
+
+    containers: Vec<String>,
+
+    // Where the image is in the eviction order, where 1 is deleted first
+    position: usize,
+
+    // How many images are in the eviction order
+    images: usize,
+}
+
+// A command sent to the daemon over the control socket
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    Status,
+    VacuumNow,
+    Pause,
+    Resume,
+    Explain(String, Output),
+}
+
+impl Command {
+    // Parse a command from a line sent over the control socket.
+    fn parse(line: &str) -> io::Result<Self> {
+        let line = line.trim();
+        let (name, argument) = line
+            .split_once(' ')
+            .map_or((line, None), |(name, argument)| (name, Some(argument)));
+
+        match (name, argument) {
+            ("status", _) => Ok(Self::Status),
+            ("vacuum-now", _) => Ok(Self::VacuumNow),
+            ("pause", _) => Ok(Self::Pause),
+            ("resume", _) => Ok(Self::Resume),
+            ("explain", Some(argument)) if !argument.trim().is_empty() => {
+                // The image may be followed by the output format. The `unwrap` is safe because the
+                // argument isn't blank.
+                let mut words = argument.split_whitespace();
+                let image = words.next().unwrap().to_owned();
+                let output = words.next().map_or(Ok(Output::Text), Output::from_str)?;
+                Ok(Self::Explain(image, output))
+            }
+            ("explain", _) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Which image should be explained?",
+            )),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown command `{name}`."),
+            )),
+        }
+    }
+
+    // Format the command as a line to send over the control socket.
+    fn line(&self) -> String {
+        match self {
+            Self::Status => "status\n".to_owned(),
+            Self::VacuumNow => "vacuum-now\n".to_owned(),
+            Self::Pause => "pause\n".to_owned(),
+            Self::Resume => "resume\n".to_owned(),
+            Self::Explain(image, Output::Text) => format!("explain {image}\n"),
+            Self::Explain(image, output) => format!("explain {image} {}\n", output.name()),
+        }
+    }
+}
+
+// Where the control socket is, unless it was given explicitly: in the runtime directory if there is
+// one, and next to the state otherwise
+pub fn default_path() -> Option<PathBuf> {
+    env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| PathBuf::from(dir).join(SOCKET_FILE_NAME))
+        .or_else(|| state::path().map(|path| path.with_file_name(SOCKET_FILE_NAME)))
+}
+
+// Ask the daemon to vacuum the next time it gets a chance.
+pub fn request_vacuum() {
+    VACUUMS_REQUESTED.fetch_add(1, Ordering::SeqCst);
+}
+
+// The number of vacuums which have been requested so far
+pub fn vacuum_requests() -> usize {
+    VACUUMS_REQUESTED.load(Ordering::SeqCst)
+}
+
+// Check whether a vacuum has been requested since `seen` was last updated, and update it.
+pub fn vacuum_requested(seen: &mut usize) -> bool {
+    let requests = vacuum_requests();
+    std::mem::replace(seen, requests) != requests
+}
+
+// Take the images which clients have asked about, along with where to send the explanations.
+pub fn explain_requests() -> Vec<ExplainRequest> {
+    std::mem::take(
+        &mut *EXPLAIN_REQUESTS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner),
+    )
+}
+
+// Describe where an image is in the eviction order and whether it's protected from deletion.
+fn explanation(image_id: &str, images: &[ListedImage]) -> String {
+    let Some((index, image)) = images
+        .iter()
+        .enumerate()
+        .find(|(_, image)| image.id == image_id)
+    else {
+        return format!("Image {image_id} isn't known to the container runtime.\n");
+    };
+
+    let containers = if image.containers.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "It was most recently used by {}.\n",
+            image.containers.join(", "),
+        )
+    };
+    let mut explanation = format!(
+        "Image {} ({}) was last used at {}.\n\
+            {containers}\
+            It is {} of {} in the eviction order, where 1 is deleted first.\n",
+        image.id,
+        image.repository_tags.join(", "),
+        DateTime::<Utc>::from(UNIX_EPOCH + image.last_used_since_epoch)
+            .to_rfc3339_opts(SecondsFormat::Secs, true),
+        index + 1,
+        images.len(),
+    );
+    explanation.push_str(&image.protection.as_ref().map_or_else(
+        || "It isn't protected from deletion.\n".to_owned(),
+        |protection| format!("It is protected from deletion by {}.\n", protection.cause()),
+    ));
+    explanation
+}
+
+// Describe where an image is in the eviction order and whether it's protected from deletion, as
+// JSON. Images which aren't known to the container runtime are `null`.
+fn explanation_json(image_id: &str, images: &[ListedImage]) -> String {
+    let explanation = images
+        .iter()
+        .enumerate()
+        .find(|(_, image)| image.id == image_id)
+        .map(|(index, image)| JsonExplanation {
+            image: JsonImage::from(image),
+            containers: image.containers.clone(),
+            position: index + 1,
+            images: images.len(),
+        });
+
+    // The `unwrap` is safe because `JsonExplanation` always serializes.
+    format!("{}\n", serde_json::to_string(&explanation).unwrap())
+}
+
+// Explain why an image would or wouldn't be deleted. This is called by the daemon between vacuums.
+pub fn explain(
+    settings: &Settings,
+    state: &State,
+    first_run: bool,
+    image: &str,
+    output: Output,
+) -> io::Result<String> {
+    let image_id = settings.backend.image_id(image)?;
+    let images = list_images(settings, state, first_run)?;
+    Ok(match output {
+        Output::Text => explanation(&image_id, &images),
+        Output::Json => explanation_json(&image_id, &images),
+    })
+}
+
+// Ask the daemon to explain why an image would or wouldn't be deleted, and wait for the answer.
+pub fn request_explanation(image: String, output: Output) -> io::Result<String> {
+    let (sender, receiver) = channel();
+    EXPLAIN_REQUESTS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push((image, output, sender));
+    receiver.recv_timeout(EXPLAIN_TIMEOUT).unwrap_or_else(|_| {
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "Docuum didn't get around to explaining the image in time.",
+        ))
+    })
+}
+
+// Carry out a command and determine the response.
+fn respond(command: Command) -> String {
+    match command {
+        Command::Status => format!("{}\n", health::status_json()),
+        Command::VacuumNow => {
+            request_vacuum();
+            "A vacuum has been requested.\n".to_owned()
+        }
+        Command::Pause => {
+            pause::set(true);
+            "Deletions are paused.\n".to_owned()
+        }
+        Command::Resume => {
+            pause::set(false);
+            "Deletions have resumed.\n".to_owned()
+        }
+        Command::Explain(image, output) => {
+            request_explanation(image, output).unwrap_or_else(|error| error_response(&error))
+        }
+    }
+}
+
+// Format an error as a response.
+fn error_response(error: &io::Error) -> String {
+    format!("{ERROR_PREFIX}{error}\n")
+}
+
+// Handle a single connection.
+#[cfg(unix)]
+fn handle(stream: UnixStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let response = Command::parse(&line).map_or_else(|error| error_response(&error), respond);
+
+    let mut stream = stream;
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
+
+// Start listening for commands on the control socket on a background thread.
+#[cfg(unix)]
+pub fn serve(path: &Path) -> io::Result<()> {
+    let error_context = |error: io::Error| {
+        io::Error::new(
+            error.kind(),
+            format!(
+                "Unable to listen on the control socket {}. Details: {}",
+                path.to_string_lossy().code_str(),
+                error,
+            ),
+        )
+    };
+
+    // A socket which nobody is listening on is left over from a previous run.
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(error_context(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "Another instance of Docuum is already listening on it.",
+            )));
+        }
+        remove_file(path).map_err(error_context)?;
+    }
+
+    // Only the user Docuum runs as may control it.
+    let listener = UnixListener::bind(path).map_err(error_context)?;
+    set_permissions(path, Permissions::from_mode(0o600)).map_err(error_context)?;
+
+    info!(
+        "Listening for commands on {}\u{2026}",
+        path.to_string_lossy().code_str(),
+    );
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            // Explanations wait for the daemon to get around to them, so each connection is
+            // handled on its own thread so as not to hold up the others.
+            match stream {
+                Ok(stream) => {
+                    thread::spawn(move || {
+                        if let Err(error) = handle(stream) {
+                            debug!("Unable to respond to a command. Details: {error}");
+                        }
+                    });
+                }
+                Err(error) => debug!("Unable to accept a command. Details: {error}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+// There are no Unix domain sockets on this platform, so there's nothing to listen on.
+#[cfg(not(unix))]
+pub fn serve(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+// Send a command to the running daemon and return its response.
+#[cfg(unix)]
+pub fn send(path: &Path, command: &Command) -> io::Result<String> {
+    let mut stream = UnixStream::connect(path).map_err(|error| {
+        io::Error::new(
+            error.kind(),
+            format!(
+                "Unable to connect to the control socket {}. Is Docuum running? Details: {}",
+                path.to_string_lossy().code_str(),
+                error,
+            ),
+        )
+    })?;
+    stream.write_all(command.line().as_bytes())?;
+    stream.flush()?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    match response.strip_prefix(ERROR_PREFIX) {
+        Some(error) => Err(io::Error::other(error.trim_end().to_owned())),
+        None => Ok(response),
+    }
+}
+
+// There are no Unix domain sockets on this platform, so we can't talk to the daemon.
+#[cfg(not(unix))]
+pub fn send(_path: &Path, _command: &Command) -> io::Result<String> {
+    Err(io::Error::other(
+        "The control socket is only supported on Unix-like systems.",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{explanation, explanation_json, Command},
+        crate::{
+            format::Output,
+            run::{ListedImage, Protection},
+        },
+        byte_unit::Byte,
+        std::time::Duration,
+    };
+
+    #[test]
+    fn command_round_trip() {
+        for command in [
+            Command::Status,
+            Command::VacuumNow,
+            Command::Pause,
+            Command::Resume,
+            Command::Explain("alpine:latest".to_owned(), Output::Text),
+            Command::Explain("alpine:latest".to_owned(), Output::Json),
+        ] {
+            assert_eq!(Command::parse(&command.line()).unwrap(), command);
+        }
+    }
+
+    #[test]
+    fn command_explain_without_image() {
+        assert!(Command::parse("explain\n").is_err());
+    }
+
+    #[test]
+    fn command_explain_invalid_output() {
+        assert!(Command::parse("explain alpine:latest yaml\n").is_err());
+    }
+
+    #[test]
+    fn command_unknown() {
+        assert!(Command::parse("vacuum\n").is_err());
+    }
+
+    fn images() -> Vec<ListedImage> {
+        vec![
+            ListedImage {
+                id: "id-0".to_owned(),
+                last_used_since_epoch: Duration::from_secs(1_714_651_210),
+                size: Byte::from_bytes(42),
+                repository_tags: vec!["alpine:latest".to_owned()],
+                containers: vec![],
+                protection: None,
+            },
+            ListedImage {
+                id: "id-1".to_owned(),
+                last_used_since_epoch: Duration::from_secs(1_714_737_610),
+                size: Byte::from_bytes(42),
+                repository_tags: vec!["debian:latest".to_owned()],
+                containers: vec!["runner-1".to_owned(), "runner-2".to_owned()],
+                protection: Some(Protection::MinAge),
+            },
+        ]
+    }
+
+    #[test]
+    fn explanation_unprotected() {
+        assert_eq!(
+            explanation("id-0", &images()),
+            "Image id-0 (alpine:latest) was last used at 2024-05-02T12:00:10Z.\n\
+                It is 1 of 2 in the eviction order, where 1 is deleted first.\n\
+                It isn't protected from deletion.\n",
+        );
+    }
+
+    #[test]
+    fn explanation_protected() {
+        assert_eq!(
+            explanation("id-1", &images()),
+            "Image id-1 (debian:latest) was last used at 2024-05-03T12:00:10Z.\n\
+                It was most recently used by runner-1, runner-2.\n\
+                It is 2 of 2 in the eviction order, where 1 is deleted first.\n\
+                It is protected from deletion by --min-age.\n",
+        );
+    }
+
+    #[test]
+    fn explanation_unknown() {
+        assert_eq!(
+            explanation("id-2", &images()),
+            "Image id-2 isn't known to the container runtime.\n",
+        );
+    }
+
+    #[test]
+    fn explanation_json_protected() {
+        assert_eq!(
+            explanation_json("id-1", &images()),
+            "{\"id\":\"id-1\",\"repository_tags\":[\"debian:latest\"],\"size_bytes\":42,\
+                \"last_used\":\"2024-05-03T12:00:10Z\",\"protected_by\":\"--min-age\",\
+                \"containers\":[\"runner-1\",\"runner-2\"],\"position\":2,\"images\":2}\n",
+        );
+    }
+
+    #[test]
+    fn explanation_json_unknown() {
+        assert_eq!(explanation_json("id-2", &images()), "null\n");
+    }
+}
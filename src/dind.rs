@@ -0,0 +1,78 @@
+use {
+    crate::{
+        backend::{Backend, NestedDaemon},
+        event,
+        format::CodeStr,
+        report::Outcome,
+        run,
+        state::{self, State},
+        Settings,
+    },
+    log::Level,
+    std::{collections::HashMap, io},
+};
+
+// The Docker daemons nested in the Docker-in-Docker containers of a container runtime, along with
+// their states (by container ID). Nested daemons usually don't outlive the CI job which started
+// them, so their states are only kept in memory.
+#[derive(Default)]
+pub struct NestedDaemons {
+    states: HashMap<String, State>,
+}
+
+impl NestedDaemons {
+    // Vacuum the nested daemons, with the settings given by `nested_settings`. Errors for
+    // individual nested daemons are logged rather than returned, since their containers may stop
+    // at any time.
+    pub fn vacuum(
+        &mut self,
+        backend: &Backend,
+        nested_settings: &dyn Fn(&NestedDaemon) -> io::Result<Settings>,
+    ) -> io::Result<Outcome> {
+        let nested_daemons = backend.nested_daemons()?;
+
+        // Forget the nested daemons whose containers have stopped.
+        self.states.retain(|container_id, _| {
+            nested_daemons
+                .iter()
+                .any(|nested_daemon| nested_daemon.container_id == *container_id)
+        });
+
+        let mut outcome = Outcome::NothingToDo;
+        for nested_daemon in &nested_daemons {
+            let first_run = !self.states.contains_key(&nested_daemon.container_id);
+            if first_run {
+                info!(
+                    "Found Docker-in-Docker container {}. Its Docker daemon will be vacuumed too.",
+                    nested_daemon.container_name.code_str(),
+                );
+            }
+
+            let state = self
+                .states
+                .entry(nested_daemon.container_id.clone())
+                .or_insert_with(state::initial);
+            match nested_settings(nested_daemon).and_then(|settings| {
+                let threshold = run::threshold_bytes(&settings)?;
+                run::vacuum(&settings, state, first_run, threshold)
+            }) {
+                Ok(report) => outcome = outcome.max(report.outcome()),
+                Err(error) => {
+                    // Log the error and proceed with the other nested daemons.
+                    event::log(
+                        Level::Error,
+                        "error",
+                        &[],
+                        format_args!(
+                            "Unable to vacuum the Docker daemon in container {}. Details: {}",
+                            nested_daemon.container_name.code_str(),
+                            error,
+                        ),
+                    );
+                }
+            }
+        }
+
+        Ok(outcome)
+    }
+}
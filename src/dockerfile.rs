@@ -0,0 +1,274 @@
+use {
+    crate::format::CodeStr,
+    std::{
+        collections::{HashMap, HashSet},
+        fs::{read_dir, read_to_string},
+        io,
+        path::Path,
+    },
+};
+
+// The image which a `FROM` instruction names to start from nothing
+const SCRATCH: &str = "scratch";
+
+// Determine whether a file is a Dockerfile by its name, e.g., `Dockerfile`, `Dockerfile.dev`,
+// `web.Dockerfile`, or `Containerfile`.
+fn is_dockerfile(file_name: &str) -> bool {
+    let lowercase = file_name.to_ascii_lowercase();
+    ["dockerfile", "containerfile"].iter().any(|name| {
+        lowercase == *name
+            || lowercase.starts_with(&format!("{name}."))
+            || lowercase.ends_with(&format!(".{name}"))
+    })
+}
+
+// Join the lines which are continued with a trailing backslash, and drop comments and blank lines.
+fn instructions(contents: &str) -> Vec<String> {
+    let mut instructions = vec![];
+    let mut instruction = String::new();
+    for line in contents.lines() {
+        let trimmed_line = line.trim();
+        if trimmed_line.starts_with('#') || (trimmed_line.is_empty() && instruction.is_empty()) {
+            continue;
+        }
+
+        if let Some(continued) = trimmed_line.strip_suffix('\\') {
+            instruction.push_str(continued);
+            instruction.push(' ');
+        } else {
+            instruction.push_str(trimmed_line);
+            instructions.push(instruction.trim().to_owned());
+            instruction.clear();
+        }
+    }
+
+    if !instruction.trim().is_empty() {
+        instructions.push(instruction.trim().to_owned());
+    }
+
+    instructions
+}
+
+// Substitute the build arguments in an image reference, e.g., `${BASE}` or `$BASE`. Returns `None`
+// if an argument has no default value, since the reference depends on how the image is built.
+fn substitute(reference: &str, arguments: &HashMap<String, String>) -> Option<String> {
+    let mut substituted = String::new();
+    let mut rest = reference;
+    while let Some(index) = rest.find('$') {
+        substituted.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+        let (name, remainder) = if let Some(braced) = rest.strip_prefix('{') {
+            braced.split_once('}')?
+        } else {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            rest.split_at(end)
+        };
+        substituted.push_str(arguments.get(name)?);
+        rest = remainder;
+    }
+    substituted.push_str(rest);
+    Some(substituted)
+}
+
+// Find the images which the `FROM` instructions of a Dockerfile start from. Build stages which
+// start from earlier stages of the same Dockerfile are skipped, as are images which depend on build
+// arguments without default values.
+fn base_images_from_dockerfile(contents: &str) -> Vec<String> {
+    let mut arguments = HashMap::new();
+    let mut stages = HashSet::new();
+    let mut base_images = vec![];
+    let mut started = false;
+    for instruction in instructions(contents) {
+        let mut words = instruction.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+
+        // Only the arguments declared before the first stage can be used in `FROM` instructions.
+        if keyword.eq_ignore_ascii_case("ARG") && !started {
+            for argument in words {
+                if let Some((name, value)) = argument.split_once('=') {
+                    arguments.insert(name.to_owned(), value.trim_matches('"').to_owned());
+                }
+            }
+            continue;
+        }
+
+        if !keyword.eq_ignore_ascii_case("FROM") {
+            continue;
+        }
+
+        started = true;
+        let mut words = words.skip_while(|word| word.starts_with("--"));
+        let Some(reference) = words.next() else {
+            continue;
+        };
+        let reference = substitute(reference, &arguments);
+
+        if let Some(reference) = &reference {
+            if !reference.eq_ignore_ascii_case(SCRATCH)
+                && !stages.contains(&reference.to_ascii_lowercase())
+            {
+                base_images.push(reference.clone());
+            }
+        }
+
+        if let (Some(keyword), Some(stage)) = (words.next(), words.next()) {
+            if keyword.eq_ignore_ascii_case("AS") {
+                stages.insert(stage.to_ascii_lowercase());
+            }
+        }
+    }
+
+    base_images
+}
+
+// Collect the base images of the Dockerfiles in a directory and its subdirectories. Hidden
+// directories (e.g., `.git`) are skipped, and symbolic links aren't followed.
+fn scan(dir: &Path, base_images: &mut HashSet<String>) -> io::Result<()> {
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+
+        if file_type.is_dir() {
+            if !file_name.starts_with('.') {
+                if let Err(error) = scan(&entry.path(), base_images) {
+                    // The directory may have been deleted or may not be readable.
+                    debug!(
+                        "Skipping {}: {}",
+                        entry.path().to_string_lossy().code_str(),
+                        error,
+                    );
+                }
+            }
+        } else if file_type.is_file() && is_dockerfile(&file_name) {
+            match read_to_string(entry.path()) {
+                Ok(contents) => base_images.extend(base_images_from_dockerfile(&contents)),
+                Err(error) => debug!(
+                    "Skipping {}: {}",
+                    entry.path().to_string_lossy().code_str(),
+                    error,
+                ),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Find the images which the Dockerfiles in the given directories start from.
+pub fn base_images(dirs: &[impl AsRef<Path>]) -> io::Result<HashSet<String>> {
+    let mut base_images = HashSet::new();
+    for dir in dirs {
+        let dir = dir.as_ref();
+        scan(dir, &mut base_images).map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!(
+                    "Unable to look for Dockerfiles in {}. Details: {}",
+                    dir.to_string_lossy().code_str(),
+                    error,
+                ),
+            )
+        })?;
+    }
+
+    Ok(base_images)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{base_images, base_images_from_dockerfile, is_dockerfile},
+        std::{collections::HashSet, fs},
+        tempfile::tempdir,
+    };
+
+    #[test]
+    fn is_dockerfile_names() {
+        for name in [
+            "Dockerfile",
+            "dockerfile",
+            "Dockerfile.dev",
+            "web.Dockerfile",
+            "Containerfile",
+        ] {
+            assert!(is_dockerfile(name), "{name}");
+        }
+
+        for name in ["Dockerfiles", "README.md", "docker-compose.yml"] {
+            assert!(!is_dockerfile(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn base_images_from_dockerfile_stages() {
+        assert_eq!(
+            base_images_from_dockerfile(
+                "\
+# syntax=docker/dockerfile:1
+FROM --platform=$BUILDPLATFORM golang:1.22 AS build
+RUN go build -o /app .
+
+from build as test
+RUN go test ./...
+
+FROM gcr.io/distroless/static \\
+    AS release
+COPY --from=build /app /app
+
+FROM scratch
+",
+            ),
+            vec![
+                "golang:1.22".to_owned(),
+                "gcr.io/distroless/static".to_owned(),
+            ],
+        );
+    }
+
+    #[test]
+    fn base_images_from_dockerfile_arguments() {
+        assert_eq!(
+            base_images_from_dockerfile(
+                "\
+ARG BASE=debian
+ARG VERSION=\"12\"
+ARG VARIANT
+FROM ${BASE}:$VERSION
+FROM alpine:${VARIANT}
+",
+            ),
+            vec!["debian:12".to_owned()],
+        );
+    }
+
+    #[test]
+    fn base_images_directories() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("web")).unwrap();
+        fs::create_dir_all(dir.path().join(".git")).unwrap();
+        fs::write(dir.path().join("Dockerfile"), "FROM alpine:3.20\n").unwrap();
+        fs::write(dir.path().join("web/dev.Dockerfile"), "FROM node:20\n").unwrap();
+        fs::write(dir.path().join(".git/Dockerfile"), "FROM debian:12\n").unwrap();
+        fs::write(dir.path().join("web/README.md"), "FROM ubuntu:24.04\n").unwrap();
+
+        assert_eq!(
+            base_images(&[dir.path()]).unwrap(),
+            ["alpine:3.20", "node:20"]
+                .iter()
+                .map(|image| (*image).to_owned())
+                .collect::<HashSet<_>>(),
+        );
+    }
+
+    #[test]
+    fn base_images_missing_directory() {
+        let dir = tempdir().unwrap();
+        assert!(base_images(&[dir.path().join("missing")]).is_err());
+    }
+}
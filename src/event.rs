@@ -0,0 +1,25 @@
+use {
+    log::{
+        kv::{Source, Value},
+        Level, Record,
+    },
+    std::fmt,
+};
+
+// Log a message for an event (e.g., `deletion`) along with other structured fields, which the JSON
+// log format includes as properties. This is a function rather than the key-value syntax of the
+// `log` macros, since Rustfmt can only format the latter if the macros look like function calls
+// [ref:format_macros].
+pub fn log(level: Level, event: &str, fields: &[(&str, Value)], message: fmt::Arguments) {
+    if level <= log::max_level() {
+        let sources: [&dyn Source; 2] = [&("event", event), &fields];
+        log::logger().log(
+            &Record::builder()
+                .level(level)
+                .target(module_path!())
+                .args(message)
+                .key_values(&sources)
+                .build(),
+        );
+    }
+}
@@ -0,0 +1,362 @@
+use {
+    crate::{
+        format::CodeStr,
+        score::{Expression, Variables},
+        state::decay_frequency,
+    },
+    std::{cmp::Ordering, io, time::Duration},
+};
+
+// The number of seconds in a day, for score expressions
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+// The number of bytes in a gigabyte, for score expressions
+const BYTES_PER_GB: f64 = 1_000_000_000.0;
+
+// What an eviction policy knows about an image
+pub struct Candidate {
+    pub created_since_epoch: Duration,
+    pub last_used_since_epoch: Duration,
+    pub uses: u64,
+    pub frequency: f64, // As of `last_used_since_epoch`; see `state::Image`.
+    pub size_bytes: u128,
+    pub tags: usize,
+    pub ancestors: usize, // The number of images this one is built on
+    pub in_use: bool,
+}
+
+// An eviction policy decides the order in which images are deleted. Ties are broken by the caller,
+// so policies only need to compare what they care about.
+pub trait EvictionPolicy {
+    // Compare two images, where `Ordering::Less` means `x` is deleted before `y`.
+    fn compare(&self, now: Duration, x: &Candidate, y: &Candidate) -> Ordering;
+}
+
+// Delete the least recently used images first.
+struct LeastRecentlyUsed;
+
+impl EvictionPolicy for LeastRecentlyUsed {
+    fn compare(&self, _: Duration, x: &Candidate, y: &Candidate) -> Ordering {
+        x.last_used_since_epoch.cmp(&y.last_used_since_epoch)
+    }
+}
+
+// Delete the least frequently used images first, with recent uses counting more than old ones.
+struct LeastFrequentlyUsed;
+
+impl EvictionPolicy for LeastFrequentlyUsed {
+    fn compare(&self, now: Duration, x: &Candidate, y: &Candidate) -> Ordering {
+        frequency(now, x).total_cmp(&frequency(now, y))
+    }
+}
+
+// Compute the usage frequency of an image as of now.
+fn frequency(now: Duration, candidate: &Candidate) -> f64 {
+    decay_frequency(
+        candidate.frequency,
+        now.saturating_sub(candidate.last_used_since_epoch),
+    )
+}
+
+// Delete the oldest images first, regardless of when they were used.
+struct FirstInFirstOut;
+
+impl EvictionPolicy for FirstInFirstOut {
+    fn compare(&self, _: Duration, x: &Candidate, y: &Candidate) -> Ordering {
+        x.created_since_epoch.cmp(&y.created_since_epoch)
+    }
+}
+
+// Delete the largest images first.
+struct LargestFirst;
+
+impl EvictionPolicy for LargestFirst {
+    fn compare(&self, _: Duration, x: &Candidate, y: &Candidate) -> Ordering {
+        y.size_bytes.cmp(&x.size_bytes)
+    }
+}
+
+// Delete the images with the largest product of size and time since last use first, so one big
+// stale image goes before many small ones.
+struct SizeWeighted;
+
+impl SizeWeighted {
+    fn score(now: Duration, candidate: &Candidate) -> u128 {
+        candidate.size_bytes.saturating_mul(
+            now.saturating_sub(candidate.last_used_since_epoch)
+                .as_secs()
+                .into(),
+        )
+    }
+}
+
+impl EvictionPolicy for SizeWeighted {
+    fn compare(&self, now: Duration, x: &Candidate, y: &Candidate) -> Ordering {
+        Self::score(now, y).cmp(&Self::score(now, x))
+    }
+}
+
+// Delete the images for which a user-supplied expression yields the highest scores first.
+impl EvictionPolicy for Expression {
+    fn compare(&self, now: Duration, x: &Candidate, y: &Candidate) -> Ordering {
+        self.evaluate(&variables(now, y))
+            .total_cmp(&self.evaluate(&variables(now, x)))
+    }
+}
+
+// Compute the variables of a score expression for an image. Scores don't need to be exact, so the
+// loss of precision is fine.
+#[allow(clippy::cast_precision_loss)]
+fn variables(now: Duration, candidate: &Candidate) -> Variables {
+    Variables {
+        age_days: now
+            .saturating_sub(candidate.last_used_since_epoch)
+            .as_secs_f64()
+            / SECONDS_PER_DAY,
+        created_days: now
+            .saturating_sub(candidate.created_since_epoch)
+            .as_secs_f64()
+            / SECONDS_PER_DAY,
+        size_gb: candidate.size_bytes as f64 / BYTES_PER_GB,
+        tags: candidate.tags as f64,
+        in_use: f64::from(u8::from(candidate.in_use)),
+        uses: candidate.uses as f64,
+        frequency: frequency(now, candidate),
+    }
+}
+
+// The eviction policies: the built-in ones, or a score expression
+#[derive(Clone, Debug, PartialEq)]
+pub enum Policy {
+    Lru,
+    Lfu,
+    Fifo,
+    LargestFirst,
+    SizeWeighted,
+    Score(Expression),
+}
+
+impl Policy {
+    // The names accepted by `from_str`
+    pub const NAMES: &'static [&'static str] =
+        &["lru", "lfu", "fifo", "largest-first", "size-weighted"];
+
+    // Parse a `Policy`.
+    pub fn from_str(policy: &str) -> io::Result<Self> {
+        match policy {
+            "lru" => Ok(Self::Lru),
+            "lfu" => Ok(Self::Lfu),
+            "fifo" => Ok(Self::Fifo),
+            "largest-first" => Ok(Self::LargestFirst),
+            "size-weighted" => Ok(Self::SizeWeighted),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid eviction policy {}.", policy.code_str()),
+            )),
+        }
+    }
+
+    // Get the implementation of the policy.
+    pub fn implementation(&self) -> &dyn EvictionPolicy {
+        match self {
+            Self::Lru => &LeastRecentlyUsed,
+            Self::Lfu => &LeastFrequentlyUsed,
+            Self::Fifo => &FirstInFirstOut,
+            Self::LargestFirst => &LargestFirst,
+            Self::SizeWeighted => &SizeWeighted,
+            Self::Score(expression) => expression,
+        }
+    }
+}
+
+// A way to order images which are otherwise equally due for deletion, e.g., after the first run,
+// when the images are assumed to have been used when they were created. Several of these are
+// applied in turn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+pub enum TieBreak {
+    MostAncestorsFirst,
+    FewestAncestorsFirst,
+    LargestFirst,
+    SmallestFirst,
+    OldestFirst,
+    NewestFirst,
+    MostTagsFirst,
+    FewestTagsFirst,
+}
+
+impl TieBreak {
+    // The names accepted by `from_str`
+    pub const NAMES: &'static [&'static str] = &[
+        "most-ancestors-first",
+        "fewest-ancestors-first",
+        "largest-first",
+        "smallest-first",
+        "oldest-first",
+        "newest-first",
+        "most-tags-first",
+        "fewest-tags-first",
+    ];
+
+    // Parse a `TieBreak`.
+    pub fn from_str(tie_break: &str) -> io::Result<Self> {
+        match tie_break {
+            "most-ancestors-first" => Ok(Self::MostAncestorsFirst),
+            "fewest-ancestors-first" => Ok(Self::FewestAncestorsFirst),
+            "largest-first" => Ok(Self::LargestFirst),
+            "smallest-first" => Ok(Self::SmallestFirst),
+            "oldest-first" => Ok(Self::OldestFirst),
+            "newest-first" => Ok(Self::NewestFirst),
+            "most-tags-first" => Ok(Self::MostTagsFirst),
+            "fewest-tags-first" => Ok(Self::FewestTagsFirst),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid tie-break {}.", tie_break.code_str()),
+            )),
+        }
+    }
+
+    // Compare two images, where `Ordering::Less` means `x` is deleted before `y`.
+    pub fn compare(self, x: &Candidate, y: &Candidate) -> Ordering {
+        match self {
+            Self::MostAncestorsFirst => y.ancestors.cmp(&x.ancestors),
+            Self::FewestAncestorsFirst => x.ancestors.cmp(&y.ancestors),
+            Self::LargestFirst => y.size_bytes.cmp(&x.size_bytes),
+            Self::SmallestFirst => x.size_bytes.cmp(&y.size_bytes),
+            Self::OldestFirst => x.created_since_epoch.cmp(&y.created_since_epoch),
+            Self::NewestFirst => y.created_since_epoch.cmp(&x.created_since_epoch),
+            Self::MostTagsFirst => y.tags.cmp(&x.tags),
+            Self::FewestTagsFirst => x.tags.cmp(&y.tags),
+        }
+    }
+}
+
+// Compare two images according to several tie-breaks in turn.
+pub fn break_tie(tie_breaks: &[TieBreak], x: &Candidate, y: &Candidate) -> Ordering {
+    tie_breaks
+        .iter()
+        .fold(Ordering::Equal, |ordering, tie_break| {
+            ordering.then_with(|| tie_break.compare(x, y))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{break_tie, Candidate, Policy, TieBreak},
+        crate::score::Expression,
+        std::{cmp::Ordering, time::Duration},
+    };
+
+    // An image which was created long ago but is used often
+    const OLD_POPULAR: Candidate = Candidate {
+        created_since_epoch: Duration::from_secs(0),
+        last_used_since_epoch: Duration::from_secs(90),
+        uses: 10,
+        frequency: 5.0,
+        size_bytes: 100,
+        tags: 1,
+        ancestors: 2,
+        in_use: true,
+    };
+
+    // An image which was created recently but has gone stale
+    const NEW_STALE: Candidate = Candidate {
+        created_since_epoch: Duration::from_secs(50),
+        last_used_since_epoch: Duration::from_secs(50),
+        uses: 1,
+        frequency: 1.0,
+        size_bytes: 1000,
+        tags: 2,
+        ancestors: 0,
+        in_use: false,
+    };
+
+    const NOW: Duration = Duration::from_secs(100);
+
+    fn compare(policy: &Policy) -> Ordering {
+        policy
+            .implementation()
+            .compare(NOW, &OLD_POPULAR, &NEW_STALE)
+    }
+
+    #[test]
+    fn lru() {
+        assert_eq!(compare(&Policy::Lru), Ordering::Greater);
+    }
+
+    #[test]
+    fn lfu() {
+        assert_eq!(compare(&Policy::Lfu), Ordering::Greater);
+    }
+
+    #[test]
+    fn fifo() {
+        assert_eq!(compare(&Policy::Fifo), Ordering::Less);
+    }
+
+    #[test]
+    fn largest_first() {
+        assert_eq!(compare(&Policy::LargestFirst), Ordering::Greater);
+    }
+
+    #[test]
+    fn size_weighted() {
+        assert_eq!(compare(&Policy::SizeWeighted), Ordering::Greater);
+    }
+
+    #[test]
+    fn score() {
+        let by_age = Policy::Score(Expression::parse("age_days").unwrap());
+        let by_tags_unless_in_use =
+            Policy::Score(Expression::parse("tags * (1 - in_use)").unwrap());
+
+        assert_eq!(compare(&by_age), Ordering::Greater);
+        assert_eq!(compare(&by_tags_unless_in_use), Ordering::Greater);
+    }
+
+    #[test]
+    fn tie_break() {
+        assert_eq!(
+            TieBreak::LargestFirst.compare(&OLD_POPULAR, &NEW_STALE),
+            Ordering::Greater,
+        );
+        assert_eq!(
+            TieBreak::SmallestFirst.compare(&OLD_POPULAR, &NEW_STALE),
+            Ordering::Less,
+        );
+        assert_eq!(
+            TieBreak::OldestFirst.compare(&OLD_POPULAR, &NEW_STALE),
+            Ordering::Less,
+        );
+        assert_eq!(
+            TieBreak::MostAncestorsFirst.compare(&OLD_POPULAR, &NEW_STALE),
+            Ordering::Less,
+        );
+        assert_eq!(
+            TieBreak::FewestTagsFirst.compare(&OLD_POPULAR, &NEW_STALE),
+            Ordering::Less,
+        );
+    }
+
+    #[test]
+    fn break_tie_in_turn() {
+        assert_eq!(break_tie(&[], &OLD_POPULAR, &NEW_STALE), Ordering::Equal);
+        assert_eq!(
+            break_tie(
+                &[TieBreak::LargestFirst, TieBreak::SmallestFirst],
+                &OLD_POPULAR,
+                &NEW_STALE,
+            ),
+            Ordering::Greater,
+        );
+        assert_eq!(
+            break_tie(
+                &[TieBreak::LargestFirst, TieBreak::OldestFirst],
+                &OLD_POPULAR,
+                &OLD_POPULAR,
+            ),
+            Ordering::Equal,
+        );
+    }
+}
@@ -1,4 +1,7 @@
-use colored::{control::SHOULD_COLORIZE, ColoredString, Colorize};
+use {
+    colored::{control::SHOULD_COLORIZE, ColoredString, Colorize},
+    std::io,
+};
 
 // This trait has a function for formatting "code-like" text, such as a file path. The reason it's
 // implemented as a trait and not just a function is so we can use it with method syntax, as in
@@ -19,13 +22,56 @@ impl CodeStr for str {
     }
 }
 
+// How subcommands print their results
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Output {
+    // Human-readable text
+    Text,
+
+    // JSON, for scripts
+    Json,
+}
+
+impl Output {
+    // The names accepted by `from_str`
+    pub const NAMES: &'static [&'static str] = &["text", "json"];
+
+    // Parse an `Output`.
+    pub fn from_str(output: &str) -> io::Result<Self> {
+        match output {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid output format {}.", output.code_str()),
+            )),
+        }
+    }
+
+    // The name which `from_str` accepts
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Json => "json",
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::format::CodeStr;
+    use crate::format::{CodeStr, Output};
 
     #[test]
     fn code_str_display() {
         // This test, like many others, depends on colors being disabled [ref:colorless_tests].
         assert_eq!(format!("{}", "foo".code_str()), "`foo`");
     }
+
+    #[test]
+    fn output_names() {
+        for name in Output::NAMES {
+            assert_eq!(Output::from_str(name).unwrap().name(), *name);
+        }
+        assert!(Output::from_str("yaml").is_err());
+    }
 }
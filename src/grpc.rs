@@ -0,0 +1,510 @@
+use crate::report::DeletedImage;
+
+#[cfg(feature = "grpc")]
+use {
+    crate::{
+        control,
+        format::{CodeStr, Output},
+        health, pause,
+        report::DeletionReason,
+    },
+    chrono::{DateTime, SecondsFormat, Utc},
+    std::{io, net::TcpListener, pin::Pin, sync::OnceLock, thread, time::SystemTime},
+    tokio::{runtime, sync::broadcast},
+    tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt},
+    tonic::{
+        transport::{server::TcpIncoming, Server},
+        Request, Response,
+    },
+};
+
+#[cfg(not(feature = "grpc"))]
+use std::io;
+
+// How many deletions are buffered for each client of `WatchDeletions`. Clients which fall further
+// behind than this miss some deletions.
+#[cfg(feature = "grpc")]
+const DELETIONS_CAPACITY: usize = 1024;
+
+// The messages and service defined in `proto/docuum.proto`, written as `tonic-build` would generate
+// them so that building Docuum doesn't require `protoc`
+#[cfg(feature = "grpc")]
+#[allow(clippy::pedantic, clippy::all)]
+mod proto {
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
+    pub struct Empty {}
+
+    #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+    pub struct Status {
+        #[prost(bool, tag = "1")]
+        pub healthy: bool,
+        #[prost(string, optional, tag = "2")]
+        pub last_successful_vacuum: Option<String>,
+        #[prost(bool, tag = "3")]
+        pub last_vacuum_failed: bool,
+        #[prost(uint64, optional, tag = "4")]
+        pub bytes: Option<u64>,
+        #[prost(uint64, optional, tag = "5")]
+        pub threshold_bytes: Option<u64>,
+        #[prost(bool, optional, tag = "6")]
+        pub events_connected: Option<bool>,
+        #[prost(bool, tag = "7")]
+        pub paused: bool,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+    pub struct ExplainRequest {
+        #[prost(string, tag = "1")]
+        pub image: String,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+    pub struct ExplainResponse {
+        #[prost(string, tag = "1")]
+        pub explanation: String,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+    pub struct Deletion {
+        #[prost(string, tag = "1")]
+        pub timestamp: String,
+        #[prost(string, tag = "2")]
+        pub id: String,
+        #[prost(string, repeated, tag = "3")]
+        pub repository_tags: Vec<String>,
+        #[prost(uint64, tag = "4")]
+        pub size_bytes: u64,
+        #[prost(enumeration = "DeletionReason", tag = "5")]
+        pub reason: i32,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum DeletionReason {
+        Unspecified = 0,
+        Threshold = 1,
+        MaxAge = 2,
+        MaxImages = 3,
+        MinFreeInodes = 4,
+        CriticalThreshold = 5,
+    }
+
+    pub mod docuum_server {
+        use {
+            tokio_stream::Stream,
+            tonic::{codegen::*, server::ServerStreamingService},
+        };
+
+        #[async_trait]
+        pub trait Docuum: Send + Sync + 'static {
+            async fn get_status(
+                &self,
+                request: tonic::Request<super::Empty>,
+            ) -> Result<tonic::Response<super::Status>, tonic::Status>;
+
+            async fn vacuum_now(
+                &self,
+                request: tonic::Request<super::Empty>,
+            ) -> Result<tonic::Response<super::Empty>, tonic::Status>;
+
+            async fn pause(
+                &self,
+                request: tonic::Request<super::Empty>,
+            ) -> Result<tonic::Response<super::Empty>, tonic::Status>;
+
+            async fn resume(
+                &self,
+                request: tonic::Request<super::Empty>,
+            ) -> Result<tonic::Response<super::Empty>, tonic::Status>;
+
+            async fn explain(
+                &self,
+                request: tonic::Request<super::ExplainRequest>,
+            ) -> Result<tonic::Response<super::ExplainResponse>, tonic::Status>;
+
+            type WatchDeletionsStream: Stream<Item = Result<super::Deletion, tonic::Status>>
+                + Send
+                + 'static;
+
+            async fn watch_deletions(
+                &self,
+                request: tonic::Request<super::Empty>,
+            ) -> Result<tonic::Response<Self::WatchDeletionsStream>, tonic::Status>;
+        }
+
+        #[derive(Debug)]
+        pub struct DocuumServer<T> {
+            inner: Arc<T>,
+        }
+
+        impl<T> DocuumServer<T> {
+            pub fn new(inner: T) -> Self {
+                Self {
+                    inner: Arc::new(inner),
+                }
+            }
+        }
+
+        impl<T> Clone for DocuumServer<T> {
+            fn clone(&self) -> Self {
+                Self {
+                    inner: self.inner.clone(),
+                }
+            }
+        }
+
+        // Define a unary method which forwards to the given method of the trait.
+        macro_rules! unary {
+            (
+                $svc:ident, $method:ident, $request:ty, $response:ty, $inner:expr, $req:expr $(,)?
+            ) => {{
+                struct $svc<T: Docuum>(Arc<T>);
+                impl<T: Docuum> tonic::server::UnaryService<$request> for $svc<T> {
+                    type Response = $response;
+                    type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                    fn call(&mut self, request: tonic::Request<$request>) -> Self::Future {
+                        let inner = Arc::clone(&self.0);
+                        Box::pin(async move { <T as Docuum>::$method(&inner, request).await })
+                    }
+                }
+                let inner = $inner;
+                let req = $req;
+                Box::pin(async move {
+                    let codec = tonic_prost::ProstCodec::default();
+                    let mut grpc = tonic::server::Grpc::new(codec);
+                    Ok(grpc.unary($svc(inner), req).await)
+                })
+            }};
+        }
+
+        // The streaming method, which forwards to `watch_deletions`
+        struct WatchDeletionsSvc<T: Docuum>(Arc<T>);
+
+        impl<T: Docuum> ServerStreamingService<super::Empty> for WatchDeletionsSvc<T> {
+            type Response = super::Deletion;
+            type ResponseStream = T::WatchDeletionsStream;
+            type Future = BoxFuture<tonic::Response<Self::ResponseStream>, tonic::Status>;
+            fn call(&mut self, request: tonic::Request<super::Empty>) -> Self::Future {
+                let inner = Arc::clone(&self.0);
+                Box::pin(async move { <T as Docuum>::watch_deletions(&inner, request).await })
+            }
+        }
+
+        impl<T, B> Service<http::Request<B>> for DocuumServer<T>
+        where
+            T: Docuum,
+            B: Body + Send + 'static,
+            B::Error: Into<StdError> + Send + 'static,
+        {
+            type Response = http::Response<tonic::body::Body>;
+            type Error = std::convert::Infallible;
+            type Future = BoxFuture<Self::Response, Self::Error>;
+
+            fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn call(&mut self, req: http::Request<B>) -> Self::Future {
+                let inner = self.inner.clone();
+                match req.uri().path() {
+                    "/docuum.v1.Docuum/GetStatus" => unary!(
+                        GetStatusSvc,
+                        get_status,
+                        super::Empty,
+                        super::Status,
+                        inner,
+                        req,
+                    ),
+                    "/docuum.v1.Docuum/VacuumNow" => unary!(
+                        VacuumNowSvc,
+                        vacuum_now,
+                        super::Empty,
+                        super::Empty,
+                        inner,
+                        req,
+                    ),
+                    "/docuum.v1.Docuum/Pause" => {
+                        unary!(PauseSvc, pause, super::Empty, super::Empty, inner, req)
+                    }
+                    "/docuum.v1.Docuum/Resume" => {
+                        unary!(ResumeSvc, resume, super::Empty, super::Empty, inner, req)
+                    }
+                    "/docuum.v1.Docuum/Explain" => unary!(
+                        ExplainSvc,
+                        explain,
+                        super::ExplainRequest,
+                        super::ExplainResponse,
+                        inner,
+                        req,
+                    ),
+                    "/docuum.v1.Docuum/WatchDeletions" => Box::pin(async move {
+                        let codec = tonic_prost::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec);
+                        Ok(grpc.server_streaming(WatchDeletionsSvc(inner), req).await)
+                    }),
+                    _ => Box::pin(async move {
+                        let mut response = http::Response::new(tonic::body::Body::default());
+                        let headers = response.headers_mut();
+                        headers.insert(
+                            tonic::Status::GRPC_STATUS,
+                            (tonic::Code::Unimplemented as i32).into(),
+                        );
+                        headers.insert(
+                            http::header::CONTENT_TYPE,
+                            tonic::metadata::GRPC_CONTENT_TYPE,
+                        );
+                        Ok(response)
+                    }),
+                }
+            }
+        }
+
+        impl<T> tonic::server::NamedService for DocuumServer<T> {
+            const NAME: &'static str = "docuum.v1.Docuum";
+        }
+    }
+}
+
+// Where deletions are broadcast to clients of `WatchDeletions`
+#[cfg(feature = "grpc")]
+fn deletions() -> &'static broadcast::Sender<proto::Deletion> {
+    static DELETIONS: OnceLock<broadcast::Sender<proto::Deletion>> = OnceLock::new();
+    DELETIONS.get_or_init(|| broadcast::channel(DELETIONS_CAPACITY).0)
+}
+
+// Convert the status reported by the health server into a message.
+#[cfg(feature = "grpc")]
+fn status_message(status: health::Status) -> proto::Status {
+    proto::Status {
+        healthy: status.healthy,
+        last_successful_vacuum: status.last_successful_vacuum,
+        last_vacuum_failed: status.last_vacuum_failed,
+        bytes: status
+            .bytes
+            .map(|bytes| u64::try_from(bytes).unwrap_or(u64::MAX)),
+        threshold_bytes: status
+            .threshold_bytes
+            .map(|bytes| u64::try_from(bytes).unwrap_or(u64::MAX)),
+        events_connected: status.events_connected,
+        paused: status.paused,
+    }
+}
+
+// Convert a deleted image into a message.
+#[cfg(feature = "grpc")]
+fn deletion_message(deleted_image: &DeletedImage, timestamp: &str) -> proto::Deletion {
+    proto::Deletion {
+        timestamp: timestamp.to_owned(),
+        id: deleted_image.id.clone(),
+        repository_tags: deleted_image.repository_tags.clone(),
+        size_bytes: u64::try_from(deleted_image.size_bytes).unwrap_or(u64::MAX),
+        reason: match deleted_image.reason {
+            DeletionReason::Threshold => proto::DeletionReason::Threshold,
+            DeletionReason::MaxAge => proto::DeletionReason::MaxAge,
+            DeletionReason::MaxImages => proto::DeletionReason::MaxImages,
+            DeletionReason::MinFreeInodes => proto::DeletionReason::MinFreeInodes,
+            DeletionReason::CriticalThreshold => proto::DeletionReason::CriticalThreshold,
+        }
+        .into(),
+    }
+}
+
+// Tell clients of `WatchDeletions` about deleted images.
+#[cfg(feature = "grpc")]
+pub fn publish(deleted_images: &[DeletedImage]) {
+    let timestamp =
+        DateTime::<Utc>::from(SystemTime::now()).to_rfc3339_opts(SecondsFormat::Secs, true);
+    for deleted_image in deleted_images {
+        // This only fails if nobody is watching, which is fine.
+        let _ = deletions().send(deletion_message(deleted_image, &timestamp));
+    }
+}
+
+// Without gRPC support, nobody can be watching.
+#[cfg(not(feature = "grpc"))]
+pub fn publish(_: &[DeletedImage]) {}
+
+// The implementation of the service, which forwards to the same machinery as the control socket
+#[cfg(feature = "grpc")]
+struct Service;
+
+#[cfg(feature = "grpc")]
+#[tonic::async_trait]
+impl proto::docuum_server::Docuum for Service {
+    async fn get_status(
+        &self,
+        _: Request<proto::Empty>,
+    ) -> Result<Response<proto::Status>, tonic::Status> {
+        Ok(Response::new(status_message(health::status())))
+    }
+
+    async fn vacuum_now(
+        &self,
+        _: Request<proto::Empty>,
+    ) -> Result<Response<proto::Empty>, tonic::Status> {
+        control::request_vacuum();
+        Ok(Response::new(proto::Empty {}))
+    }
+
+    async fn pause(
+        &self,
+        _: Request<proto::Empty>,
+    ) -> Result<Response<proto::Empty>, tonic::Status> {
+        pause::set(true);
+        Ok(Response::new(proto::Empty {}))
+    }
+
+    async fn resume(
+        &self,
+        _: Request<proto::Empty>,
+    ) -> Result<Response<proto::Empty>, tonic::Status> {
+        pause::set(false);
+        Ok(Response::new(proto::Empty {}))
+    }
+
+    async fn explain(
+        &self,
+        request: Request<proto::ExplainRequest>,
+    ) -> Result<Response<proto::ExplainResponse>, tonic::Status> {
+        let image = request.into_inner().image;
+        if image.trim().is_empty() {
+            return Err(tonic::Status::invalid_argument(
+                "Which image should be explained?",
+            ));
+        }
+
+        // The daemon only explains images between vacuums, so waiting for it blocks.
+        tokio::task::spawn_blocking(move || control::request_explanation(image, Output::Text))
+            .await
+            .map_err(|error| tonic::Status::internal(error.to_string()))?
+            .map(|explanation| Response::new(proto::ExplainResponse { explanation }))
+            .map_err(|error| match error.kind() {
+                io::ErrorKind::TimedOut => tonic::Status::deadline_exceeded(error.to_string()),
+                _ => tonic::Status::failed_precondition(error.to_string()),
+            })
+    }
+
+    type WatchDeletionsStream =
+        Pin<Box<dyn Stream<Item = Result<proto::Deletion, tonic::Status>> + Send>>;
+
+    async fn watch_deletions(
+        &self,
+        _: Request<proto::Empty>,
+    ) -> Result<Response<Self::WatchDeletionsStream>, tonic::Status> {
+        // Deletions which a slow client missed are skipped.
+        Ok(Response::new(Box::pin(
+            BroadcastStream::new(deletions().subscribe())
+                .filter_map(|deletion| deletion.ok().map(Ok)),
+        )))
+    }
+}
+
+// Start serving the gRPC API on a background thread.
+#[cfg(feature = "grpc")]
+pub fn serve(address: &str) -> io::Result<()> {
+    let error_context = |error: io::Error| {
+        io::Error::new(
+            error.kind(),
+            format!(
+                "Unable to start the gRPC server on {}. Details: {}",
+                address.code_str(),
+                error,
+            ),
+        )
+    };
+
+    // Bind here rather than on the background thread, so errors are reported right away.
+    let listener = TcpListener::bind(address).map_err(error_context)?;
+    listener.set_nonblocking(true).map_err(error_context)?;
+    let runtime = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(error_context)?;
+
+    info!(
+        "Serving the gRPC API on {}\u{2026}",
+        listener.local_addr()?.to_string().code_str(),
+    );
+
+    thread::spawn(move || {
+        if let Err(error) = runtime.block_on(async move {
+            Server::builder()
+                .add_service(proto::docuum_server::DocuumServer::new(Service))
+                .serve_with_incoming(TcpIncoming::from(tokio::net::TcpListener::from_std(
+                    listener,
+                )?))
+                .await
+                .map_err(io::Error::other)
+        }) {
+            error!("The gRPC server stopped. Details: {error}");
+        }
+    });
+
+    Ok(())
+}
+
+// Without gRPC support, there's nothing to serve.
+#[cfg(not(feature = "grpc"))]
+pub fn serve(_: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Docuum was built without gRPC support. Enable the `grpc` feature to use it.",
+    ))
+}
+
+#[cfg(all(test, feature = "grpc"))]
+mod tests {
+    use {
+        super::{deletion_message, proto, status_message},
+        crate::{
+            health,
+            report::{DeletedImage, DeletionReason},
+        },
+    };
+
+    #[test]
+    fn status_message_fields() {
+        assert_eq!(
+            status_message(health::Status {
+                healthy: true,
+                last_successful_vacuum: Some("2024-05-02T12:00:00Z".to_owned()),
+                last_vacuum_failed: false,
+                bytes: Some(100),
+                threshold_bytes: Some(u128::MAX),
+                events_connected: None,
+                paused: true,
+            }),
+            proto::Status {
+                healthy: true,
+                last_successful_vacuum: Some("2024-05-02T12:00:00Z".to_owned()),
+                last_vacuum_failed: false,
+                bytes: Some(100),
+                threshold_bytes: Some(u64::MAX),
+                events_connected: None,
+                paused: true,
+            },
+        );
+    }
+
+    #[test]
+    fn deletion_message_fields() {
+        assert_eq!(
+            deletion_message(
+                &DeletedImage {
+                    id: "sha256:0".to_owned(),
+                    repository_tags: vec!["alpine:latest".to_owned()],
+                    size_bytes: 42,
+                    reason: DeletionReason::MaxAge,
+                },
+                "2024-05-02T12:00:00Z",
+            ),
+            proto::Deletion {
+                timestamp: "2024-05-02T12:00:00Z".to_owned(),
+                id: "sha256:0".to_owned(),
+                repository_tags: vec!["alpine:latest".to_owned()],
+                size_bytes: 42,
+                reason: proto::DeletionReason::MaxAge.into(),
+            },
+        );
+    }
+}
@@ -0,0 +1,345 @@
+use {
+    crate::{format::CodeStr, ls, pause, report::Report, run::ListedImage, state::State},
+    chrono::{DateTime, SecondsFormat, Utc},
+    serde::Serialize,
+    std::{
+        io::{self, BufRead, BufReader, Write},
+        net::{TcpListener, TcpStream},
+        sync::{Mutex, PoisonError},
+        thread,
+        time::{Duration, SystemTime},
+    },
+};
+
+// How long to wait for a client to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// What the health server reports
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Status {
+    // Whether Docuum is working as intended, as also reported by `/healthz`
+    pub healthy: bool,
+
+    // When the last successful vacuum finished, in RFC 3339 format
+    pub last_successful_vacuum: Option<String>,
+
+    // Whether the last vacuum failed
+    pub last_vacuum_failed: bool,
+
+    // The space usage and threshold at the end of the last vacuum which measured them
+    pub bytes: Option<u128>,
+    pub threshold_bytes: Option<u128>,
+
+    // Whether the stream of events from the container runtime is connected. This is `None` if the
+    // container runtime is polled instead, or if we haven't tried to connect yet.
+    pub events_connected: Option<bool>,
+
+    // Whether deletions are paused
+    pub paused: bool,
+}
+
+// The current status, updated as Docuum runs
+static STATUS: Mutex<Status> = Mutex::new(Status {
+    healthy: true,
+    last_successful_vacuum: None,
+    last_vacuum_failed: false,
+    bytes: None,
+    threshold_bytes: None,
+    events_connected: None,
+    paused: false,
+});
+
+// What Docuum saw during the last vacuum, as JSON. These are `None` until the first vacuum.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Snapshot {
+    // The state, as served at `/state`
+    state: Option<String>,
+
+    // The images which remain, in the order in which they would be deleted, as served at `/images`
+    images: Option<String>,
+
+    // The report of the vacuum, as served at `/vacuum`
+    vacuum: Option<String>,
+}
+
+// The snapshot of the last vacuum
+static SNAPSHOT: Mutex<Snapshot> = Mutex::new(Snapshot {
+    state: None,
+    images: None,
+    vacuum: None,
+});
+
+// Update the snapshot. A poisoned lock is recovered from, since each field is replaced at once.
+fn update_snapshot(f: impl FnOnce(&mut Snapshot)) {
+    f(&mut SNAPSHOT.lock().unwrap_or_else(PoisonError::into_inner));
+}
+
+// Update the status. A poisoned lock is recovered from, since the status is always consistent.
+fn update(f: impl FnOnce(&mut Status)) {
+    let mut status = STATUS.lock().unwrap_or_else(PoisonError::into_inner);
+    f(&mut status);
+    status.healthy = !status.last_vacuum_failed && status.events_connected != Some(false);
+}
+
+// Record the outcome of a vacuum.
+pub fn record_vacuum(report: &Report, succeeded: bool) {
+    update(|status| {
+        status.last_vacuum_failed = !succeeded;
+        if succeeded {
+            status.last_successful_vacuum = Some(
+                DateTime::<Utc>::from(SystemTime::now()).to_rfc3339_opts(SecondsFormat::Secs, true),
+            );
+        }
+        if report.bytes.is_some() {
+            status.bytes = report.bytes;
+            status.threshold_bytes = report.threshold_bytes;
+        }
+    });
+
+    // The `unwrap` is safe because `Report` always serializes.
+    let vacuum = serde_json::to_string(report).unwrap();
+    update_snapshot(|snapshot| snapshot.vacuum = Some(vacuum));
+}
+
+// Record the state at the end of a vacuum.
+pub fn record_state(state: &State) {
+    // The `unwrap` is safe because `State` always serializes.
+    let state = serde_json::to_string(state).unwrap();
+    update_snapshot(|snapshot| snapshot.state = Some(state));
+}
+
+// Record the images which remain after a vacuum, in the order in which they would be deleted.
+pub fn record_images(images: &[ListedImage]) {
+    let images = ls::json(images);
+    update_snapshot(|snapshot| snapshot.images = Some(images));
+}
+
+// Record whether the stream of events is connected, or `None` if the container runtime is polled.
+pub fn record_events_connected(connected: Option<bool>) {
+    update(|status| status.events_connected = connected);
+}
+
+// Record that the stream of events was lost, if there was one.
+pub fn record_events_disconnected() {
+    update(|status| {
+        if status.events_connected.is_some() {
+            status.events_connected = Some(false);
+        }
+    });
+}
+
+// Take a snapshot of the current status.
+pub fn status() -> Status {
+    let mut status = STATUS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    status.paused = pause::paused();
+    status
+}
+
+// The current status as JSON, as served at `/status`
+pub fn status_json() -> String {
+    // The `unwrap` is safe because `Status` always serializes.
+    serde_json::to_string(&status()).unwrap()
+}
+
+// Serve a part of the snapshot, which is `null` until the first vacuum.
+fn snapshot_response(json: Option<&String>) -> (&'static str, &'static str, String) {
+    (
+        "200 OK",
+        "application/json",
+        format!("{}\n", json.map_or("null", String::as_str)),
+    )
+}
+
+// Determine the response to a request line such as `GET /healthz HTTP/1.1`. Returns the status,
+// the content type, and the body.
+fn respond(
+    request_line: &str,
+    status: &Status,
+    snapshot: &Snapshot,
+) -> (&'static str, &'static str, String) {
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return ("400 Bad Request", "text/plain", "Bad request.\n".to_owned());
+    };
+
+    if method != "GET" && method != "HEAD" {
+        return (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method not allowed.\n".to_owned(),
+        );
+    }
+
+    // Ignore any query string.
+    match path.split('?').next().unwrap_or(path) {
+        "/healthz" => {
+            if status.healthy {
+                ("200 OK", "text/plain", "OK\n".to_owned())
+            } else {
+                (
+                    "503 Service Unavailable",
+                    "text/plain",
+                    "Unhealthy.\n".to_owned(),
+                )
+            }
+        }
+        "/status" => (
+            "200 OK",
+            "application/json",
+            // The `unwrap` is safe because `Status` always serializes.
+            format!("{}\n", serde_json::to_string(status).unwrap()),
+        ),
+        "/state" => snapshot_response(snapshot.state.as_ref()),
+        "/images" => snapshot_response(snapshot.images.as_ref()),
+        "/vacuum" => snapshot_response(snapshot.vacuum.as_ref()),
+        _ => ("404 Not Found", "text/plain", "Not found.\n".to_owned()),
+    }
+}
+
+// Handle a single connection.
+fn handle(stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let snapshot = SNAPSHOT
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    let (status_line, content_type, body) = respond(&request_line, &status(), &snapshot);
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {status_line}\r\n\
+            Content-Type: {content_type}\r\n\
+            Content-Length: {}\r\n\
+            Connection: close\r\n\r\n",
+        body.len(),
+    )?;
+    if !request_line.starts_with("HEAD ") {
+        stream.write_all(body.as_bytes())?;
+    }
+    stream.flush()
+}
+
+// Start serving `/healthz`, `/status`, and the snapshot of the last vacuum on a background thread.
+pub fn serve(address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address).map_err(|error| {
+        io::Error::new(
+            error.kind(),
+            format!(
+                "Unable to start the health server on {}. Details: {}",
+                address.code_str(),
+                error,
+            ),
+        )
+    })?;
+
+    info!(
+        "Serving health checks on {}\u{2026}",
+        listener.local_addr()?.to_string().code_str(),
+    );
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            if let Err(error) = stream.and_then(handle) {
+                debug!("Unable to respond to a health check. Details: {error}");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{respond, Snapshot, Status};
+
+    fn status(healthy: bool) -> Status {
+        Status {
+            healthy,
+            last_successful_vacuum: Some("2024-05-02T12:00:00Z".to_owned()),
+            last_vacuum_failed: !healthy,
+            bytes: Some(100),
+            threshold_bytes: Some(200),
+            events_connected: Some(true),
+            paused: false,
+        }
+    }
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            state: None,
+            images: Some("[]".to_owned()),
+            vacuum: None,
+        }
+    }
+
+    #[test]
+    fn respond_healthy() {
+        assert_eq!(
+            respond("GET /healthz HTTP/1.1\r\n", &status(true), &snapshot()).0,
+            "200 OK",
+        );
+    }
+
+    #[test]
+    fn respond_unhealthy() {
+        assert_eq!(
+            respond("GET /healthz HTTP/1.1\r\n", &status(false), &snapshot()).0,
+            "503 Service Unavailable",
+        );
+    }
+
+    #[test]
+    fn respond_status() {
+        assert_eq!(
+            respond(
+                "GET /status?pretty HTTP/1.1\r\n",
+                &status(true),
+                &snapshot(),
+            )
+            .2,
+            "{\"healthy\":true,\"last_successful_vacuum\":\"2024-05-02T12:00:00Z\",\
+                \"last_vacuum_failed\":false,\"bytes\":100,\"threshold_bytes\":200,\
+                \"events_connected\":true,\"paused\":false}\n",
+        );
+    }
+
+    #[test]
+    fn respond_snapshot() {
+        assert_eq!(
+            respond("GET /images HTTP/1.1\r\n", &status(true), &snapshot()),
+            ("200 OK", "application/json", "[]\n".to_owned()),
+        );
+    }
+
+    #[test]
+    fn respond_snapshot_before_first_vacuum() {
+        assert_eq!(
+            respond("GET /state HTTP/1.1\r\n", &status(true), &snapshot()),
+            ("200 OK", "application/json", "null\n".to_owned()),
+        );
+    }
+
+    #[test]
+    fn respond_not_found() {
+        assert_eq!(
+            respond("GET / HTTP/1.1\r\n", &status(true), &snapshot()).0,
+            "404 Not Found",
+        );
+    }
+
+    #[test]
+    fn respond_method_not_allowed() {
+        assert_eq!(
+            respond("POST /healthz HTTP/1.1\r\n", &status(true), &snapshot()).0,
+            "405 Method Not Allowed",
+        );
+    }
+}
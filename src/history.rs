@@ -0,0 +1,201 @@
+use {
+    crate::{
+        format::CodeStr,
+        report::{DeletedImage, DeletionReason},
+        state,
+    },
+    chrono::{DateTime, SecondsFormat, Utc},
+    serde::{Deserialize, Serialize},
+    std::{
+        fs::{create_dir_all, read_to_string, OpenOptions},
+        io::{self, Write},
+        path::PathBuf,
+        time::{Duration, SystemTime},
+    },
+    tempfile::NamedTempFile,
+};
+
+// A line of the deletion history
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    // When the image was deleted, as an RFC 3339 timestamp
+    pub timestamp: String,
+
+    pub id: String,
+    pub repository_tags: Vec<String>,
+    pub size_bytes: u128,
+    pub reason: DeletionReason,
+}
+
+// Where the deletion history is kept on disk, alongside the state
+fn path() -> Option<PathBuf> {
+    // [tag:history_path_has_parent]
+    state::path().map(|path| path.with_file_name("history.jsonl"))
+}
+
+// Format a deleted image as a line of the deletion history.
+fn line(deleted_image: &DeletedImage, timestamp: &str) -> String {
+    // The `unwrap` is safe because serialization should never fail.
+    serde_json::to_string(&Entry {
+        timestamp: timestamp.to_owned(),
+        id: deleted_image.id.clone(),
+        repository_tags: deleted_image.repository_tags.clone(),
+        size_bytes: deleted_image.size_bytes,
+        reason: deleted_image.reason,
+    })
+    .unwrap()
+}
+
+// Determine whether a line of the deletion history was recorded before the cutoff. Lines we can't
+// make sense of are kept, since the history is meant to be a reliable record.
+fn expired(line: &str, cutoff: DateTime<Utc>) -> bool {
+    serde_json::from_str::<Entry>(line)
+        .ok()
+        .and_then(|entry| DateTime::parse_from_rfc3339(&entry.timestamp).ok())
+        .is_some_and(|timestamp| timestamp < cutoff)
+}
+
+// Read the deletion history, from oldest to newest. Lines we can't make sense of are skipped.
+pub fn entries() -> io::Result<Vec<Entry>> {
+    // Check if we have a path.
+    let Some(path) = path() else {
+        // Fail if we don't have a path.
+        return Err(io::Error::other("Unable to locate data directory."));
+    };
+
+    let contents = match read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(error) => return Err(error),
+    };
+
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+// Append the deleted images to the deletion history, first dropping any entries which are older
+// than `max_age`.
+pub fn record(deleted_images: &[DeletedImage], max_age: Duration) -> io::Result<()> {
+    // Check if we have a path.
+    let Some(path) = path() else {
+        // Fail if we don't have a path.
+        return Err(io::Error::other("Unable to locate data directory."));
+    };
+
+    // Log what we're trying to do in case an error occurs.
+    trace!(
+        "Updating the deletion history in {}\u{2026}",
+        path.to_string_lossy().code_str(),
+    );
+
+    let now = SystemTime::now();
+    let timestamp = DateTime::<Utc>::from(now).to_rfc3339_opts(SecondsFormat::Secs, true);
+    let new_lines = deleted_images
+        .iter()
+        .map(|deleted_image| line(deleted_image, &timestamp))
+        .collect::<Vec<_>>();
+
+    // Find the existing entries which have outlived the retention period.
+    let existing = match read_to_string(&path) {
+        Ok(existing) => existing,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(error) => return Err(error),
+    };
+    let cutoff = DateTime::<Utc>::from(now - max_age);
+    let retained_lines = existing
+        .lines()
+        .filter(|line| !expired(line, cutoff))
+        .collect::<Vec<_>>();
+
+    // The `unwrap` is safe due to [ref:history_path_has_parent].
+    let parent = path.parent().unwrap().to_owned();
+
+    if retained_lines.len() == existing.lines().count() {
+        // Nothing has expired, so we just append the new entries, if there are any.
+        if new_lines.is_empty() {
+            return Ok(());
+        }
+
+        create_dir_all(&parent)?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        for line in new_lines {
+            writeln!(file, "{line}")?;
+        }
+        file.flush()?;
+    } else {
+        // Some entries have expired, so we rewrite the file without them.
+        let mut temp_file = NamedTempFile::new_in(parent)?;
+        for line in retained_lines
+            .iter()
+            .copied()
+            .chain(new_lines.iter().map(String::as_str))
+        {
+            writeln!(temp_file, "{line}")?;
+        }
+        temp_file.flush()?;
+        temp_file.persist(path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{expired, line},
+        crate::report::{DeletedImage, DeletionReason},
+        chrono::{DateTime, Utc},
+    };
+
+    fn deleted_image() -> DeletedImage {
+        DeletedImage {
+            id: "sha256:0".to_owned(),
+            repository_tags: vec!["alpine:latest".to_owned()],
+            size_bytes: 42,
+            reason: DeletionReason::Threshold,
+        }
+    }
+
+    #[test]
+    fn line_json() {
+        assert_eq!(
+            line(&deleted_image(), "2024-05-02T12:00:00Z"),
+            concat!(
+                r#"{"timestamp":"2024-05-02T12:00:00Z","id":"sha256:0","#,
+                r#""repository_tags":["alpine:latest"],"size_bytes":42,"reason":"threshold"}"#,
+            ),
+        );
+    }
+
+    #[test]
+    fn expired_before_cutoff() {
+        let cutoff = DateTime::parse_from_rfc3339("2024-05-02T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert!(expired(
+            &line(&deleted_image(), "2024-05-01T12:00:00Z"),
+            cutoff,
+        ));
+    }
+
+    #[test]
+    fn expired_after_cutoff() {
+        let cutoff = DateTime::parse_from_rfc3339("2024-05-02T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert!(!expired(
+            &line(&deleted_image(), "2024-05-03T12:00:00Z"),
+            cutoff,
+        ));
+    }
+
+    #[test]
+    fn expired_malformed() {
+        assert!(!expired("not json", Utc::now()));
+    }
+}
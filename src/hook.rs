@@ -0,0 +1,171 @@
+use {
+    crate::{
+        format::CodeStr,
+        report::{DeletedImage, Report},
+    },
+    serde::Serialize,
+    std::{
+        io::{self, Write},
+        process::{Command, ExitStatus, Stdio},
+    },
+};
+
+// Build the command which runs a hook with the shell of the platform.
+fn shell_command(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.args(["-c", command]);
+        shell
+    }
+}
+
+// Run a hook with the given environment variables, passing it the given payload as JSON on
+// standard input, and wait for it to exit. Its standard error is passed through, so its messages
+// end up next to Docuum's, and its standard output is discarded.
+fn run(
+    command: &str,
+    environment: &[(&str, String)],
+    payload: &impl Serialize,
+) -> io::Result<ExitStatus> {
+    let payload = serde_json::to_vec(payload).map_err(io::Error::other)?;
+
+    let mut child = shell_command(command)
+        .envs(environment.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!(
+                    "Unable to run hook {}. Details: {}",
+                    command.code_str(),
+                    error,
+                ),
+            )
+        })?;
+
+    // The `unwrap` is safe because standard input was piped above. The handle is dropped right
+    // away to signal the end of the payload. A hook which doesn't read its input may exit before
+    // the payload is written, which is fine.
+    if let Err(error) = child.stdin.take().unwrap().write_all(&payload) {
+        if error.kind() != io::ErrorKind::BrokenPipe {
+            return Err(error);
+        }
+    }
+
+    child.wait()
+}
+
+// The environment variables which describe an image to the pre-delete hook
+fn pre_delete_environment(image: &DeletedImage) -> Vec<(&'static str, String)> {
+    vec![
+        ("DOCUUM_IMAGE_ID", image.id.clone()),
+        ("DOCUUM_REPOSITORY_TAGS", image.repository_tags.join(" ")),
+        ("DOCUUM_SIZE_BYTES", image.size_bytes.to_string()),
+        (
+            "DOCUUM_REASON",
+            serde_json::to_value(image.reason)
+                .ok()
+                .and_then(|reason| reason.as_str().map(ToOwned::to_owned))
+                .unwrap_or_default(),
+        ),
+    ]
+}
+
+// The environment variables which summarize a vacuum for the post-vacuum hook
+fn post_vacuum_environment(report: &Report) -> Vec<(&'static str, String)> {
+    vec![
+        (
+            "DOCUUM_DELETED_IMAGES",
+            report.deleted_images.len().to_string(),
+        ),
+        ("DOCUUM_RECLAIMED_BYTES", report.reclaimed_bytes.to_string()),
+        ("DOCUUM_ERRORS", report.errors.len().to_string()),
+    ]
+}
+
+// Ask the pre-delete hook whether an image may be deleted. The hook vetoes the deletion by exiting
+// with a non-zero status.
+pub fn pre_delete(command: &str, image: &DeletedImage) -> io::Result<bool> {
+    run(command, &pre_delete_environment(image), image).map(|status| status.success())
+}
+
+// Tell the post-vacuum hook what happened during a vacuum.
+pub fn post_vacuum(command: &str, report: &Report) -> io::Result<()> {
+    let status = run(command, &post_vacuum_environment(report), report)?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "The post-vacuum hook {} failed with {}.",
+            command.code_str(),
+            status,
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{post_vacuum_environment, pre_delete_environment},
+        crate::report::{DeletedImage, DeletionReason, Report},
+    };
+
+    fn deleted_image() -> DeletedImage {
+        DeletedImage {
+            id: "sha256:0".to_owned(),
+            repository_tags: vec!["alpine:3.20".to_owned(), "alpine:latest".to_owned()],
+            size_bytes: 42,
+            reason: DeletionReason::MaxImages,
+        }
+    }
+
+    #[test]
+    fn pre_delete_environment_image() {
+        assert_eq!(
+            pre_delete_environment(&deleted_image()),
+            vec![
+                ("DOCUUM_IMAGE_ID", "sha256:0".to_owned()),
+                (
+                    "DOCUUM_REPOSITORY_TAGS",
+                    "alpine:3.20 alpine:latest".to_owned(),
+                ),
+                ("DOCUUM_SIZE_BYTES", "42".to_owned()),
+                ("DOCUUM_REASON", "max_images".to_owned()),
+            ],
+        );
+    }
+
+    #[test]
+    fn post_vacuum_environment_report() {
+        let mut report = Report::default();
+        report.deleted_image(deleted_image());
+        report.errors.push("Uh oh.".to_owned());
+
+        assert_eq!(
+            post_vacuum_environment(&report),
+            vec![
+                ("DOCUUM_DELETED_IMAGES", "1".to_owned()),
+                ("DOCUUM_RECLAIMED_BYTES", "42".to_owned()),
+                ("DOCUUM_ERRORS", "1".to_owned()),
+            ],
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pre_delete_veto() {
+        let image = deleted_image();
+
+        assert!(super::pre_delete("true", &image).unwrap());
+        assert!(!super::pre_delete("false", &image).unwrap());
+        assert!(!super::pre_delete("test \"$DOCUUM_IMAGE_ID\" != sha256:0", &image).unwrap());
+        assert!(!super::pre_delete("! grep -q max_images", &image).unwrap());
+    }
+}
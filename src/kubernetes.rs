@@ -0,0 +1,352 @@
+use {
+    crate::format::CodeStr,
+    byte_unit::Byte,
+    serde::Deserialize,
+    std::{
+        collections::{HashMap, HashSet},
+        io,
+        process::{Command, Stdio},
+    },
+};
+
+// The output of `kubectl get pods --output json`
+#[derive(Deserialize, Debug)]
+struct PodList {
+    items: Vec<Pod>,
+}
+
+// A pod in the output of `kubectl get pods --output json`
+#[derive(Deserialize, Debug)]
+struct Pod {
+    spec: PodSpec,
+}
+
+// The `spec` field of a pod
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PodSpec {
+    #[serde(default)]
+    containers: Vec<Container>,
+
+    #[serde(default)]
+    init_containers: Vec<Container>,
+
+    #[serde(default)]
+    ephemeral_containers: Vec<Container>,
+}
+
+// A container in the `spec` of a pod
+#[derive(Deserialize, Debug)]
+struct Container {
+    image: String,
+}
+
+// The output of the kubelet's `/configz` endpoint
+#[derive(Deserialize, Debug)]
+struct Configz {
+    kubeletconfig: KubeletConfiguration,
+}
+
+// The configuration of the kubelet, as far as image garbage collection and eviction are concerned.
+// The defaults are the kubelet's.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct KubeletConfiguration {
+    #[serde(
+        rename = "imageGCHighThresholdPercent",
+        default = "default_high_threshold"
+    )]
+    image_gc_high_threshold_percent: u8,
+
+    #[serde(
+        rename = "imageGCLowThresholdPercent",
+        default = "default_low_threshold"
+    )]
+    image_gc_low_threshold_percent: u8,
+
+    #[serde(default = "default_eviction_hard")]
+    eviction_hard: HashMap<String, String>,
+
+    #[serde(default)]
+    eviction_soft: HashMap<String, String>,
+}
+
+fn default_high_threshold() -> u8 {
+    85
+}
+
+fn default_low_threshold() -> u8 {
+    80
+}
+
+fn default_eviction_hard() -> HashMap<String, String> {
+    [("imagefs.available", "15%"), ("nodefs.available", "10%")]
+        .into_iter()
+        .map(|(signal, quantity)| (signal.to_owned(), quantity.to_owned()))
+        .collect()
+}
+
+// The eviction signals which concern the filesystem the images are stored on. Without a dedicated
+// image filesystem, the kubelet uses the node filesystem for images.
+const EVICTION_SIGNALS: &[&str] = &["imagefs.available", "nodefs.available"];
+
+// The minimum amount of space the kubelet keeps available on a filesystem before it evicts pods
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MinAvailable {
+    Fraction(f64),
+    Bytes(Byte),
+}
+
+// The thresholds of the kubelet which concern the filesystem the images are stored on
+#[derive(Debug, PartialEq)]
+pub struct KubeletThresholds {
+    // The percentage of the disk usage above which the kubelet starts deleting images
+    pub image_gc_high_percent: u8,
+
+    // The percentage of the disk usage down to which the kubelet deletes images
+    pub image_gc_low_percent: u8,
+
+    // The hard and soft eviction thresholds
+    pub eviction: Vec<MinAvailable>,
+}
+
+// Collect the distinct image references from the output of `kubectl get pods --output json`.
+fn pod_images_from_json(json: &[u8]) -> io::Result<HashSet<String>> {
+    let pod_list: PodList = serde_json::from_slice(json).map_err(io::Error::other)?;
+
+    Ok(pod_list
+        .items
+        .into_iter()
+        .flat_map(|pod| {
+            pod.spec
+                .containers
+                .into_iter()
+                .chain(pod.spec.init_containers)
+                .chain(pod.spec.ephemeral_containers)
+        })
+        .map(|container| container.image)
+        .collect())
+}
+
+// Ask the Kubernetes API for the images referenced by the pods scheduled on a node, including pods
+// whose images haven't been pulled yet. `kubectl` finds the API server via its usual configuration,
+// which includes the service account of the pod it runs in.
+pub fn pod_images(node: &str) -> io::Result<HashSet<String>> {
+    let output = Command::new("kubectl")
+        .args([
+            "get",
+            "pods",
+            "--all-namespaces",
+            "--field-selector",
+            &format!("spec.nodeName={node}"),
+            "--output",
+            "json",
+        ])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Unable to determine the images of the pods on Kubernetes node {}.",
+            node.code_str(),
+        )));
+    }
+
+    pod_images_from_json(&output.stdout)
+}
+
+// Parse the quantity of an eviction threshold, e.g., `15%` or `1Gi`.
+fn min_available(quantity: &str) -> io::Result<MinAvailable> {
+    let min_available = match quantity.strip_suffix('%') {
+        Some(percent) => percent
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|percent| (0.0_f64..=100.0_f64).contains(percent))
+            .map(|percent| MinAvailable::Fraction(percent / 100.0)),
+        None => Byte::from_str(quantity.trim())
+            .ok()
+            .map(MinAvailable::Bytes),
+    };
+
+    min_available.ok_or_else(|| {
+        io::Error::other(format!(
+            "Invalid eviction threshold {}.",
+            quantity.code_str(),
+        ))
+    })
+}
+
+// Read the image garbage collection and eviction thresholds from the output of the kubelet's
+// `/configz` endpoint.
+pub fn kubelet_thresholds_from_json(json: &[u8]) -> io::Result<KubeletThresholds> {
+    let configz: Configz = serde_json::from_slice(json).map_err(io::Error::other)?;
+    let high = configz.kubeletconfig.image_gc_high_threshold_percent;
+    let low = configz.kubeletconfig.image_gc_low_threshold_percent;
+
+    // The kubelet validates these too, but the configuration can't be trusted blindly.
+    if low > high || high > 100 {
+        return Err(io::Error::other(format!(
+            "Invalid image garbage collection thresholds {} and {}.",
+            format!("{high}%").code_str(),
+            format!("{low}%").code_str(),
+        )));
+    }
+
+    let eviction = [
+        &configz.kubeletconfig.eviction_hard,
+        &configz.kubeletconfig.eviction_soft,
+    ]
+    .into_iter()
+    .flat_map(|thresholds| {
+        EVICTION_SIGNALS
+            .iter()
+            .filter_map(|signal| thresholds.get(*signal))
+    })
+    .map(|quantity| min_available(quantity))
+    .collect::<io::Result<_>>()?;
+
+    Ok(KubeletThresholds {
+        image_gc_high_percent: high,
+        image_gc_low_percent: low,
+        eviction,
+    })
+}
+
+// Ask the kubelet on a node for its image garbage collection thresholds, which are the percentages
+// of the disk usage of the image filesystem above which it starts deleting images and down to which
+// it deletes them, along with its eviction thresholds for that filesystem. This goes through the
+// API server's proxy to the node, which requires permission to get `nodes/proxy`.
+pub fn kubelet_thresholds(node: &str) -> io::Result<KubeletThresholds> {
+    let output = Command::new("kubectl")
+        .args([
+            "get",
+            "--raw",
+            &format!("/api/v1/nodes/{node}/proxy/configz"),
+        ])
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    // Ensure the command succeeded.
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Unable to determine the configuration of the kubelet on Kubernetes node {}.",
+            node.code_str(),
+        )));
+    }
+
+    kubelet_thresholds_from_json(&output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            kubelet_thresholds_from_json, pod_images_from_json, KubeletThresholds, MinAvailable,
+        },
+        byte_unit::Byte,
+        std::collections::HashSet,
+    };
+
+    #[test]
+    fn pod_images_from_json_empty() {
+        assert!(pod_images_from_json(br#"{"items":[]}"#).unwrap().is_empty());
+    }
+
+    #[test]
+    fn pod_images_from_json_all_containers() {
+        assert_eq!(
+            pod_images_from_json(
+                br#"{
+                    "items": [
+                        {
+                            "spec": {
+                                "containers": [{ "name": "app", "image": "nginx:1.27" }],
+                                "initContainers": [{ "name": "init", "image": "busybox:1.36" }]
+                            }
+                        },
+                        {
+                            "spec": {
+                                "containers": [{ "name": "app", "image": "nginx:1.27" }],
+                                "ephemeralContainers": [
+                                    { "name": "debug", "image": "alpine:3.20" }
+                                ]
+                            }
+                        }
+                    ]
+                }"#,
+            )
+            .unwrap(),
+            ["nginx:1.27", "busybox:1.36", "alpine:3.20"]
+                .into_iter()
+                .map(ToOwned::to_owned)
+                .collect::<HashSet<_>>(),
+        );
+    }
+
+    #[test]
+    fn pod_images_from_json_invalid() {
+        assert!(pod_images_from_json(b"not json").is_err());
+    }
+
+    #[test]
+    fn kubelet_thresholds_from_json_configured() {
+        assert_eq!(
+            kubelet_thresholds_from_json(
+                br#"{
+                    "kubeletconfig": {
+                        "imageGCHighThresholdPercent": 90,
+                        "imageGCLowThresholdPercent": 70,
+                        "evictionHard": {
+                            "imagefs.available": "2Gi",
+                            "memory.available": "100Mi"
+                        },
+                        "evictionSoft": { "nodefs.available": "20%" },
+                        "maxPods": 110
+                    }
+                }"#,
+            )
+            .unwrap(),
+            KubeletThresholds {
+                image_gc_high_percent: 90,
+                image_gc_low_percent: 70,
+                eviction: vec![
+                    MinAvailable::Bytes(Byte::from_bytes(2 * 1024 * 1024 * 1024)),
+                    MinAvailable::Fraction(0.2),
+                ],
+            },
+        );
+    }
+
+    #[test]
+    fn kubelet_thresholds_from_json_defaults() {
+        let thresholds = kubelet_thresholds_from_json(br#"{"kubeletconfig":{}}"#).unwrap();
+
+        assert_eq!(thresholds.image_gc_high_percent, 85);
+        assert_eq!(thresholds.image_gc_low_percent, 80);
+        assert_eq!(thresholds.eviction.len(), 2);
+        assert!(thresholds.eviction.contains(&MinAvailable::Fraction(0.15)));
+        assert!(thresholds.eviction.contains(&MinAvailable::Fraction(0.1)));
+    }
+
+    #[test]
+    fn kubelet_thresholds_from_json_invalid() {
+        assert!(
+            kubelet_thresholds_from_json(
+                concat!(
+                    r#"{"kubeletconfig":{"imageGCHighThresholdPercent":70,"#,
+                    r#""imageGCLowThresholdPercent":90}}"#,
+                )
+                .as_bytes(),
+            )
+            .is_err(),
+        );
+        assert!(
+            kubelet_thresholds_from_json(
+                br#"{"kubeletconfig":{"evictionHard":{"imagefs.available":"lots"}}}"#,
+            )
+            .is_err(),
+        );
+    }
+}
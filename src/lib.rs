@@ -0,0 +1,365 @@
+//! Docuum performs least recently used (LRU) eviction of container images. Besides the `docuum`
+//! command-line tool, this crate provides its vacuum engine for tools which manage images
+//! themselves. See [`Vacuum`] to get started.
+
+mod archive;
+mod backend;
+mod cli;
+mod config;
+mod container;
+mod control;
+mod dind;
+mod dockerfile;
+mod event;
+mod eviction;
+mod format;
+mod grpc;
+mod health;
+mod history;
+mod hook;
+mod kubernetes;
+mod log_file;
+mod logging;
+mod ls;
+mod pause;
+mod pins;
+mod pipeline;
+mod plugin;
+mod reload;
+mod report;
+mod restore;
+mod retry;
+mod run;
+mod score;
+mod service;
+mod state;
+mod stats;
+mod statsd;
+mod systemd;
+mod telemetry;
+mod tui;
+mod webhook;
+
+pub use {backend::Runtime, report::Report, state::State};
+
+// The entry point of the `docuum` binary, which isn't part of the library's API
+#[doc(hidden)]
+pub use cli::main;
+
+use {
+    crate::{backend::Backend, format::CodeStr, pipeline::Stage, plugin::Plugin},
+    byte_unit::Byte,
+    croner::Cron,
+    parse_duration::parse,
+    regex::RegexSet,
+    std::{io, path::PathBuf, str::FromStr, time::Duration},
+};
+
+#[macro_use]
+extern crate log;
+
+// Defaults, which the command-line interface shares
+const DEFAULT_DELETION_CHUNK_SIZE: usize = 1;
+const DEFAULT_HISTORY_MAX_AGE: &str = "90 days";
+const DEFAULT_INSPECT_CONCURRENCY: usize = 4;
+const DEFAULT_POLICY: &str = "lru";
+const DEFAULT_POLL_INTERVAL: &str = "1 minute";
+const DEFAULT_RETRY_INITIAL_DELAY: &str = "5 seconds";
+const DEFAULT_RETRY_MAX_DELAY: &str = "5 minutes";
+const DEFAULT_RETRY_MULTIPLIER: f64 = 2.0;
+const DEFAULT_TIE_BREAKS: &[&str] = &["most-ancestors-first", "largest-first"];
+
+// Thresholds with this prefix specify the amount of space to keep free
+const FREE_THRESHOLD_PREFIX: &str = "free:";
+
+/// A size threshold, which is absolute or relative to the size of the filesystem, or the amount of
+/// space to keep free on the filesystem
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Threshold {
+    /// The space the images may use, e.g., `10 GB`
+    Absolute(Byte),
+
+    /// The fraction of the filesystem the images may use, e.g., `0.5` for `50%`
+    Percentage(f64),
+
+    /// The space to keep free on the filesystem, e.g., `free:5 GB`
+    Free(Byte),
+}
+
+impl FromStr for Threshold {
+    type Err = io::Error;
+
+    // Parse a `Threshold`.
+    fn from_str(threshold: &str) -> io::Result<Self> {
+        if let Some(free) = threshold.strip_prefix(FREE_THRESHOLD_PREFIX) {
+            return Byte::from_str(free.trim())
+                .map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Invalid free space threshold {}.", free.code_str()),
+                    )
+                })
+                .map(Threshold::Free);
+        }
+
+        match threshold.strip_suffix('%') {
+            Some(threshold) => threshold
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Invalid relative threshold {}.", threshold.code_str()),
+                    )
+                })
+                .and_then(|f| {
+                    if f.is_normal() && (0.0_f64..=100.0_f64).contains(&f) {
+                        Ok(f)
+                    } else {
+                        Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Invalid relative threshold {}.", threshold.code_str()),
+                        ))
+                    }
+                })
+                .map(|f| Threshold::Percentage(f / 100.0)),
+            None => Byte::from_str(threshold)
+                .map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Invalid absolute threshold {}.", threshold.code_str()),
+                    )
+                })
+                .map(Threshold::Absolute),
+        }
+    }
+}
+
+/// The settings of a `Vacuum`, which usually come from the command-line arguments
+#[allow(clippy::struct_excessive_bools)]
+pub struct Settings {
+    pub archive_dir: Option<PathBuf>,
+    pub archive_max_size: Option<Byte>,
+    pub backend: Backend,
+    pub backend_candidates: Vec<Backend>, // In order of preference, including `backend`
+    pub count_container_layers: bool,
+    pub count_volumes: bool,
+    pub critical_threshold: Option<Threshold>,
+    pub deletion_chunk_size: usize,
+    pub dind_interval: Option<Duration>,
+    pub disk_check_interval: Option<Duration>,
+    pub endpoint_name: Option<String>, // `None` for the main endpoint
+    pub endpoints: Vec<EndpointSettings>,
+    pub forget_missing_after: Option<Duration>,
+    pub grpc_addr: Option<String>,
+    pub health_addr: Option<String>,
+    pub history_max_age: Duration,
+    pub inspect_concurrency: usize,
+    pub keep: Option<RegexSet>,
+    pub keep_digests: Vec<String>,
+    pub keep_dockerfile_bases: Vec<PathBuf>,
+    pub keep_ids: Vec<String>,
+    pub keep_labels: Vec<(String, String)>,
+    pub kubelet_threshold: Option<Threshold>,
+    pub kubernetes: bool,
+    pub kubernetes_node: Option<String>,
+    pub max_age: Option<Duration>,
+    pub max_images: Option<usize>,
+    pub min_free_inodes: Option<u64>,
+    pub min_age: Option<Duration>,
+    pub once: bool,
+    pub only_registries: Vec<String>,
+    pub otlp_endpoint: Option<String>,
+    pub pipeline: Vec<Stage>,
+    pub plugins: Vec<Plugin>,
+    pub policy: eviction::Policy,
+    pub poll_interval: Duration,
+    pub post_vacuum_hook: Option<String>,
+    pub pre_delete_hook: Option<String>,
+    pub protect_compose_projects: Option<Duration>,
+    pub protect_gitlab_pipelines: Option<Duration>,
+    pub protect_registries: Vec<String>,
+    pub prune_buildx_caches: bool,
+    pub prune_containers: Option<Duration>,
+    pub prune_networks: Option<Duration>,
+    pub prune_parents: bool,
+    pub prune_volumes: bool,
+    pub pull_grace: Option<Duration>,
+    pub remove_only: Option<RegexSet>,
+    pub retry: retry::Policy,
+    pub schedule: Option<Cron>,
+    pub statsd_addr: Option<String>,
+    pub target: Option<Threshold>,
+    pub threshold: Threshold,
+    pub tie_breaks: Vec<eviction::TieBreak>, // Applied in turn
+    pub untag: bool,
+    pub webhook_url: Option<String>,
+}
+
+/// An additional endpoint, along with the settings which are overridden for it
+pub struct EndpointSettings {
+    pub name: String,
+    pub backend: Backend,
+    pub keep: Option<RegexSet>,
+    pub min_age: Option<Duration>,
+    pub threshold: Option<Threshold>,
+}
+
+impl Settings {
+    /// Construct `Settings` for managing the images of a container runtime at its default endpoint,
+    /// with the same defaults as the command-line interface. The fields can be adjusted before the
+    /// settings are used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if one of the built-in defaults is invalid, which would be a bug.
+    #[must_use]
+    pub fn new(runtime: Runtime, threshold: Threshold) -> Self {
+        let backend = Backend::local(runtime);
+
+        // The `unwrap`s are safe, since the defaults are manually verified to parse.
+        Self {
+            archive_dir: None,
+            archive_max_size: None,
+            backend_candidates: vec![backend.clone()],
+            backend,
+            count_container_layers: false,
+            count_volumes: false,
+            critical_threshold: None,
+            deletion_chunk_size: DEFAULT_DELETION_CHUNK_SIZE,
+            dind_interval: None,
+            disk_check_interval: None,
+            endpoint_name: None,
+            endpoints: vec![],
+            forget_missing_after: None,
+            grpc_addr: None,
+            health_addr: None,
+            history_max_age: parse(DEFAULT_HISTORY_MAX_AGE).unwrap(),
+            inspect_concurrency: DEFAULT_INSPECT_CONCURRENCY,
+            keep: None,
+            keep_digests: vec![],
+            keep_dockerfile_bases: vec![],
+            keep_ids: vec![],
+            keep_labels: vec![],
+            kubelet_threshold: None,
+            kubernetes: false,
+            kubernetes_node: None,
+            max_age: None,
+            max_images: None,
+            min_free_inodes: None,
+            min_age: None,
+            once: false,
+            only_registries: vec![],
+            otlp_endpoint: None,
+            pipeline: pipeline::default(false, false),
+            plugins: vec![],
+            policy: eviction::Policy::from_str(DEFAULT_POLICY).unwrap(),
+            poll_interval: parse(DEFAULT_POLL_INTERVAL).unwrap(),
+            post_vacuum_hook: None,
+            pre_delete_hook: None,
+            protect_compose_projects: None,
+            protect_gitlab_pipelines: None,
+            protect_registries: vec![],
+            prune_buildx_caches: false,
+            prune_containers: None,
+            prune_networks: None,
+            prune_parents: false,
+            prune_volumes: false,
+            pull_grace: None,
+            remove_only: None,
+            retry: retry::Policy {
+                initial_delay: parse(DEFAULT_RETRY_INITIAL_DELAY).unwrap(),
+                multiplier: DEFAULT_RETRY_MULTIPLIER,
+                max_delay: parse(DEFAULT_RETRY_MAX_DELAY).unwrap(),
+            },
+            schedule: None,
+            statsd_addr: None,
+            target: None,
+            threshold,
+            tie_breaks: DEFAULT_TIE_BREAKS
+                .iter()
+                .map(|tie_break| eviction::TieBreak::from_str(tie_break).unwrap())
+                .collect(),
+            untag: false,
+            webhook_url: None,
+        }
+    }
+}
+
+/// The vacuum engine, for tools which embed Docuum's image eviction rather than running the daemon.
+/// For example:
+///
+/// ```no_run
+/// use docuum::{Runtime, Settings, Vacuum};
+///
+/// # fn main() -> std::io::Result<()> {
+/// # let image_id = "sha256:0123";
+/// let vacuum = Vacuum::new(Settings::new(Runtime::Docker, "10 GB".parse()?));
+/// let (mut state, first_run) = vacuum.load_state();
+///
+/// // Record that an image was used, so it's deleted after the images which weren't.
+/// vacuum.touch_image(&mut state, image_id)?;
+///
+/// // Delete images until the space they use is within the threshold, and see what happened.
+/// let report = vacuum.run_once(&mut state, first_run)?;
+/// println!("Deleted {} images.", report.deleted_images.len());
+/// vacuum.save_state(&state)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Vacuum {
+    settings: Settings,
+}
+
+impl Vacuum {
+    /// Construct a `Vacuum` with the given settings.
+    #[must_use]
+    pub fn new(settings: Settings) -> Self {
+        Self { settings }
+    }
+
+    /// The settings of the vacuum
+    #[must_use]
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Load the state of the endpoint from disk, along with whether this is the first run. If
+    /// there's no state yet, or it can't be read, the state starts out empty.
+    #[must_use]
+    pub fn load_state(&self) -> (State, bool) {
+        state::load(self.settings.endpoint_name.as_deref())
+            .map_or_else(|_| (state::initial(), true), |state| (state, false))
+    }
+
+    /// Save the state to disk, so the next run can pick up where this one left off.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state can't be written.
+    pub fn save_state(&self, state: &State) -> io::Result<()> {
+        state::save(state, self.settings.endpoint_name.as_deref())
+    }
+
+    /// Record that an image was just used, so it's deleted after the images which weren't. Returns
+    /// whether the image was new to the state.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the parent of a new image can't be determined.
+    pub fn touch_image(&self, state: &mut State, image_id: &str) -> io::Result<bool> {
+        run::touch_image(&self.settings.backend, state, image_id, None, None, false)
+    }
+
+    /// Delete images until the space they use is within the threshold, along with anything else the
+    /// settings call for. On the first run, the state doesn't know when any image was last used
+    /// yet, so images are assumed to have been used when they were created.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the vacuum fails, e.g., because the container runtime isn't reachable.
+    pub fn run_once(&self, state: &mut State, first_run: bool) -> io::Result<Report> {
+        let threshold = run::threshold_bytes(&self.settings)?;
+        run::vacuum(&self.settings, state, first_run, threshold)
+    }
+}
@@ -0,0 +1,188 @@
+use {
+    crate::format::CodeStr,
+    std::{
+        fs::{remove_file, rename, File, OpenOptions},
+        io::{self, Write},
+        path::{Path, PathBuf},
+        time::{Duration, SystemTime},
+    },
+};
+
+// The number of rotated log files to keep, e.g., `docuum.log.1` through `docuum.log.5`
+const ROTATED_FILES: usize = 5;
+
+// A log file which is rotated when it gets too big or too old
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_age: Option<Duration>,
+    file: File,
+    size: u64,
+    opened_at: SystemTime,
+}
+
+// The path of the log file after it has been rotated `index` times
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated_path = path.as_os_str().to_owned();
+    rotated_path.push(format!(".{index}"));
+    PathBuf::from(rotated_path)
+}
+
+// Open the log file for appending, creating it if necessary. Returns the file, its current size,
+// and when it was created (or now, if the file system doesn't record that).
+fn open(path: &Path) -> io::Result<(File, u64, SystemTime)> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!(
+                    "Unable to open log file {}. Details: {}",
+                    path.to_string_lossy().code_str(),
+                    error,
+                ),
+            )
+        })?;
+    let metadata = file.metadata()?;
+    let opened_at = metadata.created().unwrap_or_else(|_| SystemTime::now());
+    Ok((file, metadata.len(), opened_at))
+}
+
+impl RotatingFile {
+    pub fn new(path: &Path, max_size: u64, max_age: Option<Duration>) -> io::Result<Self> {
+        let (file, size, opened_at) = open(path)?;
+        Ok(Self {
+            path: path.to_owned(),
+            max_size,
+            max_age,
+            file,
+            size,
+            opened_at,
+        })
+    }
+
+    // Determine whether writing `len` more bytes calls for a rotation first. An empty file is never
+    // rotated, so records bigger than the limit still get written.
+    fn needs_rotation(&self, len: u64) -> bool {
+        self.size > 0
+            && (self.size + len > self.max_size
+                || self.max_age.is_some_and(|max_age| {
+                    self.opened_at
+                        .elapsed()
+                        .is_ok_and(|elapsed| elapsed >= max_age)
+                }))
+    }
+
+    // Shift the rotated files along, dropping the oldest one, and start a new log file.
+    fn rotate(&mut self) -> io::Result<()> {
+        match remove_file(rotated_path(&self.path, ROTATED_FILES)) {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+
+        for index in (1..ROTATED_FILES).rev() {
+            match rename(
+                rotated_path(&self.path, index),
+                rotated_path(&self.path, index + 1),
+            ) {
+                Ok(()) => {}
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        rename(&self.path, rotated_path(&self.path, 1))?;
+
+        let (file, size, _) = open(&self.path)?;
+        self.file = file;
+        self.size = size;
+        self.opened_at = SystemTime::now();
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    // Each call is expected to contain a whole record, so records are never split across files.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.needs_rotation(buf.len() as u64) {
+            self.rotate()?;
+        }
+
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{rotated_path, RotatingFile, ROTATED_FILES},
+        std::{fs::read_to_string, io::Write, path::Path, time::Duration},
+        tempfile::tempdir,
+    };
+
+    #[test]
+    fn rotated_path_suffix() {
+        assert_eq!(
+            rotated_path(Path::new("/var/log/docuum.log"), 2),
+            Path::new("/var/log/docuum.log.2"),
+        );
+    }
+
+    #[test]
+    fn rotate_by_size() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("docuum.log");
+        let mut file = RotatingFile::new(&path, 8, None).unwrap();
+
+        for line in ["a\n", "bb\n", "ccc\n", "dddd\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(read_to_string(&path).unwrap(), "dddd\n");
+        assert_eq!(read_to_string(rotated_path(&path, 1)).unwrap(), "ccc\n");
+        assert_eq!(read_to_string(rotated_path(&path, 2)).unwrap(), "a\nbb\n");
+    }
+
+    #[test]
+    fn rotate_by_age() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("docuum.log");
+        let mut file = RotatingFile::new(&path, u64::MAX, Some(Duration::ZERO)).unwrap();
+
+        file.write_all(b"a\n").unwrap();
+        file.write_all(b"b\n").unwrap();
+
+        assert_eq!(read_to_string(&path).unwrap(), "b\n");
+        assert_eq!(read_to_string(rotated_path(&path, 1)).unwrap(), "a\n");
+    }
+
+    #[test]
+    fn rotate_drops_oldest() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("docuum.log");
+        let mut file = RotatingFile::new(&path, 1, None).unwrap();
+
+        for index in 0..=ROTATED_FILES + 1 {
+            file.write_all(format!("{index}\n").as_bytes()).unwrap();
+        }
+
+        assert_eq!(
+            read_to_string(&path).unwrap(),
+            format!("{}\n", ROTATED_FILES + 1),
+        );
+        assert_eq!(
+            read_to_string(rotated_path(&path, ROTATED_FILES)).unwrap(),
+            "1\n",
+        );
+        assert!(!rotated_path(&path, ROTATED_FILES + 1).exists());
+    }
+}
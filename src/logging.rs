@@ -0,0 +1,522 @@
+use {
+    crate::{format::CodeStr, log_file::RotatingFile},
+    chrono::{Local, SecondsFormat},
+    env_logger::{fmt::Color, Builder, WriteStyle},
+    log::{
+        kv::{self, Key, Value, VisitSource},
+        Level, LevelFilter, Log, Metadata, Record,
+    },
+    serde_json::Map,
+    std::{
+        env,
+        io::{self, Write},
+        process,
+        str::FromStr,
+        sync::Mutex,
+    },
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+
+// The log level used if `LOG_LEVEL` isn't set
+const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Debug;
+
+// The name under which Docuum logs to journald and syslog
+const IDENTIFIER: &str = env!("CARGO_PKG_NAME");
+
+// The socket on which journald listens for log entries
+#[cfg(unix)]
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+// The sockets on which syslog daemons typically listen, in order of preference
+#[cfg(unix)]
+const SYSLOG_SOCKETS: &[&str] = &["/dev/log", "/var/run/syslog", "/var/run/log"];
+
+// The syslog facility for system daemons
+const SYSLOG_FACILITY_DAEMON: u8 = 3;
+
+// How log records are written
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    // Human-readable lines, colored if standard error is a terminal
+    Text,
+
+    // One JSON object per line, including the structured fields of the record
+    Json,
+}
+
+impl Format {
+    // The names accepted by `from_str`
+    pub const NAMES: &'static [&'static str] = &["text", "json"];
+
+    // Parse a `Format`.
+    pub fn from_str(format: &str) -> io::Result<Self> {
+        match format {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid log format {}.", format.code_str()),
+            )),
+        }
+    }
+}
+
+// Where log records are written
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Target {
+    Stderr,
+
+    // The systemd journal, via its native protocol
+    Journald,
+
+    // The local syslog daemon
+    Syslog,
+}
+
+impl Target {
+    // The names accepted by `from_str`
+    pub const NAMES: &'static [&'static str] = &["stderr", "journald", "syslog"];
+
+    // Parse a `Target`.
+    pub fn from_str(target: &str) -> io::Result<Self> {
+        match target {
+            "stderr" => Ok(Self::Stderr),
+            "journald" => Ok(Self::Journald),
+            "syslog" => Ok(Self::Syslog),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid log target {}.", target.code_str()),
+            )),
+        }
+    }
+}
+
+// Whether the log is colored
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ColorMode {
+    // Colored if the log is human-readable text on a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    // The names accepted by `from_str`
+    pub const NAMES: &'static [&'static str] = &["auto", "always", "never"];
+
+    // Parse a `ColorMode`.
+    pub fn from_str(mode: &str) -> io::Result<Self> {
+        match mode {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid color mode {}.", mode.code_str()),
+            )),
+        }
+    }
+}
+
+// This collects the structured fields of a log record (e.g., `event = "deletion"`) into a JSON
+// object. Numbers and Booleans are preserved, and everything else is rendered as a string.
+struct FieldCollector(Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for FieldCollector {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(number) = value.to_u64() {
+            serde_json::Value::from(number)
+        } else if let Some(number) = value.to_i64() {
+            serde_json::Value::from(number)
+        } else if let Some(boolean) = value.to_bool() {
+            serde_json::Value::from(boolean)
+        } else {
+            serde_json::Value::from(value.to_string())
+        };
+
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+// Render a log record as a line of JSON.
+fn json_line(record: &Record, timestamp: &str) -> String {
+    let mut fields = FieldCollector(Map::new());
+    fields
+        .0
+        .insert("timestamp".to_owned(), timestamp.to_owned().into());
+    fields
+        .0
+        .insert("level".to_owned(), record.level().to_string().into());
+    fields
+        .0
+        .insert("message".to_owned(), record.args().to_string().into());
+
+    // The `unwrap` is safe because `FieldCollector` never fails.
+    record.key_values().visit(&mut fields).unwrap();
+
+    serde_json::Value::Object(fields.0).to_string()
+}
+
+// Render a log record as a line of uncolored text.
+fn text_line(record: &Record, timestamp: &str) -> String {
+    format!("[{} {}] {}", timestamp, record.level(), record.args())
+}
+
+// The timestamp format for text lines
+fn text_timestamp() -> String {
+    Local::now().format("%Y-%m-%d %H:%M:%S %:z").to_string()
+}
+
+// The timestamp format for JSON lines
+fn json_timestamp() -> String {
+    Local::now().to_rfc3339_opts(SecondsFormat::Millis, false)
+}
+
+// Determine whether a record should be logged by one of our own loggers.
+fn enabled(metadata: &Metadata, level: LevelFilter) -> bool {
+    metadata.target().starts_with(IDENTIFIER) && metadata.level() <= level
+}
+
+// The syslog severity corresponding to a log level. journald uses the same values for priorities.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+// Append a field to an entry in the journald native protocol. Values containing newlines have to
+// be length-prefixed.
+fn append_journald_field(entry: &mut Vec<u8>, key: &str, value: &str) {
+    entry.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+// Encode a log record as an entry in the journald native protocol. The structured fields of the
+// record become journal fields, e.g., `image_id` becomes `IMAGE_ID`.
+fn journald_entry(record: &Record) -> Vec<u8> {
+    let mut entry = vec![];
+    append_journald_field(&mut entry, "MESSAGE", &record.args().to_string());
+    append_journald_field(
+        &mut entry,
+        "PRIORITY",
+        &severity(record.level()).to_string(),
+    );
+    append_journald_field(&mut entry, "SYSLOG_IDENTIFIER", IDENTIFIER);
+
+    let mut fields = FieldCollector(Map::new());
+
+    // The `unwrap` is safe because `FieldCollector` never fails.
+    record.key_values().visit(&mut fields).unwrap();
+
+    for (key, value) in fields.0 {
+        let value = match value {
+            serde_json::Value::String(string) => string,
+            value => value.to_string(),
+        };
+        append_journald_field(&mut entry, &key.to_uppercase(), &value);
+    }
+
+    entry
+}
+
+// Encode a message in the BSD syslog format (RFC 3164), leaving the timestamp and hostname to the
+// syslog daemon.
+fn syslog_message(level: Level, message: &str) -> Vec<u8> {
+    format!(
+        "<{}>{}[{}]: {}",
+        SYSLOG_FACILITY_DAEMON * 8 + severity(level),
+        IDENTIFIER,
+        process::id(),
+        message,
+    )
+    .into_bytes()
+}
+
+// A logger which sends each record as a datagram to journald or syslog
+#[cfg(unix)]
+struct SocketLogger {
+    socket: UnixDatagram,
+    level: LevelFilter,
+    format: Format,
+    target: Target,
+}
+
+#[cfg(unix)]
+impl Log for SocketLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        enabled(metadata, self.level)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let datagram = if self.target == Target::Journald {
+            journald_entry(record)
+        } else {
+            syslog_message(
+                record.level(),
+                &match self.format {
+                    Format::Text => record.args().to_string(),
+                    Format::Json => json_line(record, &json_timestamp()),
+                },
+            )
+        };
+
+        // There's nowhere to report a failure to log, so we ignore it.
+        let _ = self.socket.send(&datagram);
+    }
+
+    fn flush(&self) {}
+}
+
+// A logger which appends each record to a log file
+struct FileLogger {
+    file: Mutex<RotatingFile>,
+    level: LevelFilter,
+    format: Format,
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        enabled(metadata, self.level)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut line = match self.format {
+            Format::Text => text_line(record, &text_timestamp()),
+            Format::Json => json_line(record, &json_timestamp()),
+        };
+        line.push('\n');
+
+        // There's nowhere to report a failure to log, so we ignore it. The record is written in a
+        // single call so that it isn't split across files by a rotation.
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.write_all(line.as_bytes());
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+// Connect to the socket of journald or syslog.
+#[cfg(unix)]
+fn connect(target: Target) -> io::Result<UnixDatagram> {
+    let paths = if target == Target::Journald {
+        &[JOURNALD_SOCKET][..]
+    } else {
+        SYSLOG_SOCKETS
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    let mut last_error = None;
+    for path in paths {
+        match socket.connect(path) {
+            Ok(()) => return Ok(socket),
+            Err(error) => last_error = Some(error),
+        }
+    }
+
+    Err(io::Error::other(format!(
+        "Unable to connect to {}. Details: {}",
+        paths.join(" or ").code_str(),
+        last_error.map_or_else(String::new, |error| error.to_string()),
+    )))
+}
+
+// Determine the log level from the `LOG_LEVEL` environment variable.
+fn level() -> LevelFilter {
+    LevelFilter::from_str(&env::var("LOG_LEVEL").unwrap_or_else(|_| DEFAULT_LOG_LEVEL.to_string()))
+        .unwrap_or(DEFAULT_LOG_LEVEL)
+}
+
+// Set up a logger which writes to a log file.
+pub fn set_up_file(format: Format, file: RotatingFile) -> io::Result<()> {
+    let level = level();
+    log::set_max_level(level);
+    log::set_boxed_logger(Box::new(FileLogger {
+        file: Mutex::new(file),
+        level,
+        format,
+    }))
+    .map_err(io::Error::other)
+}
+
+// Set up the logger. If this fails, no logger is set up, so the caller can fall back to standard
+// error. The color mode only affects the levels of records written to standard error as text.
+pub fn set_up(format: Format, target: Target, color_mode: ColorMode) -> io::Result<()> {
+    let level = level();
+
+    if target != Target::Stderr {
+        #[cfg(unix)]
+        {
+            let socket = connect(target)?;
+            log::set_max_level(level);
+            return log::set_boxed_logger(Box::new(SocketLogger {
+                socket,
+                level,
+                format,
+                target,
+            }))
+            .map_err(io::Error::other);
+        }
+
+        #[cfg(not(unix))]
+        return Err(io::Error::other(
+            "Logging to journald or syslog is only supported on Unix-like systems.",
+        ));
+    }
+
+    let mut builder = Builder::new();
+    builder.filter_module(IDENTIFIER, level);
+    builder.write_style(match color_mode {
+        ColorMode::Auto => WriteStyle::Auto,
+        ColorMode::Always => WriteStyle::Always,
+        ColorMode::Never => WriteStyle::Never,
+    });
+
+    match format {
+        Format::Text => builder.format(|buf, record| {
+            let mut style = buf.style();
+            style.set_bold(true);
+            match record.level() {
+                Level::Error => {
+                    style.set_color(Color::Red);
+                }
+                Level::Warn => {
+                    style.set_color(Color::Yellow);
+                }
+                Level::Info => {
+                    style.set_color(Color::Green);
+                }
+                Level::Debug => {
+                    style.set_color(Color::Blue);
+                }
+                Level::Trace => {
+                    style.set_color(Color::Cyan);
+                }
+            }
+
+            writeln!(
+                buf,
+                "{} {}",
+                style.value(format!("[{} {}]", text_timestamp(), record.level())),
+                record.args(),
+            )
+        }),
+        Format::Json => {
+            builder.format(|buf, record| writeln!(buf, "{}", json_line(record, &json_timestamp())))
+        }
+    };
+
+    builder.init();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{journald_entry, json_line, syslog_message, text_line},
+        log::{Level, Record},
+        std::process,
+    };
+
+    #[test]
+    fn json_line_fields() {
+        let key_values = [("event", "deletion"), ("image_id", "sha256:0")];
+        let bytes = [("bytes", 42_u64)];
+        let sources: [&dyn log::kv::Source; 2] = [&key_values, &bytes];
+
+        assert_eq!(
+            json_line(
+                &Record::builder()
+                    .args(format_args!("Deleting image."))
+                    .level(Level::Info)
+                    .key_values(&sources)
+                    .build(),
+                "2024-05-02T12:00:00.000+00:00",
+            ),
+            concat!(
+                r#"{"bytes":42,"event":"deletion","image_id":"sha256:0","level":"INFO","#,
+                r#""message":"Deleting image.","timestamp":"2024-05-02T12:00:00.000+00:00"}"#,
+            ),
+        );
+    }
+
+    #[test]
+    fn text_line_format() {
+        assert_eq!(
+            text_line(
+                &Record::builder()
+                    .args(format_args!("Deleting image."))
+                    .level(Level::Info)
+                    .build(),
+                "2024-05-02 12:00:00 +00:00",
+            ),
+            "[2024-05-02 12:00:00 +00:00 INFO] Deleting image.",
+        );
+    }
+
+    #[test]
+    fn journald_entry_fields() {
+        let key_values = [("image_id", "sha256:0")];
+
+        assert_eq!(
+            journald_entry(
+                &Record::builder()
+                    .args(format_args!("Deleted image."))
+                    .level(Level::Debug)
+                    .key_values(&key_values)
+                    .build(),
+            ),
+            b"MESSAGE=Deleted image.\nPRIORITY=7\nSYSLOG_IDENTIFIER=docuum\nIMAGE_ID=sha256:0\n",
+        );
+    }
+
+    #[test]
+    fn journald_entry_multiline() {
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&3_u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\nPRIORITY=3\nSYSLOG_IDENTIFIER=docuum\n");
+
+        assert_eq!(
+            journald_entry(
+                &Record::builder()
+                    .args(format_args!("a\nb"))
+                    .level(Level::Error)
+                    .build(),
+            ),
+            expected,
+        );
+    }
+
+    #[test]
+    fn syslog_message_priority() {
+        assert_eq!(
+            syslog_message(Level::Warn, "Uh oh."),
+            format!("<28>docuum[{}]: Uh oh.", process::id()).into_bytes(),
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use {
+    crate::{
+        format::Output,
+        run::{list_images, ListedImage, Protection},
+        state::State,
+        Settings,
+    },
+    chrono::{DateTime, SecondsFormat, Utc},
+    serde::Serialize,
+    std::{io, iter::once, time::UNIX_EPOCH},
+};
+
+// The headings of the columns of the listing
+const HEADINGS: [&str; 5] = [
+    "LAST USED",
+    "SIZE",
+    "IMAGE ID",
+    "REPOSITORY:TAG",
+    "PROTECTED BY",
+];
+
+// An image as it appears in the listing as JSON
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct JsonImage {
+    pub id: String,
+    pub repository_tags: Vec<String>,
+    pub size_bytes: u128,
+
+    // When the image was last used, in RFC 3339 format
+    pub last_used: String,
+
+    // The setting or label which protects the image from deletion, if any
+    pub protected_by: Option<String>,
+}
+
+impl From<&ListedImage> for JsonImage {
+    fn from(image: &ListedImage) -> Self {
+        Self {
+            id: image.id.clone(),
+            repository_tags: image.repository_tags.clone(),
+            size_bytes: image.size.get_bytes(),
+            last_used: DateTime::<Utc>::from(UNIX_EPOCH + image.last_used_since_epoch)
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+            protected_by: image.protection.as_ref().map(Protection::cause),
+        }
+    }
+}
+
+// Format the images as a JSON array.
+pub fn json(images: &[ListedImage]) -> String {
+    // The `unwrap` is safe because `JsonImage` always serializes.
+    serde_json::to_string(&images.iter().map(JsonImage::from).collect::<Vec<_>>()).unwrap()
+}
+
+// Format an image as the cells of a row of the listing.
+fn row(image: &ListedImage) -> [String; 5] {
+    [
+        DateTime::<Utc>::from(UNIX_EPOCH + image.last_used_since_epoch)
+            .to_rfc3339_opts(SecondsFormat::Secs, true),
+        image.size.get_appropriate_unit(false).to_string(),
+        image.id.clone(),
+        image.repository_tags.join(", "),
+        image
+            .protection
+            .as_ref()
+            .map_or_else(String::new, Protection::cause),
+    ]
+}
+
+// Format the images as lines of a table with aligned columns.
+fn lines(images: &[ListedImage]) -> Vec<String> {
+    let rows = once(HEADINGS.map(ToOwned::to_owned))
+        .chain(images.iter().map(row))
+        .collect::<Vec<_>>();
+
+    let mut widths = [0; HEADINGS.len()];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_owned()
+        })
+        .collect()
+}
+
+// Print the images in the order in which they would be deleted.
+pub fn print(
+    settings: &Settings,
+    state: &State,
+    first_run: bool,
+    output: Output,
+) -> io::Result<()> {
+    let images = list_images(settings, state, first_run)?;
+    match output {
+        Output::Text => {
+            for line in lines(&images) {
+                println!("{line}");
+            }
+        }
+        Output::Json => println!("{}", json(&images)),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{json, lines},
+        crate::run::{ListedImage, Protection},
+        byte_unit::Byte,
+        std::time::Duration,
+    };
+
+    #[test]
+    fn lines_empty() {
+        assert_eq!(
+            lines(&[]),
+            vec!["LAST USED  SIZE  IMAGE ID  REPOSITORY:TAG  PROTECTED BY"],
+        );
+    }
+
+    #[test]
+    fn json_images() {
+        assert_eq!(
+            json(&[ListedImage {
+                id: "id-0".to_owned(),
+                last_used_since_epoch: Duration::from_secs(1_714_651_210),
+                size: Byte::from_bytes(42),
+                repository_tags: vec!["debian:latest".to_owned()],
+                containers: vec![],
+                protection: Some(Protection::Keep("debian:latest".to_owned())),
+            }]),
+            "[{\"id\":\"id-0\",\"repository_tags\":[\"debian:latest\"],\"size_bytes\":42,\
+                \"last_used\":\"2024-05-02T12:00:10Z\",\"protected_by\":\"--keep\"}]",
+        );
+    }
+
+    #[test]
+    fn lines_aligned() {
+        assert_eq!(
+            lines(&[
+                ListedImage {
+                    id: "id-0".to_owned(),
+                    last_used_since_epoch: Duration::from_secs(1_714_651_210),
+                    size: Byte::from_bytes(7_376_891),
+                    repository_tags: vec!["alpine:3.19".to_owned(), "alpine:latest".to_owned()],
+                    containers: vec![],
+                    protection: None,
+                },
+                ListedImage {
+                    id: "id-1".to_owned(),
+                    last_used_since_epoch: Duration::from_secs(1_714_737_610),
+                    size: Byte::from_bytes(42),
+                    repository_tags: vec!["debian:latest".to_owned()],
+                    containers: vec![],
+                    protection: Some(Protection::Keep("debian:latest".to_owned())),
+                },
+            ]),
+            vec![
+                "LAST USED             SIZE     IMAGE ID  REPOSITORY:TAG              PROTECTED BY",
+                "2024-05-02T12:00:10Z  7.38 MB  id-0      alpine:3.19, alpine:latest",
+                "2024-05-03T12:00:10Z  42 B     id-1      debian:latest               --keep",
+            ],
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(unix)]
+use {
+    nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal},
+    std::io,
+};
+
+// Whether deletions are paused
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+// The `SIGUSR2` handler. This must be async-signal-safe, so all it does is flip a flag.
+#[cfg(unix)]
+extern "C" fn handle_sigusr2(_: nix::libc::c_int) {
+    PAUSED.fetch_xor(true, Ordering::SeqCst);
+}
+
+// Pause or resume deletions upon receiving `SIGUSR2`.
+#[cfg(unix)]
+pub fn install_handler() -> io::Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(handle_sigusr2),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+
+    // SAFETY: The handler only touches an atomic, which is async-signal-safe.
+    unsafe { sigaction(Signal::SIGUSR2, &action) }
+        .map(|_| ())
+        .map_err(io::Error::from)
+}
+
+// There is no `SIGUSR2` on this platform, so deletions are never paused this way.
+#[cfg(not(unix))]
+pub fn install_handler() -> std::io::Result<()> {
+    Ok(())
+}
+
+// Check whether deletions are paused.
+pub fn paused() -> bool {
+    PAUSED.load(Ordering::SeqCst)
+}
+
+// Pause or resume deletions.
+pub fn set(paused: bool) {
+    PAUSED.store(paused, Ordering::SeqCst);
+}
@@ -0,0 +1,70 @@
+use {
+    crate::{format::CodeStr, state},
+    std::{collections::BTreeSet, fs::read_to_string, io, path::PathBuf},
+};
+
+#[cfg(feature = "tui")]
+use {
+    std::{fs::create_dir_all, io::Write},
+    tempfile::NamedTempFile,
+};
+
+// Where the IDs of the pinned images are kept on disk, alongside the state. They're kept apart from
+// the state so they can be changed while Docuum is running.
+fn path() -> Option<PathBuf> {
+    // [tag:pins_path_has_parent]
+    state::path().map(|path| path.with_file_name("pins.json"))
+}
+
+// Load the IDs of the images which were pinned to protect them from deletion.
+pub fn load() -> io::Result<BTreeSet<String>> {
+    // Check if we have a path.
+    let Some(path) = path() else {
+        // Fail if we don't have a path.
+        return Err(io::Error::other("Unable to locate data directory."));
+    };
+
+    match read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|error| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Unable to read the pinned images from {}. Details: {}",
+                    path.to_string_lossy().code_str(),
+                    error,
+                ),
+            )
+        }),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(BTreeSet::new()),
+        Err(error) => Err(error),
+    }
+}
+
+// Pin or unpin an image, given by ID.
+#[cfg(feature = "tui")]
+pub fn set(image_id: &str, pinned: bool) -> io::Result<()> {
+    // Check if we have a path.
+    let Some(path) = path() else {
+        // Fail if we don't have a path.
+        return Err(io::Error::other("Unable to locate data directory."));
+    };
+
+    let mut pins = load()?;
+    if pinned {
+        pins.insert(image_id.to_owned());
+    } else {
+        pins.remove(image_id);
+    }
+
+    // The `unwrap` is safe due to [ref:pins_path_has_parent].
+    let parent = path.parent().unwrap().to_owned();
+    create_dir_all(&parent)?;
+
+    // The `unwrap` is safe because serialization should never fail.
+    let mut temp_file = NamedTempFile::new_in(parent)?;
+    temp_file.write_all(serde_json::to_string(&pins).unwrap().as_bytes())?;
+    temp_file.flush()?;
+    temp_file.persist(path)?;
+
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use {
+    crate::format::CodeStr,
+    byte_unit::Byte,
+    std::{io, time::Duration},
+};
+
+// The ways in which a stage of the cleanup pipeline reclaims space
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StageKind {
+    Containers,
+    DanglingImages,
+    BuildCache,
+    BuildxCaches,
+    Images,
+    Volumes,
+}
+
+impl StageKind {
+    // The names accepted by `from_str`
+    pub const NAMES: &'static [&'static str] = &[
+        "containers",
+        "dangling-images",
+        "build-cache",
+        "buildx-caches",
+        "images",
+        "volumes",
+    ];
+
+    // Parse a `StageKind`.
+    pub fn from_str(stage: &str) -> io::Result<Self> {
+        match stage {
+            "containers" => Ok(Self::Containers),
+            "dangling-images" => Ok(Self::DanglingImages),
+            "build-cache" => Ok(Self::BuildCache),
+            "buildx-caches" => Ok(Self::BuildxCaches),
+            "images" => Ok(Self::Images),
+            "volumes" => Ok(Self::Volumes),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid pipeline stage {}.", stage.code_str()),
+            )),
+        }
+    }
+
+    // Whether the stage prunes a cache, which can be told how much of the cache to keep
+    pub fn is_cache(self) -> bool {
+        match self {
+            Self::BuildCache | Self::BuildxCaches => true,
+            Self::Containers | Self::DanglingImages | Self::Images | Self::Volumes => false,
+        }
+    }
+}
+
+// A stage of the cleanup pipeline, which runs only while the space usage is over the threshold
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stage {
+    pub kind: StageKind,
+
+    // Anything used more recently than this is left alone by the stage.
+    pub min_age: Option<Duration>,
+
+    // The amount of space the stage leaves to the cache it prunes, if it prunes one
+    pub keep: Option<Byte>,
+}
+
+impl Stage {
+    // Construct a stage without any limits of its own.
+    pub fn new(kind: StageKind) -> Self {
+        Self {
+            kind,
+            min_age: None,
+            keep: None,
+        }
+    }
+}
+
+// The pipeline which is used unless the configuration file defines one: unused anonymous volumes
+// and the caches of Buildx builders if requested, and then images.
+pub fn default(prune_volumes: bool, prune_buildx_caches: bool) -> Vec<Stage> {
+    [
+        (prune_volumes, StageKind::Volumes),
+        (prune_buildx_caches, StageKind::BuildxCaches),
+        (true, StageKind::Images),
+    ]
+    .into_iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, kind)| Stage::new(kind))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default, Stage, StageKind};
+
+    #[test]
+    fn stage_kind_names() {
+        for name in StageKind::NAMES {
+            assert!(StageKind::from_str(name).is_ok());
+        }
+        assert!(StageKind::from_str("networks").is_err());
+    }
+
+    #[test]
+    fn default_images_only() {
+        assert_eq!(default(false, false), vec![Stage::new(StageKind::Images)]);
+    }
+
+    #[test]
+    fn default_volumes_and_buildx_caches_first() {
+        assert_eq!(
+            default(true, true),
+            vec![
+                Stage::new(StageKind::Volumes),
+                Stage::new(StageKind::BuildxCaches),
+                Stage::new(StageKind::Images),
+            ],
+        );
+    }
+}
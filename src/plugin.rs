@@ -0,0 +1,325 @@
+use {
+    serde::Serialize,
+    std::{collections::HashMap, io},
+};
+
+#[cfg(feature = "plugins")]
+use {
+    crate::format::CodeStr,
+    std::{
+        fs::read,
+        sync::{Mutex, PoisonError},
+    },
+    wasmi::{Config, Engine, Linker, Memory, Module, Store, TypedFunc, WasmResults},
+};
+
+// How much fuel a plugin may consume in each call, so a plugin which doesn't terminate can't stall
+// the vacuum. Most instructions consume one unit of fuel.
+#[cfg(feature = "plugins")]
+const FUEL_PER_CALL: u64 = 10_000_000;
+
+// The names of the exports which make up the plugin interface
+#[cfg(feature = "plugins")]
+const MEMORY_EXPORT: &str = "memory";
+#[cfg(feature = "plugins")]
+const ALLOC_EXPORT: &str = "docuum_alloc";
+#[cfg(feature = "plugins")]
+const KEEP_EXPORT: &str = "docuum_keep";
+#[cfg(feature = "plugins")]
+const SCORE_EXPORT: &str = "docuum_score";
+
+// What a plugin is told about an image, as JSON
+#[derive(Serialize)]
+pub struct Image<'a> {
+    pub id: &'a str,
+    pub repository_tags: Vec<String>,
+    pub repository_digests: &'a [String],
+    pub labels: &'a HashMap<String, String>,
+    pub size_bytes: u128,
+    pub created_since_epoch: u64,
+    pub last_used_since_epoch: u64,
+    pub uses: u64,
+}
+
+// An instantiated plugin, along with the exports Docuum calls
+#[cfg(feature = "plugins")]
+struct Instance {
+    store: Store<()>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    keep: Option<TypedFunc<(i32, i32), i32>>,
+    score: Option<TypedFunc<(i32, i32), f64>>,
+}
+
+// A WebAssembly module which implements custom retention rules. Calls are serialized, since the
+// module has a single memory.
+#[cfg(feature = "plugins")]
+pub struct Plugin {
+    path: String,
+    scores: bool,
+    instance: Mutex<Instance>,
+}
+
+// Without plugin support, no plugins can be loaded.
+#[cfg(not(feature = "plugins"))]
+pub enum Plugin {}
+
+#[cfg(feature = "plugins")]
+impl Plugin {
+    // Load and instantiate a plugin. It must export its memory, an allocator for the input, and
+    // at least one of `docuum_keep` and `docuum_score`.
+    pub fn load(path: &str) -> io::Result<Self> {
+        let invalid = |details: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Unable to load plugin {}. Details: {}",
+                    path.code_str(),
+                    details,
+                ),
+            )
+        };
+
+        let wasm = read(path).map_err(|error| invalid(error.to_string()))?;
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &wasm).map_err(|error| invalid(error.to_string()))?;
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::new(&engine)
+            .instantiate(&mut store, &module)
+            .and_then(|instance| {
+                store.set_fuel(FUEL_PER_CALL)?;
+                instance.start(&mut store)
+            })
+            .map_err(|error| invalid(error.to_string()))?;
+
+        let memory = instance
+            .get_memory(&store, MEMORY_EXPORT)
+            .ok_or_else(|| invalid(format!("It doesn't export {}.", MEMORY_EXPORT.code_str())))?;
+        let alloc = instance
+            .get_typed_func(&store, ALLOC_EXPORT)
+            .map_err(|error| invalid(format!("Invalid {}: {}", ALLOC_EXPORT.code_str(), error)))?;
+        let keep = instance.get_typed_func(&store, KEEP_EXPORT).ok();
+        let score_func = instance.get_typed_func(&store, SCORE_EXPORT).ok();
+        if keep.is_none() && score_func.is_none() {
+            return Err(invalid(format!(
+                "It exports neither {} nor {} with the expected type.",
+                KEEP_EXPORT.code_str(),
+                SCORE_EXPORT.code_str(),
+            )));
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            scores: score_func.is_some(),
+            instance: Mutex::new(Instance {
+                store,
+                memory,
+                alloc,
+                keep,
+                score: score_func,
+            }),
+        })
+    }
+
+    // The path the plugin was loaded from
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // Whether the plugin computes scores
+    pub fn scores(&self) -> bool {
+        self.scores
+    }
+
+    // Copy an image into the memory of the plugin and call one of its exports with the location of
+    // the JSON. Returns `None` if the plugin doesn't have the export.
+    fn call<R: WasmResults>(
+        &self,
+        export: impl Fn(&Instance) -> Option<TypedFunc<(i32, i32), R>>,
+        image: &Image,
+    ) -> io::Result<Option<R>> {
+        let failed = |error: &dyn ToString| {
+            io::Error::other(format!(
+                "The plugin {} failed for image {}. Details: {}",
+                self.path.code_str(),
+                image.id.code_str(),
+                error.to_string(),
+            ))
+        };
+
+        // A panic while the lock was held leaves the instance in an unknown state, but the next
+        // call will most likely fail in a way which is reported.
+        let mut instance = self.instance.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(func) = export(&instance) else {
+            return Ok(None);
+        };
+
+        let json = serde_json::to_vec(image).map_err(io::Error::other)?;
+        let length = i32::try_from(json.len()).map_err(|error| failed(&error))?;
+        let Instance {
+            store,
+            memory,
+            alloc,
+            ..
+        } = &mut *instance;
+        store
+            .set_fuel(FUEL_PER_CALL)
+            .map_err(|error| failed(&error))?;
+        let pointer = alloc
+            .call(&mut *store, length)
+            .map_err(|error| failed(&error))?;
+        let offset = usize::try_from(pointer).map_err(|error| failed(&error))?;
+        memory
+            .write(&mut *store, offset, &json)
+            .map_err(|error| failed(&error))?;
+        func.call(&mut *store, (pointer, length))
+            .map(Some)
+            .map_err(|error| failed(&error))
+    }
+
+    // Ask the plugin whether an image must be kept. Plugins which don't implement `docuum_keep`
+    // don't keep any images.
+    pub fn keep(&self, image: &Image) -> io::Result<bool> {
+        self.call(|instance| instance.keep, image)
+            .map(|keep| keep.is_some_and(|keep| keep != 0_i32))
+    }
+
+    // Ask the plugin for the score of an image, if it implements `docuum_score`. Images with higher
+    // scores are deleted first.
+    pub fn score(&self, image: &Image) -> io::Result<Option<f64>> {
+        self.call(|instance| instance.score, image)
+    }
+}
+
+#[cfg(not(feature = "plugins"))]
+impl Plugin {
+    // Without plugin support, loading a plugin always fails.
+    pub fn load(_: &str) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Docuum was built without plugin support. Enable the `plugins` feature to use it.",
+        ))
+    }
+
+    pub fn path(&self) -> &str {
+        match *self {}
+    }
+
+    pub fn scores(&self) -> bool {
+        match *self {}
+    }
+
+    pub fn keep(&self, _: &Image) -> io::Result<bool> {
+        match *self {}
+    }
+
+    pub fn score(&self, _: &Image) -> io::Result<Option<f64>> {
+        match *self {}
+    }
+}
+
+#[cfg(all(test, feature = "plugins"))]
+mod tests {
+    use {
+        super::{Image, Plugin},
+        std::{collections::HashMap, io::Write},
+        tempfile::NamedTempFile,
+    };
+
+    // A plugin which keeps images with `keep` in their JSON and scores images by their size. Its
+    // allocator always returns the same location, since the input is only used during each call.
+    const PLUGIN: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "docuum_alloc") (param i32) (result i32)
+            (i32.const 0))
+          (func (export "docuum_keep") (param $pointer i32) (param $length i32) (result i32)
+            (local $index i32)
+            (block $done
+              (loop $next
+                (br_if $done (i32.gt_s (i32.add (local.get $index) (i32.const 4))
+                                       (local.get $length)))
+                (if (i32.eq (i32.load (i32.add (local.get $pointer) (local.get $index)))
+                            (i32.const 0x7065656b)) ;; "keep"
+                  (then (return (i32.const 1))))
+                (local.set $index (i32.add (local.get $index) (i32.const 1)))
+                (br $next)))
+            (i32.const 0))
+          (func (export "docuum_score") (param i32) (param $length i32) (result f64)
+            (f64.convert_i32_s (local.get $length))))
+    "#;
+
+    // A plugin which never returns
+    const SPINNING_PLUGIN: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "docuum_alloc") (param i32) (result i32)
+            (i32.const 0))
+          (func (export "docuum_keep") (param i32) (param i32) (result i32)
+            (loop $forever (br $forever))
+            (i32.const 0)))
+    "#;
+
+    fn load(wat: &str) -> std::io::Result<Plugin> {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&wat::parse_str(wat).unwrap()).unwrap();
+        Plugin::load(&file.path().to_string_lossy())
+    }
+
+    fn with_image<R>(repository_tag: &str, f: impl FnOnce(&Image) -> R) -> R {
+        f(&Image {
+            id: "sha256:0",
+            repository_tags: vec![repository_tag.to_owned()],
+            repository_digests: &[],
+            labels: &HashMap::new(),
+            size_bytes: 42,
+            created_since_epoch: 0,
+            last_used_since_epoch: 0,
+            uses: 0,
+        })
+    }
+
+    #[test]
+    fn plugin_keep() {
+        let plugin = load(PLUGIN).unwrap();
+
+        assert!(plugin.scores());
+        assert!(with_image("keep:latest", |image| {
+            plugin.keep(image).unwrap()
+        }));
+        assert!(!with_image("alpine:latest", |image| {
+            plugin.keep(image).unwrap()
+        }));
+    }
+
+    #[test]
+    fn plugin_score() {
+        let plugin = load(PLUGIN).unwrap();
+
+        let short = with_image("a:b", |image| plugin.score(image).unwrap().unwrap());
+        let long = with_image("alpine:latest", |image| {
+            plugin.score(image).unwrap().unwrap()
+        });
+        assert!(long > short);
+    }
+
+    #[test]
+    fn plugin_out_of_fuel() {
+        let plugin = load(SPINNING_PLUGIN).unwrap();
+
+        assert!(!plugin.scores());
+        assert!(with_image("alpine:latest", |image| plugin.keep(image)).is_err());
+        assert_eq!(
+            with_image("alpine:latest", |image| plugin.score(image).unwrap()),
+            None,
+        );
+    }
+
+    #[test]
+    fn plugin_invalid() {
+        assert!(load("(module)").is_err());
+        assert!(load(r#"(module (memory (export "memory") 1))"#).is_err());
+    }
+}
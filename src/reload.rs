@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(unix)]
+use {
+    nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal},
+    std::io,
+};
+
+// The number of reloads which have been requested. Each endpoint keeps track of how many of them it
+// has seen, so they all get to reload.
+static RELOADS_REQUESTED: AtomicUsize = AtomicUsize::new(0);
+
+// The `SIGHUP` handler. This must be async-signal-safe, so all it does is set a flag.
+#[cfg(unix)]
+extern "C" fn handle_sighup(_: nix::libc::c_int) {
+    RELOADS_REQUESTED.fetch_add(1, Ordering::SeqCst);
+}
+
+// Request a reload of the settings upon receiving `SIGHUP`. This replaces the handler installed by
+// the `ctrlc` crate for that signal [ref:ctrlc_term], so it must be called after that one.
+#[cfg(unix)]
+pub fn install_handler() -> io::Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(handle_sighup),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+
+    // SAFETY: The handler only touches an atomic, which is async-signal-safe.
+    unsafe { sigaction(Signal::SIGHUP, &action) }
+        .map(|_| ())
+        .map_err(io::Error::from)
+}
+
+// There is no `SIGHUP` on this platform, so reloads are never requested.
+#[cfg(not(unix))]
+pub fn install_handler() -> std::io::Result<()> {
+    Ok(())
+}
+
+// The number of reloads which have been requested so far
+pub fn requests() -> usize {
+    RELOADS_REQUESTED.load(Ordering::SeqCst)
+}
+
+// Check whether a reload has been requested since `seen` was last updated, and update it.
+pub fn requested(seen: &mut usize) -> bool {
+    let requests = requests();
+    std::mem::replace(seen, requests) != requests
+}
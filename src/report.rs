@@ -0,0 +1,232 @@
+use {
+    crate::format::CodeStr,
+    byte_unit::Byte,
+    serde::{Deserialize, Serialize},
+    std::time::Duration,
+};
+
+// Why an image was deleted
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletionReason {
+    // The space usage was over the threshold.
+    Threshold,
+
+    // The image hadn't been used for longer than `--max-age`.
+    MaxAge,
+
+    // There were more than `--max-images` images.
+    MaxImages,
+
+    // There were fewer than `--min-free-inodes` free inodes.
+    MinFreeInodes,
+
+    // The space usage was over `--critical-threshold`, so a protected image was deleted anyway.
+    CriticalThreshold,
+}
+
+// An image which was deleted during a vacuum
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct DeletedImage {
+    pub id: String,
+    pub repository_tags: Vec<String>,
+    pub size_bytes: u128,
+    pub reason: DeletionReason,
+}
+
+// How a vacuum turned out, from best to worst, for scripts which run Docuum with `--once`
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Outcome {
+    // Nothing had to be deleted.
+    NothingToDo,
+
+    // Some images were deleted, and the space usage is within the threshold.
+    Deleted,
+
+    // The space usage is still over the threshold, e.g., because the remaining images are in use or
+    // protected.
+    ThresholdUnreached,
+}
+
+impl Outcome {
+    // The exit code of a one-time vacuum with this outcome. Fatal errors exit with `1`.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::NothingToDo => 0,
+            Self::Deleted => 2,
+            Self::ThresholdUnreached => 3,
+        }
+    }
+}
+
+/// A summary of what happened during a vacuum, for consumers other than the log
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct Report {
+    /// The number of images which were considered for deletion
+    pub examined_images: usize,
+
+    pub deleted_images: Vec<DeletedImage>,
+
+    /// The sum of the sizes of the deleted images. Since images can share layers, this may be more
+    /// than the space that was actually freed.
+    pub reclaimed_bytes: u128,
+
+    /// The number of images which were removed from the state because they no longer exist
+    pub forgotten_images: usize,
+
+    /// The space usage and threshold at the end of the vacuum, unless it failed before then
+    pub bytes: Option<u128>,
+    pub threshold_bytes: Option<u128>,
+
+    /// Errors which occurred during the vacuum, including those which didn't stop it
+    pub errors: Vec<String>,
+}
+
+impl Report {
+    /// Record the deletion of an image.
+    pub fn deleted_image(&mut self, deleted_image: DeletedImage) {
+        self.reclaimed_bytes += deleted_image.size_bytes;
+        self.deleted_images.push(deleted_image);
+    }
+
+    /// How far the space usage was over the threshold at the end of the vacuum, which is negative
+    /// if it was under, unless the vacuum failed before then
+    #[must_use]
+    pub fn bytes_over_threshold(&self) -> Option<i128> {
+        let bytes = i128::try_from(self.bytes?).ok()?;
+        let threshold_bytes = i128::try_from(self.threshold_bytes?).ok()?;
+        Some(bytes.saturating_sub(threshold_bytes))
+    }
+
+    /// How the vacuum turned out
+    #[must_use]
+    pub fn outcome(&self) -> Outcome {
+        if self.bytes_over_threshold().is_some_and(|over| over > 0) {
+            Outcome::ThresholdUnreached
+        } else if self.deleted_images.is_empty() {
+            Outcome::NothingToDo
+        } else {
+            Outcome::Deleted
+        }
+    }
+
+    /// Summarize the vacuum in a single line for the log.
+    #[must_use]
+    pub fn summary(&self, duration: Duration) -> String {
+        let format_bytes = |bytes: u128| {
+            Byte::from_bytes(bytes)
+                .get_appropriate_unit(false)
+                .to_string()
+        };
+        let threshold = self.bytes_over_threshold().map_or_else(
+            || "space usage unknown".to_owned(),
+            |over| {
+                format!(
+                    "{} {} the limit",
+                    format_bytes(over.unsigned_abs()).code_str(),
+                    if over > 0 { "over" } else { "under" },
+                )
+            },
+        );
+
+        format!(
+            "Vacuum summary: examined {} image(s), deleted {} image(s), reclaimed {}, {}, took {} \
+                second(s), {} error(s).",
+            self.examined_images,
+            self.deleted_images.len(),
+            format_bytes(self.reclaimed_bytes).code_str(),
+            threshold,
+            duration.as_secs_f64(),
+            self.errors.len(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{DeletedImage, DeletionReason, Outcome, Report},
+        std::time::Duration,
+    };
+
+    #[test]
+    fn report_json() {
+        let mut report = Report::default();
+        report.deleted_image(DeletedImage {
+            id: "sha256:0".to_owned(),
+            repository_tags: vec!["alpine:latest".to_owned()],
+            size_bytes: 42,
+            reason: DeletionReason::MaxAge,
+        });
+        report.errors.push("Uh oh.".to_owned());
+
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            concat!(
+                r#"{"examined_images":0,"deleted_images":[{"id":"sha256:0","#,
+                r#""repository_tags":["alpine:latest"],"size_bytes":42,"reason":"max_age"}],"#,
+                r#""reclaimed_bytes":42,"forgotten_images":0,"bytes":null,"threshold_bytes":null,"#,
+                r#""errors":["Uh oh."]}"#,
+            ),
+        );
+    }
+
+    #[test]
+    fn summary_under_threshold() {
+        let mut report = Report {
+            examined_images: 12,
+            bytes: Some(8_000_000_000),
+            threshold_bytes: Some(10_000_000_000),
+            ..Report::default()
+        };
+        report.deleted_image(DeletedImage {
+            id: "sha256:0".to_owned(),
+            repository_tags: vec![],
+            size_bytes: 1_500_000_000,
+            reason: DeletionReason::Threshold,
+        });
+
+        assert_eq!(report.bytes_over_threshold(), Some(-2_000_000_000));
+        assert_eq!(report.outcome(), Outcome::Deleted);
+        assert_eq!(
+            report.summary(Duration::from_millis(2500)),
+            "Vacuum summary: examined 12 image(s), deleted 1 image(s), reclaimed `1.50 GB`, \
+                `2.00 GB` under the limit, took 2.5 second(s), 0 error(s).",
+        );
+    }
+
+    #[test]
+    fn summary_failed() {
+        let mut report = Report::default();
+        report.errors.push("Uh oh.".to_owned());
+
+        assert_eq!(report.bytes_over_threshold(), None);
+        assert_eq!(report.outcome(), Outcome::NothingToDo);
+        assert_eq!(
+            report.summary(Duration::from_secs(1)),
+            "Vacuum summary: examined 0 image(s), deleted 0 image(s), reclaimed `0 B`, space \
+                usage unknown, took 1 second(s), 1 error(s).",
+        );
+    }
+
+    #[test]
+    fn outcome_threshold_unreached() {
+        let report = Report {
+            bytes: Some(12_000_000_000),
+            threshold_bytes: Some(10_000_000_000),
+            ..Report::default()
+        };
+
+        assert_eq!(report.outcome(), Outcome::ThresholdUnreached);
+        assert_eq!(report.outcome().exit_code(), 3_i32);
+    }
+
+    #[test]
+    fn outcome_order() {
+        assert_eq!(Outcome::Deleted.max(Outcome::NothingToDo), Outcome::Deleted);
+        assert_eq!(
+            Outcome::Deleted.max(Outcome::ThresholdUnreached),
+            Outcome::ThresholdUnreached,
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use {
+    crate::{
+        archive,
+        format::CodeStr,
+        history::{self, Entry},
+        Settings,
+    },
+    std::io,
+};
+
+// Strip the algorithm from an image ID, if there is one.
+fn hex(image_id: &str) -> &str {
+    image_id.split_once(':').map_or(image_id, |(_, hex)| hex)
+}
+
+// Determine whether an entry of the deletion history describes the given image, which is given by
+// ID (possibly abbreviated) or by `REPOSITORY:TAG`.
+fn describes(entry: &Entry, image: &str) -> bool {
+    entry
+        .repository_tags
+        .iter()
+        .any(|repository_tag| repository_tag == image)
+        || (!hex(image).is_empty()
+            && hex(image).chars().all(|c| c.is_ascii_hexdigit())
+            && hex(&entry.id).starts_with(hex(image)))
+}
+
+// The tags an image had when it was deleted. Dangling images are listed as `<none>:<none>`.
+fn repository_tags(entry: &Entry) -> Vec<&str> {
+    entry
+        .repository_tags
+        .iter()
+        .filter(|repository_tag| !repository_tag.contains("<none>"))
+        .map(String::as_str)
+        .collect()
+}
+
+// Restore a deleted image, given by ID or by `REPOSITORY:TAG`. If the image was archived, the
+// newest archive is loaded and the tags recorded in the deletion history are applied again.
+// Otherwise, the commands which pull the image again are printed.
+pub fn restore(settings: &Settings, image: &str) -> io::Result<()> {
+    // Find the most recent deletion of the image.
+    let entries = history::entries()?;
+    let entry = entries.iter().rev().find(|entry| describes(entry, image));
+    let image_id = entry.map_or(image, |entry| entry.id.as_str());
+    let repository_tags = entry.map(repository_tags).unwrap_or_default();
+
+    // Load the archive, if there is one.
+    if let Some(archive_dir) = &settings.archive_dir {
+        if let Some(path) = archive::find(archive_dir, image_id)? {
+            archive::load(&settings.backend, &path)?;
+            for repository_tag in repository_tags {
+                settings.backend.tag_image(image_id, repository_tag)?;
+            }
+
+            info!(
+                "Restored {} from {}.",
+                image_id.code_str(),
+                path.to_string_lossy().code_str(),
+            );
+            return Ok(());
+        }
+    }
+
+    // Without an archive, the image can only be pulled again.
+    let Some(entry) = entry else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "Neither an archive nor the deletion history mentions {}.",
+                image.code_str(),
+            ),
+        ));
+    };
+
+    if repository_tags.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "Image {} wasn't archived, and it had no tags to pull it by.",
+                entry.id.code_str(),
+            ),
+        ));
+    }
+
+    for repository_tag in repository_tags {
+        println!("{}", settings.backend.pull_command(repository_tag));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{describes, repository_tags},
+        crate::{history::Entry, report::DeletionReason},
+    };
+
+    fn entry() -> Entry {
+        Entry {
+            timestamp: "2024-05-02T12:00:00Z".to_owned(),
+            id: "sha256:0123456789abcdef".to_owned(),
+            repository_tags: vec!["alpine:3.20".to_owned(), "<none>:<none>".to_owned()],
+            size_bytes: 42,
+            reason: DeletionReason::Threshold,
+        }
+    }
+
+    #[test]
+    fn describes_by_tag() {
+        assert!(describes(&entry(), "alpine:3.20"));
+        assert!(!describes(&entry(), "alpine:latest"));
+        assert!(!describes(&entry(), "<none>"));
+    }
+
+    #[test]
+    fn describes_by_id() {
+        assert!(describes(&entry(), "sha256:0123456789abcdef"));
+        assert!(describes(&entry(), "0123456789ab"));
+        assert!(!describes(&entry(), "123456789abc"));
+        assert!(!describes(&entry(), "sha256:"));
+    }
+
+    #[test]
+    fn repository_tags_dangling() {
+        assert_eq!(repository_tags(&entry()), vec!["alpine:3.20"]);
+    }
+}
@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+// How long to wait before retrying after an error, which grows exponentially with each consecutive
+// error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Policy {
+    pub initial_delay: Duration,
+    pub multiplier: f64,
+    pub max_delay: Duration,
+}
+
+impl Policy {
+    // Determine how long to wait before the given retry, where `0` is the first one. The delay is
+    // rounded to the millisecond so it can be logged without a long tail of digits.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let delay = self.initial_delay.as_secs_f64()
+            * self
+                .multiplier
+                .powi(i32::try_from(retry).unwrap_or(i32::MAX));
+
+        if delay >= self.max_delay.as_secs_f64() {
+            self.max_delay
+        } else {
+            Duration::from_millis(
+                Duration::from_secs_f64(delay)
+                    .as_millis()
+                    .try_into()
+                    .unwrap_or(u64::MAX),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::Policy, std::time::Duration};
+
+    #[test]
+    fn delay_grows() {
+        let policy = Policy {
+            initial_delay: Duration::from_secs(5),
+            multiplier: 1.5_f64,
+            max_delay: Duration::from_secs(100),
+        };
+
+        assert_eq!(policy.delay(0), Duration::from_secs(5));
+        assert_eq!(policy.delay(1), Duration::from_millis(7500));
+        assert_eq!(policy.delay(2), Duration::from_millis(11_250));
+    }
+
+    #[test]
+    fn delay_capped() {
+        let policy = Policy {
+            initial_delay: Duration::from_secs(5),
+            multiplier: 2.0_f64,
+            max_delay: Duration::from_secs(100),
+        };
+
+        assert_eq!(policy.delay(10), Duration::from_secs(100));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(100));
+    }
+
+    #[test]
+    fn delay_constant() {
+        let policy = Policy {
+            initial_delay: Duration::from_secs(5),
+            multiplier: 1.0_f64,
+            max_delay: Duration::from_secs(100),
+        };
+
+        assert_eq!(policy.delay(100), Duration::from_secs(5));
+    }
+}
@@ -1,494 +1,437 @@
 use {
     crate::{
+        archive,
+        backend::{Backend, ImageRecord, PROTECTION_LABEL_KEY, PROTECTION_LABEL_VALUE},
+        container, control, dockerfile, event, eviction,
         format::CodeStr,
+        grpc, health, history, hook,
+        kubernetes::{self, KubeletThresholds, MinAvailable},
+        pause, pins,
+        pipeline::{Stage, StageKind},
+        plugin::{self, Plugin},
+        reload,
+        report::{DeletedImage, DeletionReason, Outcome, Report},
+        service,
         state::{self, State},
-        Settings, Threshold,
+        statsd, systemd, telemetry, webhook, Settings, Threshold,
     },
     byte_unit::Byte,
-    chrono::DateTime,
+    chrono::{DateTime, Local, SecondsFormat, Utc},
+    log::{kv::ToValue, Level},
     regex::RegexSet,
-    serde::{Deserialize, Serialize},
     std::{
         cmp::max,
-        collections::{hash_map::Entry, HashMap, HashSet},
+        collections::{BTreeSet, HashMap, HashSet},
         io::{self, BufRead, BufReader},
-        ops::Deref,
-        process::{Command, Stdio},
-        sync::{Arc, Mutex},
-        time::{Duration, SystemTime, UNIX_EPOCH},
+        path::Path,
+        sync::{
+            mpsc::{channel, Receiver, RecvTimeoutError},
+            Arc, Mutex, MutexGuard, PoisonError,
+        },
+        thread::{self, sleep},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
     },
-};
-
-#[cfg(target_os = "linux")]
-use {
-    std::path::{Path, PathBuf},
     sysinfo::{Disk, DiskExt, RefreshKind, System, SystemExt},
 };
 
-// When querying Docker for the image IDs corresponding to a list of container IDs, this is the
-// maximum number of container IDs to query at once.
-const CONTAINER_IDS_CHUNK_SIZE: usize = 100;
-
-// A Docker event (a line of output from `docker events --format '{{json .}}'`)
-#[derive(Deserialize, Serialize, Debug)]
-struct Event {
-    #[serde(rename = "Type")]
-    r#type: String,
-
-    #[serde(rename = "Action")]
-    action: String,
-
-    #[serde(rename = "Actor")]
-    actor: EventActor,
-
-    id: String,
-}
-
-// A Docker event actor
-#[derive(Deserialize, Serialize, Debug)]
-struct EventActor {
-    #[serde(rename = "Attributes")]
-    attributes: EventActorAttributes,
-}
-
-// Docker event actor attributes
-#[derive(Deserialize, Serialize, Debug)]
-struct EventActorAttributes {
-    image: Option<String>,
-}
-
-// A line of output from `docker system df --format '{{json .}}'`
-#[derive(Deserialize, Serialize, Debug)]
-struct SpaceRecord {
-    #[serde(rename = "Type")]
-    r#type: String,
+#[cfg(target_os = "linux")]
+use nix::sys::statvfs::statvfs;
 
-    #[serde(rename = "Size")]
-    size: String,
-}
+// While waiting for Docker events, this is how often we check whether a reload of the settings has
+// been requested.
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
-// Each image may be associated with multiple of these repository-tag pairs. Docker will always
-// report at least one repository-tag pair for each image. For untagged images, `tag` will be
-// `<none>`, and `repository` may also take on that value [tag:at_least_one_repository_tag].
-#[derive(Clone, Debug, Eq, PartialEq)]
-struct RepositoryTag {
-    repository: String,
-    tag: String,
-}
+// If the events process terminates this soon after being spawned without producing any events, we
+// assume events can't be streamed at all, rather than that the stream was interrupted.
+const EVENTS_STARTUP_PERIOD: Duration = Duration::from_secs(10);
 
-// This is the information Docker reports about each image when listing images. Note that the image
-// ID is not included here because this struct will be used as the value type for a `HashMap` for
-// which the key type is the image ID.
-#[derive(Clone, Debug, Eq, PartialEq)]
-struct ImageRecord {
-    parent_id: Option<String>,
-    created_since_epoch: Duration,
-    repository_tags: Vec<RepositoryTag>, // [ref:at_least_one_repository_tag]
-}
+// The registry of repositories which don't name one, such as `alpine`
+const DOCKER_HUB_REGISTRY: &str = "docker.io";
 
 // This is a node in the image polyforest. Note that the image ID is not included here because this
 // struct will be used as the value type for a `HashMap` for which the key type is the image ID.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 struct ImageNode {
     image_record: ImageRecord,
     last_used_since_epoch: Duration,
-    ancestors: usize, // 0 for images with no parent or missing parent
-}
-
-// Ask Docker for the ID of an image.
-fn image_id(image: &str) -> io::Result<String> {
-    // Query Docker for the image ID.
-    let output = Command::new("docker")
-        .args(["image", "inspect", "--format", "{{.ID}}", image])
-        .stderr(Stdio::inherit())
-        .output()?;
-
-    // Ensure the command succeeded.
-    if !output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("Unable to determine ID of image {}.", image.code_str()),
-        ));
-    }
+    pulled_since_epoch: Option<Duration>, // See `state::Image`.
+    uses: u64,                            // See `state::Image`.
+    frequency: f64,                       // See `state::Image`.
+    ancestors: usize,                     // 0 for images with no parent or missing parent
+}
 
-    // Interpret the output bytes as UTF-8 and trim any leading/trailing whitespace.
-    String::from_utf8(output.stdout)
-        .map(|output| output.trim().to_owned())
-        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+// Find the disk containing a path.
+fn get_disk_by_file<'a>(disks: &'a [Disk], path: &Path) -> io::Result<&'a Disk> {
+    disks
+        .iter()
+        .filter(|d| path.starts_with(d.mount_point()))
+        .max_by_key(|d| d.mount_point().as_os_str().len())
+        .ok_or_else(|| {
+            io::Error::other(format!(
+                "Unable to find disk for path {}.",
+                path.to_string_lossy().code_str(),
+            ))
+        })
 }
 
-// Get the ID of the parent of an image (if the parent exists), querying Docker if necessary.
-fn parent_id(state: &State, image_id: &str) -> io::Result<Option<String>> {
-    // If we already know the parent, just return it.
-    if let Some(image) = state.images.get(image_id) {
-        return Ok(image.parent_id.clone());
+// Find the size of the filesystem on which a path is stored and the amount of free space on it.
+fn filesystem_space(path: &Path) -> io::Result<(Byte, Byte)> {
+    // On Linux, ask the filesystem itself rather than going by the mount table, which doesn't
+    // describe ZFS datasets or Btrfs subvolumes well. For a ZFS dataset, this accounts for its
+    // quota, if any, and otherwise for the free space it shares with the rest of the pool. Btrfs
+    // subvolumes share the space of the whole filesystem. The path may not exist, e.g., if Docuum
+    // runs in a container, in which case we fall back to the mount table.
+    #[cfg(target_os = "linux")]
+    if path.exists() {
+        let stat = statvfs(path).map_err(io::Error::from)?;
+        let fragment_size = u128::from(stat.fragment_size());
+        return Ok((
+            Byte::from_bytes(u128::from(stat.blocks()) * fragment_size),
+            Byte::from_bytes(u128::from(stat.blocks_available()) * fragment_size),
+        ));
     }
 
-    // Query Docker for the parent image ID.
-    let output = Command::new("docker")
-        .args(["image", "inspect", "--format", "{{.Parent}}", image_id])
-        .stderr(Stdio::inherit())
-        .output()?;
+    let system = System::new_with_specifics(RefreshKind::new().with_disks_list());
+    let disks = system.disks();
+    let disk = get_disk_by_file(disks, path)?;
+    Ok((
+        Byte::from(disk.total_space()),
+        Byte::from(disk.available_space()),
+    ))
+}
 
-    // Ensure the command succeeded.
-    if !output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!(
-                "Unable to determine ID of the parent of image {}.",
-                image_id.code_str(),
-            ),
-        ));
+// Find size of filesystem on which the root directory of the container runtime is stored. With the
+// devicemapper storage driver, images are kept in a thin pool instead, so that's measured instead.
+fn root_dir_filesystem_size(backend: &Backend) -> io::Result<Byte> {
+    if let Some((size, _)) = backend.thin_pool_space()? {
+        return Ok(size);
     }
 
-    // Interpret the output bytes as UTF-8 and trim any leading/trailing whitespace.
-    String::from_utf8(output.stdout)
-        .map(|output| {
-            let trimmed_output = output.trim();
+    let root_dir = backend.root_dir()?;
 
-            // Does the image even have a parent?
-            if trimmed_output.is_empty() {
-                None
-            } else {
-                Some(trimmed_output.to_owned())
-            }
-        })
-        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
-}
-
-// Query Docker for all the images.
-fn list_image_records(state: &State) -> io::Result<HashMap<String, ImageRecord>> {
-    // Get the IDs and creation timestamps of all the images.
-    let output = Command::new("docker")
-        .args([
-            "image",
-            "ls",
-            "--all",
-            "--no-trunc",
-            "--format",
-            "{{.ID}}\\t{{.Repository}}\\t{{.Tag}}\\t{{.CreatedAt}}",
-        ])
-        .stderr(Stdio::inherit())
-        .output()?;
-
-    // Ensure the command succeeded.
-    if !output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Unable to list images.",
-        ));
+    // With Docker Desktop, the root directory is inside a VM rather than on the host.
+    #[cfg(any(target_os = "macos", windows))]
+    if !root_dir.exists() {
+        return backend.desktop_disk_size();
     }
 
-    // Interpret the output bytes as UTF-8 and parse the lines.
-    let mut image_records = HashMap::<_, ImageRecord>::new();
-    for line in String::from_utf8(output.stdout)
-        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?
-        .lines()
-    {
-        let trimmed_line = line.trim();
+    filesystem_space(&root_dir).map(|(size, _)| size)
+}
 
-        if trimmed_line.is_empty() {
-            continue;
-        }
+// Find the amount of free space on the filesystem on which the root directory of the container
+// runtime is stored, or in the thin pool of the devicemapper storage driver.
+fn root_dir_filesystem_free_space(backend: &Backend) -> io::Result<Byte> {
+    if let Some((_, free_space)) = backend.thin_pool_space()? {
+        return Ok(free_space);
+    }
 
-        let image_parts = trimmed_line.split('\t').collect::<Vec<_>>();
-        if let [id, repository, tag, date_str] = image_parts[..] {
-            let repository_tag = RepositoryTag {
-                repository: repository.to_owned(),
-                tag: tag.to_owned(),
-            };
+    let root_dir = backend.root_dir()?;
 
-            match image_records.entry(id.to_owned()) {
-                Entry::Occupied(mut entry) => {
-                    (entry.get_mut()).repository_tags.push(repository_tag);
-                }
-                Entry::Vacant(entry) => {
-                    entry.insert(ImageRecord {
-                        parent_id: parent_id(state, id)?,
-                        created_since_epoch: parse_docker_date(date_str)?,
-                        repository_tags: vec![repository_tag],
-                    });
-                }
-            }
-        } else {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Failed to parse image list output from Docker.",
-            ));
-        }
-    }
-
-    Ok(image_records)
-}
-
-// Ask Docker for the IDs of the images currently in use by containers.
-fn image_ids_in_use() -> io::Result<HashSet<String>> {
-    // Query Docker for all the container IDs.
-    let container_ids_output = Command::new("docker")
-        .args([
-            "container",
-            "ls",
-            "--all",
-            "--no-trunc",
-            "--format",
-            "{{.ID}}",
-        ])
-        .stderr(Stdio::inherit())
-        .output()?;
-
-    // Ensure the command succeeded.
-    if !container_ids_output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Unable to determine IDs of images currently in use by containers.",
+    // With Docker Desktop, the root directory is inside a VM, and we can't tell how much space is
+    // free there.
+    #[cfg(any(target_os = "macos", windows))]
+    if !root_dir.exists() {
+        return Err(io::Error::other(
+            "Free space thresholds are only supported when the root directory of the container \
+                runtime is on the host.",
         ));
     }
 
-    // Interpret the output bytes as UTF-8 and parse the lines.
-    let container_ids = String::from_utf8(container_ids_output.stdout)
-        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
-        .map(|output| {
-            output
-                .lines()
-                .filter_map(|line| {
-                    let trimmed_line = line.trim();
+    filesystem_space(&root_dir).map(|(_, free_space)| free_space)
+}
 
-                    if trimmed_line.is_empty() {
-                        None
-                    } else {
-                        Some(trimmed_line.to_owned())
-                    }
-                })
-                .collect::<Vec<_>>()
-        })?;
+// Find the number of inodes which are available to unprivileged users on the filesystem on which a
+// path is stored.
+#[cfg(target_os = "linux")]
+fn filesystem_free_inodes(path: &Path) -> io::Result<u64> {
+    // The conversion is only needed on some architectures.
+    #[allow(clippy::useless_conversion)]
+    statvfs(path)
+        .map(|stat| u64::from(stat.files_available()))
+        .map_err(io::Error::from)
+}
 
-    // Group the container IDs into chunks and query Docker for the image IDs for each chunk.
-    let mut image_ids = HashSet::new();
-    for chunk in container_ids.chunks(CONTAINER_IDS_CHUNK_SIZE) {
-        // Query Docker for the image IDs for this chunk.
-        let image_ids_output = Command::new("docker")
-            .args(
-                ["container", "inspect", "--format", "{{.Image}}"]
-                    .iter()
-                    .map(Deref::deref)
-                    .chain(chunk.iter().map(AsRef::as_ref)),
-            )
-            .stderr(Stdio::inherit())
-            .output()?;
-
-        // Ensure the command succeeded.
-        if !image_ids_output.status.success() {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Unable to determine IDs of images currently in use by containers.",
-            ));
-        }
-
-        // Interpret the output bytes as UTF-8 and parse the lines.
-        image_ids.extend(
-            String::from_utf8(image_ids_output.stdout)
-                .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
-                .map(|output| {
-                    output
-                        .lines()
-                        .filter_map(|line| {
-                            let trimmed_line = line.trim();
-
-                            if trimmed_line.is_empty() {
-                                None
-                            } else {
-                                Some(trimmed_line.to_owned())
-                            }
-                        })
-                        .collect::<Vec<_>>()
-                })?,
+// Inodes are only counted on Linux.
+#[cfg(not(target_os = "linux"))]
+fn filesystem_free_inodes(_: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Counting free inodes is only supported on Linux.",
+    ))
+}
+
+// Determine how much space images may use such that at least `required` space remains free.
+fn free_space_limit(space: Byte, free_space: Byte, required: Byte) -> Byte {
+    Byte::from_bytes(
+        (space.get_bytes() + free_space.get_bytes()).saturating_sub(required.get_bytes()),
+    )
+}
+
+// Update the timestamp for an image, and remember which container it was used by, if any. The
+// image was used at `used_since_epoch` if that's known (e.g., from the time of an event), or now
+// otherwise. Returns a boolean indicating if a new entry was created for the image.
+pub fn touch_image(
+    backend: &Backend,
+    state: &mut State,
+    image_id: &str,
+    used_by: Option<&str>,
+    used_since_epoch: Option<Duration>,
+    verbose: bool,
+) -> io::Result<bool> {
+    if verbose {
+        debug!(
+            "Updating last-used timestamp for image {}\u{2026}",
+            image_id.code_str(),
+        );
+    } else {
+        trace!(
+            "Updating last-used timestamp for image {}\u{2026}",
+            image_id.code_str(),
         );
     }
 
-    Ok(image_ids)
-}
+    // An event replayed from the checkpoint may be older than the image's current timestamp, which
+    // shouldn't move backward.
+    let duration = match used_since_epoch {
+        Some(duration) => state
+            .images
+            .get(image_id)
+            .map_or(duration, |image| duration.max(image.last_used_since_epoch)),
+        None => now_since_epoch()?,
+    };
 
-// Determine Docker's root directory.
-#[cfg(target_os = "linux")]
-fn docker_root_dir() -> io::Result<PathBuf> {
-    // Query Docker for it.
-    let output = Command::new("docker")
-        .args(["info", "--format", "{{.DockerRootDir}}"])
-        .stderr(Stdio::inherit())
-        .output()?;
-
-    // Ensure the command succeeded.
-    if !output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Unable to determine the Docker root directory.",
-        ));
+    // If we haven't seen the image before, it must have just been pulled or built.
+    let pulled_since_epoch = state
+        .images
+        .get(image_id)
+        .map_or(Some(duration), |image| image.pulled_since_epoch);
+
+    // Count the use, and remember which container it was used by.
+    let (uses, frequency) = state.images.get(image_id).map_or((1, 1.0_f64), |image| {
+        (
+            image.uses.saturating_add(1),
+            state::decay_frequency(
+                image.frequency,
+                duration.saturating_sub(image.last_used_since_epoch),
+            ) + 1.0_f64,
+        )
+    });
+    let mut containers = state
+        .images
+        .get(image_id)
+        .map_or_else(Vec::new, |image| image.containers.clone());
+    if let Some(container) = used_by {
+        state::record_container(&mut containers, container);
     }
 
-    // Trim the output.
-    String::from_utf8(output.stdout)
-        .map(|s| PathBuf::from(s.trim()))
-        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+    // Store the image metadata in the state.
+    Ok(state
+        .images
+        .insert(
+            image_id.to_owned(),
+            state::Image {
+                parent_id: backend.parent_id(state, image_id)?,
+                last_used_since_epoch: duration,
+                pulled_since_epoch,
+                uses,
+                frequency,
+                containers,
+            },
+        )
+        .is_none())
 }
 
-// Find the disk containing a path.
-#[cfg(target_os = "linux")]
-fn get_disk_by_file<'a>(disks: &'a [Disk], path: &Path) -> io::Result<&'a Disk> {
-    disks
-        .iter()
-        .filter(|d| path.starts_with(d.mount_point()))
-        .max_by_key(|d| d.mount_point().as_os_str().len())
-        .ok_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "Unable to find disk for path {}.",
-                    path.to_string_lossy().code_str(),
-                ),
-            )
+// Get the current timestamp.
+fn now_since_epoch() -> io::Result<Duration> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|error| {
+            io::Error::other(format!(
+                "Unable to compute the current timestamp: {error:?}.",
+            ))
         })
 }
 
-// Find size of filesystem on which docker root directory is stored.
-#[cfg(target_os = "linux")]
-fn docker_root_dir_filesystem_size() -> io::Result<Byte> {
-    let root_dir = docker_root_dir()?;
-    let system = System::new_with_specifics(RefreshKind::new().with_disks_list());
-    let disks = system.disks();
-    let disk = get_disk_by_file(disks, &root_dir)?;
-    Ok(Byte::from(disk.total_space()))
-}
-
-// Get the total space used by Docker images.
-#[allow(clippy::map_err_ignore)]
-fn space_usage() -> io::Result<Byte> {
-    // Query Docker for the space usage.
-    let output = Command::new("docker")
-        .args(["system", "df", "--format", "{{json .}}"])
-        .stderr(Stdio::inherit())
-        .output()?;
-
-    // Ensure the command succeeded.
-    if !output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Unable to determine the disk space used by Docker images.",
-        ));
-    }
-
-    // Find the relevant line of output.
-    String::from_utf8(output.stdout)
-        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
-        .and_then(|output| {
-            for line in output.lines() {
-                // Parse the line as a space record.
-                if let Ok(space_record) = serde_json::from_str::<SpaceRecord>(line) {
-                    // Return early if we found the record we're looking for.
-                    if space_record.r#type == "Images" {
-                        return Byte::from_str(&space_record.size).map_err(|_| {
-                            io::Error::new(
-                                io::ErrorKind::Other,
-                                format!(
-                                    "Unable to parse {} from {}.",
-                                    space_record.size.code_str(),
-                                    "docker system df".code_str(),
-                                ),
-                            )
-                        });
-                    }
-                }
-            }
+// Update the timestamp for a volume.
+fn touch_volume(state: &mut State, volume: &str) -> io::Result<()> {
+    debug!(
+        "Updating last-used timestamp for volume {}\u{2026}",
+        volume.code_str(),
+    );
+
+    // Store the volume metadata in the state.
+    state.volumes.insert(
+        volume.to_owned(),
+        state::Volume {
+            last_used_since_epoch: now_since_epoch()?,
+        },
+    );
 
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "Unable to parse output of {}: {}",
-                    "docker system df".code_str(),
-                    output.code_str(),
-                ),
-            ))
-        })
+    Ok(())
 }
 
-// Delete a Docker image.
-fn delete_image(image: &str) -> io::Result<()> {
-    info!("Deleting image {}\u{2026}", image.code_str());
+// Update the timestamp for a network.
+fn touch_network(state: &mut State, network: &str) -> io::Result<()> {
+    debug!(
+        "Updating last-used timestamp for network {}\u{2026}",
+        network.code_str(),
+    );
+
+    // Store the network metadata in the state.
+    state.networks.insert(
+        network.to_owned(),
+        state::Network {
+            last_used_since_epoch: now_since_epoch()?,
+        },
+    );
 
-    // Tell Docker to delete the image.
-    let mut child = Command::new("docker")
-        .args(["image", "rm", "--force", "--no-prune", image])
-        .spawn()?;
+    Ok(())
+}
 
-    // Ensure the command succeeded.
-    if !child.wait()?.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("Unable to delete image {}.", image.code_str()),
-        ));
-    }
+// Update the timestamp for a Buildx builder.
+fn touch_buildx_builder(state: &mut State, builder: &str) -> io::Result<()> {
+    debug!(
+        "Updating last-used timestamp for Buildx builder {}\u{2026}",
+        builder.code_str(),
+    );
+
+    // Store the builder metadata in the state.
+    state.buildx_builders.insert(
+        builder.to_owned(),
+        state::BuildxBuilder {
+            last_used_since_epoch: now_since_epoch()?,
+        },
+    );
 
     Ok(())
 }
 
-// Update the timestamp for an image.
-// Returns a boolean indicating if a new entry was created for the image.
-fn touch_image(state: &mut State, image_id: &str, verbose: bool) -> io::Result<bool> {
-    if verbose {
-        debug!(
-            "Updating last-used timestamp for image {}\u{2026}",
-            image_id.code_str(),
-        );
-    } else {
-        trace!(
-            "Updating last-used timestamp for image {}\u{2026}",
-            image_id.code_str(),
+// Update what we remember about groups of containers (e.g., Docker Compose projects), given the IDs
+// of the images used by the containers of each group which currently has any. Groups which have had
+// no containers since `time_stamp` are forgotten. `kind` describes a group in the log.
+fn update_container_groups(
+    container_groups: &mut HashMap<String, state::ContainerGroup>,
+    current_image_ids: HashMap<String, HashSet<String>>,
+    now: Duration,
+    time_stamp: Duration,
+    kind: &str,
+) {
+    for (group, image_ids) in current_image_ids {
+        let mut image_ids = image_ids.into_iter().collect::<Vec<_>>();
+        image_ids.sort();
+        container_groups.insert(
+            group,
+            state::ContainerGroup {
+                image_ids,
+                last_active_since_epoch: now,
+            },
         );
     }
 
-    // Get the current timestamp.
-    match SystemTime::now().duration_since(UNIX_EPOCH) {
-        Ok(duration) => {
-            // Store the image metadata in the state.
-            Ok(state
-                .images
-                .insert(
-                    image_id.to_owned(),
-                    state::Image {
-                        parent_id: parent_id(state, image_id)?,
-                        last_used_since_epoch: duration,
-                    },
-                )
-                .is_none())
+    container_groups.retain(|group, container_group| {
+        if container_group.last_active_since_epoch > time_stamp {
+            return true;
         }
-        Err(error) => Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("Unable to compute the current timestamp: {error:?}."),
-        )),
-    }
+
+        debug!(
+            "Forgot {} {}, since it has had no containers for a while.",
+            kind,
+            group.code_str(),
+        );
+        false
+    });
+}
+
+// If the `--protect-compose-projects` argument is provided, find the IDs of the images used by the
+// Docker Compose projects which have had containers recently, updating what we remember about the
+// projects. Otherwise, forget about them.
+fn compose_image_ids_in_use(
+    settings: &Settings,
+    compose_projects: &mut HashMap<String, state::ContainerGroup>,
+) -> io::Result<HashSet<String>> {
+    let Some(idle_period) = settings.protect_compose_projects else {
+        compose_projects.clear();
+        return Ok(HashSet::new());
+    };
+
+    let now = now_since_epoch()?;
+    update_container_groups(
+        compose_projects,
+        settings.backend.compose_project_image_ids()?,
+        now,
+        now.saturating_sub(idle_period),
+        "Docker Compose project",
+    );
+
+    Ok(compose_projects
+        .values()
+        .flat_map(|compose_project| compose_project.image_ids.iter().cloned())
+        .collect())
+}
+
+// If the `--protect-gitlab-pipelines` argument is provided, find the IDs of the images used by the
+// jobs of the GitLab CI pipelines which have had containers recently, updating what we remember
+// about the pipelines. A later stage of a pipeline often uses the same images as an earlier one, so
+// the images are protected between jobs too. Otherwise, forget about the pipelines.
+fn gitlab_image_ids_in_use(
+    settings: &Settings,
+    gitlab_pipelines: &mut HashMap<String, state::ContainerGroup>,
+) -> io::Result<HashSet<String>> {
+    let Some(idle_period) = settings.protect_gitlab_pipelines else {
+        gitlab_pipelines.clear();
+        return Ok(HashSet::new());
+    };
+
+    let now = now_since_epoch()?;
+    update_container_groups(
+        gitlab_pipelines,
+        settings.backend.gitlab_pipeline_image_ids()?,
+        now,
+        now.saturating_sub(idle_period),
+        "GitLab CI pipeline",
+    );
+
+    Ok(gitlab_pipelines
+        .values()
+        .flat_map(|gitlab_pipeline| gitlab_pipeline.image_ids.iter().cloned())
+        .collect())
 }
 
-// Parse the non-standard timestamp format Docker uses for `docker image ls`.
-// Example input: "2017-12-20 16:30:49 -0500 EST".
-fn parse_docker_date(timestamp: &str) -> io::Result<Duration> {
-    // Chrono can't read the "EST", so remove it before parsing.
-    let timestamp_without_timezone_triad =
-        timestamp.trim().rsplitn(2, ' ').last().ok_or_else(|| {
-            io::Error::new(io::ErrorKind::Other, "Failed to remove timezone string.")
-        })?;
+// If the `--kubernetes-node` argument is provided, find the IDs of the images referenced by the
+// pods on that node. Images which haven't been pulled yet are skipped.
+fn pod_image_ids(settings: &Settings) -> io::Result<HashSet<String>> {
+    let Some(node) = &settings.kubernetes_node else {
+        return Ok(HashSet::new());
+    };
 
-    // Parse the date and convert it into a duration since the UNIX epoch.
-    let duration =
-        match DateTime::parse_from_str(timestamp_without_timezone_triad, "%Y-%m-%d %H:%M:%S %z") {
-            Ok(datetime) => {
-                datetime.signed_duration_since::<chrono::offset::Utc>(DateTime::from(UNIX_EPOCH))
+    let mut image_ids = HashSet::new();
+    for image in kubernetes::pod_images(node)? {
+        match settings.backend.image_id(&image) {
+            Ok(image_id) => {
+                image_ids.insert(image_id);
             }
-            Err(error) => return Err(io::Error::new(io::ErrorKind::Other, error)),
-        };
+            Err(error) => {
+                // The kubelet may not have pulled the image yet.
+                debug!("{error}");
+            }
+        }
+    }
+
+    Ok(image_ids)
+}
 
-    // Convert the duration into a `std::time::Duration`. If the duration is negative, it will be
-    // clamped to zero. This can occur when building images with `kaniko --reproducible`, as the
-    // resulting images have `0001-01-01 00:00:00 +0000 UTC` for their creation timestamp.
-    Ok(duration.to_std().unwrap_or(Duration::ZERO))
+// Find the IDs of the images which are in use, either by containers, by recently active Docker
+// Compose projects or GitLab CI pipelines, or by Kubernetes pods.
+fn image_ids_in_use(
+    settings: &Settings,
+    compose_projects: &mut HashMap<String, state::ContainerGroup>,
+    gitlab_pipelines: &mut HashMap<String, state::ContainerGroup>,
+) -> io::Result<HashSet<String>> {
+    let mut image_ids = settings.backend.image_ids_in_use()?;
+    image_ids.extend(compose_image_ids_in_use(settings, compose_projects)?);
+    image_ids.extend(gitlab_image_ids_in_use(settings, gitlab_pipelines)?);
+    image_ids.extend(pod_image_ids(settings)?);
+    Ok(image_ids)
 }
 
 // Construct a polyforest of image nodes that reflects their parent-child relationships.
@@ -501,10 +444,9 @@ fn construct_polyforest(
     // Compute the current timestamp.
     let time_since_epoch = match SystemTime::now().duration_since(UNIX_EPOCH) {
         Ok(duration) => Ok(duration),
-        Err(error) => Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("Unable to compute the current timestamp: {error:?}."),
-        )),
+        Err(error) => Err(io::Error::other(format!(
+            "Unable to compute the current timestamp: {error:?}.",
+        ))),
     }?;
 
     // Construct the graph. It's a map, just like `image_records`, except the values are
@@ -558,6 +500,23 @@ fn construct_polyforest(
                 last_used_since_epoch = max(last_used_since_epoch, time_since_epoch);
             }
 
+            // Images we haven't seen before were pulled or built since the last vacuum, unless
+            // this is the first one.
+            let pulled_since_epoch = state.images.get(&image_id_and_record_to_add.0).map_or(
+                if first_run {
+                    None
+                } else {
+                    Some(time_since_epoch)
+                },
+                |image| image.pulled_since_epoch,
+            );
+
+            // Carry over how often the image has been used.
+            let (uses, frequency) = state
+                .images
+                .get(&image_id_and_record_to_add.0)
+                .map_or((0, 0.0_f64), |image| (image.uses, image.frequency));
+
             // Compute the number of ancestors.
             let ancestors =
                 image_id_and_record_to_add
@@ -578,6 +537,9 @@ fn construct_polyforest(
                 ImageNode {
                     image_record: image_id_and_record_to_add.1.clone(),
                     last_used_since_epoch,
+                    pulled_since_epoch,
+                    uses,
+                    frequency,
                     ancestors,
                 },
             );
@@ -621,448 +583,3319 @@ fn construct_polyforest(
     Ok(polyforest)
 }
 
-// The main vacuum logic
-fn vacuum(
-    state: &mut State,
-    first_run: bool,
-    threshold: Byte,
-    keep: Option<&RegexSet>,
-    deletion_chunk_size: usize,
-    min_age: Option<Duration>,
-) -> io::Result<()> {
-    // Find all images.
-    let image_records = list_image_records(state)?;
+// Why an image is protected from deletion
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Protection {
+    // One of the image's repository-tag pairs matches `--keep`.
+    Keep(String),
 
-    // Find all images in use by containers.
-    let image_ids_in_use = image_ids_in_use()?;
+    // None of the image's repository-tag pairs match `--remove-only`.
+    RemoveOnly,
 
-    // Construct a polyforest of image nodes that reflects their parent-child relationships.
-    let polyforest = construct_polyforest(state, first_run, &image_records, &image_ids_in_use)?;
+    // The image has the protection label.
+    Label,
 
-    // Sort the images from least recently used to most recently used.
-    // Break ties using the number of dependency layers.
-    let mut sorted_image_nodes = polyforest.iter().collect::<Vec<_>>();
-    sorted_image_nodes.sort_by(|x, y| {
-        x.1.last_used_since_epoch
-            .cmp(&y.1.last_used_since_epoch)
-            .then(y.1.ancestors.cmp(&x.1.ancestors))
-    });
+    // The image was pinned in the dashboard.
+    Pinned,
 
-    // If the user provided the `--keep` argument, we need to filter out images which match the
-    // provided regexes.
-    if let Some(regex_set) = keep {
-        sorted_image_nodes.retain(|(_, image_node)| {
-            for repository_tag in &image_node.image_record.repository_tags {
-                if regex_set.is_match(&format!(
-                    "{}:{}",
-                    repository_tag.repository,
-                    repository_tag.tag,
-                )) {
-                    debug!(
-                        "Ignored image {} due to the {} flag.",
-                        format!("{}:{}", repository_tag.repository, repository_tag.tag).code_str(),
-                        "--keep".code_str(),
-                    );
-                    return false;
-                }
-            }
+    // Docuum itself is running in a container based on the image.
+    OwnImage,
 
-            true
-        });
-    }
+    // With `--kubernetes`, the container runtime pinned the image (e.g., the sandbox image), or
+    // it's a pause image.
+    Kubernetes,
 
-    // If the `--min-age` argument is provided, we need to filter out images
-    // which are newer than the provided duration.
-    if let Some(duration) = min_age {
-        match (SystemTime::now() - duration).duration_since(UNIX_EPOCH) {
-            Ok(time_stamp) => {
-                sorted_image_nodes.retain(|(image_id, image_node)| {
-                    if image_node.last_used_since_epoch > time_stamp {
-                        debug!(
-                            "Ignored image {} due to the {} flag.",
-                            image_id.code_str(),
-                            "--min-age".code_str(),
-                        );
+    // The image has one of the labels given by `--keep-label`.
+    KeepLabel,
 
-                        return false;
-                    }
+    // One of the image's repository digests matches `--keep-digest`.
+    KeepDigest(String),
 
-                    true
-                });
-            }
-            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidInput, e)),
-        };
-    }
+    // The image's ID matches `--keep-id`.
+    KeepId,
 
-    // Check if we're over the threshold.
-    let mut deleted_image_ids = HashSet::new();
-    let space = space_usage()?;
-    if space > threshold {
-        info!(
-            "Docker images are currently using {}, but the limit is {}.",
-            space.get_appropriate_unit(false).to_string().code_str(),
-            threshold.get_appropriate_unit(false).to_string().code_str(),
-        );
+    // The image is from one of the registries given by `--protect-registry`.
+    ProtectRegistry(String),
 
-        // Start deleting images, beginning with the least recently used.
-        for image_ids in sorted_image_nodes.chunks_mut(deletion_chunk_size) {
-            for (image_id, _) in image_ids {
-                // Delete the image.
-                if let Err(error) = delete_image(image_id) {
-                    // The deletion failed. Just log the error and proceed.
-                    error!("{}", error);
-                } else {
-                    // Forget about the deleted image.
-                    deleted_image_ids.insert(image_id.clone());
-                }
-            }
+    // The image isn't from any of the registries given by `--only-registry`.
+    OnlyRegistry,
 
-            // Break if we're within the threshold.
-            let new_space = space_usage()?;
-            if new_space <= threshold {
-                info!(
-                    "Docker images are now using {}, which is within the limit of {}.",
-                    new_space.get_appropriate_unit(false).to_string().code_str(),
-                    threshold.get_appropriate_unit(false).to_string().code_str(),
-                );
-                break;
-            }
+    // A Dockerfile in one of the directories given by `--keep-dockerfile-bases` starts from the
+    // image, as named by the given reference.
+    KeepDockerfileBase(String),
+
+    // The image was used more recently than `--min-age` ago.
+    MinAge,
+
+    // The image was pulled or built more recently than `--pull-grace` ago.
+    PullGrace,
+
+    // The plugin at the given path keeps the image.
+    Plugin(String),
+}
+
+impl Protection {
+    // Name the setting or label which protects the image.
+    pub fn cause(&self) -> String {
+        match self {
+            Self::Keep(_) => "--keep".to_owned(),
+            Self::RemoveOnly => "--remove-only".to_owned(),
+            Self::Label => format!("{PROTECTION_LABEL_KEY}={PROTECTION_LABEL_VALUE}"),
+            Self::Pinned => "pinned".to_owned(),
+            Self::OwnImage => "Docuum's own container".to_owned(),
+            Self::Kubernetes => "--kubernetes".to_owned(),
+            Self::KeepLabel => "--keep-label".to_owned(),
+            Self::KeepDigest(_) => "--keep-digest".to_owned(),
+            Self::KeepId => "--keep-id".to_owned(),
+            Self::ProtectRegistry(_) => "--protect-registry".to_owned(),
+            Self::OnlyRegistry => "--only-registry".to_owned(),
+            Self::KeepDockerfileBase(_) => "--keep-dockerfile-bases".to_owned(),
+            Self::MinAge => "--min-age".to_owned(),
+            Self::PullGrace => "--pull-grace".to_owned(),
+            Self::Plugin(_) => "--plugin".to_owned(),
         }
-    } else {
-        debug!(
-            "Docker images are using {}, which is within the limit of {}.",
-            space.get_appropriate_unit(false).to_string().code_str(),
-            threshold.get_appropriate_unit(false).to_string().code_str(),
-        );
     }
 
-    // Update the state.
-    state.images.clear();
-    for (image_id, image_node) in polyforest {
-        if !deleted_image_ids.contains(&image_id) {
-            state.images.insert(
-                image_id.clone(),
-                state::Image {
-                    parent_id: image_node.image_record.parent_id.clone(),
-                    last_used_since_epoch: image_node.last_used_since_epoch,
-                },
-            );
+    // Whether `--critical-threshold` may override the protection. That's the case for the keep
+    // rules and the minimum age given by the settings, but not for the label, pins, or plugins,
+    // which protect individual images on purpose. Nor is it the case for `--remove-only` and
+    // `--only-registry`, which limit which images Docuum may touch at all, or for `--pull-grace`,
+    // which promises that freshly pulled images are never deleted.
+    pub fn overridable(&self) -> bool {
+        match self {
+            Self::Keep(_)
+            | Self::KeepLabel
+            | Self::KeepDigest(_)
+            | Self::KeepId
+            | Self::ProtectRegistry(_)
+            | Self::KeepDockerfileBase(_)
+            | Self::MinAge => true,
+            Self::RemoveOnly
+            | Self::Label
+            | Self::Pinned
+            | Self::OwnImage
+            | Self::Kubernetes
+            | Self::OnlyRegistry
+            | Self::PullGrace
+            | Self::Plugin(_) => false,
         }
     }
-
-    Ok(())
 }
 
-// Stream Docker events and vacuum when necessary.
-#[allow(clippy::type_complexity)]
-pub fn run(
-    settings: &Settings,
-    state: &mut State,
-    first_run: &mut bool,
-    destructors: &Arc<Mutex<Vec<Box<dyn FnOnce() + Send>>>>,
-) -> io::Result<()> {
-    // Determine the threshold in bytes.
-    let threshold = match settings.threshold {
-        Threshold::Absolute(b) => b,
+// The times before which images must have been last used and pulled to be deleted, if the
+// `--min-age` and `--pull-grace` arguments are provided
+#[derive(Clone, Copy)]
+struct ProtectionTimeStamps {
+    min_age: Option<Duration>,
+    pull_grace: Option<Duration>,
+}
 
-        #[cfg(target_os = "linux")]
-        Threshold::Percentage(p) =>
-        {
-            #[allow(
-                clippy::cast_precision_loss,
-                clippy::cast_possible_truncation,
-                clippy::cast_sign_loss
-            )]
-            Byte::from_bytes((p * docker_root_dir_filesystem_size()?.get_bytes() as f64) as u128)
-        }
+// Determine the times before which images must have been last used and pulled to be deleted.
+fn protection_time_stamps(settings: &Settings) -> io::Result<ProtectionTimeStamps> {
+    let time_stamp = |duration: Option<Duration>| {
+        duration
+            .map(|duration| {
+                (SystemTime::now() - duration)
+                    .duration_since(UNIX_EPOCH)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+            })
+            .transpose()
     };
 
-    // NOTE: Don't change this log line, since the test in the Homebrew formula
-    // (https://github.com/Homebrew/homebrew-core/blob/HEAD/Formula/d/docuum.rb) relies on it.
-    info!("Performing an initial vacuum on startup\u{2026}");
+    Ok(ProtectionTimeStamps {
+        min_age: time_stamp(settings.min_age)?,
+        pull_grace: time_stamp(settings.pull_grace)?,
+    })
+}
 
-    // Run the main vacuum logic.
-    vacuum(
-        state,
-        *first_run,
-        threshold,
-        settings.keep.as_ref(),
-        settings.deletion_chunk_size,
-        settings.min_age,
-    )?;
-    state::save(state)?;
-    *first_run = false;
+// What `protection` needs to know besides the settings, which is determined once per vacuum
+struct ProtectionContext {
+    time_stamps: ProtectionTimeStamps,
+    dockerfile_bases: HashSet<String>,
+    pinned_image_ids: BTreeSet<String>,
+    own_image_id: Option<String>,
+    runtime_pinned_image_ids: HashSet<String>, // Only with `--kubernetes`
+}
 
-    // Spawn `docker events --format '{{json .}}'`.
-    let mut child = Command::new("docker")
-        .args(["events", "--format", "{{json .}}"])
-        .stdout(Stdio::piped()) // [tag:stdout]
-        .spawn()?;
+impl ProtectionContext {
+    // Gather what `protection` needs to know according to the settings.
+    fn load(settings: &Settings) -> io::Result<Self> {
+        Ok(Self {
+            time_stamps: protection_time_stamps(settings)?,
+            dockerfile_bases: dockerfile::base_images(&settings.keep_dockerfile_bases)?,
+            pinned_image_ids: pins::load()?,
+            own_image_id: container::own_image_id(&settings.backend),
+            runtime_pinned_image_ids: if settings.kubernetes {
+                settings.backend.pinned_image_ids()?
+            } else {
+                HashSet::new()
+            },
+        })
+    }
+}
 
-    // Buffer the data as we read it line-by-line. The `unwrap` is safe due to [ref:stdout].
-    let reader = BufReader::new(child.stdout.take().unwrap());
+// Determine whether an image is a pause image, which Kubernetes uses for the sandboxes of pods,
+// e.g., `registry.k8s.io/pause`.
+fn is_pause_image(image_record: &ImageRecord) -> bool {
+    image_record
+        .repository_tags
+        .iter()
+        .any(|repository_tag| repository_tag.repository.rsplit('/').next() == Some("pause"))
+}
 
-    // When this run is done (e.g., due to an error) or when a termination signal is received, kill
-    // the child process.
-    destructors.lock().unwrap().push(Box::new(move || {
-        if let Err(error) = child.kill() {
-            error!("{}", error);
-        } else if let Err(error) = child.wait() {
-            error!("{}", error);
+// Strip the parts of a Docker Hub repository which are usually left implicit, so that e.g.
+// `docker.io/library/alpine` and `alpine` are considered the same repository.
+fn normalize_repository(repository: &str) -> &str {
+    let repository = repository.strip_prefix("docker.io/").unwrap_or(repository);
+    repository.strip_prefix("library/").unwrap_or(repository)
+}
+
+// Determine whether a digest given by `--keep-digest` matches a repository digest of an image. A
+// digest without a repository matches the digest in any repository.
+fn digest_matches(keep_digest: &str, repository_digest: &str) -> bool {
+    let Some((repository, digest)) = repository_digest.rsplit_once('@') else {
+        return false;
+    };
+
+    match keep_digest.rsplit_once('@') {
+        Some((keep_repository, keep_digest)) => {
+            keep_digest == digest
+                && normalize_repository(keep_repository) == normalize_repository(repository)
         }
-    }));
+        None => keep_digest == digest,
+    }
+}
 
-    // Handle each incoming event.
-    info!("Listening for Docker events\u{2026}");
-    for line_option in reader.lines() {
-        // Unwrap the line.
-        let line = line_option?;
-        trace!("Incoming event: {}", line.code_str());
+// Determine the registry of a repository, if it has one. As with `docker pull`, the first component
+// of the repository only names the registry if it looks like a host, i.e., if it contains a `.` or
+// a `:` or is `localhost`. Otherwise, the repository is on Docker Hub.
+fn registry(repository: &str) -> Option<String> {
+    if repository == "<none>" {
+        return None;
+    }
 
-        // Parse the line as an event.
-        let event = match serde_json::from_str::<Event>(&line) {
-            Ok(event) => {
-                trace!("Parsed as: {}", format!("{event:?}").code_str());
-                event
-            }
-            Err(error) => {
-                trace!("Skipping due to: {}", error);
-                continue;
+    Some(match repository.split_once('/') {
+        Some((host, _)) if host.contains(['.', ':']) || host == "localhost" => {
+            match host.to_ascii_lowercase().as_str() {
+                "index.docker.io" | "registry-1.docker.io" => DOCKER_HUB_REGISTRY.to_owned(),
+                host => host.to_owned(),
             }
-        };
+        }
+        _ => DOCKER_HUB_REGISTRY.to_owned(),
+    })
+}
 
-        // Get the ID of the image.
-        let image_id = image_id(&if event.r#type == "container"
-            && (event.action == "create" || event.action == "destroy")
-        {
-            if let Some(image_name) = event.actor.attributes.image {
-                image_name
-            } else {
-                trace!("Invalid Docker event.");
-                continue;
-            }
-        } else if event.r#type == "image"
-            && (event.action == "import"
-                || event.action == "load"
-                || event.action == "pull"
-                || event.action == "push"
-                || event.action == "save"
-                || event.action == "tag")
-        {
-            event.id
-        } else {
-            trace!("Skipping due to irrelevance.");
-            continue;
-        })?;
+// Determine the registries an image is from, according to the repositories of its tags and digests.
+fn image_registries(image_record: &ImageRecord) -> impl Iterator<Item = String> + '_ {
+    image_record
+        .repository_tags
+        .iter()
+        .map(|repository_tag| repository_tag.repository.as_str())
+        .chain(
+            image_record
+                .repository_digests
+                .iter()
+                .filter_map(|repository_digest| {
+                    repository_digest
+                        .rsplit_once('@')
+                        .map(|(repository, _)| repository)
+                }),
+        )
+        .filter_map(registry)
+}
 
-        // Inform the user that we're about to vacuum.
-        debug!("Waking up\u{2026}");
+// Determine which registry given by `--protect-registry`, if any, an image is from.
+fn protected_registry<'a>(
+    protect_registries: &'a [String],
+    image_record: &ImageRecord,
+) -> Option<&'a String> {
+    image_registries(image_record).find_map(|registry| {
+        protect_registries
+            .iter()
+            .find(|protect_registry| **protect_registry == registry)
+    })
+}
 
-        // Update the timestamp for this image.
-        if touch_image(state, &image_id, true)? {
-            // Run the main vacuum logic only if a new image came in.
-            vacuum(
-                state,
-                *first_run,
-                threshold,
-                settings.keep.as_ref(),
-                settings.deletion_chunk_size,
-                settings.min_age,
-            )?;
-        }
+// Determine whether an image is from any of the registries given by `--only-registry`. Images which
+// are only known locally, without a registry, aren't.
+fn from_only_registries(only_registries: &[String], image_record: &ImageRecord) -> bool {
+    image_registries(image_record).any(|registry| only_registries.contains(&registry))
+}
 
-        // Persist the state.
-        state::save(state)?;
+// Determine whether an ID given by `--keep-id` matches the ID of an image. The former may be short,
+// and either may omit the algorithm.
+fn image_id_matches(keep_id: &str, image_id: &str) -> bool {
+    let strip_algorithm = |id: &str| id.split_once(':').map_or(id, |(_, hex)| hex).to_owned();
+    strip_algorithm(image_id).starts_with(&strip_algorithm(keep_id))
+}
 
-        // Inform the user that we're done for now.
-        debug!("Going back to sleep\u{2026}");
+// Determine whether an image is the base image of a Dockerfile, as given by a reference which may
+// omit the tag (in which case it's `latest`) and the implicit parts of a Docker Hub repository. A
+// reference with a digest only matches the image with that digest, regardless of its tag.
+fn base_image_matches(base_image: &str, image_record: &ImageRecord) -> bool {
+    let (name, digest) = base_image
+        .split_once('@')
+        .map_or((base_image, None), |(name, digest)| (name, Some(digest)));
+
+    // A colon after the last slash separates the tag, rather than the port of a registry.
+    let (repository, tag) = match name.rsplit_once(':') {
+        Some((repository, tag)) if !tag.contains('/') => (repository, tag),
+        _ => (name, "latest"),
+    };
+
+    match digest {
+        Some(digest) => image_record
+            .repository_digests
+            .iter()
+            .any(|repository_digest| {
+                digest_matches(&format!("{repository}@{digest}"), repository_digest)
+            }),
+        None => image_record.repository_tags.iter().any(|repository_tag| {
+            repository_tag.tag == tag
+                && normalize_repository(&repository_tag.repository)
+                    == normalize_repository(repository)
+        }),
     }
+}
 
-    // The `for` loop above will only terminate if something happened to `docker events`.
-    Err(io::Error::new(
-        io::ErrorKind::Other,
-        format!("{} terminated.", "docker events".code_str()),
-    ))
+// Describe an image to a plugin.
+fn plugin_image<'a>(image_id: &'a str, image_node: &'a ImageNode) -> plugin::Image<'a> {
+    plugin::Image {
+        id: image_id,
+        repository_tags: repository_tags(image_node),
+        repository_digests: &image_node.image_record.repository_digests,
+        labels: &image_node.image_record.labels,
+        size_bytes: image_node.image_record.size.get_bytes(),
+        created_since_epoch: image_node.image_record.created_since_epoch.as_secs(),
+        last_used_since_epoch: image_node.last_used_since_epoch.as_secs(),
+        uses: image_node.uses,
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use {
-        super::{construct_polyforest, parse_docker_date, ImageNode, ImageRecord, RepositoryTag},
-        crate::state::{self, State},
-        std::{
-            collections::{HashMap, HashSet},
-            io,
-            time::Duration,
-        },
-    };
+// Ask a plugin whether it keeps an image. If the plugin fails, the image is kept, since the plugin
+// may be all that protects it.
+fn plugin_keeps(plugin: &Plugin, image_id: &str, image_node: &ImageNode) -> bool {
+    plugin
+        .keep(&plugin_image(image_id, image_node))
+        .unwrap_or_else(|error| {
+            event::log(
+                Level::Error,
+                "error",
+                &[("image_id", image_id.to_value())],
+                format_args!("{error}"),
+            );
+            true
+        })
+}
 
-    #[test]
-    fn parse_docker_date_valid() {
-        assert_eq!(
-            parse_docker_date("2022-02-25 12:53:30 -0800 PST").unwrap(),
-            Duration::from_secs(1_645_822_410),
-        );
+// Determine whether the settings protect an image from deletion, and why. The protections which
+// `--critical-threshold` can't override are checked first, so an image is only reported as
+// overridable if none of those protect it too.
+#[allow(clippy::too_many_lines)]
+fn protection(
+    settings: &Settings,
+    context: &ProtectionContext,
+    image_id: &str,
+    image_node: &ImageNode,
+) -> Option<Protection> {
+    // If Docuum is running in a container, deleting its image would only untag it, and Docuum
+    // would fail to restart if it needed to pull the image again.
+    if context.own_image_id.as_deref() == Some(image_id) {
+        return Some(Protection::OwnImage);
     }
 
-    #[test]
-    fn parse_docker_date_before_unix_epoch() {
-        assert_eq!(
-            parse_docker_date("0001-01-01 00:00:00 +0000 UTC").unwrap(),
-            Duration::ZERO,
-        );
+    // With the `--kubernetes` flag, we need to protect the images which the kubelet relies on to
+    // run pods, like it does itself.
+    if settings.kubernetes
+        && (context.runtime_pinned_image_ids.contains(image_id)
+            || is_pause_image(&image_node.image_record))
+    {
+        return Some(Protection::Kubernetes);
     }
 
-    #[test]
-    fn parse_docker_date_invalid() {
-        assert!(parse_docker_date("invalid").is_err());
+    // Image authors can protect their images from deletion by labeling them.
+    if image_node
+        .image_record
+        .labels
+        .get(PROTECTION_LABEL_KEY)
+        .map(String::as_str)
+        == Some(PROTECTION_LABEL_VALUE)
+    {
+        return Some(Protection::Label);
     }
 
-    #[test]
-    fn construct_polyforest_empty() -> io::Result<()> {
-        let state = State {
-            images: HashMap::new(),
-        };
+    // Users can protect images from deletion by pinning them in the dashboard.
+    if context.pinned_image_ids.contains(image_id) {
+        return Some(Protection::Pinned);
+    }
 
-        let image_records = HashMap::new();
-        let image_ids_in_use = HashSet::new();
-        let image_graph = construct_polyforest(&state, true, &image_records, &image_ids_in_use)?;
+    // With the `--untag` flag, `--keep` and `--remove-only` apply to the individual tags of an
+    // image which has both wanted and unwanted tags, so the image itself isn't protected by them.
+    let untag = stale_repository_tags(settings, image_node).is_some();
+
+    // If the user provided the `--remove-only` argument, we need to protect images which don't
+    // match any of the provided regexes.
+    if let Some(regex_set) = settings.remove_only.as_ref().filter(|_| !untag) {
+        if !image_node
+            .image_record
+            .repository_tags
+            .iter()
+            .any(|repository_tag| {
+                regex_set.is_match(&format!(
+                    "{}:{}",
+                    repository_tag.repository,
+                    repository_tag.tag,
+                ))
+            })
+        {
+            return Some(Protection::RemoveOnly);
+        }
+    }
 
-        assert_eq!(0, image_graph.len());
+    // If the user provided the `--only-registry` argument, we need to protect the images which
+    // aren't from any of the provided registries.
+    if !settings.only_registries.is_empty()
+        && !from_only_registries(&settings.only_registries, &image_node.image_record)
+    {
+        return Some(Protection::OnlyRegistry);
+    }
 
-        Ok(())
+    // If the `--pull-grace` argument is provided, we need to protect images which were pulled or
+    // built more recently than the provided duration, even if they haven't been used yet. The
+    // creation time covers images which were built before we saw them.
+    if let Some(time_stamp) = context.time_stamps.pull_grace {
+        if max(
+            image_node.pulled_since_epoch,
+            Some(image_node.image_record.created_since_epoch),
+        ) > Some(time_stamp)
+        {
+            return Some(Protection::PullGrace);
+        }
     }
 
-    #[test]
-    fn construct_polyforest_single_image() -> io::Result<()> {
-        let image_id = "id-0";
+    // If the user provided the `--plugin` argument, the plugins may protect images by their own
+    // rules.
+    if let Some(plugin) = settings
+        .plugins
+        .iter()
+        .find(|plugin| plugin_keeps(plugin, image_id, image_node))
+    {
+        return Some(Protection::Plugin(plugin.path().to_owned()));
+    }
 
-        let mut images = HashMap::new();
-        images.insert(
-            image_id.to_owned(),
-            state::Image {
-                parent_id: None,
-                last_used_since_epoch: Duration::from_secs(42),
-            },
-        );
+    // If the user provided the `--keep` argument, we need to protect images which match the
+    // provided regexes.
+    if let Some(regex_set) = settings.keep.as_ref().filter(|_| !untag) {
+        for repository_tag in &image_node.image_record.repository_tags {
+            let repository_tag = format!("{}:{}", repository_tag.repository, repository_tag.tag);
+            if regex_set.is_match(&repository_tag) {
+                return Some(Protection::Keep(repository_tag));
+            }
+        }
+    }
 
-        let state = State { images };
+    // If the user provided the `--keep-label` argument, we need to protect images which have any
+    // of the provided labels.
+    for (key, value) in &settings.keep_labels {
+        if image_node.image_record.labels.get(key) == Some(value) {
+            return Some(Protection::KeepLabel);
+        }
+    }
 
-        let image_record = ImageRecord {
-            parent_id: None,
-            created_since_epoch: Duration::from_secs(100),
-            repository_tags: vec![RepositoryTag {
-                repository: String::from("alpine"),
-                tag: String::from("latest"),
-            }],
-        };
+    // If the user provided the `--keep-digest` argument, we need to protect images which have any
+    // of the provided digests.
+    for repository_digest in &image_node.image_record.repository_digests {
+        if settings
+            .keep_digests
+            .iter()
+            .any(|keep_digest| digest_matches(keep_digest, repository_digest))
+        {
+            return Some(Protection::KeepDigest(repository_digest.clone()));
+        }
+    }
 
-        let mut image_records = HashMap::new();
-        image_records.insert(image_id.to_owned(), image_record.clone());
-        let image_ids_in_use = HashSet::new();
-        let image_graph = construct_polyforest(&state, true, &image_records, &image_ids_in_use)?;
+    // If the user provided the `--keep-id` argument, we need to protect images with the provided
+    // IDs.
+    if settings
+        .keep_ids
+        .iter()
+        .any(|keep_id| image_id_matches(keep_id, image_id))
+    {
+        return Some(Protection::KeepId);
+    }
 
-        assert_eq!(1, image_graph.len());
+    // If the user provided the `--protect-registry` argument, we need to protect the images from
+    // the provided registries.
+    if let Some(registry) =
+        protected_registry(&settings.protect_registries, &image_node.image_record)
+    {
+        return Some(Protection::ProtectRegistry(registry.clone()));
+    }
 
-        assert_eq!(
-            Some(&ImageNode {
-                image_record,
-                last_used_since_epoch: Duration::from_secs(42),
-                ancestors: 0,
-            }),
-            image_graph.get(image_id),
-        );
+    // If the user provided the `--keep-dockerfile-bases` argument, we need to protect the images
+    // which the Dockerfiles in the provided directories start from.
+    if let Some(base_image) = context
+        .dockerfile_bases
+        .iter()
+        .find(|base_image| base_image_matches(base_image, &image_node.image_record))
+    {
+        return Some(Protection::KeepDockerfileBase(base_image.clone()));
+    }
 
-        Ok(())
+    // If the `--min-age` argument is provided, we need to protect images which are newer than the
+    // provided duration.
+    if let Some(time_stamp) = context.time_stamps.min_age {
+        if image_node.last_used_since_epoch > time_stamp {
+            return Some(Protection::MinAge);
+        }
     }
 
-    #[test]
-    fn construct_polyforest_single_image_missing_state() -> io::Result<()> {
-        let image_id = "id-0";
-        let images = HashMap::new();
-        let state = State { images };
+    None
+}
 
-        let image_record = ImageRecord {
-            parent_id: None,
-            created_since_epoch: Duration::from_secs(100),
-            repository_tags: vec![RepositoryTag {
-                repository: String::from("alpine"),
-                tag: String::from("latest"),
-            }],
+// Filter out the images which the settings protect from deletion. With `--critical-threshold`, the
+// protected images which it may delete anyway are returned, in the same order, along with the
+// protection which they're deleted in spite of.
+fn retain_deletable<'a>(
+    settings: &Settings,
+    sorted_image_nodes: &mut Vec<(&'a String, &'a ImageNode)>,
+) -> io::Result<Vec<(&'a String, &'a ImageNode, Protection)>> {
+    let context = ProtectionContext::load(settings)?;
+    let mut overridable_image_nodes = vec![];
+
+    sorted_image_nodes.retain(|&(image_id, image_node)| {
+        let Some(protection) = protection(settings, &context, image_id, image_node) else {
+            return true;
         };
 
-        let mut image_records = HashMap::new();
-        image_records.insert(image_id.to_owned(), image_record.clone());
-        let image_ids_in_use = HashSet::new();
-        let image_graph = construct_polyforest(&state, true, &image_records, &image_ids_in_use)?;
+        match &protection {
+            Protection::Keep(repository_tag) => {
+                debug!(
+                    "Ignored image {} due to the {} flag.",
+                    repository_tag.code_str(),
+                    "--keep".code_str(),
+                );
+            }
+            Protection::Label => {
+                debug!(
+                    "Ignored image {} due to the {} label.",
+                    image_id.code_str(),
+                    protection.cause().code_str(),
+                );
+            }
+            Protection::OwnImage => {
+                debug!(
+                    "Ignored image {} because Docuum is running in a container based on it.",
+                    image_id.code_str(),
+                );
+            }
+            _ => {
+                debug!(
+                    "Ignored image {} due to the {} flag.",
+                    image_id.code_str(),
+                    protection.cause().code_str(),
+                );
+            }
+        }
 
-        assert_eq!(1, image_graph.len());
+        if settings.critical_threshold.is_some() && protection.overridable() {
+            overridable_image_nodes.push((image_id, image_node, protection));
+        }
+        false
+    });
 
-        assert_eq!(
-            Some(&ImageNode {
-                image_record,
-                last_used_since_epoch: Duration::from_secs(100),
-                ancestors: 0,
-            }),
-            image_graph.get(image_id),
-        );
+    Ok(overridable_image_nodes)
+}
 
-        Ok(())
+// Describe an image to the eviction policy.
+fn eviction_candidate(image_node: &ImageNode, in_use: bool) -> eviction::Candidate {
+    eviction::Candidate {
+        created_since_epoch: image_node.image_record.created_since_epoch,
+        last_used_since_epoch: image_node.last_used_since_epoch,
+        uses: image_node.uses,
+        frequency: image_node.frequency,
+        size_bytes: image_node.image_record.size.get_bytes(),
+        tags: image_node
+            .image_record
+            .repository_tags
+            .iter()
+            .filter(|repository_tag| repository_tag.tag != "<none>")
+            .count(),
+        ancestors: image_node.ancestors,
+        in_use,
     }
+}
 
-    #[test]
-    fn construct_polyforest_parent_child_increasing_timestamps() -> io::Result<()> {
-        let image_id_0 = "id-0";
-        let image_id_1 = "id-1";
+// Compute the scores which the plugins assign to the images, if any of them compute scores. The
+// scores of several plugins are added up. If a plugin fails for an image, the image is deleted
+// last.
+fn plugin_scores(
+    polyforest: &HashMap<String, ImageNode>,
+    plugins: &[Plugin],
+) -> Option<HashMap<String, f64>> {
+    let plugins = plugins
+        .iter()
+        .filter(|plugin| plugin.scores())
+        .collect::<Vec<_>>();
+    if plugins.is_empty() {
+        return None;
+    }
 
-        let mut images = HashMap::new();
-        images.insert(
-            image_id_0.to_owned(),
-            state::Image {
-                parent_id: None,
-                last_used_since_epoch: Duration::from_secs(42),
-            },
-        );
-        images.insert(
-            image_id_1.to_owned(),
-            state::Image {
-                parent_id: Some(image_id_0.to_owned()),
-                last_used_since_epoch: Duration::from_secs(43),
-            },
-        );
+    Some(
+        polyforest
+            .iter()
+            .map(|(image_id, image_node)| {
+                let image = plugin_image(image_id, image_node);
+                let score = plugins
+                    .iter()
+                    .map(|plugin| {
+                        plugin.score(&image).map_or_else(
+                            |error| {
+                                event::log(
+                                    Level::Error,
+                                    "error",
+                                    &[("image_id", image_id.as_str().to_value())],
+                                    format_args!("{error}"),
+                                );
+                                f64::NEG_INFINITY
+                            },
+                            Option::unwrap_or_default,
+                        )
+                    })
+                    .sum();
+                (image_id.clone(), score)
+            })
+            .collect(),
+    )
+}
 
-        let state = State { images };
+// Sort the images in the order in which they're deleted according to the eviction policy, or the
+// scores of the plugins if they compute any. Ties are broken by recency of use and then by the
+// `--tie-break`s, which by default put children before their parents.
+// Finally, every image is moved ahead of its ancestors, since an image can't be deleted while it
+// has children.
+fn sort_image_nodes<'a>(
+    polyforest: &'a HashMap<String, ImageNode>,
+    image_ids_in_use: &HashSet<String>,
+    policy: &eviction::Policy,
+    tie_breaks: &[eviction::TieBreak],
+    plugins: &[Plugin],
+    now: Duration,
+) -> Vec<(&'a String, &'a ImageNode)> {
+    let policy = policy.implementation();
+    let plugin_scores = plugin_scores(polyforest, plugins);
+    let mut sorted_image_nodes = polyforest.iter().collect::<Vec<_>>();
+    sorted_image_nodes.sort_by(|x, y| {
+        let x_candidate = eviction_candidate(x.1, image_ids_in_use.contains(x.0));
+        let y_candidate = eviction_candidate(y.1, image_ids_in_use.contains(y.0));
+        plugin_scores
+            .as_ref()
+            .map_or_else(
+                || policy.compare(now, &x_candidate, &y_candidate),
+                |scores| scores[y.0].total_cmp(&scores[x.0]),
+            )
+            .then(x.1.last_used_since_epoch.cmp(&y.1.last_used_since_epoch))
+            .then_with(|| eviction::break_tie(tie_breaks, &x_candidate, &y_candidate))
+    });
+    descendants_first(&sorted_image_nodes)
+}
 
-        let image_record_0 = ImageRecord {
-            parent_id: None,
-            created_since_epoch: Duration::from_secs(100),
-            repository_tags: vec![RepositoryTag {
-                repository: String::from("alpine"),
-                tag: String::from("latest"),
-            }],
-        };
+// Reorder the images so that each one comes after all of its descendants. Otherwise, the given
+// order is preserved as much as possible.
+fn descendants_first<'a>(
+    sorted_image_nodes: &[(&'a String, &'a ImageNode)],
+) -> Vec<(&'a String, &'a ImageNode)> {
+    // Find the children of each image, in the given order.
+    let mut children = HashMap::<&str, Vec<usize>>::new();
+    for (index, (_, image_node)) in sorted_image_nodes.iter().enumerate() {
+        if let Some(parent_id) = &image_node.image_record.parent_id {
+            children.entry(parent_id.as_str()).or_default().push(index);
+        }
+    }
 
-        let image_record_1 = ImageRecord {
-            parent_id: Some(image_id_0.to_owned()),
-            created_since_epoch: Duration::from_secs(101),
-            repository_tags: vec![RepositoryTag {
-                repository: String::from("debian"),
-                tag: String::from("latest"),
-            }],
-        };
+    // Visit the images depth-first, emitting each one once its children have been emitted. Each
+    // stack entry records whether the children of the image have already been pushed.
+    let mut visited = vec![false; sorted_image_nodes.len()];
+    let mut reordered = Vec::with_capacity(sorted_image_nodes.len());
+    for root in 0..sorted_image_nodes.len() {
+        let mut stack = vec![(root, false)];
+        while let Some((index, expanded)) = stack.pop() {
+            if expanded {
+                reordered.push(sorted_image_nodes[index]);
+            } else if !visited[index] {
+                visited[index] = true;
+                stack.push((index, true));
+                if let Some(child_indices) = children.get(sorted_image_nodes[index].0.as_str()) {
+                    stack.extend(child_indices.iter().rev().map(|&child| (child, false)));
+                }
+            }
+        }
+    }
+    reordered
+}
 
-        let mut image_records = HashMap::new();
-        image_records.insert(image_id_0.to_owned(), image_record_0.clone());
-        image_records.insert(image_id_1.to_owned(), image_record_1.clone());
-        let image_ids_in_use = HashSet::new();
-        let image_graph = construct_polyforest(&state, true, &image_records, &image_ids_in_use)?;
+// Format the repository-tag pairs of an image.
+fn repository_tags(image_node: &ImageNode) -> Vec<String> {
+    image_node
+        .image_record
+        .repository_tags
+        .iter()
+        .map(|repository_tag| format!("{}:{}", repository_tag.repository, repository_tag.tag))
+        .collect()
+}
+
+// Determine whether `--keep` or `--remove-only` protect a repository-tag pair from deletion.
+fn repository_tag_wanted(
+    keep: Option<&RegexSet>,
+    remove_only: Option<&RegexSet>,
+    repository_tag: &str,
+) -> bool {
+    keep.is_some_and(|regex_set| regex_set.is_match(repository_tag))
+        || remove_only.is_some_and(|regex_set| !regex_set.is_match(repository_tag))
+}
+
+// If the `--untag` flag is provided and an image has both wanted and unwanted tags, return the
+// unwanted ones. Those are removed instead of deleting the image. Dangling tags can't be removed by
+// name, so they are ignored.
+fn stale_repository_tags(settings: &Settings, image_node: &ImageNode) -> Option<Vec<String>> {
+    if !settings.untag {
+        return None;
+    }
+
+    let (wanted, stale): (Vec<_>, Vec<_>) = image_node
+        .image_record
+        .repository_tags
+        .iter()
+        .filter(|repository_tag| repository_tag.tag != "<none>")
+        .map(|repository_tag| format!("{}:{}", repository_tag.repository, repository_tag.tag))
+        .partition(|repository_tag| {
+            repository_tag_wanted(
+                settings.keep.as_ref(),
+                settings.remove_only.as_ref(),
+                repository_tag,
+            )
+        });
+
+    if wanted.is_empty() || stale.is_empty() {
+        None
+    } else {
+        Some(stale)
+    }
+}
+
+// An image as it appears in the eviction order
+pub struct ListedImage {
+    pub id: String,
+    pub last_used_since_epoch: Duration,
+    pub size: Byte,
+    pub repository_tags: Vec<String>,
+    pub containers: Vec<String>, // See `state::Image`.
+    pub protection: Option<Protection>,
+}
+
+// List the images in the order in which they would be deleted, noting which ones the settings
+// protect from deletion.
+pub fn list_images(
+    settings: &Settings,
+    state: &State,
+    first_run: bool,
+) -> io::Result<Vec<ListedImage>> {
+    // Construct the polyforest just as a vacuum would.
+    let image_records = settings
+        .backend
+        .list_image_records(settings.inspect_concurrency)?;
+    let image_ids_in_use = image_ids_in_use(
+        settings,
+        &mut state.compose_projects.clone(),
+        &mut state.gitlab_pipelines.clone(),
+    )?;
+    let polyforest = construct_polyforest(state, first_run, &image_records, &image_ids_in_use)?;
+
+    let sorted_image_nodes = sort_image_nodes(
+        &polyforest,
+        &image_ids_in_use,
+        &settings.policy,
+        &settings.tie_breaks,
+        &settings.plugins,
+        now_since_epoch()?,
+    );
+    listed_images(settings, state, sorted_image_nodes)
+}
+
+// Describe the sorted images, noting which ones the settings protect from deletion.
+fn listed_images(
+    settings: &Settings,
+    state: &State,
+    sorted_image_nodes: Vec<(&String, &ImageNode)>,
+) -> io::Result<Vec<ListedImage>> {
+    let context = ProtectionContext::load(settings)?;
+    Ok(sorted_image_nodes
+        .into_iter()
+        .map(|(image_id, image_node)| ListedImage {
+            id: image_id.clone(),
+            last_used_since_epoch: image_node.last_used_since_epoch,
+            size: image_node.image_record.size,
+            repository_tags: repository_tags(image_node),
+            containers: state
+                .images
+                .get(image_id)
+                .map_or_else(Vec::new, |image| image.containers.clone()),
+            protection: protection(settings, &context, image_id, image_node),
+        })
+        .collect())
+}
+
+// Mark an image and its ancestors as untouchable for the remainder of the vacuum. The ancestors
+// can't be deleted while the image exists, so there's no point in trying.
+fn block_image(
+    polyforest: &HashMap<String, ImageNode>,
+    image_id: &str,
+    blocked_image_ids: &mut HashSet<String>,
+) {
+    let mut image_id = Some(image_id.to_owned());
+    while let Some(id) = image_id {
+        image_id = polyforest
+            .get(&id)
+            .and_then(|image_node| image_node.image_record.parent_id.clone());
+        if !blocked_image_ids.insert(id) {
+            // The rest of the ancestors have already been blocked.
+            break;
+        }
+    }
+}
+
+// Ask the pre-delete hook, if any, whether an image may be deleted. If the hook can't be run, the
+// image is kept, since the hook may be all that protects it.
+fn deletion_allowed(settings: &Settings, image: &DeletedImage, report: &mut Report) -> bool {
+    let Some(command) = &settings.pre_delete_hook else {
+        return true;
+    };
+
+    match hook::pre_delete(command, image) {
+        Ok(true) => true,
+        Ok(false) => {
+            debug!(
+                "Skipped image {}, since the pre-delete hook vetoed its deletion.",
+                image.id.code_str(),
+            );
+            false
+        }
+        Err(error) => {
+            event::log(
+                Level::Error,
+                "error",
+                &[("image_id", image.id.as_str().to_value())],
+                format_args!("{error}"),
+            );
+            report.errors.push(error.to_string());
+            false
+        }
+    }
+}
+
+// If the `--archive-dir` argument is provided, save an image to an archive before it's deleted, and
+// delete the oldest archives if they take up too much space. If the image can't be archived, it
+// isn't deleted, since the archive may be all that makes the deletion recoverable.
+fn archived(settings: &Settings, image: &DeletedImage, report: &mut Report) -> bool {
+    let Some(archive_dir) = &settings.archive_dir else {
+        return true;
+    };
+
+    let result = now_since_epoch()
+        .and_then(|now| archive::save(&settings.backend, archive_dir, image, now))
+        .and_then(|_| {
+            settings.archive_max_size.map_or_else(
+                || Ok(vec![]),
+                |max_size| archive::prune(archive_dir, max_size),
+            )
+        });
+
+    match result {
+        Ok(pruned_archives) => {
+            for path in pruned_archives {
+                debug!(
+                    "Deleted archive {} to stay within {}.",
+                    path.to_string_lossy().code_str(),
+                    "--archive-max-size".code_str(),
+                );
+            }
+            true
+        }
+        Err(error) => {
+            event::log(
+                Level::Error,
+                "error",
+                &[("image_id", image.id.as_str().to_value())],
+                format_args!("{error}"),
+            );
+            report.errors.push(error.to_string());
+            false
+        }
+    }
+}
+
+// Delete an image. If the deletion fails, the error is logged and reported rather than returned,
+// since we want to proceed with the other images. If the image is in use or has child images, it
+// and its ancestors are skipped for the remainder of the vacuum. The same goes for images which are
+// untagged rather than deleted due to the `--untag` flag, whose deletion is vetoed by the
+// pre-delete hook, or which can't be archived. Returns whether the image was deleted.
+fn delete_image(
+    settings: &Settings,
+    polyforest: &HashMap<String, ImageNode>,
+    image_id: &str,
+    image_node: &ImageNode,
+    reason: DeletionReason,
+    blocked_image_ids: &mut HashSet<String>,
+    report: &mut Report,
+) -> bool {
+    if blocked_image_ids.contains(image_id) {
+        debug!(
+            "Skipped image {}, since it or one of its descendants is in use or was untagged.",
+            image_id.code_str(),
+        );
+        return false;
+    }
+
+    // If only some of the tags of the image are unwanted, remove those tags and keep the image. Its
+    // ancestors must be kept too, so the image is blocked either way.
+    if let Some(stale_repository_tags) = stale_repository_tags(settings, image_node) {
+        for repository_tag in stale_repository_tags {
+            if let Err(error) = settings.backend.untag_image(&repository_tag) {
+                event::log(
+                    Level::Error,
+                    "error",
+                    &[("image_id", image_id.to_value())],
+                    format_args!("{error}"),
+                );
+                report.errors.push(error.to_string());
+            }
+        }
+        block_image(polyforest, image_id, blocked_image_ids);
+        return false;
+    }
+
+    let deleted_image = DeletedImage {
+        id: image_id.to_owned(),
+        repository_tags: repository_tags(image_node),
+        size_bytes: image_node.image_record.size.get_bytes(),
+        reason,
+    };
+
+    if !deletion_allowed(settings, &deleted_image, report)
+        || !archived(settings, &deleted_image, report)
+    {
+        block_image(polyforest, image_id, blocked_image_ids);
+        return false;
+    }
+
+    match settings
+        .backend
+        .delete_image(image_id, settings.prune_parents)
+    {
+        Ok(()) => {
+            event::log(
+                Level::Debug,
+                "deletion",
+                &[
+                    ("image_id", image_id.to_value()),
+                    (
+                        "repository_tags",
+                        deleted_image.repository_tags.join(", ").as_str().to_value(),
+                    ),
+                ],
+                format_args!("Deleted image {}.", image_id.code_str()),
+            );
+            report.deleted_image(deleted_image);
+            true
+        }
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            // The image is already gone, e.g., because it was pruned along with a child due to the
+            // `--prune-parents` flag. Either way, it no longer needs to be deleted.
+            debug!("Image {} no longer exists.", image_id.code_str());
+            true
+        }
+        Err(error) => {
+            event::log(
+                Level::Error,
+                "error",
+                &[("image_id", image_id.to_value())],
+                format_args!("{error}"),
+            );
+            report.errors.push(error.to_string());
+            if error.kind() == io::ErrorKind::ResourceBusy {
+                block_image(polyforest, image_id, blocked_image_ids);
+            }
+            false
+        }
+    }
+}
+
+// If the `--max-age` argument is provided, delete the images which haven't been used for longer
+// than the provided duration, regardless of the threshold. The deleted images are removed from
+// `sorted_image_nodes`.
+fn delete_expired_images(
+    settings: &Settings,
+    polyforest: &HashMap<String, ImageNode>,
+    sorted_image_nodes: &mut Vec<(&String, &ImageNode)>,
+    deleted_image_ids: &mut HashSet<String>,
+    blocked_image_ids: &mut HashSet<String>,
+    report: &mut Report,
+) -> io::Result<()> {
+    if let Some(duration) = settings.max_age {
+        let time_stamp = (SystemTime::now() - duration)
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        sorted_image_nodes.retain(|(image_id, image_node)| {
+            if image_node.last_used_since_epoch >= time_stamp {
+                return true;
+            }
+
+            debug!(
+                "Image {} has expired due to the {} flag.",
+                image_id.code_str(),
+                "--max-age".code_str(),
+            );
+
+            // Delete the image.
+            if delete_image(
+                settings,
+                polyforest,
+                image_id,
+                image_node,
+                DeletionReason::MaxAge,
+                blocked_image_ids,
+                report,
+            ) {
+                // Forget about the deleted image.
+                deleted_image_ids.insert((*image_id).clone());
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    Ok(())
+}
+
+// If the `--max-images` argument is provided, delete the least recently used images until the
+// number of remaining images is within the limit, regardless of the threshold. The deleted images
+// are removed from `sorted_image_nodes`.
+fn delete_excess_images(
+    settings: &Settings,
+    polyforest: &HashMap<String, ImageNode>,
+    sorted_image_nodes: &mut Vec<(&String, &ImageNode)>,
+    deleted_image_ids: &mut HashSet<String>,
+    blocked_image_ids: &mut HashSet<String>,
+    report: &mut Report,
+) {
+    if let Some(max_images) = settings.max_images {
+        let remaining_images = polyforest.len() - deleted_image_ids.len();
+        if remaining_images <= max_images {
+            debug!(
+                "There are {} images, which is within the limit of {}.",
+                remaining_images.to_string().code_str(),
+                max_images.to_string().code_str(),
+            );
+            return;
+        }
+
+        info!(
+            "There are currently {} images, but the limit is {}.",
+            remaining_images.to_string().code_str(),
+            max_images.to_string().code_str(),
+        );
+
+        // Start deleting images, beginning with the least recently used.
+        let mut excess_images = remaining_images - max_images;
+        sorted_image_nodes.retain(|(image_id, image_node)| {
+            if excess_images == 0 {
+                return true;
+            }
+
+            // Delete the image.
+            if delete_image(
+                settings,
+                polyforest,
+                image_id,
+                image_node,
+                DeletionReason::MaxImages,
+                blocked_image_ids,
+                report,
+            ) {
+                // Forget about the deleted image.
+                deleted_image_ids.insert((*image_id).clone());
+                excess_images -= 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+// If the `--prune-containers` argument is provided, delete the containers which stopped longer ago
+// than the provided duration. This frees up the images they use for deletion.
+fn prune_containers(settings: &Settings, report: &mut Report) -> io::Result<()> {
+    if let Some(duration) = settings.prune_containers {
+        let time_stamp = (SystemTime::now() - duration)
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        for (container_id, finished_since_epoch) in settings.backend.stopped_containers()? {
+            if finished_since_epoch <= time_stamp {
+                if let Err(error) = settings.backend.delete_container(&container_id) {
+                    // The deletion failed. Just log the error and proceed.
+                    error!("{error}");
+                    report.errors.push(error.to_string());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// If the `--prune-networks` argument is provided, delete the user-defined networks which have had
+// no containers connected to them for longer than the provided duration.
+fn prune_networks(settings: &Settings, state: &mut State, report: &mut Report) -> io::Result<()> {
+    let Some(duration) = settings.prune_networks else {
+        return Ok(());
+    };
+
+    // Find all unused networks.
+    let networks = settings.backend.unused_networks()?;
+
+    // Forget about networks which no longer exist or are in use. The latter will be touched again
+    // when their containers are disconnected.
+    state
+        .networks
+        .retain(|network, _| networks.contains_key(network));
+
+    let time_stamp = (SystemTime::now() - duration)
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    for (network, created_since_epoch) in networks {
+        // Networks we haven't seen being disconnected were last used before we started listening
+        // for events, so we fall back to when they were created.
+        let last_used_since_epoch = state
+            .networks
+            .get(&network)
+            .map_or(created_since_epoch, |network_state| {
+                max(network_state.last_used_since_epoch, created_since_epoch)
+            });
+
+        if last_used_since_epoch <= time_stamp {
+            if let Err(error) = settings.backend.delete_network(&network) {
+                // The deletion failed. Just log the error and proceed.
+                error!("{error}");
+                report.errors.push(error.to_string());
+            } else {
+                // Forget about the deleted network.
+                state.networks.remove(&network);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Update the timestamp for a volume or network according to a line of output from the events
+// process, if we're pruning those. Returns whether a timestamp was updated.
+fn touch_volume_or_network(settings: &Settings, state: &mut State, line: &str) -> io::Result<bool> {
+    if settings.prune_volumes {
+        if let Some(volume) = settings.backend.used_volume(line) {
+            touch_volume(state, &volume)?;
+            return Ok(true);
+        }
+    }
+
+    if settings.prune_networks.is_some() {
+        if let Some(network) = settings.backend.used_network(line) {
+            touch_network(state, &network)?;
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+// Forget an image which was deleted by something other than Docuum (e.g., `docker image rm` or
+// `docker system prune`), unless missing images are remembered for a while. Images which Docuum
+// deleted itself have already been forgotten by the time their events arrive. Returns a boolean
+// indicating whether the image was forgotten.
+fn forget_deleted_image(settings: &Settings, state: &mut State, image_id: &str) -> bool {
+    if settings.forget_missing_after.is_some() || state.images.remove(image_id).is_none() {
+        return false;
+    }
+
+    event::log(
+        Level::Info,
+        "external_deletion",
+        &[("image_id", image_id.to_value())],
+        format_args!(
+            "Image {} was deleted by something other than Docuum, so it's been forgotten.",
+            image_id.code_str(),
+        ),
+    );
+    true
+}
+
+// Get the space which counts toward the threshold, which includes volumes if we're pruning them or
+// asked to count them, the writable layers of containers if requested, and the build cache and the
+// caches of Buildx builders if we're pruning those.
+pub fn space_usage(settings: &Settings) -> io::Result<Byte> {
+    let volumes = settings.prune_volumes || settings.count_volumes;
+    let build_cache = settings
+        .pipeline
+        .iter()
+        .any(|stage| stage.kind == StageKind::BuildCache);
+    let space = if volumes || settings.count_container_layers || build_cache {
+        settings
+            .backend
+            .space_usage_with(volumes, settings.count_container_layers, build_cache)?
+    } else {
+        settings.backend.space_usage()?
+    };
+
+    // Buildx keeps the cache of a builder in a volume (or, with old versions, in the writable layer
+    // of the builder's container), in which case it's already been counted.
+    if settings.prune_buildx_caches && !volumes && !settings.count_container_layers {
+        let mut total = space.get_bytes();
+        for builder in settings.backend.buildx_builders()?.keys() {
+            total += settings.backend.buildx_cache_size(builder)?.get_bytes();
+        }
+        return Ok(Byte::from_bytes(total));
+    }
+
+    Ok(space)
+}
+
+// Determine the limit on the space usage, given the current space usage and the threshold in bytes.
+// For free space thresholds, the limit depends on how much space is currently free
+// [ref:free_threshold_bytes]. When coordinating with the kubelet, the limit never allows the disk
+// usage to reach the kubelet's thresholds.
+pub fn space_limit(settings: &Settings, space: Byte, threshold: Byte) -> io::Result<Byte> {
+    let limit = if let Threshold::Free(_) = settings.threshold {
+        free_space_limit(
+            space,
+            root_dir_filesystem_free_space(&settings.backend)?,
+            threshold,
+        )
+    } else {
+        threshold
+    };
+
+    Ok(match settings.kubelet_threshold {
+        Some(kubelet_threshold) => {
+            limit.min(other_space_limit(settings, space, kubelet_threshold)?)
+        }
+        None => limit,
+    })
+}
+
+// Determine the limit on the space usage due to a threshold other than the main one (e.g., the
+// target), given the current space usage.
+fn other_space_limit(settings: &Settings, space: Byte, threshold: Threshold) -> io::Result<Byte> {
+    let threshold_bytes = bytes(&settings.backend, threshold)?;
+    Ok(if let Threshold::Free(_) = threshold {
+        free_space_limit(
+            space,
+            root_dir_filesystem_free_space(&settings.backend)?,
+            threshold_bytes,
+        )
+    } else {
+        threshold_bytes
+    })
+}
+
+// Determine the limit on the space usage to clean down to once the threshold has been exceeded,
+// given the current space usage and the limit due to the threshold. Without a `--target`, that's
+// just the limit due to the threshold, and the target never allows more than the threshold does.
+fn target_limit(settings: &Settings, space: Byte, threshold: Byte) -> io::Result<Byte> {
+    let Some(target) = settings.target else {
+        return Ok(threshold);
+    };
+
+    Ok(other_space_limit(settings, space, target)?.min(threshold))
+}
+
+// Delete unused anonymous volumes, beginning with the least recently used, until the space usage
+// is within the given limit.
+fn vacuum_volumes(
+    settings: &Settings,
+    state: &mut State,
+    threshold: Byte,
+    min_age: Option<Duration>,
+    report: &mut Report,
+) -> io::Result<()> {
+    // Find all unused anonymous volumes.
+    let volumes = settings.backend.unused_anonymous_volumes()?;
+
+    // Forget about volumes which no longer exist or are in use. The latter will be touched again
+    // when they're unmounted.
+    state
+        .volumes
+        .retain(|volume, _| volumes.contains_key(volume));
+
+    // Sort the volumes from least recently used to most recently used. Volumes we haven't seen
+    // being unmounted were last used before we started listening for events, so we fall back to
+    // when they were created.
+    let mut sorted_volumes = volumes
+        .into_iter()
+        .map(|(volume, created_since_epoch)| {
+            let last_used_since_epoch = state
+                .volumes
+                .get(&volume)
+                .map_or(created_since_epoch, |volume_state| {
+                    max(volume_state.last_used_since_epoch, created_since_epoch)
+                });
+            (volume, last_used_since_epoch)
+        })
+        .collect::<Vec<_>>();
+    sorted_volumes.sort_by_key(|(_, last_used_since_epoch)| *last_used_since_epoch);
+
+    // If a minimum age is provided, we need to filter out volumes which are newer than the provided
+    // duration.
+    if let Some(duration) = min_age {
+        let time_stamp = (SystemTime::now() - duration)
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        sorted_volumes.retain(|(volume, last_used_since_epoch)| {
+            if *last_used_since_epoch > time_stamp {
+                debug!(
+                    "Ignored volume {} due to the {} flag.",
+                    volume.code_str(),
+                    "--min-age".code_str(),
+                );
+                return false;
+            }
+
+            true
+        });
+    }
+
+    // Start deleting volumes, beginning with the least recently used.
+    if space_usage(settings)? <= threshold {
+        return Ok(());
+    }
+    for volumes in sorted_volumes.chunks(settings.deletion_chunk_size) {
+        for (volume, _) in volumes {
+            // Delete the volume.
+            if let Err(error) = settings.backend.delete_volume(volume) {
+                // The deletion failed. Just log the error and proceed.
+                error!("{error}");
+                report.errors.push(error.to_string());
+            } else {
+                // Forget about the deleted volume.
+                state.volumes.remove(volume);
+            }
+        }
+
+        // Break if we're within the threshold.
+        if space_usage(settings)? <= threshold {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// Prune the caches of Buildx builders, beginning with the least recently used builder, until the
+// space usage is within the given limit. Each builder only prunes as much as is needed (but never
+// below `keep`), and BuildKit deletes the least recently used records of its cache first. Builders
+// used more recently than the minimum age are left alone.
+fn vacuum_buildx_caches(
+    settings: &Settings,
+    state: &mut State,
+    threshold: Byte,
+    min_age: Option<Duration>,
+    keep: Option<Byte>,
+    report: &mut Report,
+) -> io::Result<()> {
+    // Find all Buildx builders.
+    let builders = settings.backend.buildx_builders()?;
+
+    // Forget about builders which no longer exist.
+    state
+        .buildx_builders
+        .retain(|builder, _| builders.contains_key(builder));
+
+    // Sort the builders from least recently used to most recently used. Builders we haven't seen
+    // being used were last used before we started listening for events, so we fall back to when
+    // they were created.
+    let mut sorted_builders = builders
+        .into_iter()
+        .map(|(builder, created_since_epoch)| {
+            let last_used_since_epoch = state
+                .buildx_builders
+                .get(&builder)
+                .map_or(created_since_epoch, |builder_state| {
+                    max(builder_state.last_used_since_epoch, created_since_epoch)
+                });
+            (builder, last_used_since_epoch)
+        })
+        .collect::<Vec<_>>();
+    sorted_builders.sort_by_key(|(_, last_used_since_epoch)| *last_used_since_epoch);
+    if let Some(duration) = min_age {
+        let time_stamp = (SystemTime::now() - duration)
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        sorted_builders.retain(|(_, last_used_since_epoch)| *last_used_since_epoch <= time_stamp);
+    }
+
+    // Start pruning caches, beginning with the least recently used builder.
+    let mut space = space_usage(settings)?;
+    for (builder, _) in sorted_builders {
+        // Break if we're within the threshold.
+        if space <= threshold {
+            break;
+        }
+
+        // Prune the cache by as much as we're over the threshold.
+        let excess = space.get_bytes() - threshold.get_bytes();
+        if let Err(error) = settings
+            .backend
+            .buildx_cache_size(&builder)
+            .and_then(|size| {
+                settings.backend.prune_buildx_cache(
+                    &builder,
+                    Byte::from_bytes(
+                        size.get_bytes()
+                            .saturating_sub(excess)
+                            .max(keep.map_or(0, |keep| keep.get_bytes())),
+                    ),
+                )
+            })
+        {
+            // The pruning failed. Just log the error and proceed.
+            error!("{error}");
+            report.errors.push(error.to_string());
+        }
+
+        space = space_usage(settings)?;
+    }
+
+    Ok(())
+}
+
+// Delete stopped containers, beginning with those which stopped the longest ago, until the space
+// usage is within the given limit. Containers which stopped more recently than the minimum age are
+// left alone. Deleting a container also frees up its image for deletion by a later stage.
+fn vacuum_containers(
+    settings: &Settings,
+    threshold: Byte,
+    min_age: Option<Duration>,
+    report: &mut Report,
+) -> io::Result<()> {
+    // Find all stopped containers and sort them by when they stopped.
+    let mut sorted_containers = settings
+        .backend
+        .stopped_containers()?
+        .into_iter()
+        .collect::<Vec<_>>();
+    sorted_containers.sort_by_key(|(_, finished_since_epoch)| *finished_since_epoch);
+    if let Some(duration) = min_age {
+        let time_stamp = (SystemTime::now() - duration)
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        sorted_containers.retain(|(_, finished_since_epoch)| *finished_since_epoch <= time_stamp);
+    }
+
+    // Start deleting containers, beginning with the one which stopped the longest ago.
+    if space_usage(settings)? <= threshold {
+        return Ok(());
+    }
+    for containers in sorted_containers.chunks(settings.deletion_chunk_size) {
+        for (container_id, _) in containers {
+            if let Err(error) = settings.backend.delete_container(container_id) {
+                // The deletion failed. Just log the error and proceed.
+                error!("{error}");
+                report.errors.push(error.to_string());
+            }
+        }
+
+        // Break if we're within the threshold.
+        if space_usage(settings)? <= threshold {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// Prune the build cache by as much as the space usage is over the given limit, but never below
+// `keep`. The build cache deletes its least recently used records first, and records used more
+// recently than the minimum age are left alone.
+fn vacuum_build_cache(
+    settings: &Settings,
+    threshold: Byte,
+    min_age: Option<Duration>,
+    keep: Option<Byte>,
+    report: &mut Report,
+) -> io::Result<()> {
+    let space = space_usage(settings)?;
+    if space <= threshold {
+        return Ok(());
+    }
+
+    let excess = space.get_bytes() - threshold.get_bytes();
+    if let Err(error) = settings.backend.build_cache_size().and_then(|size| {
+        settings.backend.prune_build_cache(
+            min_age,
+            Byte::from_bytes(
+                size.get_bytes()
+                    .saturating_sub(excess)
+                    .max(keep.map_or(0, |keep| keep.get_bytes())),
+            ),
+        )
+    }) {
+        // The pruning failed. Just log the error and proceed.
+        error!("{error}");
+        report.errors.push(error.to_string());
+    }
+
+    Ok(())
+}
+
+// Select the images which a stage of the pipeline may delete from `sorted_image_nodes`, in the same
+// order. Images used more recently than the stage's minimum age are left alone, and the stage for
+// dangling images only deletes untagged images which no other image is built on.
+fn stage_image_nodes<'a>(
+    polyforest: &HashMap<String, ImageNode>,
+    sorted_image_nodes: &[(&'a String, &'a ImageNode)],
+    stage: &Stage,
+) -> io::Result<Vec<(&'a String, &'a ImageNode)>> {
+    let time_stamp = stage
+        .min_age
+        .map(|duration| {
+            (SystemTime::now() - duration)
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+        })
+        .transpose()?;
+    let parent_ids = polyforest
+        .values()
+        .filter_map(|image_node| image_node.image_record.parent_id.as_deref())
+        .collect::<HashSet<_>>();
+
+    Ok(sorted_image_nodes
+        .iter()
+        .copied()
+        .filter(|(image_id, image_node)| {
+            time_stamp.is_none_or(|time_stamp| image_node.last_used_since_epoch <= time_stamp)
+                && (stage.kind != StageKind::DanglingImages
+                    || (image_node
+                        .image_record
+                        .repository_tags
+                        .iter()
+                        .all(|repository_tag| repository_tag.tag == "<none>")
+                        && !parent_ids.contains(image_id.as_str())))
+        })
+        .collect())
+}
+
+// Delete the images in `image_nodes`, in order, until the space usage is within the given limit.
+// Returns the space usage afterward.
+fn delete_images_for_limit(
+    settings: &Settings,
+    polyforest: &HashMap<String, ImageNode>,
+    image_nodes: &[(&String, &ImageNode)],
+    deleted_image_ids: &mut HashSet<String>,
+    blocked_image_ids: &mut HashSet<String>,
+    limit: Byte,
+    report: &mut Report,
+) -> io::Result<Byte> {
+    let mut space = space_usage(settings)?;
+    for image_ids in image_nodes.chunks(settings.deletion_chunk_size) {
+        // Break if we're within the limit.
+        if space <= limit {
+            break;
+        }
+
+        for (image_id, image_node) in image_ids {
+            // Delete the image.
+            if delete_image(
+                settings,
+                polyforest,
+                image_id,
+                image_node,
+                DeletionReason::Threshold,
+                blocked_image_ids,
+                report,
+            ) {
+                // Forget about the deleted image.
+                deleted_image_ids.insert((*image_id).clone());
+            }
+        }
+
+        space = space_usage(settings)?;
+    }
+
+    Ok(space)
+}
+
+// If the `--min-free-inodes` argument is provided, delete the least recently used images until at
+// least that many inodes are free on the filesystem on which the root directory of the container
+// runtime is stored, regardless of the threshold. The deleted images are removed from
+// `sorted_image_nodes`.
+fn delete_images_for_inodes(
+    settings: &Settings,
+    polyforest: &HashMap<String, ImageNode>,
+    sorted_image_nodes: &mut Vec<(&String, &ImageNode)>,
+    deleted_image_ids: &mut HashSet<String>,
+    blocked_image_ids: &mut HashSet<String>,
+    report: &mut Report,
+) -> io::Result<()> {
+    let Some(min_free_inodes) = settings.min_free_inodes else {
+        return Ok(());
+    };
+
+    let root_dir = settings.backend.root_dir()?;
+    let mut free_inodes = filesystem_free_inodes(&root_dir)?;
+    if free_inodes >= min_free_inodes {
+        debug!(
+            "There are {} free inodes, which is within the limit of {}.",
+            free_inodes.to_string().code_str(),
+            min_free_inodes.to_string().code_str(),
+        );
+        return Ok(());
+    }
+
+    info!(
+        "There are currently {} free inodes, but at least {} should be free.",
+        free_inodes.to_string().code_str(),
+        min_free_inodes.to_string().code_str(),
+    );
+
+    // Start deleting images, beginning with the least recently used.
+    let mut result = Ok(());
+    sorted_image_nodes.retain(|(image_id, image_node)| {
+        if free_inodes >= min_free_inodes || result.is_err() {
+            return true;
+        }
+
+        // Delete the image.
+        if delete_image(
+            settings,
+            polyforest,
+            image_id,
+            image_node,
+            DeletionReason::MinFreeInodes,
+            blocked_image_ids,
+            report,
+        ) {
+            // Forget about the deleted image, and check how many inodes it freed.
+            deleted_image_ids.insert((*image_id).clone());
+            match filesystem_free_inodes(&root_dir) {
+                Ok(new_free_inodes) => free_inodes = new_free_inodes,
+                Err(error) => result = Err(error),
+            }
+            false
+        } else {
+            true
+        }
+    });
+
+    result
+}
+
+// If the `--critical-threshold` argument is provided and the space usage is over it, delete the
+// images which only the keep rules and ages protect, beginning with the least recently used, until
+// the space usage is within it again. Returns the new space usage.
+fn delete_images_critically(
+    settings: &Settings,
+    polyforest: &HashMap<String, ImageNode>,
+    overridable_image_nodes: &[(&String, &ImageNode, Protection)],
+    mut space: Byte,
+    deleted_image_ids: &mut HashSet<String>,
+    blocked_image_ids: &mut HashSet<String>,
+    report: &mut Report,
+) -> io::Result<Byte> {
+    let Some(critical_threshold) = settings.critical_threshold else {
+        return Ok(space);
+    };
+
+    let critical_limit = other_space_limit(settings, space, critical_threshold)?;
+    if space <= critical_limit {
+        return Ok(space);
+    }
+
+    event::log(
+        Level::Warn,
+        "critical_threshold",
+        &[
+            ("bytes", space.get_bytes().to_value()),
+            (
+                "critical_threshold_bytes",
+                critical_limit.get_bytes().to_value(),
+            ),
+        ],
+        format_args!(
+            "Docker images are using {}, which is over the critical limit of {}. Protected images \
+                will be deleted until they're within it.",
+            space.get_appropriate_unit(false).to_string().code_str(),
+            critical_limit
+                .get_appropriate_unit(false)
+                .to_string()
+                .code_str(),
+        ),
+    );
+
+    for (image_id, image_node, protection) in overridable_image_nodes {
+        event::log(
+            Level::Warn,
+            "critical_deletion",
+            &[("image_id", image_id.as_str().to_value())],
+            format_args!(
+                "Deleting image {} despite {}, since the disk is critically full.",
+                image_id.code_str(),
+                protection.cause().code_str(),
+            ),
+        );
+        if delete_image(
+            settings,
+            polyforest,
+            image_id,
+            image_node,
+            DeletionReason::CriticalThreshold,
+            blocked_image_ids,
+            report,
+        ) {
+            // Forget about the deleted image, and check if we're within the critical limit.
+            deleted_image_ids.insert((*image_id).clone());
+            space = space_usage(settings)?;
+            if space <= critical_limit {
+                break;
+            }
+        }
+    }
+
+    if space > critical_limit {
+        event::log(
+            Level::Error,
+            "error",
+            &[],
+            format_args!(
+                "Docker images are still using {}, which is over the critical limit of {}.",
+                space.get_appropriate_unit(false).to_string().code_str(),
+                critical_limit
+                    .get_appropriate_unit(false)
+                    .to_string()
+                    .code_str(),
+            ),
+        );
+    }
+
+    Ok(space)
+}
+
+// The main vacuum logic
+#[allow(clippy::too_many_lines)]
+fn vacuum_images(
+    settings: &Settings,
+    state: &mut State,
+    first_run: bool,
+    threshold: Byte,
+    report: &mut Report,
+) -> io::Result<()> {
+    // Delete stale containers first, since they prevent their images from being deleted.
+    prune_containers(settings, report)?;
+
+    // Deleting containers may have left some networks unused.
+    prune_networks(settings, state, report)?;
+
+    // Find all images.
+    let image_records = settings
+        .backend
+        .list_image_records(settings.inspect_concurrency)?;
+
+    // Find all images in use.
+    let image_ids_in_use = image_ids_in_use(
+        settings,
+        &mut state.compose_projects,
+        &mut state.gitlab_pipelines,
+    )?;
+
+    // Construct a polyforest of image nodes that reflects their parent-child relationships.
+    let polyforest = construct_polyforest(state, first_run, &image_records, &image_ids_in_use)?;
+    report.examined_images = polyforest.len();
+
+    // Sort the images in the order in which they're deleted.
+    let mut sorted_image_nodes = sort_image_nodes(
+        &polyforest,
+        &image_ids_in_use,
+        &settings.policy,
+        &settings.tie_breaks,
+        &settings.plugins,
+        now_since_epoch()?,
+    );
+
+    // Filter out the images which must not be deleted, except in an emergency. Images in use are
+    // never deleted, not even then.
+    let mut overridable_image_nodes = retain_deletable(settings, &mut sorted_image_nodes)?;
+    overridable_image_nodes.retain(|(image_id, _, _)| !image_ids_in_use.contains(*image_id));
+
+    // Delete the images which have expired. Images which turn out to be in use are skipped for the
+    // rest of the vacuum, along with their ancestors.
+    let mut deleted_image_ids = HashSet::new();
+    let mut blocked_image_ids = HashSet::new();
+    delete_expired_images(
+        settings,
+        &polyforest,
+        &mut sorted_image_nodes,
+        &mut deleted_image_ids,
+        &mut blocked_image_ids,
+        report,
+    )?;
+
+    // Delete the least recently used images if there are too many of them.
+    delete_excess_images(
+        settings,
+        &polyforest,
+        &mut sorted_image_nodes,
+        &mut deleted_image_ids,
+        &mut blocked_image_ids,
+        report,
+    );
+
+    // Delete the least recently used images if the filesystem is running out of inodes.
+    delete_images_for_inodes(
+        settings,
+        &polyforest,
+        &mut sorted_image_nodes,
+        &mut deleted_image_ids,
+        &mut blocked_image_ids,
+        report,
+    )?;
+
+    // Check if we're over the threshold. If so, clean down to the target rather than stopping as
+    // soon as we're within the threshold, so the next image doesn't trigger another vacuum.
+    let mut space = space_usage(settings)?;
+    let threshold = space_limit(settings, space, threshold)?;
+    let target = if space > threshold {
+        target_limit(settings, space, threshold)?
+    } else {
+        threshold
+    };
+    let (limit_name, limit) = if target < threshold {
+        ("target", target)
+    } else {
+        ("limit", threshold)
+    };
+
+    if space > target {
+        event::log(
+            Level::Info,
+            "vacuum_start",
+            &[
+                ("bytes", space.get_bytes().to_value()),
+                ("threshold_bytes", threshold.get_bytes().to_value()),
+                ("target_bytes", target.get_bytes().to_value()),
+            ],
+            format_args!(
+                "Docker images are currently using {}, but the {} is {}.",
+                space.get_appropriate_unit(false).to_string().code_str(),
+                limit_name,
+                limit.get_appropriate_unit(false).to_string().code_str(),
+            ),
+        );
+
+        // Reclaim space with each stage of the pipeline in turn. Without a pipeline in the
+        // configuration file, that's unused anonymous volumes and the caches of Buildx builders (if
+        // requested) and then images, beginning with the least recently used.
+        for stage in &settings.pipeline {
+            if space <= target {
+                break;
+            }
+
+            match stage.kind {
+                StageKind::Containers => {
+                    vacuum_containers(settings, target, stage.min_age, report)?;
+                }
+                StageKind::DanglingImages | StageKind::Images => {
+                    let image_nodes = stage_image_nodes(&polyforest, &sorted_image_nodes, stage)?;
+                    delete_images_for_limit(
+                        settings,
+                        &polyforest,
+                        &image_nodes,
+                        &mut deleted_image_ids,
+                        &mut blocked_image_ids,
+                        target,
+                        report,
+                    )?;
+                    sorted_image_nodes
+                        .retain(|(image_id, _)| !deleted_image_ids.contains(*image_id));
+                }
+                StageKind::BuildCache => {
+                    vacuum_build_cache(settings, target, stage.min_age, stage.keep, report)?;
+                }
+                StageKind::BuildxCaches => {
+                    vacuum_buildx_caches(
+                        settings,
+                        state,
+                        target,
+                        stage.min_age,
+                        stage.keep,
+                        report,
+                    )?;
+                }
+                StageKind::Volumes => {
+                    vacuum_volumes(
+                        settings,
+                        state,
+                        target,
+                        stage.min_age.or(settings.min_age),
+                        report,
+                    )?;
+                }
+            }
+            space = space_usage(settings)?;
+        }
+
+        if space <= target {
+            event::log(
+                Level::Info,
+                "vacuum_finish",
+                &[
+                    ("bytes", space.get_bytes().to_value()),
+                    ("threshold_bytes", threshold.get_bytes().to_value()),
+                    ("target_bytes", target.get_bytes().to_value()),
+                ],
+                format_args!(
+                    "Docker images are now using {}, which is within the {} of {}.",
+                    space.get_appropriate_unit(false).to_string().code_str(),
+                    limit_name,
+                    limit.get_appropriate_unit(false).to_string().code_str(),
+                ),
+            );
+        }
+    } else {
+        event::log(
+            Level::Debug,
+            "vacuum_finish",
+            &[
+                ("bytes", space.get_bytes().to_value()),
+                ("threshold_bytes", threshold.get_bytes().to_value()),
+            ],
+            format_args!(
+                "Docker images are using {}, which is within the limit of {}.",
+                space.get_appropriate_unit(false).to_string().code_str(),
+                threshold.get_appropriate_unit(false).to_string().code_str(),
+            ),
+        );
+    }
+
+    // If the disk is critically full even so, delete protected images too.
+    space = delete_images_critically(
+        settings,
+        &polyforest,
+        &overridable_image_nodes,
+        space,
+        &mut deleted_image_ids,
+        &mut blocked_image_ids,
+        report,
+    )?;
+
+    report.bytes = Some(space.get_bytes());
+    report.threshold_bytes = Some(threshold.get_bytes());
+
+    // Let the health server show the eviction order of the images which remain.
+    if settings.health_addr.is_some() {
+        record_remaining_images(
+            settings,
+            state,
+            &polyforest,
+            &image_ids_in_use,
+            &deleted_image_ids,
+        )?;
+    }
+
+    // Update the state.
+    update_image_state(settings, state, polyforest, &deleted_image_ids, report)
+}
+
+// Tell the health server which images remain after a vacuum, in the order in which they would be
+// deleted.
+fn record_remaining_images(
+    settings: &Settings,
+    state: &State,
+    polyforest: &HashMap<String, ImageNode>,
+    image_ids_in_use: &HashSet<String>,
+    deleted_image_ids: &HashSet<String>,
+) -> io::Result<()> {
+    let remaining_image_nodes = sort_image_nodes(
+        polyforest,
+        image_ids_in_use,
+        &settings.policy,
+        &settings.tie_breaks,
+        &settings.plugins,
+        now_since_epoch()?,
+    )
+    .into_iter()
+    .filter(|(image_id, _)| !deleted_image_ids.contains(*image_id))
+    .collect();
+    health::record_images(&listed_images(settings, state, remaining_image_nodes)?);
+    Ok(())
+}
+
+// Record the images which remain after a vacuum in the state, and forget about the rest.
+fn update_image_state(
+    settings: &Settings,
+    state: &mut State,
+    polyforest: HashMap<String, ImageNode>,
+    deleted_image_ids: &HashSet<String>,
+    report: &mut Report,
+) -> io::Result<()> {
+    for image_id in deleted_image_ids {
+        state.images.remove(image_id);
+    }
+    report.forgotten_images =
+        forget_missing_images(state, &polyforest, forget_missing_time_stamp(settings)?);
+    log_reconciliation(report.forgotten_images);
+    for (image_id, image_node) in polyforest {
+        if !deleted_image_ids.contains(&image_id) {
+            let containers = state
+                .images
+                .remove(&image_id)
+                .map(|image| image.containers)
+                .unwrap_or_default();
+            state.images.insert(
+                image_id.clone(),
+                state::Image {
+                    parent_id: image_node.image_record.parent_id.clone(),
+                    last_used_since_epoch: image_node.last_used_since_epoch,
+                    pulled_since_epoch: image_node.pulled_since_epoch,
+                    uses: image_node.uses,
+                    frequency: image_node.frequency,
+                    containers,
+                },
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Images which no longer exist are remembered if they were used more recently than this, according
+// to `--forget-missing-after`.
+fn forget_missing_time_stamp(settings: &Settings) -> io::Result<Option<Duration>> {
+    settings
+        .forget_missing_after
+        .map(|duration| {
+            (SystemTime::now() - duration)
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+        })
+        .transpose()
+}
+
+// Forget about images in the state which aren't among the existing `images`, unless they were used
+// more recently than `time_stamp`. Returns the number of images forgotten.
+fn forget_missing_images<T>(
+    state: &mut State,
+    images: &HashMap<String, T>,
+    time_stamp: Option<Duration>,
+) -> usize {
+    let image_count = state.images.len();
+    state.images.retain(|image_id, image| {
+        if images.contains_key(image_id)
+            || time_stamp.is_some_and(|time_stamp| image.last_used_since_epoch > time_stamp)
+        {
+            return true;
+        }
+
+        debug!(
+            "Forgot image {}, since it no longer exists.",
+            image_id.code_str(),
+        );
+        false
+    });
+    image_count - state.images.len()
+}
+
+// Summarize how many images were forgotten because something other than Docuum deleted them.
+fn log_reconciliation(forgotten_images: usize) {
+    if forgotten_images > 0 {
+        event::log(
+            Level::Info,
+            "reconciliation",
+            &[("forgotten_images", forgotten_images.to_value())],
+            format_args!(
+                "Forgot {forgotten_images} image(s) which were deleted by something other than \
+                    Docuum.",
+            ),
+        );
+    }
+}
+
+// Reconcile the state with the images which currently exist, e.g., after a `docker system prune`.
+// Returns the number of images forgotten.
+fn reconcile_state(settings: &Settings, state: &mut State) -> io::Result<usize> {
+    let image_records = settings
+        .backend
+        .list_image_records(settings.inspect_concurrency)?;
+    let forgotten_images =
+        forget_missing_images(state, &image_records, forget_missing_time_stamp(settings)?);
+    log_reconciliation(forgotten_images);
+    Ok(forgotten_images)
+}
+
+// Vacuum, and then tell any interested parties what happened.
+pub fn vacuum(
+    settings: &Settings,
+    state: &mut State,
+    first_run: bool,
+    threshold: Byte,
+) -> io::Result<Report> {
+    // While deletions are paused, image usage is still tracked, but nothing is deleted.
+    if pause::paused() {
+        debug!("Skipping the vacuum, since deletions are paused.");
+        return Ok(Report::default());
+    }
+
+    let span = telemetry::Span::start("vacuum");
+    if let Some(name) = &settings.endpoint_name {
+        span.set("endpoint", name.as_str());
+    }
+    let start = Instant::now();
+    let mut report = Report::default();
+    let result = vacuum_images(settings, state, first_run, threshold, &mut report);
+    if let Err(error) = &result {
+        span.fail(error);
+        report.errors.push(error.to_string());
+    }
+    span.set("deleted_images", report.deleted_images.len());
+    span.set("reclaimed_bytes", report.reclaimed_bytes);
+    state.counters.record_vacuum(&report);
+
+    // Summarize the vacuum in a single line, so it doesn't have to be pieced together from the
+    // rest of the log.
+    let duration = start.elapsed();
+    event::log(
+        Level::Info,
+        "vacuum_summary",
+        &[
+            ("examined_images", report.examined_images.to_value()),
+            ("deleted_images", report.deleted_images.len().to_value()),
+            ("reclaimed_bytes", report.reclaimed_bytes.to_value()),
+            (
+                "bytes_over_threshold",
+                report.bytes_over_threshold().to_value(),
+            ),
+            ("duration_ms", duration.as_millis().to_value()),
+            ("errors", report.errors.len().to_value()),
+        ],
+        format_args!("{}", report.summary(duration)),
+    );
+
+    if let Err(error) = history::record(&report.deleted_images, settings.history_max_age) {
+        // Log the error and proceed anyway.
+        event::log(
+            Level::Error,
+            "error",
+            &[],
+            format_args!("Unable to update the deletion history. Details: {error}"),
+        );
+    }
+
+    grpc::publish(&report.deleted_images);
+    health::record_vacuum(&report, result.is_ok());
+    if settings.health_addr.is_some() {
+        health::record_state(state);
+    }
+    systemd::vacuumed(&report);
+
+    if let Some(address) = &settings.statsd_addr {
+        if let Err(error) = statsd::send(address, &report, &state.counters, duration) {
+            // Log the error and proceed anyway.
+            event::log(
+                Level::Error,
+                "error",
+                &[],
+                format_args!("Unable to send metrics to StatsD. Details: {error}"),
+            );
+        }
+    }
+
+    if let Some(url) = &settings.webhook_url {
+        if let Err(error) = webhook::post(url, &report) {
+            // Log the error and proceed anyway.
+            event::log(Level::Error, "error", &[], format_args!("{error}"));
+        }
+    }
+
+    if let Some(command) = &settings.post_vacuum_hook {
+        if let Err(error) = hook::post_vacuum(command, &report) {
+            // Log the error and proceed anyway.
+            event::log(Level::Error, "error", &[], format_args!("{error}"));
+        }
+    }
+
+    result.map(|()| report)
+}
+
+// Determine the threshold in bytes. For free space thresholds, this is the amount of space to keep
+// free rather than a limit on the space used by images [tag:free_threshold_bytes].
+pub fn threshold_bytes(settings: &Settings) -> io::Result<Byte> {
+    bytes(&settings.backend, settings.threshold)
+}
+
+// Determine the number of bytes a threshold (or target) stands for, as for `threshold_bytes`.
+fn bytes(backend: &Backend, threshold: Threshold) -> io::Result<Byte> {
+    Ok(match threshold {
+        Threshold::Absolute(b) | Threshold::Free(b) => b,
+        Threshold::Percentage(p) =>
+        {
+            #[allow(
+                clippy::cast_precision_loss,
+                clippy::cast_possible_truncation,
+                clippy::cast_sign_loss
+            )]
+            Byte::from_bytes((p * root_dir_filesystem_size(backend)?.get_bytes() as f64) as u128)
+        }
+    })
+}
+
+// The thresholds of the kubelet on a Kubernetes node, converted into the free space which they call
+// for on the filesystem the images are stored on
+pub struct KubeletLimits {
+    filesystem_size: Byte,
+
+    // The free space below which the kubelet starts deleting images
+    image_gc_high: Byte,
+
+    // The free space up to which the kubelet deletes images
+    image_gc_low: Byte,
+
+    // The free space which keeps the disk usage strictly below all of the kubelet's thresholds for
+    // deleting images and evicting pods
+    coordinated: Byte,
+}
+
+impl KubeletLimits {
+    // Ask the kubelet on a Kubernetes node for its thresholds.
+    pub fn fetch(backend: &Backend, node: &str) -> io::Result<Self> {
+        Ok(Self::new(
+            node,
+            &kubernetes::kubelet_thresholds(node)?,
+            root_dir_filesystem_size(backend)?,
+        ))
+    }
+
+    // Convert the thresholds of the kubelet on a Kubernetes node for a filesystem of a given size.
+    fn new(node: &str, thresholds: &KubeletThresholds, filesystem_size: Byte) -> Self {
+        let size = filesystem_size.get_bytes();
+        let free = |percent: u8| Byte::from_bytes(size * u128::from(100 - percent) / 100);
+        let image_gc_high = free(thresholds.image_gc_high_percent);
+        let image_gc_low = free(thresholds.image_gc_low_percent);
+
+        // Keep an extra percent of the filesystem free, so Docuum always cleans up first.
+        let eviction = thresholds
+            .eviction
+            .iter()
+            .map(|min_available| match *min_available {
+                MinAvailable::Bytes(bytes) => bytes,
+                #[allow(
+                    clippy::cast_precision_loss,
+                    clippy::cast_possible_truncation,
+                    clippy::cast_sign_loss
+                )]
+                MinAvailable::Fraction(fraction) => {
+                    Byte::from_bytes((fraction * size as f64) as u128)
+                }
+            })
+            .max()
+            .unwrap_or(image_gc_high);
+        let coordinated = Byte::from_bytes(image_gc_high.max(eviction).get_bytes() + size / 100);
+
+        debug!(
+            "The kubelet on Kubernetes node {} deletes images when the disk usage is over {} until \
+                it's down to {}, and evicts pods when less than {} is free.",
+            node.code_str(),
+            format!("{}%", thresholds.image_gc_high_percent).code_str(),
+            format!("{}%", thresholds.image_gc_low_percent).code_str(),
+            eviction.get_appropriate_unit(false).to_string().code_str(),
+        );
+
+        Self {
+            filesystem_size,
+            image_gc_high,
+            image_gc_low,
+            coordinated,
+        }
+    }
+
+    // Determine the threshold and target which make Docuum clean up before the kubelet does. When
+    // coordinating with the kubelet, the threshold also stays clear of its eviction thresholds.
+    pub fn defaults(&self, coordinate: bool) -> (Threshold, Threshold) {
+        if coordinate {
+            (
+                Threshold::Free(self.coordinated),
+                Threshold::Free(self.image_gc_low.max(self.coordinated)),
+            )
+        } else {
+            (
+                Threshold::Free(self.image_gc_high),
+                Threshold::Free(self.image_gc_low),
+            )
+        }
+    }
+
+    // Determine the threshold which keeps the disk usage strictly below the kubelet's thresholds.
+    pub fn coordinated(&self) -> Threshold {
+        Threshold::Free(self.coordinated)
+    }
+
+    // Determine whether a threshold would let the disk usage reach the kubelet's thresholds even if
+    // nothing but images were stored on the filesystem.
+    pub fn conflicts_with(&self, threshold: Threshold) -> bool {
+        let size = self.filesystem_size.get_bytes();
+        match threshold {
+            Threshold::Free(free) => free < self.coordinated,
+            Threshold::Absolute(bytes) => {
+                bytes.get_bytes() > size.saturating_sub(self.coordinated.get_bytes())
+            }
+            #[allow(
+                clippy::cast_precision_loss,
+                clippy::cast_possible_truncation,
+                clippy::cast_sign_loss
+            )]
+            Threshold::Percentage(p) => {
+                (p * size as f64) as u128 > size.saturating_sub(self.coordinated.get_bytes())
+            }
+        }
+    }
+}
+
+// Check whether the space usage is over the threshold, without listing or inspecting any images.
+fn over_threshold(settings: &Settings, threshold: Byte) -> io::Result<bool> {
+    let space = space_usage(settings)?;
+    Ok(space > space_limit(settings, space, threshold)?)
+}
+
+// Lock the state. A panic while the lock was held doesn't matter, since the state is only ever
+// used as a record of timestamps.
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Vacuum and persist the state, holding the lock on the state throughout.
+fn vacuum_and_save(
+    settings: &Settings,
+    state: &Mutex<State>,
+    first_run: bool,
+    threshold: Byte,
+) -> io::Result<Report> {
+    let mut state = lock(state);
+    let report = vacuum(settings, &mut state, first_run, threshold)?;
+    state::save(&state, settings.endpoint_name.as_deref())?;
+    Ok(report)
+}
+
+// Perform a single vacuum, persist the state, and report how it turned out.
+pub fn run_once(settings: &Settings, state: &Mutex<State>, first_run: bool) -> io::Result<Outcome> {
+    // Determine the threshold in bytes.
+    let threshold = threshold_bytes(settings)?;
+
+    // Run the main vacuum logic.
+    info!("Performing a one-time vacuum\u{2026}");
+    vacuum_and_save(settings, state, first_run, threshold).map(|report| report.outcome())
+}
+
+// Spawn the process which streams events from the container runtime, and read its output on a
+// separate thread so we can periodically check for reload requests while waiting for events. The
+// thread stops when the events process terminates. The events which happened after the given
+// checkpoint, if any, are replayed first.
+#[allow(clippy::type_complexity)]
+fn stream_events(
+    backend: &Backend,
+    checkpoint: Option<Duration>,
+    destructors: &Arc<Mutex<Vec<Box<dyn FnOnce() + Send>>>>,
+) -> io::Result<Receiver<io::Result<String>>> {
+    if let Some(checkpoint) = checkpoint {
+        debug!(
+            "Resuming the events from {}\u{2026}",
+            DateTime::<Utc>::from(UNIX_EPOCH + checkpoint)
+                .to_rfc3339_opts(SecondsFormat::Nanos, true)
+                .code_str(),
+        );
+    }
+    let mut child = backend.spawn_events(checkpoint)?;
+
+    // Buffer the data as we read it line-by-line. The `unwrap` is safe due to
+    // [ref:events_stdout].
+    let reader = BufReader::new(child.stdout.take().unwrap());
+
+    // When this run is done (e.g., due to an error) or when a termination signal is received, kill
+    // the child process.
+    destructors.lock().unwrap().push(Box::new(move || {
+        if let Err(error) = child.kill() {
+            error!("{error}");
+        } else if let Err(error) = child.wait() {
+            error!("{error}");
+        }
+    }));
+
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        for line_option in reader.lines() {
+            if sender.send(line_option).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(receiver)
+}
+
+// Determine when the next scheduled vacuum is due, if there's a schedule.
+fn next_scheduled_vacuum(settings: &Settings) -> Option<DateTime<Local>> {
+    let schedule = settings.schedule.as_ref()?;
+    match schedule.find_next_occurrence(&Local::now(), false) {
+        Ok(next) => {
+            debug!(
+                "The next scheduled vacuum is at {}.",
+                next.to_rfc3339().code_str(),
+            );
+            Some(next)
+        }
+        Err(error) => {
+            // This only happens if the schedule can never be satisfied, e.g., `0 0 31 2 *`.
+            warn!("Unable to determine when the next scheduled vacuum is. Details: {error}");
+            None
+        }
+    }
+}
+
+// Tell any interested parties that the container runtime is being polled rather than streaming
+// events.
+fn record_polling() {
+    health::record_events_connected(None);
+    systemd::ready("Polling the container runtime");
+}
+
+// Replace the settings (and the threshold derived from them) with freshly loaded ones. If they
+// can't be loaded, the old ones are kept. Returns a boolean indicating whether the settings were
+// replaced.
+fn reload(
+    settings: &mut Settings,
+    threshold: &mut Byte,
+    reload_settings: &dyn Fn() -> io::Result<Settings>,
+) -> bool {
+    info!("Reloading settings\u{2026}");
+
+    match reload_settings().and_then(|new_settings| {
+        threshold_bytes(&new_settings).map(|new_threshold| (new_settings, new_threshold))
+    }) {
+        Ok((new_settings, new_threshold)) => {
+            *settings = new_settings;
+            *threshold = new_threshold;
+            true
+        }
+        Err(error) => {
+            error!("Unable to reload settings. Details: {error}");
+            false
+        }
+    }
+}
+
+// Stream Docker events and vacuum when necessary. This only returns `Ok` if we've been asked to
+// stop, which only happens when running as a Windows service.
+#[allow(clippy::too_many_lines, clippy::type_complexity)]
+pub fn run(
+    settings: &mut Settings,
+    reload_settings: &dyn Fn() -> io::Result<Settings>,
+    state: &Mutex<State>,
+    first_run: &mut bool,
+    destructors: &Arc<Mutex<Vec<Box<dyn FnOnce() + Send>>>>,
+) -> io::Result<()> {
+    // Determine the threshold in bytes.
+    let mut threshold = threshold_bytes(settings)?;
+
+    // NOTE: Don't change this log line, since the test in the Homebrew formula
+    // (https://github.com/Homebrew/homebrew-core/blob/HEAD/Formula/d/docuum.rb) relies on it.
+    info!("Performing an initial vacuum on startup\u{2026}");
+    systemd::activity("Performing an initial vacuum");
+
+    // Run the main vacuum logic.
+    vacuum_and_save(settings, state, *first_run, threshold)?;
+    *first_run = false;
+
+    // Stream events from the container runtime, if it supports that. If the events can't be
+    // streamed after all, fall back to polling.
+    let mut events = if settings.backend.streams_events() {
+        let checkpoint = lock(state).last_event_since_epoch;
+        match stream_events(&settings.backend, checkpoint, destructors) {
+            Ok(receiver) => {
+                health::record_events_connected(Some(true));
+                systemd::ready("Listening for Docker events");
+                info!("Listening for Docker events\u{2026}");
+                Some(receiver)
+            }
+            Err(error) => {
+                warn!(
+                    "Unable to stream events from the container runtime, so it will be polled \
+                        every {} instead. Details: {}",
+                    format!("{} seconds", settings.poll_interval.as_secs()).code_str(),
+                    error,
+                );
+                record_polling();
+                None
+            }
+        }
+    } else {
+        info!(
+            "The container runtime doesn't provide events, so it will be polled every {}.",
+            format!("{} seconds", settings.poll_interval.as_secs()).code_str(),
+        );
+        record_polling();
+        None
+    };
+    let events_started = Instant::now();
+    let mut events_received = false;
+
+    // Vacuums also happen on a schedule, if there is one.
+    let mut next_scheduled_vacuum = next_scheduled_vacuum(settings);
+
+    // Whether deletions were paused as of the last iteration below
+    let mut paused = pause::paused();
+
+    // The reloads and vacuums which clients have requested so far
+    let mut reloads_seen = reload::requests();
+    let mut vacuums_seen = control::vacuum_requests();
+
+    // Handle each incoming event.
+    let mut last_poll = Instant::now();
+    let mut last_disk_check = Instant::now();
+    loop {
+        // Stop if requested. The state has already been saved.
+        if service::stop_requested() {
+            return Ok(());
+        }
+
+        // Reload the settings if requested. The new settings may call for a different set of
+        // images, so vacuum right away.
+        if reload::requested(&mut reloads_seen) && reload(settings, &mut threshold, reload_settings)
+        {
+            vacuum_and_save(settings, state, *first_run, threshold)?;
+            next_scheduled_vacuum = self::next_scheduled_vacuum(settings);
+        }
+
+        // Report when deletions are paused or resumed. Since images may have piled up in the
+        // meantime, vacuum right away upon resuming.
+        if pause::paused() != paused {
+            paused = !paused;
+            if paused {
+                info!("Deletions are paused.");
+                systemd::activity("Paused");
+            } else {
+                info!("Deletions have resumed.");
+                systemd::activity(if events.is_some() {
+                    "Listening for Docker events"
+                } else {
+                    "Polling the container runtime"
+                });
+                vacuum_and_save(settings, state, *first_run, threshold)?;
+            }
+        }
+
+        // Vacuum if a scheduled vacuum is due.
+        if next_scheduled_vacuum.is_some_and(|next| Local::now() >= next) {
+            info!("Performing a scheduled vacuum\u{2026}");
+            vacuum_and_save(settings, state, *first_run, threshold)?;
+            next_scheduled_vacuum = self::next_scheduled_vacuum(settings);
+        }
+
+        // Space can fill up between events (e.g., due to container logs or `docker cp`), so check
+        // it periodically if requested. Polling vacuums regardless, so this is only needed for
+        // events.
+        if events.is_some()
+            && settings
+                .disk_check_interval
+                .is_some_and(|interval| last_disk_check.elapsed() >= interval)
+        {
+            last_disk_check = Instant::now();
+            match over_threshold(settings, threshold) {
+                Ok(true) => {
+                    info!("The space usage is over the threshold, so performing a vacuum\u{2026}");
+                    vacuum_and_save(settings, state, *first_run, threshold)?;
+                }
+                Ok(false) => {}
+                Err(error) => {
+                    // Log the error and proceed anyway.
+                    event::log(
+                        Level::Error,
+                        "error",
+                        &[],
+                        format_args!("Unable to check the space usage. Details: {error}"),
+                    );
+                }
+            }
+        }
+
+        // Vacuum if a client asked for it.
+        if control::vacuum_requested(&mut vacuums_seen) {
+            info!("Performing a requested vacuum\u{2026}");
+            vacuum_and_save(settings, state, *first_run, threshold)?;
+        }
+
+        // Answer any questions from clients about why images would or wouldn't be deleted.
+        // Errors are sent to the client rather than stopping the daemon. Only the main endpoint
+        // answers them.
+        if settings.endpoint_name.is_none() {
+            for (image, output, sender) in control::explain_requests() {
+                // The client may have given up waiting, which is fine.
+                let _ = sender.send(control::explain(
+                    settings,
+                    &lock(state),
+                    *first_run,
+                    &image,
+                    output,
+                ));
+            }
+        }
+
+        // If we can't stream events, periodically vacuum instead. Images in use by containers are
+        // considered used whenever this happens.
+        let Some(receiver) = &events else {
+            sleep(RELOAD_POLL_INTERVAL);
+            if last_poll.elapsed() >= settings.poll_interval {
+                debug!("Waking up\u{2026}");
+                vacuum_and_save(settings, state, *first_run, threshold)?;
+                last_poll = Instant::now();
+                debug!("Going back to sleep\u{2026}");
+            }
+            continue;
+        };
+
+        // Wait for the next line.
+        let line = match receiver.recv_timeout(RELOAD_POLL_INTERVAL) {
+            Ok(line_option) => line_option?,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected)
+                if !events_received && events_started.elapsed() < EVENTS_STARTUP_PERIOD =>
+            {
+                // The events process gave up right away, so the container runtime (or something
+                // in between) probably doesn't support streaming events.
+                warn!(
+                    "{} terminated right away, so the container runtime will be polled every {} \
+                        instead.",
+                    settings.backend.events_command().code_str(),
+                    format!("{} seconds", settings.poll_interval.as_secs()).code_str(),
+                );
+                record_polling();
+                events = None;
+                last_poll = Instant::now();
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        events_received = true;
+        trace!("Incoming event: {}", line.code_str());
+
+        {
+            let mut state = lock(state);
+
+            // Remember when the event happened, so we can pick up where we left off if the events
+            // process terminates or Docuum restarts. This happens before the event is handled, so
+            // an event which can't be handled isn't replayed over and over. The checkpoint is
+            // persisted along with the rest of the state.
+            if let Some(time) = settings.backend.event_time(&line) {
+                state.last_event_since_epoch = Some(time);
+            }
+
+            // Update the timestamp for a volume or network if the event is about one of those.
+            if touch_volume_or_network(settings, &mut state, &line)? {
+                state::save(&state, settings.endpoint_name.as_deref())?;
+                continue;
+            }
+
+            // Update the timestamp for a Buildx builder if a build connected to it. The event also
+            // counts as a use of the builder's image, so the state is saved below.
+            if settings.prune_buildx_caches {
+                if let Some(builder) = settings.backend.used_buildx_builder(&line) {
+                    touch_buildx_builder(&mut state, &builder)?;
+                }
+            }
+
+            // Keep the state consistent with images which were deleted behind our back.
+            if let Some(image_id) = settings.backend.deleted_image(&line) {
+                if forget_deleted_image(settings, &mut state, &image_id) {
+                    state::save(&state, settings.endpoint_name.as_deref())?;
+                }
+                continue;
+            }
+
+            // A prune doesn't necessarily announce every image it deletes, so check which remain.
+            if settings.backend.pruned_images(&line) {
+                match reconcile_state(settings, &mut state) {
+                    Ok(0) => {}
+                    Ok(_) => state::save(&state, settings.endpoint_name.as_deref())?,
+                    Err(error) => {
+                        // Log the error and proceed anyway. The next vacuum reconciles the state
+                        // too.
+                        event::log(
+                            Level::Error,
+                            "error",
+                            &[],
+                            format_args!(
+                                "Unable to reconcile the state after a prune. Details: {error}",
+                            ),
+                        );
+                    }
+                }
+                continue;
+            }
+        }
+
+        // Get the ID of the image.
+        let Some(image) = settings.backend.used_image(&line) else {
+            continue;
+        };
+        let image_id = settings.backend.image_id(&image)?;
+        let used_by = settings.backend.image_container(&line);
+
+        // Inform the user that we're about to vacuum.
+        debug!("Waking up\u{2026}");
+
+        // Update the timestamp for this image.
+        let mut state = lock(state);
+        if touch_image(
+            &settings.backend,
+            &mut state,
+            &image_id,
+            used_by.as_deref(),
+            settings.backend.event_time(&line),
+            true,
+        )? {
+            // Run the main vacuum logic only if a new image came in.
+            vacuum(settings, &mut state, *first_run, threshold)?;
+        }
+
+        // Persist the state.
+        state::save(&state, settings.endpoint_name.as_deref())?;
+
+        // Inform the user that we're done for now.
+        debug!("Going back to sleep\u{2026}");
+    }
+
+    // The loop above will only terminate if something happened to the events process.
+    Err(io::Error::other(format!(
+        "{} terminated.",
+        settings.backend.events_command().code_str(),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            base_image_matches, block_image, construct_polyforest, digest_matches,
+            forget_deleted_image, forget_missing_images, free_space_limit, from_only_registries,
+            image_id_matches, is_pause_image, protected_registry, protection, registry,
+            repository_tag_wanted, sort_image_nodes, stage_image_nodes, target_limit, touch_image,
+            update_container_groups, ImageNode, KubeletLimits, Protection, ProtectionContext,
+            ProtectionTimeStamps,
+        },
+        crate::{
+            backend::{
+                Backend, ImageRecord, RepositoryTag, Runtime, PROTECTION_LABEL_KEY,
+                PROTECTION_LABEL_VALUE,
+            },
+            eviction,
+            kubernetes::kubelet_thresholds_from_json,
+            pipeline::{Stage, StageKind},
+            state::{self, State},
+            Settings, Threshold,
+        },
+        byte_unit::Byte,
+        regex::RegexSet,
+        std::{
+            collections::{BTreeSet, HashMap, HashSet},
+            io,
+            str::FromStr,
+            time::Duration,
+        },
+    };
+
+    fn state_image(parent_id: Option<String>, last_used_since_epoch: u64) -> state::Image {
+        state::Image {
+            parent_id,
+            last_used_since_epoch: Duration::from_secs(last_used_since_epoch),
+            pulled_since_epoch: None,
+            uses: 0,
+            frequency: 0.0,
+            containers: vec![],
+        }
+    }
+
+    #[test]
+    fn construct_polyforest_empty() -> io::Result<()> {
+        let state = State {
+            images: HashMap::new(),
+            ..state::initial()
+        };
+
+        let image_records = HashMap::new();
+        let image_ids_in_use = HashSet::new();
+        let image_graph = construct_polyforest(&state, true, &image_records, &image_ids_in_use)?;
+
+        assert_eq!(0, image_graph.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn construct_polyforest_single_image() -> io::Result<()> {
+        let image_id = "id-0";
+
+        let mut images = HashMap::new();
+        images.insert(
+            image_id.to_owned(),
+            state::Image {
+                parent_id: None,
+                last_used_since_epoch: Duration::from_secs(42),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                containers: vec![],
+            },
+        );
+
+        let state = State {
+            images,
+            ..state::initial()
+        };
+
+        let image_record = ImageRecord {
+            parent_id: None,
+            created_since_epoch: Duration::from_secs(100),
+            repository_tags: vec![RepositoryTag {
+                repository: String::from("alpine"),
+                tag: String::from("latest"),
+            }],
+            repository_digests: vec![],
+            labels: HashMap::new(),
+            size: Byte::from_bytes(0),
+        };
+
+        let mut image_records = HashMap::new();
+        image_records.insert(image_id.to_owned(), image_record.clone());
+        let image_ids_in_use = HashSet::new();
+        let image_graph = construct_polyforest(&state, true, &image_records, &image_ids_in_use)?;
+
+        assert_eq!(1, image_graph.len());
+
+        assert_eq!(
+            Some(&ImageNode {
+                image_record,
+                last_used_since_epoch: Duration::from_secs(42),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                ancestors: 0,
+            }),
+            image_graph.get(image_id),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn construct_polyforest_single_image_missing_state() -> io::Result<()> {
+        let image_id = "id-0";
+        let images = HashMap::new();
+        let state = State {
+            images,
+            ..state::initial()
+        };
+
+        let image_record = ImageRecord {
+            parent_id: None,
+            created_since_epoch: Duration::from_secs(100),
+            repository_tags: vec![RepositoryTag {
+                repository: String::from("alpine"),
+                tag: String::from("latest"),
+            }],
+            repository_digests: vec![],
+            labels: HashMap::new(),
+            size: Byte::from_bytes(0),
+        };
+
+        let mut image_records = HashMap::new();
+        image_records.insert(image_id.to_owned(), image_record.clone());
+        let image_ids_in_use = HashSet::new();
+        let image_graph = construct_polyforest(&state, true, &image_records, &image_ids_in_use)?;
+
+        assert_eq!(1, image_graph.len());
+
+        assert_eq!(
+            Some(&ImageNode {
+                image_record,
+                last_used_since_epoch: Duration::from_secs(100),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                ancestors: 0,
+            }),
+            image_graph.get(image_id),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn construct_polyforest_new_image_after_first_run() -> io::Result<()> {
+        let image_id = "id-0";
+        let state = state::initial();
+
+        let image_record = ImageRecord {
+            parent_id: None,
+            created_since_epoch: Duration::from_secs(100),
+            repository_tags: vec![RepositoryTag {
+                repository: String::from("alpine"),
+                tag: String::from("latest"),
+            }],
+            repository_digests: vec![],
+            labels: HashMap::new(),
+            size: Byte::from_bytes(0),
+        };
+
+        let mut image_records = HashMap::new();
+        image_records.insert(image_id.to_owned(), image_record);
+        let image_ids_in_use = HashSet::new();
+        let image_graph = construct_polyforest(&state, false, &image_records, &image_ids_in_use)?;
+
+        // The image is considered to have been pulled just now, when it was first seen.
+        let image_node = image_graph.get(image_id).unwrap();
+        assert_eq!(
+            image_node.pulled_since_epoch,
+            Some(image_node.last_used_since_epoch),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn construct_polyforest_parent_child_increasing_timestamps() -> io::Result<()> {
+        let image_id_0 = "id-0";
+        let image_id_1 = "id-1";
+
+        let mut images = HashMap::new();
+        images.insert(
+            image_id_0.to_owned(),
+            state::Image {
+                parent_id: None,
+                last_used_since_epoch: Duration::from_secs(42),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                containers: vec![],
+            },
+        );
+        images.insert(
+            image_id_1.to_owned(),
+            state::Image {
+                parent_id: Some(image_id_0.to_owned()),
+                last_used_since_epoch: Duration::from_secs(43),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                containers: vec![],
+            },
+        );
+
+        let state = State {
+            images,
+            ..state::initial()
+        };
+
+        let image_record_0 = ImageRecord {
+            parent_id: None,
+            created_since_epoch: Duration::from_secs(100),
+            repository_tags: vec![RepositoryTag {
+                repository: String::from("alpine"),
+                tag: String::from("latest"),
+            }],
+            repository_digests: vec![],
+            labels: HashMap::new(),
+            size: Byte::from_bytes(0),
+        };
+
+        let image_record_1 = ImageRecord {
+            parent_id: Some(image_id_0.to_owned()),
+            created_since_epoch: Duration::from_secs(101),
+            repository_tags: vec![RepositoryTag {
+                repository: String::from("debian"),
+                tag: String::from("latest"),
+            }],
+            repository_digests: vec![],
+            labels: HashMap::new(),
+            size: Byte::from_bytes(0),
+        };
+
+        let mut image_records = HashMap::new();
+        image_records.insert(image_id_0.to_owned(), image_record_0.clone());
+        image_records.insert(image_id_1.to_owned(), image_record_1.clone());
+        let image_ids_in_use = HashSet::new();
+        let image_graph = construct_polyforest(&state, true, &image_records, &image_ids_in_use)?;
+
+        assert_eq!(2, image_graph.len());
+
+        assert_eq!(
+            Some(&ImageNode {
+                image_record: image_record_0,
+                last_used_since_epoch: Duration::from_secs(43),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                ancestors: 0,
+            }),
+            image_graph.get(image_id_0),
+        );
+
+        assert_eq!(
+            Some(&ImageNode {
+                image_record: image_record_1,
+                last_used_since_epoch: Duration::from_secs(43),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                ancestors: 1,
+            }),
+            image_graph.get(image_id_1),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn construct_polyforest_parent_child_decreasing_timestamps() -> io::Result<()> {
+        let image_id_0 = "id-0";
+        let image_id_1 = "id-1";
+
+        let mut images = HashMap::new();
+        images.insert(
+            image_id_0.to_owned(),
+            state::Image {
+                parent_id: None,
+                last_used_since_epoch: Duration::from_secs(43),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                containers: vec![],
+            },
+        );
+        images.insert(
+            image_id_1.to_owned(),
+            state::Image {
+                parent_id: Some(image_id_0.to_owned()),
+                last_used_since_epoch: Duration::from_secs(42),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                containers: vec![],
+            },
+        );
+
+        let state = State {
+            images,
+            ..state::initial()
+        };
+
+        let image_record_0 = ImageRecord {
+            parent_id: None,
+            created_since_epoch: Duration::from_secs(100),
+            repository_tags: vec![RepositoryTag {
+                repository: String::from("alpine"),
+                tag: String::from("latest"),
+            }],
+            repository_digests: vec![],
+            labels: HashMap::new(),
+            size: Byte::from_bytes(0),
+        };
+
+        let image_record_1 = ImageRecord {
+            parent_id: Some(image_id_0.to_owned()),
+            created_since_epoch: Duration::from_secs(101),
+            repository_tags: vec![RepositoryTag {
+                repository: String::from("debian"),
+                tag: String::from("latest"),
+            }],
+            repository_digests: vec![],
+            labels: HashMap::new(),
+            size: Byte::from_bytes(0),
+        };
+
+        let mut image_records = HashMap::new();
+        image_records.insert(image_id_0.to_owned(), image_record_0.clone());
+        image_records.insert(image_id_1.to_owned(), image_record_1.clone());
+        let image_ids_in_use = HashSet::new();
+        let image_graph = construct_polyforest(&state, true, &image_records, &image_ids_in_use)?;
 
         assert_eq!(2, image_graph.len());
 
         assert_eq!(
             Some(&ImageNode {
                 image_record: image_record_0,
-                last_used_since_epoch: Duration::from_secs(43),
+                last_used_since_epoch: Duration::from_secs(43),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                ancestors: 0,
+            }),
+            image_graph.get(image_id_0),
+        );
+
+        assert_eq!(
+            Some(&ImageNode {
+                image_record: image_record_1,
+                last_used_since_epoch: Duration::from_secs(42),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                ancestors: 1,
+            }),
+            image_graph.get(image_id_1),
+        );
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_lines)]
+    #[test]
+    fn construct_polyforest_grandparent_parent_child_increasing_timestamps() -> io::Result<()> {
+        let image_id_0 = "id-0";
+        let image_id_1 = "id-1";
+        let image_id_2 = "id-2";
+
+        let mut images = HashMap::new();
+        images.insert(
+            image_id_0.to_owned(),
+            state::Image {
+                parent_id: None,
+                last_used_since_epoch: Duration::from_secs(42),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                containers: vec![],
+            },
+        );
+        images.insert(
+            image_id_1.to_owned(),
+            state::Image {
+                parent_id: Some(image_id_0.to_owned()),
+                last_used_since_epoch: Duration::from_secs(43),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                containers: vec![],
+            },
+        );
+        images.insert(
+            image_id_2.to_owned(),
+            state::Image {
+                parent_id: Some(image_id_1.to_owned()),
+                last_used_since_epoch: Duration::from_secs(44),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                containers: vec![],
+            },
+        );
+
+        let state = State {
+            images,
+            ..state::initial()
+        };
+
+        let image_record_0 = ImageRecord {
+            parent_id: None,
+            created_since_epoch: Duration::from_secs(100),
+            repository_tags: vec![RepositoryTag {
+                repository: String::from("alpine"),
+                tag: String::from("latest"),
+            }],
+            repository_digests: vec![],
+            labels: HashMap::new(),
+            size: Byte::from_bytes(0),
+        };
+
+        let image_record_1 = ImageRecord {
+            parent_id: Some(image_id_0.to_owned()),
+            created_since_epoch: Duration::from_secs(101),
+            repository_tags: vec![RepositoryTag {
+                repository: String::from("debian"),
+                tag: String::from("latest"),
+            }],
+            repository_digests: vec![],
+            labels: HashMap::new(),
+            size: Byte::from_bytes(0),
+        };
+
+        let image_record_2 = ImageRecord {
+            parent_id: Some(image_id_1.to_owned()),
+            created_since_epoch: Duration::from_secs(102),
+            repository_tags: vec![RepositoryTag {
+                repository: String::from("ubuntu"),
+                tag: String::from("latest"),
+            }],
+            repository_digests: vec![],
+            labels: HashMap::new(),
+            size: Byte::from_bytes(0),
+        };
+
+        let mut image_records = HashMap::new();
+        image_records.insert(image_id_0.to_owned(), image_record_0.clone());
+        image_records.insert(image_id_1.to_owned(), image_record_1.clone());
+        image_records.insert(image_id_2.to_owned(), image_record_2.clone());
+        let image_ids_in_use = HashSet::new();
+        let image_graph = construct_polyforest(&state, true, &image_records, &image_ids_in_use)?;
+
+        assert_eq!(3, image_graph.len());
+
+        assert_eq!(
+            Some(&ImageNode {
+                image_record: image_record_0,
+                last_used_since_epoch: Duration::from_secs(44),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                ancestors: 0,
+            }),
+            image_graph.get(image_id_0),
+        );
+
+        assert_eq!(
+            Some(&ImageNode {
+                image_record: image_record_1,
+                last_used_since_epoch: Duration::from_secs(44),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                ancestors: 1,
+            }),
+            image_graph.get(image_id_1),
+        );
+
+        assert_eq!(
+            Some(&ImageNode {
+                image_record: image_record_2,
+                last_used_since_epoch: Duration::from_secs(44),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                ancestors: 2,
+            }),
+            image_graph.get(image_id_2),
+        );
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_lines)]
+    #[test]
+    fn construct_polyforest_grandparent_parent_child_decreasing_timestamps() -> io::Result<()> {
+        let image_id_0 = "id-0";
+        let image_id_1 = "id-1";
+        let image_id_2 = "id-2";
+
+        let mut images = HashMap::new();
+        images.insert(
+            image_id_0.to_owned(),
+            state::Image {
+                parent_id: None,
+                last_used_since_epoch: Duration::from_secs(44),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                containers: vec![],
+            },
+        );
+        images.insert(
+            image_id_1.to_owned(),
+            state::Image {
+                parent_id: Some(image_id_0.to_owned()),
+                last_used_since_epoch: Duration::from_secs(43),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                containers: vec![],
+            },
+        );
+        images.insert(
+            image_id_2.to_owned(),
+            state::Image {
+                parent_id: Some(image_id_1.to_owned()),
+                last_used_since_epoch: Duration::from_secs(42),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                containers: vec![],
+            },
+        );
+
+        let state = State {
+            images,
+            ..state::initial()
+        };
+
+        let image_record_0 = ImageRecord {
+            parent_id: None,
+            created_since_epoch: Duration::from_secs(100),
+            repository_tags: vec![RepositoryTag {
+                repository: String::from("alpine"),
+                tag: String::from("latest"),
+            }],
+            repository_digests: vec![],
+            labels: HashMap::new(),
+            size: Byte::from_bytes(0),
+        };
+
+        let image_record_1 = ImageRecord {
+            parent_id: Some(image_id_0.to_owned()),
+            created_since_epoch: Duration::from_secs(101),
+            repository_tags: vec![RepositoryTag {
+                repository: String::from("debian"),
+                tag: String::from("latest"),
+            }],
+            repository_digests: vec![],
+            labels: HashMap::new(),
+            size: Byte::from_bytes(0),
+        };
+
+        let image_record_2 = ImageRecord {
+            parent_id: Some(image_id_1.to_owned()),
+            created_since_epoch: Duration::from_secs(102),
+            repository_tags: vec![RepositoryTag {
+                repository: String::from("ubuntu"),
+                tag: String::from("latest"),
+            }],
+            repository_digests: vec![],
+            labels: HashMap::new(),
+            size: Byte::from_bytes(0),
+        };
+
+        let mut image_records = HashMap::new();
+        image_records.insert(image_id_0.to_owned(), image_record_0.clone());
+        image_records.insert(image_id_1.to_owned(), image_record_1.clone());
+        image_records.insert(image_id_2.to_owned(), image_record_2.clone());
+        let image_ids_in_use = HashSet::new();
+        let image_graph = construct_polyforest(&state, true, &image_records, &image_ids_in_use)?;
+
+        assert_eq!(3, image_graph.len());
+
+        assert_eq!(
+            Some(&ImageNode {
+                image_record: image_record_0,
+                last_used_since_epoch: Duration::from_secs(44),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
                 ancestors: 0,
             }),
             image_graph.get(image_id_0),
@@ -1072,18 +3905,35 @@ mod tests {
             Some(&ImageNode {
                 image_record: image_record_1,
                 last_used_since_epoch: Duration::from_secs(43),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
                 ancestors: 1,
             }),
             image_graph.get(image_id_1),
         );
 
+        assert_eq!(
+            Some(&ImageNode {
+                image_record: image_record_2,
+                last_used_since_epoch: Duration::from_secs(42),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                ancestors: 2,
+            }),
+            image_graph.get(image_id_2),
+        );
+
         Ok(())
     }
 
+    #[allow(clippy::too_many_lines)]
     #[test]
-    fn construct_polyforest_parent_child_decreasing_timestamps() -> io::Result<()> {
+    fn construct_polyforest_multiple_children() -> io::Result<()> {
         let image_id_0 = "id-0";
         let image_id_1 = "id-1";
+        let image_id_2 = "id-2";
 
         let mut images = HashMap::new();
         images.insert(
@@ -1091,6 +3941,10 @@ mod tests {
             state::Image {
                 parent_id: None,
                 last_used_since_epoch: Duration::from_secs(43),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                containers: vec![],
             },
         );
         images.insert(
@@ -1098,10 +3952,28 @@ mod tests {
             state::Image {
                 parent_id: Some(image_id_0.to_owned()),
                 last_used_since_epoch: Duration::from_secs(42),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                containers: vec![],
+            },
+        );
+        images.insert(
+            image_id_2.to_owned(),
+            state::Image {
+                parent_id: Some(image_id_0.to_owned()),
+                last_used_since_epoch: Duration::from_secs(44),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                containers: vec![],
             },
         );
 
-        let state = State { images };
+        let state = State {
+            images,
+            ..state::initial()
+        };
 
         let image_record_0 = ImageRecord {
             parent_id: None,
@@ -1110,6 +3982,9 @@ mod tests {
                 repository: String::from("alpine"),
                 tag: String::from("latest"),
             }],
+            repository_digests: vec![],
+            labels: HashMap::new(),
+            size: Byte::from_bytes(0),
         };
 
         let image_record_1 = ImageRecord {
@@ -1119,20 +3994,39 @@ mod tests {
                 repository: String::from("debian"),
                 tag: String::from("latest"),
             }],
+            repository_digests: vec![],
+            labels: HashMap::new(),
+            size: Byte::from_bytes(0),
+        };
+
+        let image_record_2 = ImageRecord {
+            parent_id: Some(image_id_0.to_owned()),
+            created_since_epoch: Duration::from_secs(102),
+            repository_tags: vec![RepositoryTag {
+                repository: String::from("ubuntu"),
+                tag: String::from("latest"),
+            }],
+            repository_digests: vec![],
+            labels: HashMap::new(),
+            size: Byte::from_bytes(0),
         };
 
         let mut image_records = HashMap::new();
         image_records.insert(image_id_0.to_owned(), image_record_0.clone());
         image_records.insert(image_id_1.to_owned(), image_record_1.clone());
+        image_records.insert(image_id_2.to_owned(), image_record_2.clone());
         let image_ids_in_use = HashSet::new();
         let image_graph = construct_polyforest(&state, true, &image_records, &image_ids_in_use)?;
 
-        assert_eq!(2, image_graph.len());
+        assert_eq!(3, image_graph.len());
 
         assert_eq!(
             Some(&ImageNode {
                 image_record: image_record_0,
-                last_used_since_epoch: Duration::from_secs(43),
+                last_used_since_epoch: Duration::from_secs(44),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
                 ancestors: 0,
             }),
             image_graph.get(image_id_0),
@@ -1142,300 +4036,881 @@ mod tests {
             Some(&ImageNode {
                 image_record: image_record_1,
                 last_used_since_epoch: Duration::from_secs(42),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
                 ancestors: 1,
             }),
             image_graph.get(image_id_1),
         );
 
+        assert_eq!(
+            Some(&ImageNode {
+                image_record: image_record_2,
+                last_used_since_epoch: Duration::from_secs(44),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                ancestors: 1,
+            }),
+            image_graph.get(image_id_2),
+        );
+
         Ok(())
     }
 
     #[test]
-    fn construct_polyforest_grandparent_parent_child_increasing_timestamps() -> io::Result<()> {
-        let image_id_0 = "id-0";
-        let image_id_1 = "id-1";
-        let image_id_2 = "id-2";
+    fn free_space_limit_enough_free() {
+        assert_eq!(
+            free_space_limit(
+                Byte::from_bytes(10),
+                Byte::from_bytes(50),
+                Byte::from_bytes(20),
+            ),
+            Byte::from_bytes(40),
+        );
+    }
+
+    #[test]
+    fn free_space_limit_not_enough_free() {
+        assert_eq!(
+            free_space_limit(
+                Byte::from_bytes(10),
+                Byte::from_bytes(5),
+                Byte::from_bytes(20),
+            ),
+            Byte::from_bytes(0),
+        );
+    }
+
+    #[test]
+    fn target_limit_none() {
+        let settings = Settings::new(Runtime::Docker, Threshold::from_str("80 GB").unwrap());
+
+        assert_eq!(
+            target_limit(
+                &settings,
+                Byte::from_bytes(90_000_000_000),
+                Byte::from_bytes(80_000_000_000),
+            )
+            .unwrap(),
+            Byte::from_bytes(80_000_000_000),
+        );
+    }
+
+    #[test]
+    fn target_limit_below_threshold() {
+        let mut settings = Settings::new(Runtime::Docker, Threshold::from_str("80 GB").unwrap());
+        settings.target = Some(Threshold::from_str("60 GB").unwrap());
+
+        assert_eq!(
+            target_limit(
+                &settings,
+                Byte::from_bytes(90_000_000_000),
+                Byte::from_bytes(80_000_000_000),
+            )
+            .unwrap(),
+            Byte::from_bytes(60_000_000_000),
+        );
+    }
+
+    #[test]
+    fn target_limit_above_threshold() {
+        let mut settings = Settings::new(Runtime::Docker, Threshold::from_str("80 GB").unwrap());
+        settings.target = Some(Threshold::from_str("100 GB").unwrap());
+
+        assert_eq!(
+            target_limit(
+                &settings,
+                Byte::from_bytes(90_000_000_000),
+                Byte::from_bytes(80_000_000_000),
+            )
+            .unwrap(),
+            Byte::from_bytes(80_000_000_000),
+        );
+    }
+
+    fn kubelet_limits(configz: &str) -> KubeletLimits {
+        KubeletLimits::new(
+            "node-0",
+            &kubelet_thresholds_from_json(configz.as_bytes()).unwrap(),
+            Byte::from_bytes(100_000_000_000),
+        )
+    }
+
+    #[test]
+    fn kubelet_limits_defaults() {
+        let kubelet_limits = kubelet_limits(r#"{"kubeletconfig":{}}"#);
+
+        assert_eq!(
+            kubelet_limits.defaults(false),
+            (
+                Threshold::Free(Byte::from_bytes(15_000_000_000)),
+                Threshold::Free(Byte::from_bytes(20_000_000_000)),
+            ),
+        );
+        assert_eq!(
+            kubelet_limits.defaults(true),
+            (
+                Threshold::Free(Byte::from_bytes(16_000_000_000)),
+                Threshold::Free(Byte::from_bytes(20_000_000_000)),
+            ),
+        );
+        assert_eq!(
+            kubelet_limits.coordinated(),
+            Threshold::Free(Byte::from_bytes(16_000_000_000)),
+        );
+    }
 
+    #[test]
+    fn kubelet_limits_percent_eviction() {
+        let kubelet_limits = kubelet_limits(
+            r#"{
+                "kubeletconfig": {
+                    "imageGCHighThresholdPercent": 90,
+                    "imageGCLowThresholdPercent": 70,
+                    "evictionHard": { "imagefs.available": "20%" }
+                }
+            }"#,
+        );
+
+        assert_eq!(
+            kubelet_limits.defaults(false),
+            (
+                Threshold::Free(Byte::from_bytes(10_000_000_000)),
+                Threshold::Free(Byte::from_bytes(30_000_000_000)),
+            ),
+        );
+        assert_eq!(
+            kubelet_limits.defaults(true),
+            (
+                Threshold::Free(Byte::from_bytes(21_000_000_000)),
+                Threshold::Free(Byte::from_bytes(30_000_000_000)),
+            ),
+        );
+    }
+
+    #[test]
+    fn kubelet_limits_absolute_eviction() {
+        let kubelet_limits = kubelet_limits(
+            r#"{
+                "kubeletconfig": {
+                    "evictionHard": { "nodefs.available": "1Gi" },
+                    "evictionSoft": { "imagefs.available": "20Gi" }
+                }
+            }"#,
+        );
+
+        assert_eq!(
+            kubelet_limits.defaults(false),
+            (
+                Threshold::Free(Byte::from_bytes(15_000_000_000)),
+                Threshold::Free(Byte::from_bytes(20_000_000_000)),
+            ),
+        );
+        assert_eq!(
+            kubelet_limits.coordinated(),
+            Threshold::Free(Byte::from_bytes(20 * 1024 * 1024 * 1024 + 1_000_000_000)),
+        );
+    }
+
+    #[test]
+    fn kubelet_limits_conflicts_with() {
+        let kubelet_limits = kubelet_limits(r#"{"kubeletconfig":{}}"#);
+
+        // Thresholds which are equal to the kubelet's conflict with them.
+        assert!(kubelet_limits.conflicts_with(Threshold::Free(Byte::from_bytes(15_000_000_000))));
+        assert!(
+            kubelet_limits.conflicts_with(Threshold::Absolute(Byte::from_bytes(85_000_000_000))),
+        );
+        assert!(kubelet_limits.conflicts_with(Threshold::Percentage(0.85)));
+
+        assert!(!kubelet_limits.conflicts_with(Threshold::Free(Byte::from_bytes(16_000_000_000))));
+        assert!(
+            !kubelet_limits.conflicts_with(Threshold::Absolute(Byte::from_bytes(84_000_000_000))),
+        );
+        assert!(!kubelet_limits.conflicts_with(Threshold::Percentage(0.8)));
+    }
+
+    fn state_with_missing_images() -> State {
         let mut images = HashMap::new();
-        images.insert(
-            image_id_0.to_owned(),
-            state::Image {
-                parent_id: None,
-                last_used_since_epoch: Duration::from_secs(42),
+        for (image_id, last_used_secs) in [("id-0", 10), ("id-1", 20), ("id-2", 30)] {
+            images.insert(
+                image_id.to_owned(),
+                state::Image {
+                    parent_id: None,
+                    last_used_since_epoch: Duration::from_secs(last_used_secs),
+                    pulled_since_epoch: None,
+                    uses: 0,
+                    frequency: 0.0,
+                    containers: vec![],
+                },
+            );
+        }
+
+        State {
+            images,
+            ..state::initial()
+        }
+    }
+
+    fn polyforest_with_existing_image() -> HashMap<String, ImageNode> {
+        let mut polyforest = HashMap::new();
+        polyforest.insert(
+            "id-0".to_owned(),
+            ImageNode {
+                image_record: ImageRecord {
+                    parent_id: None,
+                    created_since_epoch: Duration::from_secs(5),
+                    repository_tags: vec![],
+                    repository_digests: vec![],
+                    labels: HashMap::new(),
+                    size: Byte::from_bytes(0),
+                },
+                last_used_since_epoch: Duration::from_secs(10),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                ancestors: 0,
             },
         );
-        images.insert(
-            image_id_1.to_owned(),
-            state::Image {
-                parent_id: Some(image_id_0.to_owned()),
-                last_used_since_epoch: Duration::from_secs(43),
-            },
+        polyforest
+    }
+
+    #[test]
+    fn forget_missing_images_immediately() {
+        let mut state = state_with_missing_images();
+
+        assert_eq!(
+            forget_missing_images(&mut state, &polyforest_with_existing_image(), None),
+            2,
         );
-        images.insert(
-            image_id_2.to_owned(),
-            state::Image {
-                parent_id: Some(image_id_1.to_owned()),
-                last_used_since_epoch: Duration::from_secs(44),
+        assert_eq!(
+            state.images.keys().collect::<Vec<_>>(),
+            vec![&"id-0".to_owned()],
+        );
+    }
+
+    #[test]
+    fn forget_missing_images_after_grace_period() {
+        let mut state = state_with_missing_images();
+
+        assert_eq!(
+            forget_missing_images(
+                &mut state,
+                &polyforest_with_existing_image(),
+                Some(Duration::from_secs(25)),
+            ),
+            1,
+        );
+        assert!(state.images.contains_key("id-0"));
+        assert!(!state.images.contains_key("id-1"));
+        assert!(state.images.contains_key("id-2"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn filesystem_free_inodes_root() {
+        assert!(super::filesystem_free_inodes(std::path::Path::new("/")).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn filesystem_space_root() {
+        let (size, free_space) = super::filesystem_space(std::path::Path::new("/")).unwrap();
+
+        assert!(size.get_bytes() > 0);
+        assert!(free_space <= size);
+    }
+
+    #[test]
+    fn forget_deleted_image_external() {
+        let settings = Settings::new(Runtime::Docker, Threshold::from_str("10 GB").unwrap());
+        let mut state = state_with_missing_images();
+
+        assert!(forget_deleted_image(&settings, &mut state, "id-1"));
+        assert!(!forget_deleted_image(&settings, &mut state, "id-1"));
+        assert!(!state.images.contains_key("id-1"));
+        assert_eq!(state.images.len(), 2);
+    }
+
+    #[test]
+    fn forget_deleted_image_remembered() {
+        let mut settings = Settings::new(Runtime::Docker, Threshold::from_str("10 GB").unwrap());
+        settings.forget_missing_after = Some(Duration::from_secs(25));
+        let mut state = state_with_missing_images();
+
+        assert!(!forget_deleted_image(&settings, &mut state, "id-1"));
+        assert!(state.images.contains_key("id-1"));
+    }
+
+    fn polyforest_with_lineage() -> HashMap<String, ImageNode> {
+        let mut polyforest = HashMap::new();
+        for (image_id, parent_id, ancestors) in [
+            ("id-0", None, 0),
+            ("id-1", Some("id-0"), 1),
+            ("id-2", Some("id-1"), 2),
+            ("id-3", Some("id-0"), 1),
+        ] {
+            polyforest.insert(
+                image_id.to_owned(),
+                ImageNode {
+                    image_record: ImageRecord {
+                        parent_id: parent_id.map(ToOwned::to_owned),
+                        created_since_epoch: Duration::from_secs(5),
+                        repository_tags: vec![],
+                        repository_digests: vec![],
+                        labels: HashMap::new(),
+                        size: Byte::from_bytes(0),
+                    },
+                    last_used_since_epoch: Duration::from_secs(10),
+                    pulled_since_epoch: None,
+                    uses: 0,
+                    frequency: 0.0,
+                    ancestors,
+                },
+            );
+        }
+        polyforest
+    }
+
+    #[test]
+    fn block_image_ancestors() {
+        let mut blocked_image_ids = HashSet::new();
+        block_image(&polyforest_with_lineage(), "id-2", &mut blocked_image_ids);
+
+        assert_eq!(
+            blocked_image_ids,
+            ["id-0", "id-1", "id-2"]
+                .into_iter()
+                .map(ToOwned::to_owned)
+                .collect::<HashSet<_>>(),
+        );
+    }
+
+    #[test]
+    fn block_image_missing_parent() {
+        let mut polyforest = polyforest_with_lineage();
+        polyforest.remove("id-0");
+        let mut blocked_image_ids = HashSet::new();
+        block_image(&polyforest, "id-3", &mut blocked_image_ids);
+
+        assert_eq!(
+            blocked_image_ids,
+            ["id-0", "id-3"]
+                .into_iter()
+                .map(ToOwned::to_owned)
+                .collect::<HashSet<_>>(),
+        );
+    }
+
+    #[test]
+    fn update_container_groups_remembers_and_forgets() {
+        let mut compose_projects = HashMap::new();
+        for (project, last_active_secs) in [("idle", 10), ("down", 30)] {
+            compose_projects.insert(
+                project.to_owned(),
+                state::ContainerGroup {
+                    image_ids: vec![format!("id-{project}")],
+                    last_active_since_epoch: Duration::from_secs(last_active_secs),
+                },
+            );
+        }
+
+        let mut current_image_ids = HashMap::new();
+        current_image_ids.insert(
+            "up".to_owned(),
+            ["id-1", "id-0"]
+                .into_iter()
+                .map(ToOwned::to_owned)
+                .collect::<HashSet<_>>(),
+        );
+
+        update_container_groups(
+            &mut compose_projects,
+            current_image_ids,
+            Duration::from_secs(40),
+            Duration::from_secs(20),
+            "Docker Compose project",
+        );
+
+        assert!(!compose_projects.contains_key("idle"));
+        assert_eq!(
+            compose_projects["down"].last_active_since_epoch,
+            Duration::from_secs(30),
+        );
+        assert_eq!(
+            compose_projects["up"],
+            state::ContainerGroup {
+                image_ids: vec!["id-0".to_owned(), "id-1".to_owned()],
+                last_active_since_epoch: Duration::from_secs(40),
             },
         );
+    }
 
-        let state = State { images };
+    #[test]
+    fn digest_matches_repository_digest() {
+        assert!(digest_matches("alpine@sha256:0123", "alpine@sha256:0123"));
+        assert!(!digest_matches("alpine@sha256:0123", "alpine@sha256:4567"));
+        assert!(!digest_matches("debian@sha256:0123", "alpine@sha256:0123"));
+    }
 
-        let image_record_0 = ImageRecord {
+    #[test]
+    fn digest_matches_bare_digest() {
+        assert!(digest_matches("sha256:0123", "alpine@sha256:0123"));
+        assert!(!digest_matches("sha256:0123", "alpine@sha256:4567"));
+    }
+
+    #[test]
+    fn digest_matches_docker_hub() {
+        assert!(digest_matches(
+            "docker.io/library/alpine@sha256:0123",
+            "alpine@sha256:0123",
+        ));
+        assert!(digest_matches(
+            "alpine@sha256:0123",
+            "docker.io/library/alpine@sha256:0123",
+        ));
+        assert!(!digest_matches(
+            "ghcr.io/library/alpine@sha256:0123",
+            "alpine@sha256:0123",
+        ));
+    }
+
+    fn base_image_record(
+        repository_tags: &[(&str, &str)],
+        repository_digests: &[&str],
+    ) -> ImageRecord {
+        ImageRecord {
             parent_id: None,
-            created_since_epoch: Duration::from_secs(100),
-            repository_tags: vec![RepositoryTag {
-                repository: String::from("alpine"),
-                tag: String::from("latest"),
-            }],
-        };
+            created_since_epoch: Duration::ZERO,
+            repository_tags: repository_tags
+                .iter()
+                .map(|(repository, tag)| RepositoryTag {
+                    repository: (*repository).to_owned(),
+                    tag: (*tag).to_owned(),
+                })
+                .collect(),
+            repository_digests: repository_digests
+                .iter()
+                .map(|repository_digest| (*repository_digest).to_owned())
+                .collect(),
+            labels: HashMap::new(),
+            size: Byte::from_bytes(0),
+        }
+    }
 
-        let image_record_1 = ImageRecord {
-            parent_id: Some(image_id_0.to_owned()),
-            created_since_epoch: Duration::from_secs(101),
-            repository_tags: vec![RepositoryTag {
-                repository: String::from("debian"),
-                tag: String::from("latest"),
-            }],
-        };
+    #[test]
+    fn base_image_matches_tag() {
+        let image_record = base_image_record(&[("alpine", "3.20")], &[]);
 
-        let image_record_2 = ImageRecord {
-            parent_id: Some(image_id_1.to_owned()),
-            created_since_epoch: Duration::from_secs(102),
-            repository_tags: vec![RepositoryTag {
-                repository: String::from("ubuntu"),
-                tag: String::from("latest"),
-            }],
-        };
+        assert!(base_image_matches("alpine:3.20", &image_record));
+        assert!(base_image_matches(
+            "docker.io/library/alpine:3.20",
+            &image_record,
+        ));
+        assert!(!base_image_matches("alpine:3.19", &image_record));
+        assert!(!base_image_matches("alpine", &image_record));
+    }
 
-        let mut image_records = HashMap::new();
-        image_records.insert(image_id_0.to_owned(), image_record_0.clone());
-        image_records.insert(image_id_1.to_owned(), image_record_1.clone());
-        image_records.insert(image_id_2.to_owned(), image_record_2.clone());
-        let image_ids_in_use = HashSet::new();
-        let image_graph = construct_polyforest(&state, true, &image_records, &image_ids_in_use)?;
+    #[test]
+    fn base_image_matches_implicit_tag() {
+        let image_record = base_image_record(&[("localhost:5000/app", "latest")], &[]);
 
-        assert_eq!(3, image_graph.len());
+        assert!(base_image_matches("localhost:5000/app", &image_record));
+        assert!(!base_image_matches("localhost:5000/app:1.0", &image_record));
+    }
+
+    #[test]
+    fn base_image_matches_digest() {
+        let image_record = base_image_record(&[("alpine", "latest")], &["alpine@sha256:0123"]);
+
+        assert!(base_image_matches("alpine@sha256:0123", &image_record));
+        assert!(base_image_matches("alpine:3.20@sha256:0123", &image_record));
+        assert!(!base_image_matches(
+            "alpine:latest@sha256:4567",
+            &image_record,
+        ));
+    }
+
+    #[test]
+    fn registry_implicit() {
+        assert_eq!(registry("alpine").as_deref(), Some("docker.io"));
+        assert_eq!(registry("stepchowfun/docuum").as_deref(), Some("docker.io"));
+        assert_eq!(registry("<none>"), None);
+    }
 
+    #[test]
+    fn registry_explicit() {
         assert_eq!(
-            Some(&ImageNode {
-                image_record: image_record_0,
-                last_used_since_epoch: Duration::from_secs(44),
-                ancestors: 0,
-            }),
-            image_graph.get(image_id_0),
+            registry("registry.example.com:5000/team/app").as_deref(),
+            Some("registry.example.com:5000"),
         );
-
+        assert_eq!(registry("localhost/app").as_deref(), Some("localhost"));
         assert_eq!(
-            Some(&ImageNode {
-                image_record: image_record_1,
-                last_used_since_epoch: Duration::from_secs(44),
-                ancestors: 1,
-            }),
-            image_graph.get(image_id_1),
+            registry("index.docker.io/library/alpine").as_deref(),
+            Some("docker.io"),
         );
+    }
 
-        assert_eq!(
-            Some(&ImageNode {
-                image_record: image_record_2,
-                last_used_since_epoch: Duration::from_secs(44),
-                ancestors: 2,
-            }),
-            image_graph.get(image_id_2),
+    #[test]
+    fn protection_overridable() {
+        assert!(Protection::Keep("alpine:latest".to_owned()).overridable());
+        assert!(Protection::MinAge.overridable());
+        assert!(!Protection::Label.overridable());
+        assert!(!Protection::Pinned.overridable());
+        assert!(!Protection::RemoveOnly.overridable());
+        assert!(!Protection::OnlyRegistry.overridable());
+        assert!(!Protection::PullGrace.overridable());
+    }
+
+    #[test]
+    fn protection_keep_and_label() {
+        let mut settings = Settings::new(Runtime::Docker, Threshold::from_str("10 GB").unwrap());
+        settings.keep = Some(RegexSet::new(["^alpine:"]).unwrap());
+        let context = ProtectionContext {
+            time_stamps: ProtectionTimeStamps {
+                min_age: None,
+                pull_grace: None,
+            },
+            dockerfile_bases: HashSet::new(),
+            pinned_image_ids: BTreeSet::new(),
+            own_image_id: None,
+            runtime_pinned_image_ids: HashSet::new(),
+        };
+        let mut image_record = base_image_record(&[("alpine", "latest")], &[]);
+        image_record.labels.insert(
+            PROTECTION_LABEL_KEY.to_owned(),
+            PROTECTION_LABEL_VALUE.to_owned(),
         );
+        let image_node = ImageNode {
+            image_record,
+            last_used_since_epoch: Duration::ZERO,
+            pulled_since_epoch: None,
+            uses: 0,
+            frequency: 0.0,
+            ancestors: 0,
+        };
 
-        Ok(())
+        let protection = protection(&settings, &context, "abc", &image_node).unwrap();
+        assert_eq!(protection.cause(), Protection::Label.cause());
+        assert!(!protection.overridable());
     }
 
     #[test]
-    fn construct_polyforest_grandparent_parent_child_decreasing_timestamps() -> io::Result<()> {
-        let image_id_0 = "id-0";
-        let image_id_1 = "id-1";
-        let image_id_2 = "id-2";
+    fn protected_registry_tags_and_digests() {
+        let protect_registries = vec!["ghcr.io".to_owned(), "docker.io".to_owned()];
 
-        let mut images = HashMap::new();
-        images.insert(
-            image_id_0.to_owned(),
-            state::Image {
-                parent_id: None,
-                last_used_since_epoch: Duration::from_secs(44),
-            },
+        assert_eq!(
+            protected_registry(
+                &protect_registries,
+                &base_image_record(&[("ghcr.io/acme/app", "1.0")], &[]),
+            ),
+            Some(&"ghcr.io".to_owned()),
         );
-        images.insert(
-            image_id_1.to_owned(),
-            state::Image {
-                parent_id: Some(image_id_0.to_owned()),
-                last_used_since_epoch: Duration::from_secs(43),
-            },
+        assert_eq!(
+            protected_registry(
+                &protect_registries,
+                &base_image_record(&[("<none>", "<none>")], &["alpine@sha256:0123"]),
+            ),
+            Some(&"docker.io".to_owned()),
         );
-        images.insert(
-            image_id_2.to_owned(),
-            state::Image {
-                parent_id: Some(image_id_1.to_owned()),
-                last_used_since_epoch: Duration::from_secs(42),
-            },
+        assert_eq!(
+            protected_registry(
+                &protect_registries,
+                &base_image_record(&[("quay.io/acme/app", "1.0")], &[]),
+            ),
+            None,
         );
+    }
 
-        let state = State { images };
+    #[test]
+    fn from_only_registries_tags_and_digests() {
+        let only_registries = vec!["docker.io".to_owned()];
 
-        let image_record_0 = ImageRecord {
-            parent_id: None,
-            created_since_epoch: Duration::from_secs(100),
-            repository_tags: vec![RepositoryTag {
-                repository: String::from("alpine"),
-                tag: String::from("latest"),
-            }],
-        };
+        assert!(from_only_registries(
+            &only_registries,
+            &base_image_record(&[("ghcr.io/acme/app", "1.0")], &["alpine@sha256:0123"]),
+        ));
+        assert!(!from_only_registries(
+            &only_registries,
+            &base_image_record(&[("ghcr.io/acme/app", "1.0")], &[]),
+        ));
+        assert!(!from_only_registries(
+            &only_registries,
+            &base_image_record(&[("<none>", "<none>")], &[]),
+        ));
+    }
 
-        let image_record_1 = ImageRecord {
-            parent_id: Some(image_id_0.to_owned()),
-            created_since_epoch: Duration::from_secs(101),
-            repository_tags: vec![RepositoryTag {
-                repository: String::from("debian"),
-                tag: String::from("latest"),
-            }],
-        };
+    #[test]
+    fn is_pause_image_registries() {
+        assert!(is_pause_image(&base_image_record(
+            &[("registry.k8s.io/pause", "3.10")],
+            &[],
+        )));
+        assert!(is_pause_image(&base_image_record(
+            &[(
+                "602401143452.dkr.ecr.us-west-2.amazonaws.com/eks/pause",
+                "3.5",
+            )],
+            &[],
+        )));
+        assert!(!is_pause_image(&base_image_record(
+            &[("ghcr.io/acme/pause-button", "1.0")],
+            &[],
+        )));
+    }
 
-        let image_record_2 = ImageRecord {
-            parent_id: Some(image_id_1.to_owned()),
-            created_since_epoch: Duration::from_secs(102),
-            repository_tags: vec![RepositoryTag {
-                repository: String::from("ubuntu"),
-                tag: String::from("latest"),
-            }],
-        };
+    #[test]
+    fn image_id_matches_full() {
+        assert!(image_id_matches("sha256:0123abcd", "sha256:0123abcd"));
+        assert!(image_id_matches("0123abcd", "sha256:0123abcd"));
+        assert!(!image_id_matches("sha256:4567abcd", "sha256:0123abcd"));
+    }
 
-        let mut image_records = HashMap::new();
-        image_records.insert(image_id_0.to_owned(), image_record_0.clone());
-        image_records.insert(image_id_1.to_owned(), image_record_1.clone());
-        image_records.insert(image_id_2.to_owned(), image_record_2.clone());
-        let image_ids_in_use = HashSet::new();
-        let image_graph = construct_polyforest(&state, true, &image_records, &image_ids_in_use)?;
+    #[test]
+    fn image_id_matches_short() {
+        assert!(image_id_matches("0123", "sha256:0123abcd"));
+        assert!(image_id_matches("sha256:0123", "sha256:0123abcd"));
+        assert!(!image_id_matches("abcd", "sha256:0123abcd"));
+    }
 
-        assert_eq!(3, image_graph.len());
+    #[test]
+    fn repository_tag_wanted_keep() {
+        let keep = RegexSet::new(["^alpine:"]).unwrap();
 
-        assert_eq!(
-            Some(&ImageNode {
-                image_record: image_record_0,
-                last_used_since_epoch: Duration::from_secs(44),
-                ancestors: 0,
-            }),
-            image_graph.get(image_id_0),
-        );
+        assert!(repository_tag_wanted(Some(&keep), None, "alpine:3.20"));
+        assert!(!repository_tag_wanted(Some(&keep), None, "debian:12"));
+    }
+
+    #[test]
+    fn repository_tag_wanted_remove_only() {
+        let keep = RegexSet::new(["^alpine:"]).unwrap();
+        let remove_only = RegexSet::new(["^example.com/my-team/"]).unwrap();
+
+        assert!(repository_tag_wanted(None, Some(&remove_only), "debian:12"));
+        assert!(!repository_tag_wanted(
+            None,
+            Some(&remove_only),
+            "example.com/my-team/app:1",
+        ));
+        assert!(repository_tag_wanted(
+            Some(&keep),
+            Some(&remove_only),
+            "alpine:3.20",
+        ));
+    }
+
+    fn polyforest_with_sizes() -> HashMap<String, ImageNode> {
+        let mut polyforest = HashMap::new();
+        for (image_id, size, last_used) in
+            [("big", 8000, 50), ("small-old", 50, 0), ("small", 50, 10)]
+        {
+            polyforest.insert(
+                image_id.to_owned(),
+                ImageNode {
+                    image_record: ImageRecord {
+                        parent_id: None,
+                        created_since_epoch: Duration::from_secs(0),
+                        repository_tags: vec![RepositoryTag {
+                            repository: "<none>".to_owned(),
+                            tag: "<none>".to_owned(),
+                        }],
+                        repository_digests: vec![],
+                        labels: HashMap::new(),
+                        size: Byte::from_bytes(size),
+                    },
+                    last_used_since_epoch: Duration::from_secs(last_used),
+                    pulled_since_epoch: None,
+                    uses: 0,
+                    frequency: 0.0,
+                    ancestors: 0,
+                },
+            );
+        }
+        polyforest
+    }
+
+    #[test]
+    fn sort_image_nodes_lru() {
+        let polyforest = polyforest_with_sizes();
 
         assert_eq!(
-            Some(&ImageNode {
-                image_record: image_record_1,
-                last_used_since_epoch: Duration::from_secs(43),
-                ancestors: 1,
-            }),
-            image_graph.get(image_id_1),
+            sort_image_nodes(
+                &polyforest,
+                &HashSet::new(),
+                &eviction::Policy::Lru,
+                &[
+                    eviction::TieBreak::MostAncestorsFirst,
+                    eviction::TieBreak::LargestFirst,
+                ],
+                &[],
+                Duration::from_secs(100),
+            )
+            .into_iter()
+            .map(|(image_id, _)| image_id.as_str())
+            .collect::<Vec<_>>(),
+            vec!["small-old", "small", "big"],
         );
+    }
+
+    #[test]
+    fn sort_image_nodes_size_weighted() {
+        let polyforest = polyforest_with_sizes();
 
         assert_eq!(
-            Some(&ImageNode {
-                image_record: image_record_2,
-                last_used_since_epoch: Duration::from_secs(42),
-                ancestors: 2,
-            }),
-            image_graph.get(image_id_2),
+            sort_image_nodes(
+                &polyforest,
+                &HashSet::new(),
+                &eviction::Policy::SizeWeighted,
+                &[
+                    eviction::TieBreak::MostAncestorsFirst,
+                    eviction::TieBreak::LargestFirst,
+                ],
+                &[],
+                Duration::from_secs(100),
+            )
+            .into_iter()
+            .map(|(image_id, _)| image_id.as_str())
+            .collect::<Vec<_>>(),
+            vec!["big", "small-old", "small"],
         );
-
-        Ok(())
     }
 
     #[test]
-    fn construct_polyforest_multiple_children() -> io::Result<()> {
-        let image_id_0 = "id-0";
-        let image_id_1 = "id-1";
-        let image_id_2 = "id-2";
+    fn sort_image_nodes_descendants_first() {
+        // The parent is bigger than its child and was last used at the same time, so the
+        // policy alone would put it first.
+        let mut polyforest = polyforest_with_sizes();
+        let child = polyforest.get_mut("small").unwrap();
+        child.image_record.parent_id = Some("big".to_owned());
+        child.last_used_since_epoch = Duration::from_secs(50);
+        child.ancestors = 1;
 
-        let mut images = HashMap::new();
-        images.insert(
-            image_id_0.to_owned(),
-            state::Image {
-                parent_id: None,
-                last_used_since_epoch: Duration::from_secs(43),
-            },
-        );
-        images.insert(
-            image_id_1.to_owned(),
-            state::Image {
-                parent_id: Some(image_id_0.to_owned()),
-                last_used_since_epoch: Duration::from_secs(42),
-            },
-        );
-        images.insert(
-            image_id_2.to_owned(),
-            state::Image {
-                parent_id: Some(image_id_0.to_owned()),
-                last_used_since_epoch: Duration::from_secs(44),
-            },
+        assert_eq!(
+            sort_image_nodes(
+                &polyforest,
+                &HashSet::new(),
+                &eviction::Policy::SizeWeighted,
+                &[
+                    eviction::TieBreak::MostAncestorsFirst,
+                    eviction::TieBreak::LargestFirst,
+                ],
+                &[],
+                Duration::from_secs(100),
+            )
+            .into_iter()
+            .map(|(image_id, _)| image_id.as_str())
+            .collect::<Vec<_>>(),
+            vec!["small", "big", "small-old"],
         );
+    }
 
-        let state = State { images };
+    #[test]
+    fn stage_image_nodes_dangling() {
+        let mut polyforest = polyforest_with_sizes();
+        polyforest
+            .get_mut("big")
+            .unwrap()
+            .image_record
+            .repository_tags = vec![RepositoryTag {
+            repository: "alpine".to_owned(),
+            tag: "latest".to_owned(),
+        }];
+        polyforest.get_mut("small").unwrap().image_record.parent_id = Some("small-old".to_owned());
+        let mut sorted_image_nodes = polyforest.iter().collect::<Vec<_>>();
+        sorted_image_nodes.sort_by_key(|(image_id, _)| image_id.as_str());
 
-        let image_record_0 = ImageRecord {
-            parent_id: None,
-            created_since_epoch: Duration::from_secs(100),
-            repository_tags: vec![RepositoryTag {
-                repository: String::from("alpine"),
-                tag: String::from("latest"),
-            }],
-        };
+        assert_eq!(
+            stage_image_nodes(
+                &polyforest,
+                &sorted_image_nodes,
+                &Stage::new(StageKind::DanglingImages),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|(image_id, _)| image_id.as_str())
+            .collect::<Vec<_>>(),
+            vec!["small"],
+        );
+    }
 
-        let image_record_1 = ImageRecord {
-            parent_id: Some(image_id_0.to_owned()),
-            created_since_epoch: Duration::from_secs(101),
-            repository_tags: vec![RepositoryTag {
-                repository: String::from("debian"),
-                tag: String::from("latest"),
-            }],
+    #[test]
+    fn sort_image_nodes_tie_break() {
+        let mut polyforest = polyforest_with_sizes();
+        for image_node in polyforest.values_mut() {
+            image_node.last_used_since_epoch = Duration::from_secs(0);
+        }
+        let sort = |tie_break| {
+            sort_image_nodes(
+                &polyforest,
+                &HashSet::new(),
+                &eviction::Policy::Lru,
+                &[tie_break],
+                &[],
+                Duration::from_secs(100),
+            )
+            .into_iter()
+            .map(|(image_id, _)| image_id.as_str())
+            .collect::<Vec<_>>()
         };
 
-        let image_record_2 = ImageRecord {
-            parent_id: Some(image_id_0.to_owned()),
-            created_since_epoch: Duration::from_secs(102),
-            repository_tags: vec![RepositoryTag {
-                repository: String::from("ubuntu"),
-                tag: String::from("latest"),
-            }],
+        assert_eq!(sort(eviction::TieBreak::LargestFirst)[0], "big");
+        assert_eq!(sort(eviction::TieBreak::SmallestFirst)[2], "big");
+    }
+    #[test]
+    fn touch_image_replayed_event() -> io::Result<()> {
+        let backend = Backend::new(Runtime::Cri);
+        let mut state = State {
+            images: HashMap::from([("id-0".to_owned(), state_image(None, 100))]),
+            ..state::initial()
         };
 
-        let mut image_records = HashMap::new();
-        image_records.insert(image_id_0.to_owned(), image_record_0.clone());
-        image_records.insert(image_id_1.to_owned(), image_record_1.clone());
-        image_records.insert(image_id_2.to_owned(), image_record_2.clone());
-        let image_ids_in_use = HashSet::new();
-        let image_graph = construct_polyforest(&state, true, &image_records, &image_ids_in_use)?;
+        let event_time = Duration::from_secs(1_714_651_210);
+        assert!(!touch_image(
+            &backend,
+            &mut state,
+            "id-0",
+            None,
+            Some(event_time),
+            false,
+        )?);
+        assert!(touch_image(
+            &backend,
+            &mut state,
+            "id-1",
+            None,
+            Some(event_time),
+            false,
+        )?);
+
+        assert_eq!(state.images["id-0"].last_used_since_epoch, event_time);
+        assert_eq!(state.images["id-1"].last_used_since_epoch, event_time);
+        assert_eq!(state.images["id-1"].pulled_since_epoch, Some(event_time));
 
-        assert_eq!(3, image_graph.len());
+        Ok(())
+    }
 
-        assert_eq!(
-            Some(&ImageNode {
-                image_record: image_record_0,
-                last_used_since_epoch: Duration::from_secs(44),
-                ancestors: 0,
-            }),
-            image_graph.get(image_id_0),
-        );
+    #[test]
+    fn touch_image_replayed_event_out_of_order() -> io::Result<()> {
+        let backend = Backend::new(Runtime::Cri);
+        let mut state = State {
+            images: HashMap::from([("id-0".to_owned(), state_image(None, 200))]),
+            ..state::initial()
+        };
 
-        assert_eq!(
-            Some(&ImageNode {
-                image_record: image_record_1,
-                last_used_since_epoch: Duration::from_secs(42),
-                ancestors: 1,
-            }),
-            image_graph.get(image_id_1),
-        );
+        touch_image(
+            &backend,
+            &mut state,
+            "id-0",
+            None,
+            Some(Duration::from_secs(100)),
+            false,
+        )?;
 
         assert_eq!(
-            Some(&ImageNode {
-                image_record: image_record_2,
-                last_used_since_epoch: Duration::from_secs(44),
-                ancestors: 1,
-            }),
-            image_graph.get(image_id_2),
+            state.images["id-0"].last_used_since_epoch,
+            Duration::from_secs(200),
         );
 
         Ok(())
@@ -0,0 +1,278 @@
+use {
+    crate::format::CodeStr,
+    std::{io, iter::Peekable, str::Chars},
+};
+
+// The facts about an image which a score expression can refer to
+pub struct Variables {
+    pub age_days: f64,
+    pub created_days: f64,
+    pub size_gb: f64,
+    pub tags: f64,
+    pub in_use: f64,
+    pub uses: f64,
+    pub frequency: f64,
+}
+
+// A variable in a score expression
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Variable {
+    AgeDays,
+    CreatedDays,
+    SizeGb,
+    Tags,
+    InUse,
+    Uses,
+    Frequency,
+}
+
+impl Variable {
+    // The names of the variables, in the order in which they're documented
+    const NAMES: &'static [&'static str] = &[
+        "age_days",
+        "created_days",
+        "size_gb",
+        "tags",
+        "in_use",
+        "uses",
+        "frequency",
+    ];
+
+    // Look up a variable by name.
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "age_days" => Some(Self::AgeDays),
+            "created_days" => Some(Self::CreatedDays),
+            "size_gb" => Some(Self::SizeGb),
+            "tags" => Some(Self::Tags),
+            "in_use" => Some(Self::InUse),
+            "uses" => Some(Self::Uses),
+            "frequency" => Some(Self::Frequency),
+            _ => None,
+        }
+    }
+
+    // Get the value of the variable for an image.
+    fn value(self, variables: &Variables) -> f64 {
+        match self {
+            Self::AgeDays => variables.age_days,
+            Self::CreatedDays => variables.created_days,
+            Self::SizeGb => variables.size_gb,
+            Self::Tags => variables.tags,
+            Self::InUse => variables.in_use,
+            Self::Uses => variables.uses,
+            Self::Frequency => variables.frequency,
+        }
+    }
+}
+
+// A binary operator in a score expression
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+// A node in the syntax tree of a score expression
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Number(f64),
+    Variable(Variable),
+    Negate(Box<Node>),
+    Binary(Operator, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn evaluate(&self, variables: &Variables) -> f64 {
+        match self {
+            Self::Number(number) => *number,
+            Self::Variable(variable) => variable.value(variables),
+            Self::Negate(operand) => -operand.evaluate(variables),
+            Self::Binary(operator, left, right) => {
+                let (left, right) = (left.evaluate(variables), right.evaluate(variables));
+                match operator {
+                    Operator::Add => left + right,
+                    Operator::Subtract => left - right,
+                    Operator::Multiply => left * right,
+                    Operator::Divide => left / right,
+                }
+            }
+        }
+    }
+}
+
+// A recursive descent parser for score expressions. The grammar is:
+//
+//   expression = term (("+" | "-") term)*
+//   term       = factor (("*" | "/") factor)*
+//   factor     = "-" factor | NUMBER | VARIABLE | "(" expression ")"
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    // Skip whitespace and return the next character without consuming it.
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+        self.chars.peek().copied()
+    }
+
+    fn expression(&mut self) -> Result<Node, String> {
+        let mut node = self.term()?;
+        loop {
+            let operator = match self.peek() {
+                Some('+') => Operator::Add,
+                Some('-') => Operator::Subtract,
+                _ => return Ok(node),
+            };
+            self.chars.next();
+            node = Node::Binary(operator, Box::new(node), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Node, String> {
+        let mut node = self.factor()?;
+        loop {
+            let operator = match self.peek() {
+                Some('*') => Operator::Multiply,
+                Some('/') => Operator::Divide,
+                _ => return Ok(node),
+            };
+            self.chars.next();
+            node = Node::Binary(operator, Box::new(node), Box::new(self.factor()?));
+        }
+    }
+
+    fn factor(&mut self) -> Result<Node, String> {
+        match self.peek() {
+            Some('-') => {
+                self.chars.next();
+                Ok(Node::Negate(Box::new(self.factor()?)))
+            }
+            Some('(') => {
+                self.chars.next();
+                let node = self.expression()?;
+                if self.peek() == Some(')') {
+                    self.chars.next();
+                    Ok(node)
+                } else {
+                    Err("expected a closing parenthesis".to_owned())
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let number = self.take_while(|c| c.is_ascii_digit() || c == '.');
+                number
+                    .parse()
+                    .map(Node::Number)
+                    .map_err(|_| format!("invalid number {}", number.code_str()))
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+                Variable::from_name(&name)
+                    .map(Node::Variable)
+                    .ok_or_else(|| {
+                        format!(
+                            "unknown variable {} (expected one of {})",
+                            name.code_str(),
+                            Variable::NAMES.join(", "),
+                        )
+                    })
+            }
+            Some(c) => Err(format!("unexpected {}", c.to_string().code_str())),
+            None => Err("unexpected end of expression".to_owned()),
+        }
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let mut token = String::new();
+        while let Some(c) = self.chars.next_if(|c| predicate(*c)) {
+            token.push(c);
+        }
+        token
+    }
+}
+
+// An arithmetic expression which computes the eviction score of an image
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    root: Node,
+}
+
+impl Expression {
+    // Parse an `Expression`.
+    pub fn parse(expression: &str) -> io::Result<Self> {
+        let mut parser = Parser {
+            chars: expression.chars().peekable(),
+        };
+
+        parser
+            .expression()
+            .and_then(|root| match parser.peek() {
+                None => Ok(Self { root }),
+                Some(c) => Err(format!("unexpected {}", c.to_string().code_str())),
+            })
+            .map_err(|error| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid score expression {}: {error}.",
+                        expression.code_str(),
+                    ),
+                )
+            })
+    }
+
+    // Compute the score of an image.
+    pub fn evaluate(&self, variables: &Variables) -> f64 {
+        self.root.evaluate(variables)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Expression, Variables};
+
+    const VARIABLES: Variables = Variables {
+        age_days: 10.0,
+        created_days: 30.0,
+        size_gb: 2.0,
+        tags: 3.0,
+        in_use: 0.0,
+        uses: 4.0,
+        frequency: 1.5,
+    };
+
+    fn evaluate(expression: &str) -> f64 {
+        Expression::parse(expression).unwrap().evaluate(&VARIABLES)
+    }
+
+    #[test]
+    fn evaluate_variables() {
+        assert!((evaluate("age_days * size_gb") - 20.0).abs() < f64::EPSILON);
+        assert!((evaluate("created_days - uses / tags * 3") - 26.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn evaluate_precedence() {
+        assert!((evaluate("1 + 2 * 3") - 7.0).abs() < f64::EPSILON);
+        assert!((evaluate("(1 + 2) * 3") - 9.0).abs() < f64::EPSILON);
+        assert!((evaluate("8 - 2 - 1") - 5.0).abs() < f64::EPSILON);
+        assert!((evaluate("-age_days * (1 - in_use) + 0.5") - -9.5).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for expression in [
+            "",
+            "age_days *",
+            "(age_days",
+            "age_days size_gb",
+            "1.2.3",
+            "foo",
+        ] {
+            assert!(Expression::parse(expression).is_err(), "{expression}");
+        }
+    }
+}
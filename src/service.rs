@@ -0,0 +1,443 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(windows)]
+use {
+    crate::format::CodeStr,
+    std::{
+        env,
+        ffi::c_void,
+        io,
+        iter::once,
+        ptr::{from_ref, null, null_mut},
+        sync::{atomic::AtomicPtr, Mutex, PoisonError},
+    },
+};
+
+#[cfg(not(windows))]
+use std::io;
+
+// The name under which Docuum is registered with the service control manager
+#[cfg(windows)]
+const SERVICE_NAME: &str = "Docuum";
+
+// How long we expect stopping to take, so the service control manager doesn't give up on us
+#[cfg(windows)]
+const STOP_WAIT_HINT_MS: u32 = 30_000;
+
+// Whether the service control manager has asked us to stop
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// The parts of the Windows API we need. See the documentation of the corresponding functions and
+// structs at https://learn.microsoft.com/en-us/windows/win32/services/services.
+#[cfg(windows)]
+#[allow(non_snake_case)]
+mod ffi {
+    use std::ffi::c_void;
+
+    pub type Handle = *mut c_void;
+
+    pub const SERVICE_WIN32_OWN_PROCESS: u32 = 0x10;
+    pub const SERVICE_AUTO_START: u32 = 2;
+    pub const SERVICE_ERROR_NORMAL: u32 = 1;
+    pub const SERVICE_ALL_ACCESS: u32 = 0xF_01FF;
+    pub const SC_MANAGER_CONNECT: u32 = 0x1;
+    pub const SC_MANAGER_CREATE_SERVICE: u32 = 0x2;
+    pub const DELETE: u32 = 0x1_0000;
+
+    pub const SERVICE_STOPPED: u32 = 1;
+    pub const SERVICE_STOP_PENDING: u32 = 3;
+    pub const SERVICE_RUNNING: u32 = 4;
+
+    pub const SERVICE_ACCEPT_STOP: u32 = 0x1;
+    pub const SERVICE_ACCEPT_SHUTDOWN: u32 = 0x4;
+
+    pub const SERVICE_CONTROL_STOP: u32 = 1;
+    pub const SERVICE_CONTROL_INTERROGATE: u32 = 4;
+    pub const SERVICE_CONTROL_SHUTDOWN: u32 = 5;
+
+    pub const NO_ERROR: u32 = 0;
+    pub const ERROR_CALL_NOT_IMPLEMENTED: u32 = 120;
+
+    #[repr(C)]
+    pub struct ServiceTableEntry {
+        pub service_name: *mut u16,
+        pub service_proc: Option<unsafe extern "system" fn(u32, *mut *mut u16)>,
+    }
+
+    #[repr(C)]
+    pub struct ServiceStatus {
+        pub service_type: u32,
+        pub current_state: u32,
+        pub controls_accepted: u32,
+        pub win32_exit_code: u32,
+        pub service_specific_exit_code: u32,
+        pub check_point: u32,
+        pub wait_hint: u32,
+    }
+
+    pub type HandlerEx = unsafe extern "system" fn(u32, u32, *mut c_void, *mut c_void) -> u32;
+
+    #[link(name = "advapi32")]
+    extern "system" {
+        pub fn StartServiceCtrlDispatcherW(service_table: *const ServiceTableEntry) -> i32;
+
+        pub fn RegisterServiceCtrlHandlerExW(
+            service_name: *const u16,
+            handler: HandlerEx,
+            context: *mut c_void,
+        ) -> Handle;
+
+        pub fn SetServiceStatus(status_handle: Handle, status: *const ServiceStatus) -> i32;
+
+        pub fn OpenSCManagerW(
+            machine_name: *const u16,
+            database_name: *const u16,
+            desired_access: u32,
+        ) -> Handle;
+
+        pub fn CreateServiceW(
+            manager: Handle,
+            service_name: *const u16,
+            display_name: *const u16,
+            desired_access: u32,
+            service_type: u32,
+            start_type: u32,
+            error_control: u32,
+            binary_path_name: *const u16,
+            load_order_group: *const u16,
+            tag_id: *mut u32,
+            dependencies: *const u16,
+            service_start_name: *const u16,
+            password: *const u16,
+        ) -> Handle;
+
+        pub fn OpenServiceW(
+            manager: Handle,
+            service_name: *const u16,
+            desired_access: u32,
+        ) -> Handle;
+
+        pub fn DeleteService(service: Handle) -> i32;
+
+        pub fn CloseServiceHandle(handle: Handle) -> i32;
+    }
+}
+
+// The handle through which we report our status to the service control manager
+#[cfg(windows)]
+static STATUS_HANDLE: AtomicPtr<c_void> = AtomicPtr::new(null_mut());
+
+// The work to do once the service has started
+#[cfg(windows)]
+#[allow(clippy::type_complexity)]
+static BODY: Mutex<Option<Box<dyn FnOnce() + Send>>> = Mutex::new(None);
+
+// A handle to the service control manager or a service, which is closed when dropped
+#[cfg(windows)]
+struct ServiceHandle(ffi::Handle);
+
+#[cfg(windows)]
+impl ServiceHandle {
+    // Check the result of a function which returns a handle, adding some context to any error.
+    fn new(handle: ffi::Handle, context: &str) -> io::Result<Self> {
+        if handle.is_null() {
+            let error = io::Error::last_os_error();
+            Err(io::Error::new(
+                error.kind(),
+                format!("{context} Details: {error}"),
+            ))
+        } else {
+            Ok(Self(handle))
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for ServiceHandle {
+    fn drop(&mut self) {
+        // SAFETY: The handle is valid, and this is the only place it's closed.
+        unsafe { ffi::CloseServiceHandle(self.0) };
+    }
+}
+
+// Encode a string as a null-terminated UTF-16 string for the Windows API.
+#[cfg(windows)]
+fn wide(string: &str) -> Vec<u16> {
+    string.encode_utf16().chain(once(0)).collect()
+}
+
+// Quote an argument so that it survives the parsing done by `CommandLineToArgvW`.
+#[cfg(windows)]
+fn quote_argument(argument: &str) -> String {
+    if !argument.is_empty() && !argument.contains([' ', '\t', '\n', '"']) {
+        return argument.to_owned();
+    }
+
+    // Backslashes are only special when they precede a quote.
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for character in argument.chars() {
+        match character {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                quoted.push(character);
+                backslashes = 0;
+            }
+        }
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+// Report our status to the service control manager.
+#[cfg(windows)]
+fn set_status(current_state: u32) {
+    let status = ffi::ServiceStatus {
+        service_type: ffi::SERVICE_WIN32_OWN_PROCESS,
+        current_state,
+        controls_accepted: if current_state == ffi::SERVICE_RUNNING {
+            ffi::SERVICE_ACCEPT_STOP | ffi::SERVICE_ACCEPT_SHUTDOWN
+        } else {
+            0
+        },
+        win32_exit_code: ffi::NO_ERROR,
+        service_specific_exit_code: 0,
+        check_point: 0,
+        wait_hint: if current_state == ffi::SERVICE_STOP_PENDING {
+            STOP_WAIT_HINT_MS
+        } else {
+            0
+        },
+    };
+
+    // SAFETY: The handle was returned by `RegisterServiceCtrlHandlerExW`, and the status is valid.
+    if unsafe { ffi::SetServiceStatus(STATUS_HANDLE.load(Ordering::SeqCst), from_ref(&status)) }
+        == 0_i32
+    {
+        error!(
+            "Unable to report the service status. Details: {}",
+            io::Error::last_os_error(),
+        );
+    }
+}
+
+// Handle a request from the service control manager. Stopping is cooperative: we set a flag which
+// the main loop checks, and the state is saved on the way out.
+#[cfg(windows)]
+unsafe extern "system" fn control_handler(
+    control: u32,
+    _: u32,
+    _: *mut c_void,
+    _: *mut c_void,
+) -> u32 {
+    match control {
+        ffi::SERVICE_CONTROL_STOP | ffi::SERVICE_CONTROL_SHUTDOWN => {
+            info!("Stopping the service\u{2026}");
+            STOP_REQUESTED.store(true, Ordering::SeqCst);
+            set_status(ffi::SERVICE_STOP_PENDING);
+            ffi::NO_ERROR
+        }
+        ffi::SERVICE_CONTROL_INTERROGATE => ffi::NO_ERROR,
+        _ => ffi::ERROR_CALL_NOT_IMPLEMENTED,
+    }
+}
+
+// The entry point of the service, which the service control manager calls on a thread of its own
+#[cfg(windows)]
+unsafe extern "system" fn service_main(_: u32, _: *mut *mut u16) {
+    let service_name = wide(SERVICE_NAME);
+
+    // SAFETY: The name is a valid null-terminated string, and the handler never uses the context.
+    let handle = unsafe {
+        ffi::RegisterServiceCtrlHandlerExW(service_name.as_ptr(), control_handler, null_mut())
+    };
+    if handle.is_null() {
+        error!(
+            "Unable to register the service control handler. Details: {}",
+            io::Error::last_os_error(),
+        );
+        return;
+    }
+    STATUS_HANDLE.store(handle, Ordering::SeqCst);
+
+    set_status(ffi::SERVICE_RUNNING);
+    if let Some(body) = BODY.lock().unwrap_or_else(PoisonError::into_inner).take() {
+        body();
+    }
+    set_status(ffi::SERVICE_STOPPED);
+}
+
+// Run `body` as a Windows service. This blocks until the service stops.
+#[cfg(windows)]
+pub fn run(body: Box<dyn FnOnce() + Send>) -> io::Result<()> {
+    *BODY.lock().unwrap_or_else(PoisonError::into_inner) = Some(body);
+
+    let mut service_name = wide(SERVICE_NAME);
+    let service_table = [
+        ffi::ServiceTableEntry {
+            service_name: service_name.as_mut_ptr(),
+            service_proc: Some(service_main),
+        },
+        ffi::ServiceTableEntry {
+            service_name: null_mut(),
+            service_proc: None,
+        },
+    ];
+
+    // SAFETY: The table is terminated by a null entry, and it outlives the call.
+    if unsafe { ffi::StartServiceCtrlDispatcherW(service_table.as_ptr()) } == 0_i32 {
+        let error = io::Error::last_os_error();
+        return Err(io::Error::new(
+            error.kind(),
+            format!(
+                "Unable to connect to the service control manager. This mode is only for use by \
+                    the service control manager. Details: {error}",
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+// Register Docuum as a Windows service which starts automatically and runs with the given
+// arguments.
+#[cfg(windows)]
+pub fn install(arguments: &[String]) -> io::Result<()> {
+    let executable = env::current_exe()?;
+    let command_line = once(executable.to_string_lossy().into_owned())
+        .chain(arguments.iter().cloned())
+        .map(|argument| quote_argument(&argument))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    // SAFETY: Null names refer to the local machine and its default database.
+    let manager = ServiceHandle::new(
+        unsafe { ffi::OpenSCManagerW(null(), null(), ffi::SC_MANAGER_CREATE_SERVICE) },
+        "Unable to connect to the service control manager. Are you running as an administrator?",
+    )?;
+
+    let service_name = wide(SERVICE_NAME);
+    let command_line_wide = wide(&command_line);
+
+    // SAFETY: The strings are null-terminated and outlive the call, and null is allowed for the
+    // optional parameters.
+    ServiceHandle::new(
+        unsafe {
+            ffi::CreateServiceW(
+                manager.0,
+                service_name.as_ptr(),
+                service_name.as_ptr(),
+                ffi::SERVICE_ALL_ACCESS,
+                ffi::SERVICE_WIN32_OWN_PROCESS,
+                ffi::SERVICE_AUTO_START,
+                ffi::SERVICE_ERROR_NORMAL,
+                command_line_wide.as_ptr(),
+                null(),
+                null_mut(),
+                null(),
+                null(),
+                null(),
+            )
+        },
+        &format!("Unable to create the {} service.", SERVICE_NAME.code_str()),
+    )?;
+
+    info!(
+        "Installed the {} service with command line {}.",
+        SERVICE_NAME.code_str(),
+        command_line.code_str(),
+    );
+
+    Ok(())
+}
+
+// Remove the Windows service.
+#[cfg(windows)]
+pub fn uninstall() -> io::Result<()> {
+    // SAFETY: Null names refer to the local machine and its default database.
+    let manager = ServiceHandle::new(
+        unsafe { ffi::OpenSCManagerW(null(), null(), ffi::SC_MANAGER_CONNECT) },
+        "Unable to connect to the service control manager. Are you running as an administrator?",
+    )?;
+
+    let service_name = wide(SERVICE_NAME);
+
+    // SAFETY: The name is null-terminated and outlives the call.
+    let service = ServiceHandle::new(
+        unsafe { ffi::OpenServiceW(manager.0, service_name.as_ptr(), ffi::DELETE) },
+        &format!("Unable to open the {} service.", SERVICE_NAME.code_str()),
+    )?;
+
+    // SAFETY: The handle was opened with the `DELETE` access right.
+    if unsafe { ffi::DeleteService(service.0) } == 0_i32 {
+        return Err(io::Error::last_os_error());
+    }
+
+    info!("Uninstalled the {} service.", SERVICE_NAME.code_str());
+
+    Ok(())
+}
+
+// Windows services only exist on Windows.
+#[cfg(not(windows))]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Running as a service is only supported on Windows.",
+    )
+}
+
+#[cfg(not(windows))]
+pub fn run(_: Box<dyn FnOnce() + Send>) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(windows))]
+pub fn install(_: &[String]) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(windows))]
+pub fn uninstall() -> io::Result<()> {
+    Err(unsupported())
+}
+
+// Check whether we've been asked to stop.
+pub fn stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::SeqCst)
+}
+
+#[cfg(all(test, windows))]
+mod tests {
+    use super::quote_argument;
+
+    #[test]
+    fn quote_argument_plain() {
+        assert_eq!(quote_argument("--once"), "--once");
+    }
+
+    #[test]
+    fn quote_argument_spaces() {
+        assert_eq!(quote_argument("10 GB"), "\"10 GB\"");
+    }
+
+    #[test]
+    fn quote_argument_quotes_and_backslashes() {
+        assert_eq!(
+            quote_argument(r#"C:\Program Files\a"b\"#),
+            r#""C:\Program Files\a\"b\\""#,
+        );
+    }
+
+    #[test]
+    fn quote_argument_empty() {
+        assert_eq!(quote_argument(""), "\"\"");
+    }
+}
@@ -1,17 +1,33 @@
 use {
-    crate::format::CodeStr,
+    crate::{format::CodeStr, report::Report},
     serde::{Deserialize, Serialize},
     std::{
         collections::HashMap,
         env,
-        fs::{create_dir_all, read_to_string},
-        io::{self, Write},
-        path::PathBuf,
+        fs::{copy, create_dir_all, metadata, read_to_string, remove_file, rename},
+        io::{self, Read, Write},
+        path::{Path, PathBuf},
+        sync::OnceLock,
         time::Duration,
     },
     tempfile::NamedTempFile,
 };
 
+// The number of backups of the state to keep, e.g., `state.yml.1` through `state.yml.3`
+const BACKUPS: usize = 3;
+
+// How often the state is backed up. The state is saved whenever an image is used, so backing it up
+// every time would leave us with nearly identical backups.
+const BACKUP_INTERVAL_SECS: u64 = 60 * 60;
+const BACKUP_INTERVAL: Duration = Duration::from_secs(BACKUP_INTERVAL_SECS);
+
+// How long it takes for a use of an image to count half as much toward its frequency
+const FREQUENCY_HALF_LIFE_SECS: u64 = 30 * 24 * 60 * 60;
+const FREQUENCY_HALF_LIFE: Duration = Duration::from_secs(FREQUENCY_HALF_LIFE_SECS);
+
+// The number of containers we remember for each image
+const MAX_CONTAINERS: usize = 10;
+
 // What we want to remember about an individual image
 #[derive(Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
@@ -22,19 +38,145 @@ pub struct Image {
     // The amount of time that has passed between the UNIX epoch and the moment the image was most
     // recently used
     pub last_used_since_epoch: Duration,
+
+    // The amount of time that has passed between the UNIX epoch and the moment we first saw the
+    // image, which is usually when it was pulled or built. This is unknown for images which were
+    // already around when Docuum first ran.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pulled_since_epoch: Option<Duration>,
+
+    // The number of times the image has been used since we first saw it
+    #[serde(default)]
+    pub uses: u64,
+
+    // Like `uses`, except each use counts half as much for every `FREQUENCY_HALF_LIFE` that has
+    // passed since, as of when the image was most recently used. See `decay_frequency`.
+    #[serde(default)]
+    pub frequency: f64,
+
+    // The names of the containers which most recently used the image, oldest first. See
+    // `record_container`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub containers: Vec<String>,
+}
+
+// What we want to remember about an individual volume
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Volume {
+    // The amount of time that has passed between the UNIX epoch and the moment the volume was most
+    // recently mounted or unmounted
+    pub last_used_since_epoch: Duration,
+}
+
+// What we want to remember about an individual network
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Network {
+    // The amount of time that has passed between the UNIX epoch and the moment a container was most
+    // recently connected to or disconnected from the network
+    pub last_used_since_epoch: Duration,
+}
+
+// What we want to remember about an individual Docker Buildx builder
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BuildxBuilder {
+    // The amount of time that has passed between the UNIX epoch and the moment a build most
+    // recently connected to the builder
+    pub last_used_since_epoch: Duration,
+}
+
+// What we want to remember about a group of containers, like a Docker Compose project or a GitLab
+// CI pipeline
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContainerGroup {
+    // The IDs of the images used by the group's containers when we last saw any of them
+    pub image_ids: Vec<String>,
+
+    // The amount of time that has passed between the UNIX epoch and the moment we last saw any of
+    // the group's containers
+    pub last_active_since_epoch: Duration,
+}
+
+// Running totals which are kept across restarts, so operators can see what Docuum has done for them
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Counters {
+    // The number of vacuums which have been performed
+    pub vacuums: u64,
+
+    // The number of images which have been deleted
+    pub deletions: u64,
+
+    // The sum of the sizes of the deleted images
+    pub reclaimed_bytes: u128,
+
+    // The amount of time that has passed between the UNIX epoch and the moment Docuum first started
+    // with this state. This is unknown for states from before the counters were introduced until
+    // Docuum starts again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_since_epoch: Option<Duration>,
+}
+
+impl Counters {
+    // Add a vacuum to the totals.
+    pub fn record_vacuum(&mut self, report: &Report) {
+        self.vacuums = self.vacuums.saturating_add(1);
+        self.deletions = self
+            .deletions
+            .saturating_add(u64::try_from(report.deleted_images.len()).unwrap_or(u64::MAX));
+        self.reclaimed_bytes = self.reclaimed_bytes.saturating_add(report.reclaimed_bytes);
+    }
 }
 
-// The program state
+/// The program state
 #[derive(Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct State {
-    // Map from image ID to `Image`
+    /// Map from image ID to `Image`
     pub images: HashMap<String, Image>,
+
+    /// Map from volume name to `Volume`. This is only populated when pruning volumes.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub volumes: HashMap<String, Volume>,
+
+    /// Map from network ID to `Network`. This is only populated when pruning networks.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub networks: HashMap<String, Network>,
+
+    /// Map from the container name of a Buildx builder to `BuildxBuilder`. This is only populated
+    /// when pruning the caches of Buildx builders.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub buildx_builders: HashMap<String, BuildxBuilder>,
+
+    /// Map from Docker Compose project name to `ContainerGroup`. This is only populated when
+    /// protecting the images of Compose projects.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub compose_projects: HashMap<String, ContainerGroup>,
+
+    /// Map from GitLab CI pipeline ID to `ContainerGroup`. This is only populated when protecting
+    /// the images of GitLab CI pipelines.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub gitlab_pipelines: HashMap<String, ContainerGroup>,
+
+    /// Lifetime totals, which are reported by `docuum stats` and in the metrics
+    #[serde(default)]
+    pub counters: Counters,
+
+    /// The amount of time that has passed between the UNIX epoch and the moment the most recent
+    /// event we received happened, so the events can be resumed from there after a disconnect or a
+    /// restart
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_event_since_epoch: Option<Duration>,
 }
 
-// Where the program state is persisted on disk
-fn path() -> Option<PathBuf> {
-    // [tag:state_path_has_parent]
+// Where the state is persisted if it was specified explicitly with `--state-file`
+static PATH_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+// The directory in which Docuum keeps its data by default
+fn data_dir() -> Option<PathBuf> {
     dirs::data_local_dir()
         .or_else(|| {
             // In the `mcr.microsoft.com/windows/nanoserver` Docker image, `dirs::data_local_dir()`
@@ -42,44 +184,168 @@ fn path() -> Option<PathBuf> {
             // fall back to the value of the `LOCALAPPDATA` environment variable in that case.
             env::var("LOCALAPPDATA").ok().map(Into::into)
         })
-        .map(|path| path.join("docuum/state.yml"))
+        .map(|path| path.join("docuum"))
+}
+
+// Persist the program state at the given path rather than in the default location. The path must
+// have a parent [ref:state_path_has_parent]. Only the first call has any effect.
+pub fn set_path(path: PathBuf) {
+    let _ = PATH_OVERRIDE.set(path);
+}
+
+// Where the program state is persisted on disk
+pub fn path() -> Option<PathBuf> {
+    // [tag:state_path_has_parent]
+    PATH_OVERRIDE
+        .get()
+        .cloned()
+        .or_else(|| data_dir().map(|path| path.join("state.yml")))
+}
+
+// The path of the state of an additional endpoint, which is kept next to the main state, e.g.,
+// `state-builder.yml` for the endpoint named `builder`
+fn namespaced_path(path: &Path, namespace: &str) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_owned();
+    file_name.push(format!("-{namespace}"));
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
+
+// Where the state of an endpoint is persisted on disk. The main endpoint has no namespace.
+fn path_for(namespace: Option<&str>) -> Option<PathBuf> {
+    path().map(|path| match namespace {
+        Some(namespace) => namespaced_path(&path, namespace),
+        None => path,
+    })
+}
+
+// Remember that a container used an image, forgetting the container which used it least recently if
+// there are more than `MAX_CONTAINERS`.
+pub fn record_container(containers: &mut Vec<String>, container: &str) {
+    containers.retain(|existing_container| existing_container != container);
+    containers.push(container.to_owned());
+    if containers.len() > MAX_CONTAINERS {
+        containers.remove(0);
+    }
+}
+
+// Decay the usage frequency of an image by the time that has elapsed since it was computed.
+pub fn decay_frequency(frequency: f64, elapsed: Duration) -> f64 {
+    frequency * 0.5_f64.powf(elapsed.as_secs_f64() / FREQUENCY_HALF_LIFE.as_secs_f64())
 }
 
 // Return the state in which the program starts, if no state was loaded from disk.
 pub fn initial() -> State {
     State {
         images: HashMap::new(),
+        volumes: HashMap::new(),
+        networks: HashMap::new(),
+        buildx_builders: HashMap::new(),
+        compose_projects: HashMap::new(),
+        gitlab_pipelines: HashMap::new(),
+        counters: Counters::default(),
+        last_event_since_epoch: None,
     }
 }
 
+// The path of the backup of the state which is `index` backups old
+fn backup_path(path: &Path, index: usize) -> PathBuf {
+    let mut backup_path = path.as_os_str().to_owned();
+    backup_path.push(format!(".{index}"));
+    PathBuf::from(backup_path)
+}
+
+// Read and deserialize the state from a file.
+fn load_from(path: &Path) -> io::Result<State> {
+    // Read the YAML from disk.
+    let yaml = read_to_string(path)?;
+
+    // Deserialize the YAML.
+    serde_yaml::from_str(&yaml).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+// Load the state from a file. If it can't be deserialized, fall back to the most recent backup
+// which can be.
+fn load_or_recover(path: &Path) -> io::Result<State> {
+    let error = match load_from(path) {
+        Err(error) if error.kind() == io::ErrorKind::InvalidData => error,
+        result => return result,
+    };
+
+    for index in 1..=BACKUPS {
+        let backup_path = backup_path(path, index);
+        if let Ok(state) = load_from(&backup_path) {
+            warn!(
+                "Unable to parse the state. Falling back to the backup in {}. Details: {}",
+                backup_path.to_string_lossy().code_str(),
+                error.to_string().code_str(),
+            );
+            return Ok(state);
+        }
+    }
+
+    Err(error)
+}
+
 // Load the program state from disk.
-pub fn load() -> io::Result<State> {
+pub fn load(namespace: Option<&str>) -> io::Result<State> {
     // Check if we have a path.
-    if let Some(path) = path() {
+    if let Some(path) = path_for(namespace) {
         // Log what we are trying to do in case an error occurs.
         trace!(
             "Attempting to load the state from {}\u{2026}",
             path.to_string_lossy().code_str(),
         );
 
-        // Read the YAML from disk.
-        let yaml = read_to_string(path)?;
-
-        // Deserialize the YAML.
-        serde_yaml::from_str(&yaml).map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+        load_or_recover(&path)
     } else {
         // Fail if we don't have a path.
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Unable to locate data directory.",
-        ))
+        Err(io::Error::other("Unable to locate data directory."))
     }
 }
 
+// Copy the state file to a new backup, shifting the existing backups along and dropping the oldest
+// one, unless the most recent backup is younger than `BACKUP_INTERVAL`.
+fn back_up(path: &Path) -> io::Result<()> {
+    let due = match metadata(backup_path(path, 1)).and_then(|metadata| metadata.modified()) {
+        Ok(modified) => modified
+            .elapsed()
+            .map_or(true, |elapsed| elapsed >= BACKUP_INTERVAL),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => true,
+        Err(error) => return Err(error),
+    };
+    if !due || !path.exists() {
+        return Ok(());
+    }
+
+    match remove_file(backup_path(path, BACKUPS)) {
+        Ok(()) => {}
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+        Err(error) => return Err(error),
+    }
+
+    for index in (1..BACKUPS).rev() {
+        match rename(backup_path(path, index), backup_path(path, index + 1)) {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+    }
+
+    // The state file is copied rather than moved so there's always a state file, even if we fail
+    // to write the new one.
+    copy(path, backup_path(path, 1))?;
+
+    Ok(())
+}
+
 // Save the program state to disk.
-pub fn save(state: &State) -> io::Result<()> {
+pub fn save(state: &State, namespace: Option<&str>) -> io::Result<()> {
     // Check if we have a path.
-    if let Some(path) = path() {
+    if let Some(path) = path_for(namespace) {
         // Log what we're trying to do in case an error occurs.
         trace!(
             "Persisting the state to {}\u{2026}",
@@ -95,6 +361,15 @@ pub fn save(state: &State) -> io::Result<()> {
         // Create the ancestor directories, if needed.
         create_dir_all(parent.clone())?;
 
+        // Back up the previous state, in case the new one gets corrupted somehow.
+        if let Err(error) = back_up(&path) {
+            // Log the error and proceed anyway.
+            warn!(
+                "Unable to back up the state. Details: {}",
+                error.to_string().code_str(),
+            );
+        }
+
         // Persist the state to disk.
         let mut temp_file = NamedTempFile::new_in(parent)?;
         temp_file.write_all(payload.as_bytes())?;
@@ -102,11 +377,211 @@ pub fn save(state: &State) -> io::Result<()> {
         temp_file.persist(path)?;
     } else {
         // Fail if we don't have a path.
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Unable to locate data directory.",
-        ));
+        return Err(io::Error::other("Unable to locate data directory."));
     }
 
     Ok(())
 }
+
+// Parse the program state from JSON.
+fn from_json(json: &str) -> io::Result<State> {
+    serde_json::from_str(json).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+// Write the program state to standard output as JSON.
+pub fn export(state: &State) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, state).map_err(io::Error::other)?;
+    writeln!(stdout)?;
+    stdout.flush()
+}
+
+// Read the program state from standard input as JSON and persist it, replacing any existing state.
+pub fn import() -> io::Result<()> {
+    let mut json = String::new();
+    io::stdin().read_to_string(&mut json)?;
+    save(
+        &from_json(&json).map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!("Unable to parse the state. Details: {error}"),
+            )
+        })?,
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            back_up, backup_path, decay_frequency, from_json, initial, load_or_recover,
+            namespaced_path, record_container, Image, State, BACKUPS, FREQUENCY_HALF_LIFE,
+            MAX_CONTAINERS,
+        },
+        crate::report::{DeletedImage, DeletionReason, Report},
+        std::{
+            fs::{read_to_string, write},
+            path::{Path, PathBuf},
+            time::Duration,
+        },
+        tempfile::tempdir,
+    };
+
+    #[test]
+    fn json_round_trip() {
+        let mut state = initial();
+        state.images.insert(
+            "id-0".to_owned(),
+            Image {
+                parent_id: Some("id-1".to_owned()),
+                last_used_since_epoch: Duration::from_secs(42),
+                pulled_since_epoch: None,
+                uses: 0,
+                frequency: 0.0,
+                containers: vec![],
+            },
+        );
+
+        let state = from_json(&serde_json::to_string(&state).unwrap()).unwrap();
+
+        assert_eq!(state.images.len(), 1);
+        assert_eq!(state.images["id-0"].parent_id.as_deref(), Some("id-1"));
+        assert_eq!(
+            state.images["id-0"].last_used_since_epoch,
+            Duration::from_secs(42),
+        );
+    }
+
+    #[test]
+    fn counters_record_vacuum() {
+        let mut report = Report::default();
+        report.deleted_image(DeletedImage {
+            id: "sha256:0".to_owned(),
+            repository_tags: vec![],
+            size_bytes: 42,
+            reason: DeletionReason::Threshold,
+        });
+        let mut state = initial();
+        state.counters.record_vacuum(&report);
+        state.counters.record_vacuum(&Report::default());
+
+        assert_eq!(state.counters.vacuums, 2);
+        assert_eq!(state.counters.deletions, 1);
+        assert_eq!(state.counters.reclaimed_bytes, 42);
+    }
+
+    #[test]
+    fn counters_yaml_round_trip() {
+        let mut state = initial();
+        state.counters.reclaimed_bytes = 12_345_678_901;
+        state.counters.started_since_epoch = Some(Duration::from_secs(42));
+
+        let state: State = serde_yaml::from_str(&serde_yaml::to_string(&state).unwrap()).unwrap();
+
+        assert_eq!(state.counters.reclaimed_bytes, 12_345_678_901);
+        assert_eq!(
+            state.counters.started_since_epoch,
+            Some(Duration::from_secs(42)),
+        );
+    }
+
+    #[test]
+    fn decay_frequency_half_life() {
+        assert!((decay_frequency(4.0, Duration::ZERO) - 4.0).abs() < f64::EPSILON);
+        assert!((decay_frequency(4.0, FREQUENCY_HALF_LIFE) - 2.0).abs() < f64::EPSILON);
+        assert!((decay_frequency(4.0, FREQUENCY_HALF_LIFE * 2) - 1.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn record_container_moves_to_end() {
+        let mut containers = vec!["a".to_owned(), "b".to_owned()];
+        record_container(&mut containers, "a");
+
+        assert_eq!(containers, vec!["b".to_owned(), "a".to_owned()]);
+    }
+
+    #[test]
+    fn record_container_bounded() {
+        let mut containers = vec![];
+        for i in 0..=MAX_CONTAINERS {
+            record_container(&mut containers, &i.to_string());
+        }
+
+        assert_eq!(containers.len(), MAX_CONTAINERS);
+        assert_eq!(containers[0], "1");
+        assert_eq!(containers[MAX_CONTAINERS - 1], MAX_CONTAINERS.to_string());
+    }
+
+    #[test]
+    fn from_json_unknown_field() {
+        assert!(from_json(r#"{"images": {}, "containers": {}}"#).is_err());
+    }
+
+    #[test]
+    fn back_up_rotates() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("state.yml");
+
+        // Since the most recent backup is fresh, only the first of these makes a backup.
+        write(&path, "first").unwrap();
+        back_up(&path).unwrap();
+        write(&path, "second").unwrap();
+        back_up(&path).unwrap();
+
+        assert_eq!(read_to_string(backup_path(&path, 1)).unwrap(), "first");
+        assert!(!backup_path(&path, 2).exists());
+        assert!(!backup_path(&path, BACKUPS + 1).exists());
+    }
+
+    #[test]
+    fn namespaced_path_extension() {
+        assert_eq!(
+            namespaced_path(Path::new("/data/docuum/state.yml"), "builder"),
+            PathBuf::from("/data/docuum/state-builder.yml"),
+        );
+    }
+
+    #[test]
+    fn namespaced_path_no_extension() {
+        assert_eq!(
+            namespaced_path(Path::new("/data/docuum/state"), "dind"),
+            PathBuf::from("/data/docuum/state-dind"),
+        );
+    }
+
+    #[test]
+    fn load_or_recover_valid() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("state.yml");
+        write(&path, "images: {}\n").unwrap();
+
+        assert!(load_or_recover(&path).unwrap().images.is_empty());
+    }
+
+    #[test]
+    fn load_or_recover_corrupt() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("state.yml");
+        write(&path, "images: [").unwrap();
+        write(backup_path(&path, 1), "images: [").unwrap();
+        write(
+            backup_path(&path, 2),
+            "images:\n  id-0:\n    parent_id: ~\n    last_used_since_epoch:\n      secs: 42\n      \
+                nanos: 0\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            load_or_recover(&path).unwrap().images["id-0"].last_used_since_epoch,
+            Duration::from_secs(42),
+        );
+    }
+
+    #[test]
+    fn load_or_recover_missing() {
+        let dir = tempdir().unwrap();
+
+        assert!(load_or_recover(&dir.path().join("state.yml")).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use {
+    crate::{
+        format::Output,
+        run::{space_limit, space_usage, threshold_bytes},
+        state::State,
+        Settings,
+    },
+    byte_unit::Byte,
+    chrono::{DateTime, SecondsFormat, Utc},
+    serde::Serialize,
+    std::{
+        io,
+        time::{Duration, UNIX_EPOCH},
+    },
+};
+
+// A summary of the persisted state and the current space usage
+struct Stats {
+    tracked_images: usize,
+
+    // The sum of the sizes of the tracked images which still exist
+    tracked_bytes: u128,
+
+    oldest_last_used_since_epoch: Option<Duration>,
+    newest_last_used_since_epoch: Option<Duration>,
+    bytes: u128,
+    threshold_bytes: u128,
+
+    // The lifetime totals from the state
+    vacuums: u64,
+    deletions: u64,
+    reclaimed_bytes: u128,
+    started_since_epoch: Option<Duration>,
+}
+
+// The summary as JSON, with timestamps in RFC 3339 format
+#[derive(Serialize)]
+struct JsonStats {
+    tracked_images: usize,
+    tracked_bytes: u128,
+    least_recently_used: Option<String>,
+    most_recently_used: Option<String>,
+    bytes: u128,
+    threshold_bytes: u128,
+    vacuums: u64,
+    deletions: u64,
+    reclaimed_bytes: u128,
+    running_since: Option<String>,
+}
+
+// Format an amount of space for humans.
+fn format_bytes(bytes: u128) -> String {
+    Byte::from_bytes(bytes)
+        .get_appropriate_unit(false)
+        .to_string()
+}
+
+// Format a timestamp as RFC 3339, if there is one.
+fn timestamp(since_epoch: Option<Duration>) -> Option<String> {
+    since_epoch.map(|since_epoch| {
+        DateTime::<Utc>::from(UNIX_EPOCH + since_epoch).to_rfc3339_opts(SecondsFormat::Secs, true)
+    })
+}
+
+// Format a timestamp for humans, if there is one.
+fn format_timestamp(since_epoch: Option<Duration>) -> String {
+    timestamp(since_epoch).unwrap_or_else(|| "none".to_owned())
+}
+
+// Format the summary as JSON.
+fn json(stats: &Stats) -> String {
+    // The `unwrap` is safe because `JsonStats` always serializes.
+    serde_json::to_string(&JsonStats {
+        tracked_images: stats.tracked_images,
+        tracked_bytes: stats.tracked_bytes,
+        least_recently_used: timestamp(stats.oldest_last_used_since_epoch),
+        most_recently_used: timestamp(stats.newest_last_used_since_epoch),
+        bytes: stats.bytes,
+        threshold_bytes: stats.threshold_bytes,
+        vacuums: stats.vacuums,
+        deletions: stats.deletions,
+        reclaimed_bytes: stats.reclaimed_bytes,
+        running_since: timestamp(stats.started_since_epoch),
+    })
+    .unwrap()
+}
+
+// Format the summary as lines of text.
+fn lines(stats: &Stats) -> Vec<String> {
+    vec![
+        format!("Tracked images: {}", stats.tracked_images),
+        format!(
+            "Total size of tracked images: {}",
+            format_bytes(stats.tracked_bytes),
+        ),
+        format!(
+            "Least recently used: {}",
+            format_timestamp(stats.oldest_last_used_since_epoch),
+        ),
+        format!(
+            "Most recently used: {}",
+            format_timestamp(stats.newest_last_used_since_epoch),
+        ),
+        format!("Space usage: {}", format_bytes(stats.bytes)),
+        format!("Threshold: {}", format_bytes(stats.threshold_bytes)),
+        if stats.bytes > stats.threshold_bytes {
+            format!(
+                "Over the threshold by {}",
+                format_bytes(stats.bytes - stats.threshold_bytes),
+            )
+        } else {
+            format!(
+                "Under the threshold by {}",
+                format_bytes(stats.threshold_bytes - stats.bytes),
+            )
+        },
+        format!("Vacuums performed: {}", stats.vacuums),
+        format!("Images deleted: {}", stats.deletions),
+        format!("Space reclaimed: {}", format_bytes(stats.reclaimed_bytes)),
+        format!(
+            "Running since: {}",
+            format_timestamp(stats.started_since_epoch),
+        ),
+    ]
+}
+
+// Print a summary of the persisted state and how the current space usage compares to the threshold.
+pub fn print(settings: &Settings, state: &State, output: Output) -> io::Result<()> {
+    // The state doesn't record the sizes of images, so we ask the container runtime for them.
+    let image_records = settings
+        .backend
+        .list_image_records(settings.inspect_concurrency)?;
+
+    // Determine how much space is used compared to the threshold.
+    let bytes = space_usage(settings)?;
+    let threshold = space_limit(settings, bytes, threshold_bytes(settings)?)?;
+
+    let last_used = state
+        .images
+        .values()
+        .map(|image| image.last_used_since_epoch);
+    let summary = Stats {
+        tracked_images: state.images.len(),
+        tracked_bytes: state
+            .images
+            .keys()
+            .filter_map(|image_id| image_records.get(image_id))
+            .map(|image_record| image_record.size.get_bytes())
+            .sum(),
+        oldest_last_used_since_epoch: last_used.clone().min(),
+        newest_last_used_since_epoch: last_used.max(),
+        bytes: bytes.get_bytes(),
+        threshold_bytes: threshold.get_bytes(),
+        vacuums: state.counters.vacuums,
+        deletions: state.counters.deletions,
+        reclaimed_bytes: state.counters.reclaimed_bytes,
+        started_since_epoch: state.counters.started_since_epoch,
+    };
+
+    match output {
+        Output::Text => {
+            for line in lines(&summary) {
+                println!("{line}");
+            }
+        }
+        Output::Json => println!("{}", json(&summary)),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{json, lines, Stats},
+        std::time::Duration,
+    };
+
+    #[test]
+    fn lines_under_threshold() {
+        assert_eq!(
+            lines(&Stats {
+                tracked_images: 2,
+                tracked_bytes: 3_000_000,
+                oldest_last_used_since_epoch: Some(Duration::from_secs(1_714_651_210)),
+                newest_last_used_since_epoch: Some(Duration::from_secs(1_714_737_610)),
+                bytes: 4_000_000,
+                threshold_bytes: 10_000_000,
+                vacuums: 12,
+                deletions: 3,
+                reclaimed_bytes: 5_000_000,
+                started_since_epoch: Some(Duration::from_secs(1_714_564_810)),
+            }),
+            vec![
+                "Tracked images: 2",
+                "Total size of tracked images: 3.00 MB",
+                "Least recently used: 2024-05-02T12:00:10Z",
+                "Most recently used: 2024-05-03T12:00:10Z",
+                "Space usage: 4.00 MB",
+                "Threshold: 10.00 MB",
+                "Under the threshold by 6.00 MB",
+                "Vacuums performed: 12",
+                "Images deleted: 3",
+                "Space reclaimed: 5.00 MB",
+                "Running since: 2024-05-01T12:00:10Z",
+            ],
+        );
+    }
+
+    #[test]
+    fn json_no_timestamps() {
+        assert_eq!(
+            json(&Stats {
+                tracked_images: 0,
+                tracked_bytes: 0,
+                oldest_last_used_since_epoch: None,
+                newest_last_used_since_epoch: None,
+                bytes: 12_000_000,
+                threshold_bytes: 10_000_000,
+                vacuums: 0,
+                deletions: 0,
+                reclaimed_bytes: 0,
+                started_since_epoch: Some(Duration::from_secs(1_714_564_810)),
+            }),
+            "{\"tracked_images\":0,\"tracked_bytes\":0,\"least_recently_used\":null,\
+                \"most_recently_used\":null,\"bytes\":12000000,\"threshold_bytes\":10000000,\
+                \"vacuums\":0,\"deletions\":0,\"reclaimed_bytes\":0,\
+                \"running_since\":\"2024-05-01T12:00:10Z\"}",
+        );
+    }
+
+    #[test]
+    fn lines_over_threshold() {
+        assert_eq!(
+            lines(&Stats {
+                tracked_images: 0,
+                tracked_bytes: 0,
+                oldest_last_used_since_epoch: None,
+                newest_last_used_since_epoch: None,
+                bytes: 12_000_000,
+                threshold_bytes: 10_000_000,
+                vacuums: 0,
+                deletions: 0,
+                reclaimed_bytes: 0,
+                started_since_epoch: None,
+            }),
+            vec![
+                "Tracked images: 0",
+                "Total size of tracked images: 0 B",
+                "Least recently used: none",
+                "Most recently used: none",
+                "Space usage: 12.00 MB",
+                "Threshold: 10.00 MB",
+                "Over the threshold by 2.00 MB",
+                "Vacuums performed: 0",
+                "Images deleted: 0",
+                "Space reclaimed: 0 B",
+                "Running since: none",
+            ],
+        );
+    }
+}
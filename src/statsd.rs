@@ -0,0 +1,133 @@
+use {
+    crate::{format::CodeStr, report::Report, state::Counters},
+    std::{
+        io,
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+        time::Duration,
+    },
+};
+
+// The prefix for the names of all metrics
+const PREFIX: &str = "docuum";
+
+// Format the metrics for a vacuum as StatsD lines. DogStatsD accepts the same format.
+fn metrics(report: &Report, counters: &Counters, duration: Duration) -> Vec<String> {
+    let mut metrics = vec![
+        format!("{PREFIX}.vacuums:1|c"),
+        format!("{PREFIX}.deletions:{}|c", report.deleted_images.len()),
+        format!("{PREFIX}.reclaimed_bytes:{}|c", report.reclaimed_bytes),
+        format!("{PREFIX}.errors:{}|c", report.errors.len()),
+        format!("{PREFIX}.vacuum_duration:{}|ms", duration.as_millis()),
+        format!("{PREFIX}.lifetime_vacuums:{}|g", counters.vacuums),
+        format!("{PREFIX}.lifetime_deletions:{}|g", counters.deletions),
+        format!(
+            "{PREFIX}.lifetime_reclaimed_bytes:{}|g",
+            counters.reclaimed_bytes,
+        ),
+    ];
+
+    // Gauges are only sent if the vacuum got far enough to measure them, since sending zero would
+    // be misleading.
+    if let Some(bytes) = report.bytes {
+        metrics.push(format!("{PREFIX}.usage_bytes:{bytes}|g"));
+    }
+    if let Some(threshold_bytes) = report.threshold_bytes {
+        metrics.push(format!("{PREFIX}.threshold_bytes:{threshold_bytes}|g"));
+    }
+
+    metrics
+}
+
+// Send the metrics for a vacuum to a StatsD server in a single datagram. The address is resolved
+// each time, so a server behind a DNS name can move.
+pub fn send(
+    address: &str,
+    report: &Report,
+    counters: &Counters,
+    duration: Duration,
+) -> io::Result<()> {
+    let address = address.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Unable to resolve StatsD address {}.", address.code_str()),
+        )
+    })?;
+
+    let socket = UdpSocket::bind(match address {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    })?;
+    socket.send_to(
+        metrics(report, counters, duration).join("\n").as_bytes(),
+        address,
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::metrics,
+        crate::{
+            report::{DeletedImage, DeletionReason, Report},
+            state::Counters,
+        },
+        std::time::Duration,
+    };
+
+    #[test]
+    fn metrics_complete() {
+        let mut report = Report::default();
+        report.deleted_image(DeletedImage {
+            id: "sha256:0".to_owned(),
+            repository_tags: vec![],
+            size_bytes: 42,
+            reason: DeletionReason::Threshold,
+        });
+        report.bytes = Some(100);
+        report.threshold_bytes = Some(200);
+        let counters = Counters {
+            vacuums: 3,
+            deletions: 5,
+            reclaimed_bytes: 1000,
+            started_since_epoch: None,
+        };
+
+        assert_eq!(
+            metrics(&report, &counters, Duration::from_millis(1500)),
+            vec![
+                "docuum.vacuums:1|c",
+                "docuum.deletions:1|c",
+                "docuum.reclaimed_bytes:42|c",
+                "docuum.errors:0|c",
+                "docuum.vacuum_duration:1500|ms",
+                "docuum.lifetime_vacuums:3|g",
+                "docuum.lifetime_deletions:5|g",
+                "docuum.lifetime_reclaimed_bytes:1000|g",
+                "docuum.usage_bytes:100|g",
+                "docuum.threshold_bytes:200|g",
+            ],
+        );
+    }
+
+    #[test]
+    fn metrics_failed() {
+        let mut report = Report::default();
+        report.errors.push("Uh oh.".to_owned());
+
+        assert_eq!(
+            metrics(&report, &Counters::default(), Duration::ZERO),
+            vec![
+                "docuum.vacuums:1|c",
+                "docuum.deletions:0|c",
+                "docuum.reclaimed_bytes:0|c",
+                "docuum.errors:1|c",
+                "docuum.vacuum_duration:0|ms",
+                "docuum.lifetime_vacuums:0|g",
+                "docuum.lifetime_deletions:0|g",
+                "docuum.lifetime_reclaimed_bytes:0|g",
+            ],
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use {
+    crate::report::Report,
+    byte_unit::Byte,
+    std::sync::{Mutex, PoisonError},
+};
+
+#[cfg(unix)]
+use std::{env, os::unix::net::UnixDatagram};
+
+#[cfg(target_os = "linux")]
+use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+// What Docuum is currently doing, for the status shown by `systemctl status`
+static ACTIVITY: Mutex<&str> = Mutex::new("Starting up");
+
+// The space usage and threshold as of the last vacuum which measured them
+static USAGE: Mutex<Option<(u128, u128)>> = Mutex::new(None);
+
+// Format the status line from the current activity and usage.
+fn status_line(activity: &str, usage: Option<(u128, u128)>) -> String {
+    match usage {
+        Some((bytes, threshold_bytes)) => format!(
+            "{}, {} / {} used",
+            activity,
+            Byte::from_bytes(bytes).get_appropriate_unit(false),
+            Byte::from_bytes(threshold_bytes).get_appropriate_unit(false),
+        ),
+        None => activity.to_owned(),
+    }
+}
+
+// Send a message to the service manager, if there is one. See `sd_notify(3)`.
+#[cfg(unix)]
+fn notify(message: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        // Sockets in the abstract namespace are indicated by a leading `@`.
+        #[cfg(target_os = "linux")]
+        if let Some(name) = path.to_str().and_then(|path| path.strip_prefix('@')) {
+            let address = SocketAddr::from_abstract_name(name)?;
+            return socket
+                .send_to_addr(message.as_bytes(), &address)
+                .map(|_| ());
+        }
+
+        socket.send_to(message.as_bytes(), &path).map(|_| ())
+    });
+
+    if let Err(error) = result {
+        debug!("Unable to notify the service manager. Details: {error}");
+    }
+}
+
+// There is no systemd on this platform.
+#[cfg(not(unix))]
+fn notify(_: &str) {}
+
+// Send the current status to the service manager.
+fn send_status(extra: &str) {
+    let activity = *ACTIVITY.lock().unwrap_or_else(PoisonError::into_inner);
+    let usage = *USAGE.lock().unwrap_or_else(PoisonError::into_inner);
+    notify(&format!("{}STATUS={}", extra, status_line(activity, usage)));
+}
+
+// Tell the service manager what Docuum is doing now.
+pub fn activity(activity: &'static str) {
+    *ACTIVITY.lock().unwrap_or_else(PoisonError::into_inner) = activity;
+    send_status("");
+}
+
+// Tell the service manager that startup is complete, along with what Docuum is doing now.
+pub fn ready(activity: &'static str) {
+    *ACTIVITY.lock().unwrap_or_else(PoisonError::into_inner) = activity;
+    send_status("READY=1\n");
+}
+
+// Update the usage shown in the status after a vacuum.
+pub fn vacuumed(report: &Report) {
+    if let (Some(bytes), Some(threshold_bytes)) = (report.bytes, report.threshold_bytes) {
+        *USAGE.lock().unwrap_or_else(PoisonError::into_inner) = Some((bytes, threshold_bytes));
+        send_status("");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::status_line;
+
+    #[test]
+    fn status_line_with_usage() {
+        assert_eq!(
+            status_line(
+                "Listening for Docker events",
+                Some((7_200_000_000, 10_000_000_000)),
+            ),
+            "Listening for Docker events, 7.20 GB / 10.00 GB used",
+        );
+    }
+
+    #[test]
+    fn status_line_without_usage() {
+        assert_eq!(status_line("Starting up", None), "Starting up");
+    }
+}
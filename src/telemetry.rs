@@ -0,0 +1,186 @@
+use std::io;
+
+#[cfg(feature = "otel")]
+use {
+    opentelemetry::{
+        global,
+        trace::{Status, TraceContextExt, Tracer},
+        Context, ContextGuard, KeyValue,
+    },
+    opentelemetry_otlp::{SpanExporter, WithExportConfig},
+    opentelemetry_sdk::{trace::SdkTracerProvider, Resource},
+    std::sync::OnceLock,
+};
+
+// The name under which Docuum reports its spans
+#[cfg(feature = "otel")]
+const SERVICE_NAME: &str = "docuum";
+
+// Where OTLP/HTTP collectors accept spans, relative to their base URL
+#[cfg(feature = "otel")]
+const TRACES_PATH: &str = "/v1/traces";
+
+// The tracer provider, which is kept so the remaining spans can be flushed upon termination
+#[cfg(feature = "otel")]
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+// The value of an attribute of a span
+#[cfg_attr(not(feature = "otel"), allow(dead_code))]
+pub enum Value {
+    Int(i64),
+    String(String),
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_owned())
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        Self::Int(i64::try_from(value).unwrap_or(i64::MAX))
+    }
+}
+
+impl From<u128> for Value {
+    fn from(value: u128) -> Self {
+        Self::Int(i64::try_from(value).unwrap_or(i64::MAX))
+    }
+}
+
+// A span which ends when it's dropped. While it exists, spans started on the same thread are its
+// children, so spans must be dropped in the reverse order in which they were started.
+pub struct Span {
+    #[cfg(feature = "otel")]
+    context: Context,
+
+    #[cfg(feature = "otel")]
+    _guard: ContextGuard,
+}
+
+#[cfg(feature = "otel")]
+impl Span {
+    // Start a span. Until `init` is called, spans aren't recorded.
+    pub fn start(name: &'static str) -> Self {
+        let context = Context::current_with_span(global::tracer(SERVICE_NAME).start(name));
+        let guard = context.clone().attach();
+        Self {
+            context,
+            _guard: guard,
+        }
+    }
+
+    // Set an attribute of the span.
+    pub fn set(&self, key: &'static str, value: impl Into<Value>) {
+        self.context.span().set_attribute(KeyValue::new(
+            key,
+            match value.into() {
+                Value::Int(value) => opentelemetry::Value::I64(value),
+                Value::String(value) => opentelemetry::Value::String(value.into()),
+            },
+        ));
+    }
+
+    // Mark the span as failed.
+    pub fn fail(&self, error: &io::Error) {
+        self.context
+            .span()
+            .set_status(Status::error(error.to_string()));
+    }
+}
+
+// Without OpenTelemetry support, spans do nothing.
+#[cfg(not(feature = "otel"))]
+#[allow(clippy::unused_self)]
+impl Span {
+    pub fn start(_: &'static str) -> Self {
+        Self {}
+    }
+
+    pub fn set(&self, _: &'static str, _: impl Into<Value>) {}
+
+    pub fn fail(&self, _: &io::Error) {}
+}
+
+// Run a function in a span, which is marked as failed if the function fails.
+pub fn traced<T>(name: &'static str, f: impl FnOnce(&Span) -> io::Result<T>) -> io::Result<T> {
+    let span = Span::start(name);
+    let result = f(&span);
+    if let Err(error) = &result {
+        span.fail(error);
+    }
+    result
+}
+
+// Determine where to send spans, given the base URL of a collector.
+#[cfg(feature = "otel")]
+fn traces_url(endpoint: &str) -> String {
+    format!("{}{TRACES_PATH}", endpoint.trim_end_matches('/'))
+}
+
+// Export spans to an OpenTelemetry collector via OTLP/HTTP. Spans are sent in batches from a
+// background thread.
+#[cfg(feature = "otel")]
+pub fn init(endpoint: &str) -> io::Result<()> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(traces_url(endpoint))
+        .build()
+        .map_err(|error| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unable to set up the OpenTelemetry exporter. Details: {error}"),
+            )
+        })?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+        .build();
+    global::set_tracer_provider(provider.clone());
+    let _ = PROVIDER.set(provider);
+
+    Ok(())
+}
+
+// Without OpenTelemetry support, there's nowhere to send spans.
+#[cfg(not(feature = "otel"))]
+pub fn init(_: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Docuum was built without OpenTelemetry support. Enable the `otel` feature to use it.",
+    ))
+}
+
+// Send the spans which haven't been exported yet.
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    if let Some(provider) = PROVIDER.get() {
+        if let Err(error) = provider.shutdown() {
+            // Log the error and proceed anyway.
+            error!("Unable to export the remaining spans. Details: {error}");
+        }
+    }
+}
+
+#[cfg(all(test, feature = "otel"))]
+mod tests {
+    use super::traces_url;
+
+    #[test]
+    fn traces_url_base() {
+        assert_eq!(
+            traces_url("http://localhost:4318"),
+            "http://localhost:4318/v1/traces",
+        );
+    }
+
+    #[test]
+    fn traces_url_trailing_slash() {
+        assert_eq!(
+            traces_url("https://collector.example.com/otlp/"),
+            "https://collector.example.com/otlp/v1/traces",
+        );
+    }
+}
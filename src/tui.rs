@@ -0,0 +1,549 @@
+use {crate::Settings, std::io};
+
+#[cfg(feature = "tui")]
+use {
+    crate::{
+        format::CodeStr,
+        history::{self, Entry},
+        pins,
+        run::{list_images, space_limit, space_usage, threshold_bytes, ListedImage, Protection},
+        state,
+    },
+    byte_unit::Byte,
+    chrono::{DateTime, Local},
+    ratatui::{
+        crossterm::event::{self, Event, KeyCode, KeyEventKind},
+        layout::{Constraint, Layout},
+        style::{Modifier, Style},
+        text::Line,
+        widgets::{Block, Gauge, List, Paragraph, Row, Table, TableState},
+        DefaultTerminal, Frame,
+    },
+    std::{
+        cmp::Ordering,
+        collections::BTreeSet,
+        time::{Duration, Instant, UNIX_EPOCH},
+    },
+};
+
+// How often the dashboard refreshes on its own
+#[cfg(feature = "tui")]
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+// How long to wait for a key press before checking whether it's time to refresh
+#[cfg(feature = "tui")]
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// How many of the most recent deletions are shown
+#[cfg(feature = "tui")]
+const RECENT_DELETIONS: usize = 8;
+
+// How many hexadecimal digits of an image ID are shown, as in the output of `docker image ls`
+#[cfg(feature = "tui")]
+const SHORT_ID_LENGTH: usize = 12;
+
+// The keybindings, as shown at the bottom of the dashboard
+#[cfg(feature = "tui")]
+const HELP: &str =
+    "\u{2191}/\u{2193} select  s sort  S reverse  p pin/unpin  d delete  r refresh  q quit";
+
+// The column by which the images are sorted
+#[cfg(feature = "tui")]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum SortColumn {
+    // The order in which the images would be deleted
+    Order,
+
+    LastUsed,
+    Size,
+    RepositoryTags,
+}
+
+#[cfg(feature = "tui")]
+impl SortColumn {
+    // The column which `s` switches to
+    fn next(self) -> Self {
+        match self {
+            Self::Order => Self::LastUsed,
+            Self::LastUsed => Self::Size,
+            Self::Size => Self::RepositoryTags,
+            Self::RepositoryTags => Self::Order,
+        }
+    }
+
+    // How the column is described in the title of the table
+    fn description(self) -> &'static str {
+        match self {
+            Self::Order => "deletion order",
+            Self::LastUsed => "last used",
+            Self::Size => "size",
+            Self::RepositoryTags => "repository:tag",
+        }
+    }
+
+    // Compare two images by the column.
+    fn compare(self, (i, a): (usize, &ListedImage), (j, b): (usize, &ListedImage)) -> Ordering {
+        match self {
+            Self::Order => i.cmp(&j),
+            Self::LastUsed => a.last_used_since_epoch.cmp(&b.last_used_since_epoch),
+            Self::Size => a.size.get_bytes().cmp(&b.size.get_bytes()),
+            Self::RepositoryTags => a.repository_tags.cmp(&b.repository_tags),
+        }
+    }
+}
+
+// Determine the order in which the images are shown, as indices into the deletion order. Ties are
+// broken by the deletion order.
+#[cfg(feature = "tui")]
+fn sorted(images: &[ListedImage], column: SortColumn, reverse: bool) -> Vec<usize> {
+    let mut indices = (0..images.len()).collect::<Vec<_>>();
+    indices.sort_by(|&i, &j| {
+        let ordering = column.compare((i, &images[i]), (j, &images[j]));
+        if reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+        .then(i.cmp(&j))
+    });
+    indices
+}
+
+// Abbreviate an image ID.
+#[cfg(feature = "tui")]
+fn short_id(image_id: &str) -> &str {
+    let hex = image_id.split_once(':').map_or(image_id, |(_, hex)| hex);
+    &hex[..hex.len().min(SHORT_ID_LENGTH)]
+}
+
+// Format an amount of space for humans.
+#[cfg(feature = "tui")]
+fn format_bytes(bytes: u128) -> String {
+    Byte::from_bytes(bytes)
+        .get_appropriate_unit(false)
+        .to_string()
+}
+
+// Describe the space usage compared to the threshold.
+#[cfg(feature = "tui")]
+fn usage_label(bytes: u128, threshold_bytes: u128) -> String {
+    format!(
+        "{} of {} ({})",
+        format_bytes(bytes),
+        format_bytes(threshold_bytes),
+        if bytes > threshold_bytes {
+            format!("over by {}", format_bytes(bytes - threshold_bytes))
+        } else {
+            format!("under by {}", format_bytes(threshold_bytes - bytes))
+        },
+    )
+}
+
+// Determine how full the gauge is. It's full when the space usage reaches the threshold.
+#[cfg(feature = "tui")]
+fn usage_ratio(bytes: u128, threshold_bytes: u128) -> f64 {
+    if threshold_bytes == 0 {
+        1.0
+    } else {
+        #[allow(clippy::cast_precision_loss)]
+        (bytes as f64 / threshold_bytes as f64).min(1.0)
+    }
+}
+
+// Format a deletion from the history as a line of the list of recent deletions.
+#[cfg(feature = "tui")]
+fn deletion_line(entry: &Entry) -> String {
+    format!(
+        "{}  {}  {}  {}",
+        entry.timestamp,
+        format_bytes(entry.size_bytes),
+        short_id(&entry.id),
+        entry.repository_tags.join(", "),
+    )
+}
+
+// What the dashboard shows, along with the user's selection
+#[cfg(feature = "tui")]
+struct Dashboard {
+    // The images in the order in which they would be deleted
+    images: Vec<ListedImage>,
+
+    // The order in which the images are shown, as indices into `images`
+    order: Vec<usize>,
+
+    pinned_image_ids: BTreeSet<String>,
+    usage: Option<(u128, u128)>,
+    recent_deletions: Vec<Entry>,
+    sort_column: SortColumn,
+    reverse: bool,
+    table_state: TableState,
+
+    // The image which is about to be deleted, once the user confirms it
+    pending_deletion: Option<String>,
+
+    // The outcome of the last action, or an error
+    message: String,
+
+    refreshed: Instant,
+}
+
+#[cfg(feature = "tui")]
+impl Dashboard {
+    fn new() -> Self {
+        Self {
+            images: vec![],
+            order: vec![],
+            pinned_image_ids: BTreeSet::new(),
+            usage: None,
+            recent_deletions: vec![],
+            sort_column: SortColumn::Order,
+            reverse: false,
+            table_state: TableState::default(),
+            pending_deletion: None,
+            message: String::new(),
+            refreshed: Instant::now(),
+        }
+    }
+
+    // The image which is currently selected, if any
+    fn selected(&self) -> Option<&ListedImage> {
+        self.table_state
+            .selected()
+            .and_then(|row| self.order.get(row))
+            .map(|&index| &self.images[index])
+    }
+
+    // Sort the images again, keeping the same image selected.
+    fn sort(&mut self) {
+        let selected_id = self.selected().map(|image| image.id.clone());
+        self.order = sorted(&self.images, self.sort_column, self.reverse);
+        self.table_state.select(if self.order.is_empty() {
+            None
+        } else {
+            Some(
+                selected_id
+                    .and_then(|image_id| {
+                        self.order
+                            .iter()
+                            .position(|&index| self.images[index].id == image_id)
+                    })
+                    .unwrap_or(0),
+            )
+        });
+    }
+
+    // Ask the container runtime and the files on disk what's going on.
+    fn refresh(&mut self, settings: &Settings) -> io::Result<()> {
+        self.refreshed = Instant::now();
+
+        let (state, first_run) =
+            state::load(None).map_or_else(|_| (state::initial(), true), |state| (state, false));
+        let images = list_images(settings, &state, first_run)?;
+        let pinned_image_ids = pins::load()?;
+        let bytes = space_usage(settings)?;
+        let threshold = space_limit(settings, bytes, threshold_bytes(settings)?)?;
+        let mut recent_deletions = history::entries()?;
+        recent_deletions.reverse();
+        recent_deletions.truncate(RECENT_DELETIONS);
+
+        self.images = images;
+        self.pinned_image_ids = pinned_image_ids;
+        self.usage = Some((bytes.get_bytes(), threshold.get_bytes()));
+        self.recent_deletions = recent_deletions;
+        self.sort();
+
+        Ok(())
+    }
+
+    // Pin the selected image, or unpin it if it's already pinned.
+    fn toggle_pin(&mut self) -> io::Result<String> {
+        let Some(image_id) = self.selected().map(|image| image.id.clone()) else {
+            return Ok(String::new());
+        };
+
+        let pinned = !self.pinned_image_ids.contains(&image_id);
+        pins::set(&image_id, pinned)?;
+        Ok(format!(
+            "{} image {}.",
+            if pinned { "Pinned" } else { "Unpinned" },
+            short_id(&image_id),
+        ))
+    }
+
+    // Handle a key press. Returns whether the user wants to quit.
+    fn handle_key(&mut self, settings: &Settings, key: KeyCode) -> bool {
+        // A pending deletion only happens if the user confirms it with the very next key.
+        if let Some(image_id) = self.pending_deletion.take() {
+            self.message = if key == KeyCode::Char('y') {
+                match settings.backend.delete_image(&image_id, false) {
+                    Ok(()) => format!("Deleted image {}.", short_id(&image_id)),
+                    Err(error) => error.to_string(),
+                }
+            } else {
+                "Kept the image.".to_owned()
+            };
+            self.refresh_or_report(settings);
+            return false;
+        }
+
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Up | KeyCode::Char('k') => self.table_state.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.table_state.select_next(),
+            KeyCode::Char('s') => {
+                self.sort_column = self.sort_column.next();
+                self.sort();
+            }
+            KeyCode::Char('S') => {
+                self.reverse = !self.reverse;
+                self.sort();
+            }
+            KeyCode::Char('p') => {
+                self.message = self.toggle_pin().unwrap_or_else(|error| error.to_string());
+                self.refresh_or_report(settings);
+            }
+            KeyCode::Char('d') => {
+                if let Some(image) = self.selected() {
+                    let message = format!(
+                        "Delete image {} ({})? Press y to confirm.",
+                        short_id(&image.id),
+                        image.repository_tags.join(", "),
+                    );
+                    self.pending_deletion = Some(image.id.clone());
+                    self.message = message;
+                }
+            }
+            KeyCode::Char('r') => self.refresh_or_report(settings),
+            _ => {}
+        }
+
+        false
+    }
+
+    // Refresh, showing the error if that fails.
+    fn refresh_or_report(&mut self, settings: &Settings) {
+        if let Err(error) = self.refresh(settings) {
+            self.message = error.to_string();
+        }
+    }
+
+    // Draw the dashboard.
+    fn draw(&mut self, frame: &mut Frame) {
+        let [usage_area, images_area, deletions_area, help_area] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(5),
+            Constraint::Length(u16::try_from(RECENT_DELETIONS).unwrap_or(u16::MAX) + 2),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let (bytes, threshold_bytes) = self.usage.unwrap_or((0, 0));
+        frame.render_widget(
+            Gauge::default()
+                .block(Block::bordered().title("Space usage"))
+                .ratio(usage_ratio(bytes, threshold_bytes))
+                .label(usage_label(bytes, threshold_bytes)),
+            usage_area,
+        );
+
+        let rows = self.order.iter().map(|&index| {
+            let image = &self.images[index];
+            Row::new([
+                (index + 1).to_string(),
+                DateTime::<Local>::from(UNIX_EPOCH + image.last_used_since_epoch)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string(),
+                format_bytes(image.size.get_bytes()),
+                short_id(&image.id).to_owned(),
+                image.repository_tags.join(", "),
+                if self.pinned_image_ids.contains(&image.id) {
+                    Protection::Pinned.cause()
+                } else {
+                    image
+                        .protection
+                        .as_ref()
+                        .map_or_else(String::new, Protection::cause)
+                },
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(5),
+                Constraint::Length(16),
+                Constraint::Length(10),
+                Constraint::Length(12),
+                Constraint::Fill(1),
+                Constraint::Length(24),
+            ],
+        )
+        .header(
+            Row::new([
+                "#",
+                "LAST USED",
+                "SIZE",
+                "IMAGE ID",
+                "REPOSITORY:TAG",
+                "PROTECTED BY",
+            ])
+            .style(Style::new().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::bordered().title(format!(
+            "Images ({} by {})",
+            if self.reverse {
+                "reverse-sorted"
+            } else {
+                "sorted"
+            },
+            self.sort_column.description(),
+        )))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, images_area, &mut self.table_state);
+
+        frame.render_widget(
+            List::new(self.recent_deletions.iter().map(deletion_line))
+                .block(Block::bordered().title("Recent deletions")),
+            deletions_area,
+        );
+
+        frame.render_widget(
+            Paragraph::new(Line::from(if self.message.is_empty() {
+                HELP.to_owned()
+            } else {
+                format!("{}  |  {HELP}", self.message)
+            })),
+            help_area,
+        );
+    }
+}
+
+// Redraw the dashboard and handle key presses until the user quits.
+#[cfg(feature = "tui")]
+fn event_loop(settings: &Settings, terminal: &mut DefaultTerminal) -> io::Result<()> {
+    let mut dashboard = Dashboard::new();
+    dashboard.refresh_or_report(settings);
+
+    loop {
+        terminal.draw(|frame| dashboard.draw(frame))?;
+
+        if event::poll(POLL_INTERVAL)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    if dashboard.handle_key(settings, key.code) {
+                        return Ok(());
+                    }
+
+                    // The container runtime may have printed something, so draw from scratch.
+                    terminal.clear()?;
+                }
+            }
+        } else if dashboard.refreshed.elapsed() >= REFRESH_INTERVAL {
+            dashboard.refresh_or_report(settings);
+        }
+    }
+}
+
+// Show a live dashboard of the images until the user quits.
+#[cfg(feature = "tui")]
+pub fn run(settings: &Settings) -> io::Result<()> {
+    debug!(
+        "Starting the dashboard for {}\u{2026}",
+        "docuum tui".code_str(),
+    );
+
+    let mut terminal = ratatui::try_init()?;
+    let result = event_loop(settings, &mut terminal);
+    ratatui::try_restore()?;
+    result
+}
+
+// Without dashboard support, there's nothing to show.
+#[cfg(not(feature = "tui"))]
+pub fn run(_: &Settings) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Docuum was built without dashboard support. Enable the `tui` feature to use it.",
+    ))
+}
+
+#[cfg(all(test, feature = "tui"))]
+mod tests {
+    use {
+        super::{short_id, sorted, usage_label, usage_ratio, SortColumn},
+        crate::run::ListedImage,
+        byte_unit::Byte,
+        std::time::Duration,
+    };
+
+    fn image(last_used: u64, size: u128, repository_tag: &str) -> ListedImage {
+        ListedImage {
+            id: format!("sha256:{repository_tag}"),
+            last_used_since_epoch: Duration::from_secs(last_used),
+            size: Byte::from_bytes(size),
+            repository_tags: vec![repository_tag.to_owned()],
+            containers: vec![],
+            protection: None,
+        }
+    }
+
+    fn images() -> Vec<ListedImage> {
+        vec![
+            image(300, 10, "debian:12"),
+            image(100, 30, "alpine:3.20"),
+            image(200, 10, "node:20"),
+        ]
+    }
+
+    #[test]
+    fn sorted_order() {
+        assert_eq!(sorted(&images(), SortColumn::Order, false), vec![0, 1, 2]);
+        assert_eq!(sorted(&images(), SortColumn::Order, true), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn sorted_last_used() {
+        assert_eq!(
+            sorted(&images(), SortColumn::LastUsed, false),
+            vec![1, 2, 0],
+        );
+    }
+
+    #[test]
+    fn sorted_size_ties() {
+        assert_eq!(sorted(&images(), SortColumn::Size, false), vec![0, 2, 1]);
+        assert_eq!(sorted(&images(), SortColumn::Size, true), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn sorted_repository_tags() {
+        assert_eq!(
+            sorted(&images(), SortColumn::RepositoryTags, false),
+            vec![1, 0, 2],
+        );
+    }
+
+    #[test]
+    fn short_id_algorithm() {
+        assert_eq!(short_id("sha256:0123456789abcdef"), "0123456789ab");
+        assert_eq!(short_id("0123"), "0123");
+    }
+
+    #[test]
+    fn usage_under_threshold() {
+        assert_eq!(
+            usage_label(4_000_000, 10_000_000),
+            "4.00 MB of 10.00 MB (under by 6.00 MB)",
+        );
+        assert!((usage_ratio(4_000_000, 10_000_000) - 0.4).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn usage_over_threshold() {
+        assert_eq!(
+            usage_label(12_000_000, 10_000_000),
+            "12.00 MB of 10.00 MB (over by 2.00 MB)",
+        );
+        assert!((usage_ratio(12_000_000, 10_000_000) - 1.0).abs() < f64::EPSILON);
+        assert!((usage_ratio(1, 0) - 1.0).abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,59 @@
+use {
+    crate::{format::CodeStr, report::Report},
+    std::{
+        io::{self, Write},
+        process::{Command, Stdio},
+    },
+};
+
+// How long to wait for the webhook to respond, in seconds
+const TIMEOUT_SECS: &str = "10";
+
+// Post a vacuum report to a webhook as JSON. We use `curl` for this, just as we use the CLI of the
+// container runtime rather than talking to its API directly.
+pub fn post(url: &str, report: &Report) -> io::Result<()> {
+    let payload = serde_json::to_vec(report).map_err(io::Error::other)?;
+
+    let mut child = Command::new("curl")
+        .args([
+            "--silent",
+            "--show-error",
+            "--fail",
+            "--max-time",
+            TIMEOUT_SECS,
+            "--header",
+            "Content-Type: application/json",
+            "--data-binary",
+            "@-",
+            "--url",
+            url,
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!(
+                    "Unable to run {} to notify the webhook. Details: {}",
+                    "curl".code_str(),
+                    error,
+                ),
+            )
+        })?;
+
+    // The `unwrap` is safe because standard input was piped above. The handle is dropped right
+    // away to signal the end of the payload.
+    child.stdin.take().unwrap().write_all(&payload)?;
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Unable to notify the webhook. Details: {}",
+            String::from_utf8_lossy(&output.stderr).trim(),
+        )));
+    }
+
+    Ok(())
+}